@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::{env, fs};
 
@@ -8,11 +9,78 @@ struct DeviceConfig {
     id: String,
     bt_name: String,
     colour: [u8; 3],
+    // Optional bands of colour for the stripes animation
+    #[serde(default)]
+    stripes: Vec<[u8; 3]>,
+    // Optional contact token handed over in a soul-link exchange
+    #[serde(default)]
+    contact: String,
+    // Development units can be put on the test network permanently
+    #[serde(default)]
+    test_network: bool,
+    // Optional names of the souls that get a fanfare when they come into range
+    #[serde(default)]
+    favourites: Vec<String>,
+    // Optional role at an event: regular, organiser, medic or bar_crew
+    #[serde(default)]
+    role: Option<String>,
+    // Optional colours we would rather show other souls in, by advertised name
+    #[serde(default)]
+    colours: BTreeMap<String, [u8; 3]>,
+    // Optional pixels for friends in constellation mode, by advertised name
+    #[serde(default)]
+    constellation: BTreeMap<String, u16>,
+    // Optional revision of the board the soul is built on
+    #[serde(default)]
+    hardware: Option<String>,
+    // Optional GPIO that switches the strip's power rail or level shifter on
+    #[serde(default)]
+    led_power_pin: Option<u8>,
 }
 
+// Optional company IDs that override the defaults in src/configuration.rs
+#[derive(Deserialize, Default)]
+struct NetworkConfig {
+    company_id: Option<u16>,
+    test_company_id: Option<u16>,
+    // Optional key that controllers tag their commands with
+    group_key: Option<[u8; 16]>,
+    // Optional venue Wi-Fi, used to take light shows from a lighting desk
+    #[serde(default)]
+    wifi_ssid: String,
+    // Only here to stop the build if somebody puts the password in souls.toml, see src/wifi.rs
+    wifi_password: Option<String>,
+}
+
+// The longest hardware revision the Device Information Service can hold, see src/soul_link.rs
+const MAX_HARDWARE_REVISION: usize = 16;
+
+// The GPIOs the firmware already drives, see src/main.rs, or that the board needs for USB. Keep in step with
+// RESERVED_GPIOS in src/buttons.rs, which checks the buttons.
+const RESERVED_GPIOS: &[(u8, &str)] = &[
+    (0, "the battery"),
+    (1, "the light sensor"),
+    (5, "the vibration motor"),
+    (6, "the LED strip"),
+    (7, "the trigger output"),
+    (10, "DMX"),
+    (12, "USB"),
+    (13, "USB"),
+    (18, "the microphone"),
+    (19, "the microphone"),
+    (20, "the microphone"),
+    (22, "the GPIO expander"),
+    (23, "the GPIO expander"),
+];
+
+// GPIO24 to GPIO30 are wired to the flash, and there is no GPIO past that
+const FIRST_FLASH_GPIO: u8 = 24;
+
 // Wrapper struct to match the top-level TOML structure
 #[derive(Deserialize)]
 struct Config {
+    #[serde(default)]
+    network: NetworkConfig,
     device: Vec<DeviceConfig>,
 }
 
@@ -20,7 +88,7 @@ fn main() {
     // Pretty shamelessly AI code here. carefully eyeballed....
 
     // Force a rebuild if our soul configuration or the targeted soul has changed
-    println!("cargo:rerun-if-changed=soul_config.toml");
+    println!("cargo:rerun-if-changed=souls.toml");
     println!("cargo:rerun-if-env-changed=SOUL_ID");
 
     let dest_path = Path::new("src/soul_config.rs");
@@ -41,6 +109,10 @@ fn main() {
     let config: Config = toml::from_str(&config_str).expect("Could not parse devices.toml");
 
     // Find the specific device's configuration.
+    let network = config.network;
+    if network.wifi_password.is_some() {
+        panic!("Keep the Wi-Fi password out of souls.toml and set SOULSTAR_WIFI_PASSWORD when building instead");
+    }
     let device_config = config
         .device
         .into_iter()
@@ -48,14 +120,97 @@ fn main() {
         .unwrap_or_else(|| panic!("Could not find configuration for device ID: {device_id}"));
 
     // Generate the Rust code with the device's parameters.
+    let stripes = device_config
+        .stripes
+        .iter()
+        .map(|s| format!("[{}, {}, {}]", s[0], s[1], s[2]))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let favourites = device_config
+        .favourites
+        .iter()
+        .map(|f| format!("{f:?}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let colours = device_config
+        .colours
+        .iter()
+        .map(|(name, c)| format!("({name:?}, [{}, {}, {}])", c[0], c[1], c[2]))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let strip = led_string_size();
+    if let Some((name, pixel)) = device_config.constellation.iter().find(|(_, pixel)| **pixel >= strip) {
+        panic!("Constellation pixel {pixel} for {name} is off the end of the {strip} pixel strip");
+    }
+    let constellation = device_config
+        .constellation
+        .iter()
+        .map(|(name, pixel)| format!("({name:?}, {pixel})"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if let Some(pin) = device_config.led_power_pin {
+        if pin >= FIRST_FLASH_GPIO {
+            panic!("LED power pin GPIO{pin} is a flash pin or doesn't exist");
+        }
+        if let Some((_, user)) = RESERVED_GPIOS.iter().find(|(reserved, _)| *reserved == pin) {
+            panic!("LED power pin GPIO{pin} is already used for {user}");
+        }
+        if button_pins().contains(&pin) {
+            panic!("LED power pin GPIO{pin} is already used for a button");
+        }
+    }
+    let hardware = device_config.hardware.as_deref().unwrap_or("1");
+    if hardware.len() > MAX_HARDWARE_REVISION {
+        panic!("Hardware revision {hardware} is longer than {MAX_HARDWARE_REVISION} bytes");
+    }
+    let role = match device_config.role.as_deref().unwrap_or("regular") {
+        "regular" => "Regular",
+        "organiser" => "Organiser",
+        "medic" => "Medic",
+        "bar_crew" => "BarCrew",
+        other => panic!("Unknown role {other} for device ID: {device_id}"),
+    };
+    let id = |id: Option<u16>| id.map_or("None".to_string(), |id| format!("Some(0x{id:04X})"));
+    let group_key = network
+        .group_key
+        .map_or("None".to_string(), |key| format!("Some({key:?})"));
     let generated_code = format!(
         r#"
 // This file is automatically generated. Do not edit.
 
 pub const ADVERTISED_NAME: &str = "{}";
 pub const COLOUR: [u8; 3] = [{}, {}, {}];
+pub const STRIPES: &[[u8; 3]] = &[{}];
+pub const CONTACT: &str = "{}";
+pub const COMPANY_ID: Option<u16> = {};
+pub const TEST_COMPANY_ID: Option<u16> = {};
+pub const TEST_NETWORK: bool = {};
+pub const FAVOURITES: &[&str] = &[{}];
+pub const COLOUR_OVERRIDES: &[(&str, [u8; 3])] = &[{}];
+pub const CONSTELLATION: &[(&str, u16)] = &[{}];
+pub const ROLE: soulstar::fields::Role = soulstar::fields::Role::{};
+pub const GROUP_KEY: Option<soulstar::command::GroupKey> = {};
+pub const HARDWARE_REVISION: &str = {:?};
+pub const LED_POWER_PIN: Option<u8> = {:?};
+pub const WIFI_SSID: &str = {:?};
 "#,
-        device_config.bt_name, device_config.colour[0], device_config.colour[1], device_config.colour[2]
+        device_config.bt_name,
+        device_config.colour[0],
+        device_config.colour[1],
+        device_config.colour[2],
+        stripes,
+        device_config.contact,
+        id(network.company_id),
+        id(network.test_company_id),
+        device_config.test_network,
+        favourites,
+        colours,
+        constellation,
+        role,
+        group_key,
+        hardware,
+        device_config.led_power_pin,
+        network.wifi_ssid
     );
 
     // 7. Write the generated code to the file.
@@ -64,8 +219,33 @@ pub const COLOUR: [u8; 3] = [{}, {}, {}];
 
     // Linker stuff
     linker_be_nice();
-    // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
-    println!("cargo:rustc-link-arg=-Tlinkall.x");
+    // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link).
+    // Only the binary needs it, the library tests are linked for the host.
+    println!("cargo:rustc-link-arg-bins=-Tlinkall.x");
+}
+
+// The length of the strip from src/configuration.rs, so pixels in souls.toml can be checked
+fn led_string_size() -> u16 {
+    println!("cargo:rerun-if-changed=src/configuration.rs");
+    fs::read_to_string("src/configuration.rs")
+        .expect("Could not read src/configuration.rs")
+        .lines()
+        .find_map(|line| line.strip_prefix("pub const LED_STRING_SIZE: usize = "))
+        .and_then(|size| size.trim_end_matches(';').parse().ok())
+        .expect("Could not find LED_STRING_SIZE in src/configuration.rs")
+}
+
+// The pins of the buttons in GPIO_BUTTONS in src/configuration.rs
+fn button_pins() -> Vec<u8> {
+    println!("cargo:rerun-if-changed=src/configuration.rs");
+    fs::read_to_string("src/configuration.rs")
+        .expect("Could not read src/configuration.rs")
+        .lines()
+        .skip_while(|line| !line.starts_with("pub const GPIO_BUTTONS"))
+        .take_while(|line| *line != "];")
+        .filter_map(|line| line.trim().strip_prefix("pin: "))
+        .map(|pin| pin.trim_end_matches(',').parse().expect("Could not read a button pin"))
+        .collect()
 }
 
 fn linker_be_nice() {
@@ -113,5 +293,5 @@ fn linker_be_nice() {
         std::process::exit(0);
     }
 
-    println!("cargo:rustc-link-arg=--error-handling-script={}", std::env::current_exe().unwrap().display());
+    println!("cargo:rustc-link-arg-bins=--error-handling-script={}", std::env::current_exe().unwrap().display());
 }
@@ -0,0 +1,242 @@
+//! Assembly of raw advertisement data.
+//!
+//! Advertisement data is a list of structures, each a length byte, a type byte and the data. The
+//! [AdvertBuilder] adds them one at a time and checks each against the room that is left, so a new
+//! field that doesn't fit is reported instead of silently truncating the beacon. Legacy
+//! advertisements hold 31 bytes, and unless asked for more that is all the builder allows, so an
+//! advertisement that would be cut short on air fails in the tests instead. Only our extended
+//! (coded PHY) beacon is built with room for up to
+//! [MAX_ADVERTISEMENT_SIZE](crate::payload::MAX_ADVERTISEMENT_SIZE).
+
+use defmt::Format;
+use heapless::Vec;
+
+/// The advertisement data type for the flags
+pub const FLAGS_AD_TYPE: u8 = 0x01;
+
+/// The advertisement data type for the complete local name
+pub const COMPLETE_LOCAL_NAME_AD_TYPE: u8 = 0x09;
+
+/// The advertisement data type used for the transmitter power level
+pub const TX_POWER_AD_TYPE: u8 = 0x0A;
+
+/// The advertisement data type for the appearance
+pub const APPEARANCE_AD_TYPE: u8 = 0x19;
+
+/// The advertisement data type for manufacturer specific data
+pub const MANUFACTURER_DATA_AD_TYPE: u8 = 0xFF;
+
+/// The most data a legacy advertisement can carry
+pub const LEGACY_ADVERTISEMENT_SIZE: usize = 31;
+
+/// A structure that didn't fit in the advertisement
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct AdvertOverflow {
+    /// The type of the structure that didn't fit
+    pub ty: u8,
+    /// The bytes it needed, including its length and type
+    pub needed: usize,
+    /// The bytes that were left
+    pub available: usize,
+}
+
+/// Builds advertisement data of at most N bytes, see the module documentation
+pub struct AdvertBuilder<const N: usize = LEGACY_ADVERTISEMENT_SIZE> {
+    data: Vec<u8, N>,
+    /// The first structure that didn't fit. Nothing is added after it.
+    overflow: Option<AdvertOverflow>,
+}
+
+impl Default for AdvertBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdvertBuilder {
+    /// Build a legacy advertisement
+    pub fn new() -> Self {
+        Self::extended()
+    }
+}
+
+impl<const N: usize> AdvertBuilder<N> {
+    /// Build an extended advertisement of up to N bytes
+    pub fn extended() -> Self {
+        Self {
+            data: Vec::new(),
+            overflow: None,
+        }
+    }
+
+    /// Add the flags structure
+    pub fn flags(self, flags: u8) -> Self {
+        self.structure(FLAGS_AD_TYPE, &[&[flags]])
+    }
+
+    /// Add the complete local name
+    pub fn name(self, name: &str) -> Self {
+        self.structure(COMPLETE_LOCAL_NAME_AD_TYPE, &[name.as_bytes()])
+    }
+
+    /// Add manufacturer specific data, tagged with the company ID
+    pub fn manufacturer(self, company_id: u16, payload: &[u8]) -> Self {
+        self.structure(MANUFACTURER_DATA_AD_TYPE, &[&company_id.to_le_bytes(), payload])
+    }
+
+    /// Add the transmitter power level in dBm
+    pub fn tx_power(self, dbm: i8) -> Self {
+        self.structure(TX_POWER_AD_TYPE, &[&[dbm as u8]])
+    }
+
+    /// Add the appearance, as in the GAP service
+    pub fn appearance(self, appearance: u16) -> Self {
+        self.structure(APPEARANCE_AD_TYPE, &[&appearance.to_le_bytes()])
+    }
+
+    /// Add a structure of any type, made up of the given parts
+    pub fn structure(mut self, ty: u8, parts: &[&[u8]]) -> Self {
+        if self.overflow.is_some() {
+            return self;
+        }
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+        let needed = len + 2;
+        let available = N - self.data.len();
+        // The length byte covers the type and the data
+        if needed > available || len + 1 > u8::MAX as usize {
+            self.overflow = Some(AdvertOverflow { ty, needed, available });
+            return self;
+        }
+        // There is room, checked above
+        let _ = self.data.push(len as u8 + 1);
+        let _ = self.data.push(ty);
+        parts.iter().for_each(|p| {
+            let _ = self.data.extend_from_slice(p);
+        });
+        self
+    }
+
+    /// The bytes still free
+    pub fn remaining(&self) -> usize {
+        N - self.data.len()
+    }
+
+    /// The finished advertisement data, or the first structure that didn't fit
+    pub fn build(self) -> Result<Vec<u8, N>, AdvertOverflow> {
+        match self.overflow {
+            Some(overflow) => Err(overflow),
+            None => Ok(self.data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_builds_structures() {
+        let data = AdvertBuilder::<LEGACY_ADVERTISEMENT_SIZE>::new()
+            .flags(0x06)
+            .name("Dr Who")
+            .manufacturer(0xBEEF, &[1, 2, 3])
+            .tx_power(-4)
+            .build()
+            .unwrap();
+        assert_eq!(
+            data.as_slice(),
+            &[
+                2,
+                FLAGS_AD_TYPE,
+                0x06,
+                7,
+                COMPLETE_LOCAL_NAME_AD_TYPE,
+                b'D',
+                b'r',
+                b' ',
+                b'W',
+                b'h',
+                b'o',
+                6,
+                MANUFACTURER_DATA_AD_TYPE,
+                0xEF,
+                0xBE,
+                1,
+                2,
+                3,
+                2,
+                TX_POWER_AD_TYPE,
+                0xFC
+            ]
+        );
+    }
+
+    #[test]
+    pub fn if_it_reports_overflow() {
+        let builder = AdvertBuilder::<LEGACY_ADVERTISEMENT_SIZE>::new().name("Dr Who and all his companions");
+        assert_eq!(builder.remaining(), 0);
+        // Exactly full is fine, one more byte is not
+        assert!(builder.build().is_ok());
+        let overflow = AdvertBuilder::<LEGACY_ADVERTISEMENT_SIZE>::new()
+            .name("Dr Who and all his companions")
+            .tx_power(8)
+            .flags(0x06)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            overflow,
+            AdvertOverflow {
+                ty: TX_POWER_AD_TYPE,
+                needed: 3,
+                available: 0
+            }
+        );
+    }
+
+    #[test]
+    pub fn if_arbitrary_structures_fit_or_are_reported() {
+        let mut rng = fastrand::Rng::with_seed(0xAD5);
+        for _ in 0..10_000 {
+            let mut builder = AdvertBuilder::<LEGACY_ADVERTISEMENT_SIZE>::new();
+            let mut added: Vec<(u8, Vec<u8, 40>), 16> = Vec::new();
+            let mut used = 0;
+            let mut first_overflow = None;
+            for _ in 0..rng.usize(0..8) {
+                let ty = rng.u8(..);
+                let mut data: Vec<u8, 40> = Vec::new();
+                data.resize(rng.usize(..=40), 0).unwrap();
+                rng.fill(&mut data);
+                builder = builder.structure(ty, &[data.as_slice()]);
+                let needed = data.len() + 2;
+                if first_overflow.is_none() {
+                    if used + needed > LEGACY_ADVERTISEMENT_SIZE {
+                        first_overflow = Some(AdvertOverflow {
+                            ty,
+                            needed,
+                            available: LEGACY_ADVERTISEMENT_SIZE - used,
+                        });
+                    } else {
+                        used += needed;
+                        added.push((ty, data)).unwrap();
+                    }
+                }
+            }
+            match (builder.build(), first_overflow) {
+                (Err(overflow), Some(expected)) => assert_eq!(overflow, expected),
+                (Ok(data), None) => {
+                    // Walking the structures gives back exactly what went in
+                    assert_eq!(data.len(), used);
+                    let mut rest = data.as_slice();
+                    for (ty, d) in &added {
+                        assert_eq!(rest[0] as usize, d.len() + 1);
+                        assert_eq!(rest[1], *ty);
+                        assert_eq!(&rest[2..2 + d.len()], d.as_slice());
+                        rest = &rest[2 + d.len()..];
+                    }
+                    assert!(rest.is_empty());
+                }
+                (result, expected) => panic!("Built {:?}, expected overflow {:?}", result, expected),
+            }
+        }
+    }
+}
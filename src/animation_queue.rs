@@ -0,0 +1,129 @@
+//! The queue of animations waiting to be displayed.
+//!
+//! Presence animations describe the current layout of visible souls, so only the newest one is
+//! worth keeping. Any pending presence animation is replaced by a newer one, which always goes to
+//! the back of the queue so it is shown after any pending celebrations. Celebrations (e.g. the
+//! sparkle for a new soul) are only dropped when the queue is full, oldest first.
+
+use crate::animations::{Animation, postpone};
+use embassy_time::Duration;
+use heapless::Vec;
+
+/// A bounded animation queue that coalesces presence animations
+pub struct AnimationQueue<const N: usize> {
+    pending: Vec<Animation, N>,
+}
+
+impl<const N: usize> Default for AnimationQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> AnimationQueue<N> {
+    pub fn new() -> Self {
+        Self { pending: Vec::new() }
+    }
+
+    /// Add an animation to the back of the queue, applying the overwrite policy described in
+    /// the module documentation.
+    ///
+    /// # Returns
+    /// The animation that had to be dropped to make space, if any
+    pub fn enqueue(&mut self, animation: Animation) -> Option<Animation> {
+        if matches!(animation, Animation::Presence(_)) {
+            self.pending.retain(|a| !matches!(a, Animation::Presence(_)));
+        }
+        let dropped = if self.pending.is_full() {
+            self.drop_oldest_celebration()
+        } else {
+            None
+        };
+        // We can only still be full if the queue holds nothing but a presence animation
+        match self.pending.push(animation) {
+            Ok(()) => dropped,
+            Err(animation) => Some(animation),
+        }
+    }
+
+    /// The next animation to be displayed
+    pub fn peek(&self) -> Option<&Animation> {
+        self.pending.first()
+    }
+
+    /// Remove and return the next animation to be displayed
+    pub fn dequeue(&mut self) -> Option<Animation> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(self.pending.remove(0))
+        }
+    }
+
+    /// Postpone the expiry of all the pending animations
+    pub fn postpone(&mut self, by: Duration) {
+        self.pending.iter_mut().for_each(|a| postpone(a, by));
+    }
+
+    /// Throw away everything waiting to be displayed
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Number of animations waiting to be displayed
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn drop_oldest_celebration(&mut self) -> Option<Animation> {
+        self.pending
+            .iter()
+            .position(|a| !matches!(a, Animation::Presence(_)))
+            .map(|idx| self.pending.remove(idx))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::animations::{PresenceAnimation, SparkleAnimation};
+    use smart_leds::RGB8;
+
+    fn sparkle() -> Animation {
+        Animation::Sparkle(SparkleAnimation::new(RGB8::new(1, 2, 3), Some(Duration::from_secs(1))))
+    }
+
+    fn presence() -> Animation {
+        Animation::Presence(PresenceAnimation::new())
+    }
+
+    #[test]
+    pub fn if_it_coalesces_presence() {
+        let mut q: AnimationQueue<4> = AnimationQueue::new();
+        q.enqueue(sparkle());
+        q.enqueue(presence());
+        q.enqueue(sparkle());
+        q.enqueue(presence());
+        assert_eq!(q.len(), 3);
+        assert!(matches!(q.dequeue(), Some(Animation::Sparkle(_))));
+        assert!(matches!(q.dequeue(), Some(Animation::Sparkle(_))));
+        assert!(matches!(q.dequeue(), Some(Animation::Presence(_))));
+        assert!(q.dequeue().is_none());
+    }
+
+    #[test]
+    pub fn if_it_drops_celebrations_when_full() {
+        let mut q: AnimationQueue<2> = AnimationQueue::new();
+        assert!(q.enqueue(presence()).is_none());
+        assert!(q.enqueue(sparkle()).is_none());
+        assert!(matches!(q.enqueue(sparkle()), Some(Animation::Sparkle(_))));
+        assert!(q.enqueue(presence()).is_none());
+        assert_eq!(q.len(), 2);
+        assert!(matches!(q.dequeue(), Some(Animation::Sparkle(_))));
+        assert!(matches!(q.dequeue(), Some(Animation::Presence(_))));
+    }
+}
@@ -1,18 +1,95 @@
 //! Animations module provides different LED animation patterns for the Soul Star device.
 //!
 //! This module contains implementations for various LED animations including:
-//! - Sparkle animations that create random brightness variations of a single colour
+//! - Sparkle animations that create random brightness variations of a single colour, optionally
+//!   phase-locked to the souls around us
 //! - Presence animations that display and rotate colours representing visible souls
+//! - Wave animations that throb smoothly with the phase shifted along the strip
+//! - Scanner animations with a dot bouncing along the strip (see [scanner])
+//! - Lava lamp animations with soft blobs of colour drifting along the strip (see [lava])
+//! - Strobe animations for party mode, rate limited for safety (see [strobe])
+//! - Stripe animations with scrolling bands of colour, like a flag (see [stripes])
+//! - Ocean animations with gentle layered waves in blues and greens (see [ocean])
+//! - VU meter animations that follow the audio level from the microphone (see [vu_meter])
+//! - A spectrum analyser with the bass, middle and treble side by side (see [spectrum])
+//! - Morse code animations that blink our name (see [morse])
+//! - A dance meter that fills up as the wearer moves about (see [dance_meter])
+//! - Pulse and chase animations that keep time with the music (see [pulse] and [chase])
+//! - A boot sequence that checks the strip and our colour at power-on (see [boot])
+//! - Hot/cold animations that guide you towards one particular soul (see [hot_cold])
+//! - A fanfare when a favourite soul comes into range (see [favourite])
+//! - An emergency SOS and the red alert it raises on friends' stars (see [sos] and [alert])
+//! - A celebration when the wearer earns a badge (see [badge])
+//! - A hint that a soul is coming closer or walking away (see [approach])
+//! - A countdown that warns of a factory reset (see [countdown])
+//! - A signal strength meter for range-testing antennas (see [rssi_meter])
+//! - A swirl that confirms a gesture (see [swirl])
+//! - A heatmap that glows warmer as the crowd around us grows (see [heatmap])
+//!
+//! Newer animations live in their own submodules and are re-exported from here.
 
-use crate::colour::set_brightness;
-use crate::configuration::LED_STRING_SIZE;
-use crate::led_driver::LedBuffer;
+use crate::clock::shared_now;
+use crate::colour::{LedBuffer, Theme, adjust_brightness_for_loss, is_whiteish, set_brightness};
+use crate::configuration::{
+    ANIMATION_UPDATE, CLOCK_REFRESH_INTERVAL, CONSTELLATION_ENABLED, LED_LAYOUT, LED_STRING_SIZE,
+};
+use crate::constellation::place;
 use crate::throbber::Throbber;
-use crate::tracker::VisibleSouls;
+use crate::tracker::{SoulSummary, VisibleSouls};
+use crate::tuning::Tuning;
 use defmt::{Format, Formatter, write};
 use embassy_time::{Duration, Instant};
 use smart_leds::RGB8;
 
+mod alert;
+mod approach;
+mod badge;
+mod boot;
+mod chase;
+mod countdown;
+mod dance_meter;
+mod favourite;
+mod heatmap;
+mod hot_cold;
+mod lava;
+mod morse;
+mod ocean;
+mod pulse;
+mod rssi_meter;
+mod scanner;
+mod sos;
+mod spectrum;
+mod stripes;
+mod strobe;
+mod swirl;
+mod vu_meter;
+
+#[cfg(test)]
+mod golden;
+
+pub use alert::AlertAnimation;
+pub use approach::ApproachAnimation;
+pub use badge::BadgeAnimation;
+pub use boot::BootAnimation;
+pub use chase::ChaseAnimation;
+pub use countdown::CountdownAnimation;
+pub use dance_meter::DanceMeterAnimation;
+pub use favourite::FavouriteAnimation;
+pub use heatmap::HeatmapAnimation;
+pub use hot_cold::HotColdAnimation;
+pub use lava::LavaAnimation;
+pub use morse::MorseAnimation;
+pub use ocean::OceanAnimation;
+pub use pulse::PulseAnimation;
+pub use rssi_meter::RssiMeterAnimation;
+pub use scanner::ScannerAnimation;
+pub use sos::SosAnimation;
+pub use spectrum::SpectrumAnimation;
+pub use stripes::{MAX_STRIPES, StripesAnimation};
+pub use strobe::{MAX_FLASH_HZ, StrobeAnimation};
+pub use swirl::SwirlAnimation;
+pub use vu_meter::VuMeterAnimation;
+
 type ThrobberVec = [Throbber; LED_STRING_SIZE];
 
 /// Represents different types of animations that can be displayed on the LED strip
@@ -24,6 +101,222 @@ pub enum Animation {
     Presence(PresenceAnimation),
     /// Trobber animation that runs smooth on/off transitions on leds
     Wave(WaveAnimation),
+    /// Larson scanner with a bouncing dot and a fading tail
+    Scanner(ScannerAnimation),
+    /// Lava lamp with soft blobs of colour drifting and merging
+    Lava(LavaAnimation),
+    /// Rate limited strobe for party mode
+    Strobe(StrobeAnimation),
+    /// Scrolling bands of colour
+    Stripes(StripesAnimation),
+    /// Calm layered waves in blues and greens
+    Ocean(OceanAnimation),
+    /// VU meter driven by the audio envelope
+    VuMeter(VuMeterAnimation),
+    /// Dance meter driven by the accelerometer
+    DanceMeter(DanceMeterAnimation),
+    /// Flashes on every beat of the music
+    Pulse(PulseAnimation),
+    /// Steps along the strip on every beat of the music
+    Chase(ChaseAnimation),
+    /// Spectrum analyser driven by the microphone
+    Spectrum(SpectrumAnimation),
+    /// Hot/cold friend finder, a signal meter for one soul
+    HotCold(HotColdAnimation),
+    /// Blinks a message in Morse code
+    Morse(MorseAnimation),
+    /// Boot sequence played once at power-on
+    Boot(BootAnimation),
+    /// Fanfare for a favourite soul coming into range
+    Favourite(FavouriteAnimation),
+    /// Emergency SOS in Morse code
+    Sos(SosAnimation),
+    /// Red alert for a friend sending an SOS
+    Alert(AlertAnimation),
+    /// Celebration for a newly unlocked badge
+    Badge(BadgeAnimation),
+    /// A hint that a soul is coming closer or walking away
+    Approach(ApproachAnimation),
+    /// A countdown to something drastic, like a factory reset
+    Countdown(CountdownAnimation),
+    /// A signal strength meter for range-testing in the field
+    RssiMeter(RssiMeterAnimation),
+    /// A swirl to confirm a gesture
+    Swirl(SwirlAnimation),
+    /// A colour temperature wash showing how big and close the crowd is
+    Heatmap(HeatmapAnimation),
+}
+
+/// How many kinds of [Animation] there are, one for each [name]
+pub const ANIMATION_COUNT: usize = 25;
+
+/// The built-in animations that can be selected as the default or played on demand. Presence
+/// animations are not in here as they are driven by the souls we can see.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum AnimationKind {
+    Sparkle,
+    Wave,
+    Scanner,
+    Lava,
+    Stripes,
+    Ocean,
+    Morse,
+    /// Only useful with a microphone, so it is left out of the button cycle
+    VuMeter,
+    /// Only useful with an accelerometer, so it is left out of the button cycle
+    DanceMeter,
+    Pulse,
+    Chase,
+    /// Only useful with a microphone, so it is left out of the button cycle
+    Spectrum,
+    Heatmap,
+}
+
+impl AnimationKind {
+    /// Create an animation of this kind
+    ///
+    /// # Arguments
+    /// * `theme` - The colours to animate with
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn animation(self, theme: &Theme, ttl: Option<Duration>) -> Animation {
+        let colour = theme.colour;
+        match self {
+            AnimationKind::Sparkle => Animation::Sparkle(SparkleAnimation::phase_locked(colour, theme.id, ttl)),
+            AnimationKind::Wave => Animation::Wave(WaveAnimation::new(colour, ttl)),
+            AnimationKind::Scanner => Animation::Scanner(ScannerAnimation::new(colour, 1, ttl)),
+            // Our colour at a few different brightness levels so the blobs stand out where they merge
+            AnimationKind::Lava => Animation::Lava(LavaAnimation::new(
+                [colour, set_brightness(160, colour), set_brightness(96, colour)],
+                ttl,
+            )),
+            // Without any stripes configured, fall back to our colour and a darker band
+            AnimationKind::Stripes if theme.stripes.is_empty() => {
+                Animation::Stripes(StripesAnimation::new([colour, set_brightness(64, colour)], ttl))
+            }
+            AnimationKind::Stripes => {
+                Animation::Stripes(StripesAnimation::new(theme.stripes.iter().map(|c| RGB8::from(*c)), ttl))
+            }
+            AnimationKind::Ocean => Animation::Ocean(OceanAnimation::new(ttl)),
+            AnimationKind::Morse => Animation::Morse(MorseAnimation::new(colour, theme.name, ttl)),
+            AnimationKind::VuMeter => Animation::VuMeter(VuMeterAnimation::new(colour, LED_LAYOUT, ttl)),
+            AnimationKind::DanceMeter => Animation::DanceMeter(DanceMeterAnimation::new(colour, ttl)),
+            AnimationKind::Pulse => Animation::Pulse(PulseAnimation::new(colour, ttl)),
+            AnimationKind::Chase => Animation::Chase(ChaseAnimation::new(colour, ttl)),
+            AnimationKind::Spectrum => Animation::Spectrum(SpectrumAnimation::new(colour, ttl)),
+            AnimationKind::Heatmap => Animation::Heatmap(HeatmapAnimation::new(ttl)),
+        }
+    }
+
+    /// The kind with the given number, None if we don't know it
+    pub fn from_u8(kind: u8) -> Option<Self> {
+        [
+            AnimationKind::Sparkle,
+            AnimationKind::Wave,
+            AnimationKind::Scanner,
+            AnimationKind::Lava,
+            AnimationKind::Stripes,
+            AnimationKind::Ocean,
+            AnimationKind::Morse,
+            AnimationKind::VuMeter,
+            AnimationKind::DanceMeter,
+            AnimationKind::Pulse,
+            AnimationKind::Chase,
+            AnimationKind::Spectrum,
+            AnimationKind::Heatmap,
+        ]
+        .get(kind as usize)
+        .copied()
+    }
+
+    /// A random kind from [SHUFFLE], never the same as this one
+    ///
+    /// # Arguments
+    /// * `rng` - Random number generator to pick with
+    pub fn shuffle(self, rng: &mut fastrand::Rng) -> Self {
+        self.shuffle_from(SHUFFLE, rng)
+    }
+
+    /// A random kind from the set, never the same as this one unless there is nothing else
+    ///
+    /// # Arguments
+    /// * `set` - The kinds to pick from, e.g. [SHUFFLE] or [LIVELY]
+    /// * `rng` - Random number generator to pick with
+    pub fn shuffle_from(self, set: &[AnimationKind], rng: &mut fastrand::Rng) -> Self {
+        let mut others = set.iter().copied().filter(|&k| k != self);
+        match others.clone().count() {
+            0 => self,
+            count => others.nth(rng.usize(0..count)).unwrap_or(self),
+        }
+    }
+
+    /// The kind after this one, wrapping around at the end. Used to cycle through the
+    /// animations with a button.
+    pub fn next(self) -> Self {
+        match self {
+            AnimationKind::Sparkle => AnimationKind::Wave,
+            AnimationKind::Wave => AnimationKind::Scanner,
+            AnimationKind::Scanner => AnimationKind::Lava,
+            AnimationKind::Lava => AnimationKind::Stripes,
+            AnimationKind::Stripes => AnimationKind::Ocean,
+            AnimationKind::Ocean => AnimationKind::Morse,
+            AnimationKind::Morse => AnimationKind::Pulse,
+            AnimationKind::Pulse => AnimationKind::Chase,
+            AnimationKind::Chase => AnimationKind::Heatmap,
+            AnimationKind::Heatmap => AnimationKind::Sparkle,
+            AnimationKind::VuMeter => AnimationKind::Sparkle,
+            AnimationKind::DanceMeter => AnimationKind::Sparkle,
+            AnimationKind::Spectrum => AnimationKind::Sparkle,
+        }
+    }
+}
+
+/// The animations that shuffle mode picks from
+pub const SHUFFLE: &[AnimationKind] = &[
+    AnimationKind::Sparkle,
+    AnimationKind::Wave,
+    AnimationKind::Scanner,
+    AnimationKind::Lava,
+    AnimationKind::Stripes,
+    AnimationKind::Ocean,
+    AnimationKind::Morse,
+];
+
+/// The high energy animations we show when the crowd is lively
+pub const LIVELY: &[AnimationKind] = &[AnimationKind::Sparkle, AnimationKind::Scanner, AnimationKind::Stripes];
+
+/// The kind of the given animation, or None if it is not one of the selectable built-ins
+///
+/// # Arguments
+/// * `anim` - Reference to the Animation to check
+pub fn kind(anim: &Animation) -> Option<AnimationKind> {
+    match anim {
+        Animation::Sparkle(_) => Some(AnimationKind::Sparkle),
+        Animation::Presence(_) => None,
+        Animation::Wave(_) => Some(AnimationKind::Wave),
+        Animation::Scanner(_) => Some(AnimationKind::Scanner),
+        Animation::Lava(_) => Some(AnimationKind::Lava),
+        Animation::Strobe(_) => None,
+        Animation::Stripes(_) => Some(AnimationKind::Stripes),
+        Animation::Ocean(_) => Some(AnimationKind::Ocean),
+        Animation::VuMeter(_) => Some(AnimationKind::VuMeter),
+        Animation::DanceMeter(_) => Some(AnimationKind::DanceMeter),
+        Animation::Pulse(_) => Some(AnimationKind::Pulse),
+        Animation::Chase(_) => Some(AnimationKind::Chase),
+        Animation::Spectrum(_) => Some(AnimationKind::Spectrum),
+        Animation::HotCold(_) => None,
+        Animation::Morse(_) => Some(AnimationKind::Morse),
+        Animation::Boot(_) => None,
+        Animation::Favourite(_) => None,
+        Animation::Sos(_) => None,
+        Animation::Alert(_) => None,
+        Animation::Badge(_) => None,
+        Animation::Approach(_) => None,
+        Animation::Countdown(_) => None,
+        Animation::RssiMeter(_) => None,
+        Animation::Swirl(_) => None,
+        Animation::Heatmap(_) => Some(AnimationKind::Heatmap),
+    }
 }
 
 /// Checks if the given animation can be interrupted
@@ -38,6 +331,65 @@ pub fn is_interruptable(anim: &Animation) -> bool {
         Animation::Sparkle(s) => s.is_interruptable(),
         Animation::Presence(s) => s.is_interruptable(),
         Animation::Wave(s) => s.is_interruptable(),
+        Animation::Scanner(s) => s.is_interruptable(),
+        Animation::Lava(s) => s.is_interruptable(),
+        Animation::Strobe(s) => s.is_interruptable(),
+        Animation::Stripes(s) => s.is_interruptable(),
+        Animation::Ocean(s) => s.is_interruptable(),
+        Animation::VuMeter(s) => s.is_interruptable(),
+        Animation::DanceMeter(s) => s.is_interruptable(),
+        Animation::Pulse(s) => s.is_interruptable(),
+        Animation::Chase(s) => s.is_interruptable(),
+        Animation::Spectrum(s) => s.is_interruptable(),
+        Animation::HotCold(s) => s.is_interruptable(),
+        Animation::Morse(s) => s.is_interruptable(),
+        Animation::Boot(s) => s.is_interruptable(),
+        Animation::Favourite(s) => s.is_interruptable(),
+        Animation::Sos(s) => s.is_interruptable(),
+        Animation::Alert(s) => s.is_interruptable(),
+        Animation::Badge(s) => s.is_interruptable(),
+        Animation::Approach(s) => s.is_interruptable(),
+        Animation::Countdown(s) => s.is_interruptable(),
+        Animation::RssiMeter(s) => s.is_interruptable(),
+        Animation::Swirl(s) => s.is_interruptable(),
+        Animation::Heatmap(s) => s.is_interruptable(),
+    }
+}
+
+/// Checks if the given animation is mostly white, which we avoid in night mode
+///
+/// # Arguments
+/// * `anim` - Reference to the Animation to check
+///
+/// # Returns
+/// True if the animation is dominated by white light
+pub fn is_white_heavy(anim: &Animation) -> bool {
+    match anim {
+        Animation::Sparkle(s) => is_whiteish(s.colour),
+        Animation::Presence(_) => false,
+        Animation::Wave(s) => is_whiteish(s.colour),
+        Animation::Scanner(s) => is_whiteish(s.colour()),
+        Animation::Lava(s) => is_whiteish(s.colour()),
+        Animation::Strobe(s) => is_whiteish(s.colour()),
+        Animation::Stripes(s) => s.has_white(),
+        Animation::Ocean(_) => false,
+        Animation::VuMeter(s) => is_whiteish(s.colour()),
+        Animation::DanceMeter(s) => is_whiteish(s.colour()),
+        Animation::Pulse(s) => is_whiteish(s.colour()),
+        Animation::Chase(s) => is_whiteish(s.colour()),
+        Animation::Spectrum(s) => is_whiteish(s.colour()),
+        Animation::HotCold(_) => false,
+        Animation::Morse(s) => is_whiteish(s.colour()),
+        Animation::Boot(s) => is_whiteish(s.colour()),
+        Animation::Favourite(s) => is_whiteish(s.colour()),
+        Animation::Sos(_) => true,
+        Animation::Alert(_) => false,
+        Animation::Badge(_) => false,
+        Animation::Approach(s) => is_whiteish(s.colour()),
+        Animation::Countdown(_) => false,
+        Animation::RssiMeter(_) => false,
+        Animation::Swirl(s) => is_whiteish(s.colour()),
+        Animation::Heatmap(_) => false,
     }
 }
 
@@ -54,16 +406,159 @@ pub fn next_buffer(anim: &mut Animation) -> Option<LedBuffer> {
         Animation::Sparkle(s) => s.next(),
         Animation::Presence(p) => p.next(),
         Animation::Wave(t) => t.next(),
+        Animation::Scanner(s) => s.next(),
+        Animation::Lava(s) => s.next(),
+        Animation::Strobe(s) => s.next(),
+        Animation::Stripes(s) => s.next(),
+        Animation::Ocean(s) => s.next(),
+        Animation::VuMeter(s) => s.next(),
+        Animation::DanceMeter(s) => s.next(),
+        Animation::Pulse(s) => s.next(),
+        Animation::Chase(s) => s.next(),
+        Animation::Spectrum(s) => s.next(),
+        Animation::HotCold(s) => s.next(),
+        Animation::Morse(s) => s.next(),
+        Animation::Boot(s) => s.next(),
+        Animation::Favourite(s) => s.next(),
+        Animation::Sos(s) => s.next(),
+        Animation::Alert(s) => s.next(),
+        Animation::Badge(s) => s.next(),
+        Animation::Approach(s) => s.next(),
+        Animation::Countdown(s) => s.next(),
+        Animation::RssiMeter(s) => s.next(),
+        Animation::Swirl(s) => s.next(),
+        Animation::Heatmap(s) => s.next(),
+    }
+}
+
+/// Push back the expiry time of the animation, used to suspend its clock while the display
+/// is paused. Animations without an expiry time are not affected.
+///
+/// # Arguments
+/// * `anim` - A mutable reference to the Animation to postpone
+/// * `by` - How long to postpone the expiry by
+pub fn postpone(anim: &mut Animation, by: Duration) {
+    match anim {
+        Animation::Sparkle(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Presence(_) => (),
+        Animation::Wave(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Scanner(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Lava(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Strobe(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Stripes(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Ocean(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::VuMeter(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::DanceMeter(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Pulse(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Chase(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Spectrum(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::HotCold(_) => (),
+        Animation::Morse(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Boot(_) => (),
+        Animation::Favourite(_) => (),
+        Animation::Sos(_) => (),
+        Animation::Alert(s) => s.expires = s.expires.map(|e| e + by),
+        Animation::Badge(_) => (),
+        Animation::Approach(_) => (),
+        Animation::Countdown(s) => s.postpone(by),
+        Animation::RssiMeter(_) => (),
+        Animation::Swirl(_) => (),
+        Animation::Heatmap(s) => s.expires = s.expires.map(|e| e + by),
+    }
+}
+
+/// The preferred interval between frames for the animation. Slow effects look fine at a low
+/// frame rate, while anything that moves needs a faster one to look smooth.
+///
+/// # Arguments
+/// * `anim` - Reference to the Animation to check
+pub fn frame_interval(anim: &Animation) -> Duration {
+    match anim {
+        Animation::Sparkle(_) => SparkleAnimation::FRAME_INTERVAL,
+        Animation::Presence(_) => PresenceAnimation::FRAME_INTERVAL,
+        Animation::Wave(_) => WaveAnimation::FRAME_INTERVAL,
+        Animation::Scanner(_) => ScannerAnimation::FRAME_INTERVAL,
+        Animation::Lava(_) => LavaAnimation::FRAME_INTERVAL,
+        Animation::Strobe(_) => StrobeAnimation::FRAME_INTERVAL,
+        Animation::Stripes(_) => StripesAnimation::FRAME_INTERVAL,
+        Animation::Ocean(_) => OceanAnimation::FRAME_INTERVAL,
+        Animation::VuMeter(_) => VuMeterAnimation::FRAME_INTERVAL,
+        Animation::DanceMeter(_) => DanceMeterAnimation::FRAME_INTERVAL,
+        Animation::Pulse(_) => PulseAnimation::FRAME_INTERVAL,
+        Animation::Chase(_) => ChaseAnimation::FRAME_INTERVAL,
+        Animation::Spectrum(_) => SpectrumAnimation::FRAME_INTERVAL,
+        Animation::HotCold(_) => HotColdAnimation::FRAME_INTERVAL,
+        Animation::Morse(_) => MorseAnimation::FRAME_INTERVAL,
+        Animation::Boot(_) => BootAnimation::FRAME_INTERVAL,
+        Animation::Favourite(_) => FavouriteAnimation::FRAME_INTERVAL,
+        Animation::Sos(_) => SosAnimation::FRAME_INTERVAL,
+        Animation::Alert(_) => AlertAnimation::FRAME_INTERVAL,
+        Animation::Badge(_) => BadgeAnimation::FRAME_INTERVAL,
+        Animation::Approach(_) => ApproachAnimation::FRAME_INTERVAL,
+        Animation::Countdown(_) => CountdownAnimation::FRAME_INTERVAL,
+        Animation::RssiMeter(_) => RssiMeterAnimation::FRAME_INTERVAL,
+        Animation::Swirl(_) => SwirlAnimation::FRAME_INTERVAL,
+        Animation::Heatmap(_) => HeatmapAnimation::FRAME_INTERVAL,
+    }
+}
+
+/// The name of the animation for logging and status reports
+///
+/// # Arguments
+/// * `anim` - Reference to the Animation to name
+pub fn name(anim: &Animation) -> &'static str {
+    match anim {
+        Animation::Sparkle(_) => "Sparkle",
+        Animation::Presence(_) => "Presence",
+        Animation::Wave(_) => "Wave",
+        Animation::Scanner(_) => "Scanner",
+        Animation::Lava(_) => "Lava",
+        Animation::Strobe(_) => "Strobe",
+        Animation::Stripes(_) => "Stripes",
+        Animation::Ocean(_) => "Ocean",
+        Animation::VuMeter(_) => "VuMeter",
+        Animation::DanceMeter(_) => "DanceMeter",
+        Animation::Pulse(_) => "Pulse",
+        Animation::Chase(_) => "Chase",
+        Animation::Spectrum(_) => "Spectrum",
+        Animation::HotCold(_) => "HotCold",
+        Animation::Morse(_) => "Morse",
+        Animation::Boot(_) => "Boot",
+        Animation::Favourite(_) => "Favourite",
+        Animation::Sos(_) => "Sos",
+        Animation::Alert(_) => "Alert",
+        Animation::Badge(_) => "Badge",
+        Animation::Approach(_) => "Approach",
+        Animation::Countdown(_) => "Countdown",
+        Animation::RssiMeter(_) => "RssiMeter",
+        Animation::Swirl(_) => "Swirl",
+        Animation::Heatmap(_) => "Heatmap",
+    }
+}
+
+/// Apply live tuning to an animation. Each animation takes the parameters that mean something to
+/// it and the speed is applied through the frame interval (see [Tuning::frame_interval]).
+pub fn tune(anim: &mut Animation, tuning: &Tuning) {
+    match anim {
+        Animation::Sparkle(s) => s.tune(tuning),
+        Animation::Wave(s) => s.tune(tuning),
+        Animation::Scanner(s) => s.tune(tuning),
+        _ => (),
+    }
+}
+
+/// True if an animation with the given expiry time has expired. No expiry time never expires.
+pub(crate) fn has_expired(expires: Option<Instant>) -> bool {
+    match expires {
+        Some(exp) if Instant::now() < exp => false, // Have expiration but not expired so not done
+        None => false,                              // No expiration is never done
+        _ => true,                                  // All other cases are done
     }
 }
 
 impl Format for Animation {
     fn format(&self, fmt: Formatter) {
-        match self {
-            Animation::Sparkle(_) => write!(fmt, "Sparkle"),
-            Animation::Presence(_) => write!(fmt, "Presence"),
-            Animation::Wave(_) => write!(fmt, "Throbber"),
-        }
+        write!(fmt, "{=str}", name(self))
     }
 }
 
@@ -75,37 +570,54 @@ pub trait Interruptable {
     fn is_interruptable(&self) -> bool;
 }
 
+/// The number of parts souls are split into by phase-locked sparkles
+const SPARKLE_VOICES: u32 = 4;
+
+/// How often a phase-locked sparkle reseeds from the shared clock (ms). The clocks of a group only
+/// agree to within [CLOCK_REFRESH_INTERVAL], so reseeding much more often than that would leave
+/// each soul on a different seed most of the time. Reseeding less often lets a group draw the same
+/// frames for most of each period.
+const SPARKLE_RESEED: u32 = 5 * CLOCK_REFRESH_INTERVAL as u32 * 1000;
+
 /// Takes one colour and generates a random brightness up to the maximum brightness
 /// specified. It will continue to return `Some(buffer)` until the expiry time is reached
-/// if one was specified
+/// if one was specified.
+///
+/// A phase-locked sparkle reseeds its random numbers from the [shared clock](crate::clock)
+/// every [SPARKLE_RESEED] milliseconds, so every phase-locked soul nearby draws the same
+/// sequence. Each soul takes one of [SPARKLE_VOICES] parts, picked by its soul ID, and only
+/// sparkles brightly on the pixels that belong to its part. Together the group twinkles in turn
+/// rather than as independent noise.
 #[derive(Clone)]
 pub struct SparkleAnimation {
     /// The colour to sparkle
     colour: RGB8,
+    /// How many of the pixels sparkle, out of 255
+    density: u8,
     /// The system time at which the animation should expire. If it is None, the animation
     /// will run but will mark itself as interruptable.
     expires: Option<Instant>,
     /// Random number generator for the sparkle effect
     rng: fastrand::Rng,
+    /// Our part when phase-locked, None to sparkle on our own
+    voice: Option<u32>,
+    /// The reseed period the random numbers were last seeded for when phase-locked
+    period: Option<u32>,
 }
 
 impl Iterator for SparkleAnimation {
     type Item = LedBuffer;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let done = match self.expires {
-            Some(exp) if Instant::now() < exp => false, // Have expiration but not expired so not done
-            None => false,                              // No expiration is never done
-            _ => true,                                  // All other cases are done
-        };
+        let done = has_expired(self.expires);
 
         if !done {
-            let mut buffer = LedBuffer::default();
-            for led in buffer.iter_mut() {
-                let b = self.rng.u8(0..255);
-                *led = set_brightness(b, self.colour);
+            let period = shared_now() / SPARKLE_RESEED;
+            if self.voice.is_some() && self.period != Some(period) {
+                self.rng.seed(period as u64);
+                self.period = Some(period);
             }
-            Some(buffer)
+            Some(self.frame())
         } else {
             None
         }
@@ -119,6 +631,8 @@ impl Interruptable for SparkleAnimation {
 }
 
 impl SparkleAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(ANIMATION_UPDATE);
+
     /// Creates a new SparkleAnimation instance that generates random brightness variations of a base colour
     ///
     /// # Arguments
@@ -127,42 +641,111 @@ impl SparkleAnimation {
     ///
     /// Returns a new SparkleAnimation instance initialised with the current time as the RNG seed and
     /// the specified parameters. The animation will be interruptible if no ttl is provided
-    pub(crate) fn new(colour: RGB8, ttl: Option<Duration>) -> Self {
-        let seed = Instant::now().as_ticks();
+    pub fn new(colour: RGB8, ttl: Option<Duration>) -> Self {
+        Self::with_seed(colour, ttl, Instant::now().as_ticks())
+    }
+
+    /// Creates a SparkleAnimation that always sparkles the same way, for tests
+    ///
+    /// # Arguments
+    /// * `colour` - The base RGB colour to be used for the sparkle effect
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    /// * `seed` - Seed for the random brightness
+    pub fn with_seed(colour: RGB8, ttl: Option<Duration>, seed: u64) -> Self {
         let expires = ttl.map(|t| Instant::now() + t);
         Self {
             colour,
+            density: 255,
             expires,
             rng: fastrand::Rng::with_seed(seed),
+            voice: None,
+            period: None,
+        }
+    }
+
+    /// Creates a SparkleAnimation that sparkles in step with the other phase-locked souls nearby
+    ///
+    /// # Arguments
+    /// * `colour` - The base RGB colour to be used for the sparkle effect
+    /// * `id` - Our soul ID, which picks our part of the sparkle. It should stay the same across
+    ///   restarts so that we keep our part.
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn phase_locked(colour: RGB8, id: u32, ttl: Option<Duration>) -> Self {
+        Self {
+            voice: Some(id % SPARKLE_VOICES),
+            ..Self::new(colour, ttl)
+        }
+    }
+
+    /// Draw the next frame from the random numbers. Pixels outside our part are dimmed.
+    fn frame(&mut self) -> LedBuffer {
+        let mut buffer = LedBuffer::default();
+        for led in buffer.iter_mut() {
+            let b = self.rng.u8(0..255);
+            let lit = self.density == 255 || self.rng.u8(..) < self.density;
+            let b = match self.voice {
+                Some(voice) if self.rng.u32(0..SPARKLE_VOICES) != voice => b / 4,
+                _ => b,
+            };
+            if lit {
+                *led = set_brightness(b, self.colour);
+            }
         }
+        buffer
+    }
+
+    /// Take the colour and density from the tuning
+    fn tune(&mut self, tuning: &Tuning) {
+        self.colour = tuning.palette.unwrap_or(self.colour);
+        self.density = tuning.density;
     }
 }
 
 /// Animation that displays and rotates colours representing visible souls
 ///
 /// This animation takes a collection of visible souls and their associated colours,
-/// displays them on the LED strip, and rotates their positions over time. If the
-/// number of souls in the presence list is zero then the animation will terminate.
-#[derive(Clone)]
+/// displays them on the LED strip, and rotates their positions over time. Nearby souls are
+/// drawn brighter than distant ones. If the number of souls in the presence list is zero then
+/// the animation will terminate.
+///
+/// In constellation mode nothing rotates. Each friend lights their own pixel and everybody else is
+/// left off, so the animation ends when none of our friends are around (see
+/// [constellation](crate::constellation)).
+///
+/// It does not carry the souls around while it waits in the queue. Instead, the display task
+/// calls [PresenceAnimation::refresh] with the tracker summary before each frame and the souls
+/// are only copied when the tracker generation has changed.
+#[derive(Clone, Default)]
 pub struct PresenceAnimation {
     /// Collection of currently visible souls and their colours
     souls: VisibleSouls,
+    /// Tracker generation that the souls were copied from, None if they never were
+    generation: Option<u32>,
     /// Current rotation index for the animation
     index: usize,
+    /// Friends stay on their own pixels rather than rotating
+    constellation: bool,
 }
 
 impl Iterator for PresenceAnimation {
     type Item = LedBuffer;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.constellation {
+            return self.draw_constellation();
+        }
         if self.souls.is_empty() {
             return None;
         }
         let mut buffer = LedBuffer::default();
-        let mut idx = 0;
-        #[allow(clippy::explicit_counter_loop)]
+        let mut pixels = buffer.iter_mut();
+        // Event staff get a halo pixel beside their own colour, souls that don't fit are left off
         for s in &self.souls {
-            buffer[idx] = s.colour;
-            idx += 1;
+            let halo = s.role.halo();
+            for colour in core::iter::once(s.colour).chain(halo) {
+                if let Some(pixel) = pixels.next() {
+                    *pixel = adjust_brightness_for_loss(colour, s.tx_loss, 255);
+                }
+            }
         }
         buffer.rotate_right(self.index);
         self.index = (self.index + 1) % buffer.len();
@@ -178,10 +761,46 @@ impl Interruptable for PresenceAnimation {
 }
 
 impl PresenceAnimation {
-    pub fn new(souls: &VisibleSouls) -> Self {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(ANIMATION_UPDATE);
+
+    pub fn new() -> Self {
+        Self::with_constellation(CONSTELLATION_ENABLED)
+    }
+
+    /// Create a presence animation in either layout
+    ///
+    /// # Arguments
+    /// * `constellation` - Keep friends on their own pixels rather than rotating everybody
+    pub fn with_constellation(constellation: bool) -> Self {
         Self {
-            souls: souls.clone(),
-            index: 0,
+            constellation,
+            ..Self::default()
+        }
+    }
+
+    /// Draw each friend on their own pixel, None if no friends are around
+    fn draw_constellation(&self) -> Option<LedBuffer> {
+        let mut buffer = LedBuffer::default();
+        let mut taken = [false; LED_STRING_SIZE];
+        for s in &self.souls {
+            if let Some(p) = s.pixel.and_then(|pixel| place(pixel, &taken)) {
+                taken[p] = true;
+                buffer[p] = adjust_brightness_for_loss(s.colour, s.tx_loss, 255);
+            }
+        }
+        taken.contains(&true).then_some(buffer)
+    }
+
+    /// Catch up with the tracker if its summary has changed, e.g. as signal strengths change,
+    /// without restarting the rotation
+    ///
+    /// # Arguments
+    /// * `souls` - The tracker summary
+    /// * `generation` - The tracker generation of the summary
+    pub fn refresh(&mut self, souls: &[SoulSummary], generation: u32) {
+        if self.generation != Some(generation) {
+            self.souls = souls.iter().copied().take(self.souls.capacity()).collect();
+            self.generation = Some(generation);
         }
     }
 }
@@ -200,11 +819,14 @@ impl Interruptable for WaveAnimation {
 }
 
 impl WaveAnimation {
+    /// Runs at twice the default frame rate with half the step so it moves at the same speed, just smoother
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(ANIMATION_UPDATE / 2);
+
     pub fn new(colour: RGB8, ttl: Option<Duration>) -> Self {
-        let mut t: ThrobberVec = [Throbber::new(10, 16, false); LED_STRING_SIZE];
-        for i in 1..LED_STRING_SIZE {
+        let mut t: ThrobberVec = [Throbber::new(5, 16, false); LED_STRING_SIZE];
+        for (i, throbber) in t.iter_mut().enumerate().skip(1) {
             // Start them on different phases
-            t[i].advance(i as u8)
+            throbber.advance(2 * i as u8)
         }
         Self {
             throbbers: t,
@@ -212,16 +834,17 @@ impl WaveAnimation {
             colour,
         }
     }
+
+    /// Take the colour from the tuning
+    fn tune(&mut self, tuning: &Tuning) {
+        self.colour = tuning.palette.unwrap_or(self.colour);
+    }
 }
 
 impl Iterator for WaveAnimation {
     type Item = LedBuffer;
     fn next(&mut self) -> Option<Self::Item> {
-        let done = match self.expires {
-            Some(exp) if Instant::now() < exp => false, // Have expiration but not expired so not done
-            None => false,                              // No expiration is never done
-            _ => true,                                  // All other cases are done
-        };
+        let done = has_expired(self.expires);
 
         if !done {
             let mut buffer = LedBuffer::default();
@@ -238,3 +861,111 @@ impl Iterator for WaveAnimation {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fields::Role;
+
+    #[test]
+    pub fn if_presence_refreshes_on_a_new_generation() {
+        let soul = SoulSummary {
+            colour: RGB8::new(255, 0, 0),
+            tx_loss: 0,
+            role: Role::Regular,
+            pixel: None,
+        };
+        let mut presence = PresenceAnimation::new();
+        presence.refresh(&[soul], 1);
+        assert_eq!(presence.souls.len(), 1);
+        // Same generation so nothing is copied
+        presence.refresh(&[], 1);
+        assert_eq!(presence.souls.len(), 1);
+        presence.refresh(&[], 2);
+        assert!(presence.next().is_none());
+    }
+
+    #[test]
+    pub fn if_friends_keep_their_pixels() {
+        let soul = |colour, pixel| SoulSummary {
+            colour,
+            tx_loss: 0,
+            role: Role::Regular,
+            pixel,
+        };
+        let red = RGB8::new(255, 0, 0);
+        let blue = RGB8::new(0, 0, 255);
+        let mut presence = PresenceAnimation::with_constellation(true);
+        presence.refresh(&[soul(red, None)], 1);
+        assert!(presence.next().is_none());
+        presence.refresh(&[soul(red, Some(5)), soul(blue, Some(5)), soul(red, None)], 2);
+        let buffer = presence.next().unwrap();
+        assert_eq!(buffer[5], adjust_brightness_for_loss(red, 0, 255));
+        assert_eq!(buffer[6], adjust_brightness_for_loss(blue, 0, 255));
+        assert_eq!(buffer.iter().filter(|&&c| c != RGB8::default()).count(), 2);
+        // Nothing moves from one frame to the next
+        assert_eq!(presence.next().unwrap(), buffer);
+    }
+
+    #[test]
+    pub fn if_staff_get_a_halo() {
+        let soul = |colour, role| SoulSummary {
+            colour,
+            tx_loss: 0,
+            role,
+            pixel: None,
+        };
+        let red = RGB8::new(255, 0, 0);
+        let blue = RGB8::new(0, 0, 255);
+        let mut presence = PresenceAnimation::new();
+        presence.refresh(&[soul(red, Role::Medic), soul(blue, Role::Regular)], 1);
+        let buffer = presence.next().unwrap();
+        assert_eq!(buffer[0], adjust_brightness_for_loss(red, 0, 255));
+        assert_eq!(buffer[1], adjust_brightness_for_loss(Role::Medic.halo().unwrap(), 0, 255));
+        assert_eq!(buffer[2], adjust_brightness_for_loss(blue, 0, 255));
+        assert_eq!(buffer[3], RGB8::default());
+    }
+
+    #[test]
+    pub fn if_shuffle_picks_something_else() {
+        let mut rng = fastrand::Rng::with_seed(42);
+        for _ in 0..50 {
+            let next = AnimationKind::Wave.shuffle(&mut rng);
+            assert!(next != AnimationKind::Wave);
+            assert!(SHUFFLE.contains(&next));
+        }
+    }
+
+    #[test]
+    pub fn if_phase_locked_souls_take_turns() {
+        let colour = RGB8::new(255, 255, 255);
+        let frames: [LedBuffer; 5] =
+            core::array::from_fn(|id| SparkleAnimation::phase_locked(colour, id as u32, None).next().unwrap());
+        // Same part, same sparkle
+        assert_eq!(frames[0], frames[4]);
+        // Only one of the parts sparkles brightly on each pixel
+        for pixel in 0..LED_STRING_SIZE {
+            let levels = frames[..4].iter().map(|f| f[pixel].r);
+            let (max, min) = (levels.clone().max().unwrap(), levels.clone().min().unwrap());
+            if max > min {
+                assert_eq!(levels.filter(|&l| l == max).count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    pub fn if_phase_locked_sparkles_keep_moving_between_reseeds() {
+        // The mock time driver does not advance, so both frames are in the same reseed period
+        let mut sparkle = SparkleAnimation::phase_locked(RGB8::new(255, 255, 255), 0, None);
+        assert_ne!(sparkle.next(), sparkle.next());
+    }
+
+    #[test]
+    pub fn if_postponing_extends_the_expiry() {
+        // The mock time driver does not advance, so a zero ttl has expired straight away
+        let mut anim = Animation::Sparkle(SparkleAnimation::new(RGB8::new(1, 2, 3), Some(Duration::from_ticks(0))));
+        assert!(next_buffer(&mut anim).is_none());
+        postpone(&mut anim, Duration::from_secs(1));
+        assert!(next_buffer(&mut anim).is_some());
+    }
+}
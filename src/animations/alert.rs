@@ -0,0 +1,86 @@
+//! Red alert, shown when a friend nearby is sending an SOS. The two halves of the strip flash
+//! red in turn, like an emergency vehicle, for as long as the SOS keeps arriving.
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::LED_STRING_SIZE;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+const ALERT_COLOUR: RGB8 = RGB8::new(255, 0, 0);
+
+/// Brightness of the half of the strip that is not flashing
+const ALERT_DIM: u8 = 24;
+
+#[derive(Clone)]
+pub struct AlertAnimation {
+    frame: u32,
+    pub(super) expires: Option<Instant>,
+}
+
+impl AlertAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Create a red alert
+    ///
+    /// # Arguments
+    /// * `ttl` - How long to keep the alert up for
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            frame: 0,
+            expires: Some(Instant::now() + ttl),
+        }
+    }
+
+    /// Keep the alert up for a while longer, e.g. because the SOS is still being received
+    ///
+    /// # Arguments
+    /// * `ttl` - How long to keep the alert up for from now
+    pub fn extend(&mut self, ttl: Duration) {
+        self.expires = Some(Instant::now() + ttl);
+    }
+}
+
+impl Interruptable for AlertAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for AlertAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        let mut buffer = LedBuffer::default();
+        let (first, second) = buffer.split_at_mut(LED_STRING_SIZE / 2);
+        let (bright, dim) = if self.frame.is_multiple_of(2) {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        bright.fill(ALERT_COLOUR);
+        dim.fill(set_brightness(ALERT_DIM, ALERT_COLOUR));
+        self.frame = self.frame.wrapping_add(1);
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_the_halves_take_turns() {
+        let mut alert = AlertAnimation::new(Duration::from_secs(10));
+        let first = alert.next().unwrap();
+        let second = alert.next().unwrap();
+        assert_eq!(first[0], ALERT_COLOUR);
+        assert_eq!(first[LED_STRING_SIZE - 1], set_brightness(ALERT_DIM, ALERT_COLOUR));
+        assert_eq!(second[0], set_brightness(ALERT_DIM, ALERT_COLOUR));
+        assert_eq!(second[LED_STRING_SIZE - 1], ALERT_COLOUR);
+        assert!(!alert.is_interruptable());
+    }
+}
@@ -0,0 +1,87 @@
+//! A subtle hint that a soul is on the move. A dim pair of their colour glides in from the ends
+//! of the strip to the middle as they come closer, or out from the middle as they walk away.
+
+use super::Interruptable;
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::LED_STRING_SIZE;
+use crate::tracker::Movement;
+use embassy_time::Duration;
+use smart_leds::RGB8;
+
+/// Frames for the pair to cross half the strip
+const GLIDE_FRAMES: usize = LED_STRING_SIZE / 2;
+
+/// Kept dim so it doesn't distract from the souls around us
+const GLIDE_BRIGHTNESS: u8 = 96;
+
+#[derive(Clone)]
+pub struct ApproachAnimation {
+    colour: RGB8,
+    inwards: bool,
+    frame: usize,
+}
+
+impl ApproachAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(60);
+
+    /// Create the hint
+    ///
+    /// # Arguments
+    /// * `colour` - The colour of the soul that is moving
+    /// * `movement` - Which way it is moving. Steady souls get nothing.
+    pub fn new(colour: RGB8, movement: Movement) -> Self {
+        Self {
+            colour: set_brightness(GLIDE_BRIGHTNESS, colour),
+            inwards: movement == Movement::Approaching,
+            frame: if movement == Movement::Steady { GLIDE_FRAMES } else { 0 },
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+}
+
+impl Interruptable for ApproachAnimation {
+    /// It is short, so let it finish
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for ApproachAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame >= GLIDE_FRAMES {
+            return None;
+        }
+        let mut buffer = LedBuffer::default();
+        let from_middle = if self.inwards {
+            GLIDE_FRAMES - 1 - self.frame
+        } else {
+            self.frame
+        };
+        buffer[GLIDE_FRAMES - 1 - from_middle] = self.colour;
+        buffer[LED_STRING_SIZE - GLIDE_FRAMES + from_middle] = self.colour;
+        self.frame += 1;
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_approaching_souls_glide_inwards() {
+        let colour = RGB8::new(255, 0, 0);
+        let mut hint = ApproachAnimation::new(colour, Movement::Approaching);
+        let first = hint.next().unwrap();
+        assert_ne!(first[0], RGB8::default());
+        assert_ne!(first[LED_STRING_SIZE - 1], RGB8::default());
+        let last = hint.by_ref().last().unwrap();
+        assert_ne!(last[GLIDE_FRAMES - 1], RGB8::default());
+        assert!(ApproachAnimation::new(colour, Movement::Steady).next().is_none());
+    }
+}
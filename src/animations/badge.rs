@@ -0,0 +1,90 @@
+//! Celebration for a newly unlocked badge. A rainbow spins around the strip and then settles into
+//! the badge colour, which blinks once for the first badge, twice for the second and so on, so
+//! the wearer can tell which one they earned.
+
+use super::Interruptable;
+use crate::badges::Badge;
+use crate::colour::LedBuffer;
+use crate::configuration::LED_STRING_SIZE;
+use embassy_time::Duration;
+use smart_leds::hsv::{Hsv, hsv2rgb};
+
+/// Frames of spinning rainbow
+const RAINBOW_FRAMES: usize = 40;
+
+/// Frames for each blink, half on and half off
+const BLINK_FRAMES: usize = 8;
+
+#[derive(Clone)]
+pub struct BadgeAnimation {
+    badge: Badge,
+    frame: usize,
+}
+
+impl BadgeAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Create the celebration
+    ///
+    /// # Arguments
+    /// * `badge` - The badge that was unlocked
+    pub fn new(badge: Badge) -> Self {
+        Self { badge, frame: 0 }
+    }
+
+    fn blinks(&self) -> usize {
+        self.badge as usize + 1
+    }
+}
+
+impl Interruptable for BadgeAnimation {
+    /// Always runs to completion
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for BadgeAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = LedBuffer::default();
+        if self.frame < RAINBOW_FRAMES {
+            let spin = (self.frame * 256 / RAINBOW_FRAMES) as u8;
+            for (i, led) in buffer.iter_mut().enumerate() {
+                let hue = spin.wrapping_add((i * 256 / LED_STRING_SIZE) as u8);
+                *led = hsv2rgb(Hsv {
+                    hue,
+                    sat: 255,
+                    val: 255,
+                });
+            }
+        } else if self.frame < RAINBOW_FRAMES + self.blinks() * BLINK_FRAMES {
+            if (self.frame - RAINBOW_FRAMES) % BLINK_FRAMES < BLINK_FRAMES / 2 {
+                buffer.fill(self.badge.colour());
+            }
+        } else {
+            return None;
+        }
+        self.frame += 1;
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_blinks_the_badge_number() {
+        let mut celebration = BadgeAnimation::new(Badge::NightOwl);
+        let blinks = celebration
+            .by_ref()
+            .skip(RAINBOW_FRAMES)
+            .step_by(BLINK_FRAMES)
+            .filter(|b| b[0] == Badge::NightOwl.colour())
+            .count();
+        assert_eq!(blinks, 3);
+        assert!(celebration.next().is_none());
+    }
+}
@@ -0,0 +1,91 @@
+//! Boot sequence, played once at power-on before the default animation. A sweep in our own
+//! colour confirms the configured colour and that the strip is wired up, followed by a slow
+//! count-off of every pixel so the LED count can be checked by eye. Every fifth pixel is drawn
+//! at full brightness to make counting easier.
+
+use super::Interruptable;
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::LED_STRING_SIZE;
+use embassy_time::Duration;
+use smart_leds::RGB8;
+
+/// Frames each pixel is held for during the count-off
+const COUNT_FRAMES: usize = 4;
+
+/// Brightness of the pixels between the fifth pixel markers in the count-off
+const COUNT_BRIGHTNESS: u8 = 48;
+
+#[derive(Clone)]
+pub struct BootAnimation {
+    colour: RGB8,
+    frame: usize,
+}
+
+impl BootAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Create the boot animation
+    ///
+    /// # Arguments
+    /// * `colour` - Our own colour
+    pub fn new(colour: RGB8) -> Self {
+        Self { colour, frame: 0 }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+}
+
+impl Interruptable for BootAnimation {
+    /// Always runs to completion
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for BootAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = LedBuffer::default();
+        if self.frame < LED_STRING_SIZE {
+            // Sweep
+            buffer[..=self.frame].fill(self.colour);
+        } else if self.frame < LED_STRING_SIZE * (1 + COUNT_FRAMES) {
+            // Count-off
+            let counted = (self.frame - LED_STRING_SIZE) / COUNT_FRAMES;
+            for (i, led) in buffer[..=counted].iter_mut().enumerate() {
+                *led = if i % 5 == 4 {
+                    self.colour
+                } else {
+                    set_brightness(COUNT_BRIGHTNESS, self.colour)
+                };
+            }
+        } else {
+            return None;
+        }
+        self.frame += 1;
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_sweeps_counts_and_ends() {
+        let colour = RGB8::new(0, 0, 255);
+        let mut boot = BootAnimation::new(colour);
+        let swept = boot.by_ref().take(LED_STRING_SIZE).last().unwrap();
+        assert!(swept.iter().all(|&c| c == colour));
+        let first = boot.next().unwrap();
+        assert_eq!(first[0], set_brightness(COUNT_BRIGHTNESS, colour));
+        assert_eq!(first[1], RGB8::default());
+        let counted = boot.by_ref().last().unwrap();
+        assert_eq!(counted[4], colour);
+        assert_eq!(counted[LED_STRING_SIZE - 1], set_brightness(COUNT_BRIGHTNESS, colour));
+        assert!(boot.next().is_none());
+    }
+}
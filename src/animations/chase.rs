@@ -0,0 +1,91 @@
+//! Chase in time with the music. Every few pixels is lit and the lights step along the strip by
+//! one pixel on each beat, glowing brightest on the beat, see [tempo](crate::tempo).
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, set_brightness};
+use crate::tempo::beat;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// One pixel in this many is lit
+const SPACING: usize = 4;
+
+/// Brightness of the lights just before the next beat
+const RESTING_BRIGHTNESS: u8 = 96;
+
+#[derive(Clone)]
+pub struct ChaseAnimation {
+    colour: RGB8,
+    pub(super) expires: Option<Instant>,
+}
+
+impl ChaseAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+    /// Create a chase animation
+    ///
+    /// # Arguments
+    /// * `colour` - Colour of the lights
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colour: RGB8, ttl: Option<Duration>) -> Self {
+        Self {
+            colour,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+
+    /// Draw the chase
+    ///
+    /// # Arguments
+    /// * `beats` - The number of beats so far
+    /// * `phase` - How far through the beat we are, from 0 (on the beat) to 255
+    fn draw(&self, beats: u32, phase: u8) -> LedBuffer {
+        let step = beats as usize % SPACING;
+        let brightness = 255 - (phase as u16 * (255 - RESTING_BRIGHTNESS as u16) / 255) as u8;
+        let mut buffer = LedBuffer::default();
+        for pixel in buffer.iter_mut().skip(step).step_by(SPACING) {
+            *pixel = set_brightness(brightness, self.colour);
+        }
+        buffer
+    }
+}
+
+impl Interruptable for ChaseAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for ChaseAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        let (beats, phase) = beat(Instant::now());
+        Some(self.draw(beats, phase))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_steps_on_the_beat() {
+        let colour = RGB8::new(0, 255, 0);
+        let chase = ChaseAnimation::new(colour, None);
+        let lit = |buffer: LedBuffer| buffer.iter().position(|&p| p != RGB8::default());
+        assert_eq!(lit(chase.draw(0, 0)), Some(0));
+        assert_eq!(chase.draw(0, 0)[0], colour);
+        assert_eq!(chase.draw(0, 0)[SPACING], colour);
+        assert_eq!(lit(chase.draw(1, 0)), Some(1));
+        assert_eq!(lit(chase.draw(SPACING as u32 + 2, 200)), Some(2));
+        assert!(chase.draw(0, 255)[0].g < 128);
+    }
+}
@@ -0,0 +1,75 @@
+//! A bar of colour that shrinks along the strip as a countdown runs out, so the wearer can see
+//! how long is left before something drastic happens, like a factory reset.
+
+use super::Interruptable;
+use crate::colour::LedBuffer;
+use crate::configuration::LED_STRING_SIZE;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+#[derive(Clone)]
+pub struct CountdownAnimation {
+    colour: RGB8,
+    started: Instant,
+    duration: Duration,
+}
+
+impl CountdownAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Start counting down
+    ///
+    /// # Arguments
+    /// * `colour` - The colour of the bar
+    /// * `duration` - How long the countdown runs for
+    pub fn new(colour: RGB8, duration: Duration) -> Self {
+        Self {
+            colour,
+            started: Instant::now(),
+            duration,
+        }
+    }
+
+    /// Push the end of the countdown back
+    pub(super) fn postpone(&mut self, by: Duration) {
+        self.started += by;
+    }
+}
+
+impl Interruptable for CountdownAnimation {
+    /// Runs until the countdown is over or cancelled
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for CountdownAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let elapsed = Instant::now().saturating_duration_since(self.started);
+        if elapsed >= self.duration {
+            return None;
+        }
+        // Round up so the last pixel stays lit until the very end
+        let left = (self.duration - elapsed).as_millis() as usize * LED_STRING_SIZE;
+        let lit = left.div_ceil(self.duration.as_millis().max(1) as usize);
+        let mut buffer = LedBuffer::default();
+        buffer[..lit.min(LED_STRING_SIZE)].fill(self.colour);
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_starts_full_and_runs_out() {
+        let colour = RGB8::new(255, 0, 0);
+        let mut countdown = CountdownAnimation::new(colour, Duration::from_secs(3600));
+        assert!(countdown.next().unwrap().iter().all(|p| *p == colour));
+        let mut countdown = CountdownAnimation::new(colour, Duration::from_secs(0));
+        assert!(countdown.next().is_none());
+    }
+}
@@ -0,0 +1,110 @@
+//! Dance meter. Fills up along the strip in our colour as the wearer moves about over the
+//! evening, see [dance](crate::dance). The pixel being filled glows brighter as it fills and the
+//! top of the bar breathes gently so the meter doesn't look frozen.
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::LED_STRING_SIZE;
+use crate::dance::dance_level;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// Brightness of the filled part of the bar relative to its top
+const BAR_BRIGHTNESS: u8 = 128;
+
+/// Frames in one breath of the top of the bar
+const BREATH_FRAMES: usize = 20;
+
+#[derive(Clone)]
+pub struct DanceMeterAnimation {
+    colour: RGB8,
+    frame: usize,
+    pub(super) expires: Option<Instant>,
+}
+
+impl DanceMeterAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Create a dance meter animation
+    ///
+    /// # Arguments
+    /// * `colour` - Colour of the bar
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colour: RGB8, ttl: Option<Duration>) -> Self {
+        Self {
+            colour,
+            frame: 0,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+
+    /// Draw the meter
+    ///
+    /// # Arguments
+    /// * `level` - How full the meter is, from 0 to 255
+    fn draw(&self, level: u8) -> LedBuffer {
+        // The level in 255ths of a pixel
+        let level = level as usize * LED_STRING_SIZE;
+        let (full, part) = (level / 255, level % 255);
+        let top = if part > 0 { Some(full) } else { full.checked_sub(1) };
+        // Half way through a breath the top is half as bright again
+        let phase = self.frame % BREATH_FRAMES;
+        let breath = phase.min(BREATH_FRAMES - phase);
+        let mut buffer = LedBuffer::default();
+        for (i, pixel) in buffer.iter_mut().enumerate().take(full + 1) {
+            let mut brightness = if i < full {
+                BAR_BRIGHTNESS as usize
+            } else {
+                part * BAR_BRIGHTNESS as usize / 255
+            };
+            if Some(i) == top {
+                brightness += brightness * breath / BREATH_FRAMES;
+            }
+            *pixel = set_brightness(brightness as u8, self.colour);
+        }
+        buffer
+    }
+}
+
+impl Interruptable for DanceMeterAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for DanceMeterAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        let buffer = self.draw(dance_level());
+        self.frame += 1;
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_fills_with_the_dancing() {
+        let colour = RGB8::new(0, 0, 255);
+        let bar = set_brightness(BAR_BRIGHTNESS, colour);
+        let mut meter = DanceMeterAnimation::new(colour, None);
+        assert!(meter.draw(0).iter().all(|&p| p == RGB8::default()));
+        assert!(meter.draw(255).iter().all(|&p| p == bar));
+        // Half full, with the top of the bar breathing
+        meter.frame = BREATH_FRAMES / 2;
+        let half = meter.draw(128);
+        assert!(half[..LED_STRING_SIZE / 2].iter().all(|&p| p == bar));
+        assert!(half[LED_STRING_SIZE / 2].g == 0 && half[LED_STRING_SIZE / 2].b > 0);
+        assert!(half[LED_STRING_SIZE / 2 + 1..].iter().all(|&p| p == RGB8::default()));
+    }
+}
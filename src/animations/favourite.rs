@@ -0,0 +1,95 @@
+//! Fanfare for a favourite soul coming into range. A burst of their colour spreads out from the
+//! middle of the strip and then the whole strip pulses a few times, so it can't be missed however
+//! many other souls are around.
+
+use super::Interruptable;
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::LED_STRING_SIZE;
+use crate::math::triangle8;
+use embassy_time::Duration;
+use smart_leds::RGB8;
+
+/// Frames for the burst to reach both ends of the strip
+const BURST_FRAMES: usize = LED_STRING_SIZE / 2;
+
+/// Number of full strip pulses after the burst
+const PULSES: usize = 3;
+
+/// Frames in each pulse
+const PULSE_FRAMES: usize = 16;
+
+#[derive(Clone)]
+pub struct FavouriteAnimation {
+    colour: RGB8,
+    frame: usize,
+}
+
+impl FavouriteAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Create the fanfare
+    ///
+    /// # Arguments
+    /// * `colour` - The favourite soul's colour
+    pub fn new(colour: RGB8) -> Self {
+        Self { colour, frame: 0 }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+}
+
+impl Interruptable for FavouriteAnimation {
+    /// Always runs to completion
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for FavouriteAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = LedBuffer::default();
+        if self.frame < BURST_FRAMES {
+            // Burst out from the middle in both directions
+            for (i, led) in buffer.iter_mut().enumerate() {
+                let from_middle = if i < BURST_FRAMES {
+                    BURST_FRAMES - 1 - i
+                } else {
+                    i - BURST_FRAMES
+                };
+                if from_middle <= self.frame {
+                    *led = self.colour;
+                }
+            }
+        } else if self.frame < BURST_FRAMES + PULSES * PULSE_FRAMES {
+            // Dip down and back up to full brightness for each pulse
+            let phase = ((self.frame - BURST_FRAMES) % PULSE_FRAMES * 256 / PULSE_FRAMES) as u8;
+            buffer.fill(set_brightness(255 - triangle8(phase), self.colour));
+        } else {
+            return None;
+        }
+        self.frame += 1;
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_bursts_pulses_and_ends() {
+        let colour = RGB8::new(255, 0, 255);
+        let mut fanfare = FavouriteAnimation::new(colour);
+        let first = fanfare.next().unwrap();
+        assert_eq!(first.iter().filter(|&&c| c == colour).count(), 2);
+        assert_eq!(first[BURST_FRAMES - 1], colour);
+        assert_eq!(first[BURST_FRAMES], colour);
+        let burst = fanfare.by_ref().take(BURST_FRAMES - 1).last().unwrap();
+        assert!(burst.iter().all(|&c| c == colour));
+        assert_eq!(fanfare.by_ref().count(), PULSES * PULSE_FRAMES);
+    }
+}
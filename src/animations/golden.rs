@@ -0,0 +1,111 @@
+//! Golden-frame regression tests.
+//!
+//! Each animation is run for [FRAMES] frames from a fixed start and put through the same gamma,
+//! brightness and current limiting as the LED driver. The result is compared with the frames
+//! recorded in `src/animations/golden`, so a refactor that changes the look of an animation fails
+//! here rather than on the dancefloor. If the change is intended, record the new frames with
+//! `just bless` and check the diff before committing them. A new animation has no frames to
+//! compare with, so the test fails until they have been recorded the same way.
+//!
+//! The VU meter is left out as it follows the audio envelope, which other tests change.
+
+use super::{Animation, AnimationKind, BootAnimation, LavaAnimation, SosAnimation, SparkleAnimation, next_buffer};
+use crate::colour::{Theme, set_brightness};
+use crate::gamma::brightness_lut;
+use crate::power::limit_current;
+use smart_leds::RGB8;
+use std::fmt::Write;
+
+/// How many frames of each animation are compared
+const FRAMES: usize = 64;
+
+/// The global brightness the frames are rendered at
+const BRIGHTNESS: u8 = 128;
+
+/// Seed for the animations that use random numbers
+const SEED: u64 = 0x5017_57A2;
+
+/// Set this environment variable to record new golden frames
+const BLESS: &str = "SOULSTAR_BLESS";
+
+fn theme() -> Theme {
+    Theme {
+        colour: RGB8::new(255, 96, 0),
+        stripes: &[],
+        name: "Dr Who",
+        id: 1,
+    }
+}
+
+/// The animations under test and the names of their golden files
+fn animations() -> [(&'static str, Animation); 9] {
+    let theme = theme();
+    let colour = theme.colour;
+    [
+        ("sparkle", Animation::Sparkle(SparkleAnimation::with_seed(colour, None, SEED))),
+        ("wave", AnimationKind::Wave.animation(&theme, None)),
+        ("scanner", AnimationKind::Scanner.animation(&theme, None)),
+        (
+            "lava",
+            Animation::Lava(LavaAnimation::with_seed(
+                [colour, set_brightness(160, colour), set_brightness(96, colour)],
+                None,
+                SEED,
+            )),
+        ),
+        ("stripes", AnimationKind::Stripes.animation(&theme, None)),
+        ("ocean", AnimationKind::Ocean.animation(&theme, None)),
+        ("morse", AnimationKind::Morse.animation(&theme, None)),
+        ("boot", Animation::Boot(BootAnimation::new(colour))),
+        ("sos", Animation::Sos(SosAnimation::new())),
+    ]
+}
+
+/// Run an animation the way the LED driver would, one line of hex RGB pixels per frame
+fn render(mut anim: Animation) -> String {
+    let lut = brightness_lut(BRIGHTNESS);
+    let mut out = String::new();
+    for _ in 0..FRAMES {
+        let Some(mut frame) = next_buffer(&mut anim) else {
+            out.push_str("end\n");
+            break;
+        };
+        for pix in frame.iter_mut() {
+            *pix = RGB8::new(lut[pix.r as usize], lut[pix.g as usize], lut[pix.b as usize]);
+        }
+        limit_current(&mut frame);
+        let pixels: Vec<String> = frame
+            .iter()
+            .map(|p| format!("{:02x}{:02x}{:02x}", p.r, p.g, p.b))
+            .collect();
+        let _ = writeln!(out, "{}", pixels.join(" "));
+    }
+    out
+}
+
+#[test]
+pub fn if_the_animations_look_the_same() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/animations/golden");
+    let bless = std::env::var_os(BLESS).is_some();
+    let mut changed = Vec::new();
+    for (name, anim) in animations() {
+        let path = format!("{}/{}.txt", dir, name);
+        let frames = render(anim);
+        if bless {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(&path, frames).unwrap();
+            continue;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(golden) => {
+                if let Some(frame) = golden.lines().zip(frames.lines()).position(|(g, f)| g != f) {
+                    changed.push(format!("{} (frame {})", name, frame));
+                } else if golden.lines().count() != frames.lines().count() {
+                    changed.push(format!("{} (length)", name));
+                }
+            }
+            Err(_) => changed.push(format!("{} (not recorded, run `just bless`)", name)),
+        }
+    }
+    assert!(changed.is_empty(), "Animations no longer match their golden frames: {}", changed.join(", "));
+}
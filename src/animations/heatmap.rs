@@ -0,0 +1,151 @@
+//! Crowd heatmap. The whole strip glows with a colour temperature that shows how social the spot
+//! is: a dim, cool blue when we are on our own, warming through amber to a bright red as more
+//! souls gather close by. Nearby souls count for more than distant ones, so a few friends right
+//! beside us feel as warm as a room full of strangers across the hall. A slow shimmer rolls
+//! along the strip so it still looks alive when the crowd is steady.
+//!
+//! Like the presence animation it doesn't keep the souls itself. The display task calls
+//! [HeatmapAnimation::refresh] with the tracker summary before each frame.
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::{HEATMAP_FULL_CROWD, PRESENCE_FAR_LOSS, PRESENCE_NEAR_LOSS};
+use crate::math::{lerp8, scale8, sin8};
+use crate::tracker::SoulSummary;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// The colour of an empty room
+const COLD: RGB8 = RGB8::new(0, 40, 255);
+
+/// The colour halfway to a full crowd
+const WARM: RGB8 = RGB8::new(255, 140, 0);
+
+/// The colour of a full crowd
+const HOT: RGB8 = RGB8::new(255, 0, 0);
+
+/// Brightness of an empty room, so the strip doesn't look switched off
+const MIN_GLOW: u8 = 32;
+
+/// Phase change of the shimmer from one pixel to the next
+const SHIMMER_SPREAD: u8 = 13;
+
+#[derive(Clone)]
+pub struct HeatmapAnimation {
+    /// How warm the crowd is, 0 for nobody to 255 for a full crowd
+    target: u8,
+    /// The heat being shown, which eases towards the target so arrivals don't make it jump
+    heat: u8,
+    /// Phase of the shimmer
+    phase: u8,
+    pub(super) expires: Option<Instant>,
+}
+
+impl HeatmapAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Create a heatmap animation
+    ///
+    /// # Arguments
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            target: 0,
+            heat: 0,
+            phase: 0,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    /// Work out how warm the crowd is from the tracker summary. Each soul counts for up to 255,
+    /// depending on how close it is, and [HEATMAP_FULL_CROWD] souls right next to us are as hot
+    /// as it gets.
+    ///
+    /// # Arguments
+    /// * `souls` - The tracker summary
+    pub fn refresh(&mut self, souls: &[SoulSummary]) {
+        let range = PRESENCE_FAR_LOSS - PRESENCE_NEAR_LOSS;
+        let warmth: u32 = souls
+            .iter()
+            .map(|s| {
+                (PRESENCE_FAR_LOSS - s.tx_loss.clamp(PRESENCE_NEAR_LOSS, PRESENCE_FAR_LOSS)) as u32 * 255 / range as u32
+            })
+            .sum();
+        self.target = (warmth / HEATMAP_FULL_CROWD).min(255) as u8;
+    }
+
+    /// The colour for a heat, from cold blue through warm amber to hot red
+    fn colour(heat: u8) -> RGB8 {
+        let blend = |from: RGB8, to: RGB8, amount: u8| {
+            RGB8::new(lerp8(from.r, to.r, amount), lerp8(from.g, to.g, amount), lerp8(from.b, to.b, amount))
+        };
+        if heat < 128 {
+            blend(COLD, WARM, heat * 2)
+        } else {
+            blend(WARM, HOT, (heat - 128) * 2 + 1)
+        }
+    }
+}
+
+impl Interruptable for HeatmapAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for HeatmapAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        // Ease an eighth of the way towards the target, but always at least one step
+        let gap = self.target as i16 - self.heat as i16;
+        let step = if gap / 8 != 0 { gap / 8 } else { gap.signum() };
+        self.heat = (self.heat as i16 + step) as u8;
+        let colour = set_brightness(lerp8(MIN_GLOW, 255, self.heat), Self::colour(self.heat));
+        let mut buffer = LedBuffer::default();
+        for (idx, led) in buffer.iter_mut().enumerate() {
+            // Between three quarters and full brightness
+            let shimmer = 192 + sin8(self.phase.wrapping_add((idx as u8).wrapping_mul(SHIMMER_SPREAD))) / 4;
+            *led = RGB8::new(scale8(colour.r, shimmer), scale8(colour.g, shimmer), scale8(colour.b, shimmer));
+        }
+        self.phase = self.phase.wrapping_add(2);
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fields::Role;
+
+    fn soul(tx_loss: i32) -> SoulSummary {
+        SoulSummary {
+            colour: RGB8::new(0, 255, 0),
+            tx_loss,
+            role: Role::default(),
+            pixel: None,
+        }
+    }
+
+    #[test]
+    pub fn if_a_close_crowd_is_hotter() {
+        let mut heatmap = HeatmapAnimation::new(None);
+        heatmap.refresh(&[]);
+        let empty = heatmap.next().unwrap();
+        assert!(empty.iter().all(|c| c.r == 0 && c.b > 0));
+        // Distant souls hardly warm it up
+        heatmap.refresh(&[soul(PRESENCE_FAR_LOSS); 4]);
+        assert_eq!(heatmap.target, 0);
+        heatmap.refresh(&[soul(PRESENCE_NEAR_LOSS); HEATMAP_FULL_CROWD as usize]);
+        assert_eq!(heatmap.target, 255);
+        // It warms up gradually and ends up red
+        let warming = heatmap.next().unwrap();
+        assert!(warming[0].r > 0 && warming[0].r < 255);
+        let hot = heatmap.by_ref().take(100).last().unwrap();
+        assert_eq!(heatmap.heat, 255);
+        assert!(hot.iter().all(|c| c.r > 128 && c.g == 0 && c.b == 0));
+    }
+}
@@ -0,0 +1,164 @@
+//! Hot/cold friend finder. The strip becomes a signal strength meter for one selected soul, with
+//! more pixels lit and warmer colours the closer we get. The first pixel shows the colour of the
+//! soul being hunted so you know who you are looking for.
+//!
+//! The display task feeds the animation with the path loss of the target soul as its
+//! advertisements arrive. With the [crew](crate::crew) finder on, it also passes on what the
+//! rest of the crew can see of the target. While the target is nearer to one of them than to us,
+//! a pulse runs along the strip in that crew member's colour instead, so you know who to head for.
+
+use super::Interruptable;
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::{HOT_COLD_FAR_LOSS, HOT_COLD_NEAR_LOSS, LED_STRING_SIZE};
+use crate::crew::{CrewFinder, Report, Zone};
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+#[derive(Clone)]
+pub struct HotColdAnimation {
+    /// Tracker key of the soul we are looking for
+    target: u32,
+    /// Colour of the soul we are looking for
+    colour: RGB8,
+    /// Latest path loss to the target, None if we have lost sight of it
+    tx_loss: Option<i32>,
+    /// What the crew can see of the target
+    crew: CrewFinder,
+    /// Where the pulse pointing at a crew member has got to
+    pulse: usize,
+}
+
+impl HotColdAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Create a hot/cold animation
+    ///
+    /// # Arguments
+    /// * `target` - Tracker key of the soul to find
+    /// * `colour` - Colour of the soul to find
+    /// * `tx_loss` - Current path loss to the soul in dB
+    pub fn new(target: u32, colour: RGB8, tx_loss: Option<i32>) -> Self {
+        Self {
+            target,
+            colour,
+            tx_loss,
+            crew: CrewFinder::new(target),
+            pulse: 0,
+        }
+    }
+
+    /// Tracker key of the soul we are looking for
+    pub fn target(&self) -> u32 {
+        self.target
+    }
+
+    /// Update the path loss to the target. None means we can no longer see it.
+    pub fn update(&mut self, tx_loss: Option<i32>) {
+        self.tx_loss = tx_loss;
+    }
+
+    /// Pass on what a crew member can see of the target
+    ///
+    /// # Arguments
+    /// * `member` - Tracker key of the crew member
+    /// * `colour` - The crew member's colour
+    /// * `report` - Its report
+    pub fn crew_report(&mut self, member: u32, colour: RGB8, report: Report) {
+        self.crew.hear(member, colour, report, Instant::now());
+    }
+
+    /// How close we are on a scale of 0 (far away or out of sight) to 255 (right next to it)
+    fn closeness(&self) -> u8 {
+        match self.tx_loss {
+            Some(loss) => {
+                let loss = loss.clamp(HOT_COLD_NEAR_LOSS, HOT_COLD_FAR_LOSS);
+                ((HOT_COLD_FAR_LOSS - loss) * 255 / (HOT_COLD_FAR_LOSS - HOT_COLD_NEAR_LOSS)) as u8
+            }
+            None => 0,
+        }
+    }
+}
+
+/// Blend from cold blue to hot red
+fn temperature(closeness: u8) -> RGB8 {
+    RGB8::new(closeness, 0, 255 - closeness)
+}
+
+impl Interruptable for HotColdAnimation {
+    /// The game runs until it is switched off
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for HotColdAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let zone = self.crew.zone(self.tx_loss, Instant::now());
+        Some(self.draw(zone))
+    }
+}
+
+impl HotColdAnimation {
+    /// Draw the meter, or the pulse pointing at the crew member the target is nearest to
+    fn draw(&mut self, zone: Zone) -> LedBuffer {
+        let mut buffer = LedBuffer::default();
+        buffer[0] = self.colour;
+        match zone {
+            Zone::Ours => {
+                let closeness = self.closeness();
+                // Always light one pixel while the target is in sight
+                let lit = 1 + closeness as usize * (LED_STRING_SIZE - 2) / 255;
+                buffer[1..=lit].fill(temperature(closeness));
+            }
+            Zone::Member { colour, .. } => {
+                let at = 1 + self.pulse % (LED_STRING_SIZE - 1);
+                buffer[at] = colour;
+                if at > 1 {
+                    buffer[at - 1] = set_brightness(64, colour);
+                }
+                self.pulse += 1;
+            }
+            Zone::Lost => (),
+        }
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_gets_warmer_closer() {
+        let colour = RGB8::new(0, 255, 0);
+        let mut game = HotColdAnimation::new(1, colour, Some(HOT_COLD_FAR_LOSS));
+        let far = game.next().unwrap();
+        assert_eq!(far[0], colour);
+        assert_eq!(far[1], temperature(0));
+        assert_eq!(far[2], RGB8::default());
+        game.update(Some(HOT_COLD_NEAR_LOSS));
+        let near = game.next().unwrap();
+        assert_eq!(near[LED_STRING_SIZE - 1], temperature(255));
+        game.update(None);
+        let lost = game.next().unwrap();
+        assert_eq!(lost[1], RGB8::default());
+    }
+
+    #[test]
+    pub fn if_it_points_at_the_crew() {
+        let (colour, member) = (RGB8::new(0, 255, 0), RGB8::new(255, 0, 255));
+        let mut game = HotColdAnimation::new(1, colour, None);
+        game.crew_report(2, member, Report { target: 1, loss: 60 });
+        let first = game.draw(Zone::Member { key: 2, colour: member });
+        assert_eq!(first[1], member);
+        let second = game.draw(Zone::Member { key: 2, colour: member });
+        assert_eq!(second[2], member);
+        assert_ne!(second[1], RGB8::default());
+        // The pulse is only drawn while the crew can see the target better than we can
+        assert_eq!(game.next().unwrap()[3], member);
+        game.update(Some(HOT_COLD_NEAR_LOSS));
+        assert_eq!(game.next().unwrap()[3], temperature(255));
+    }
+}
@@ -0,0 +1,135 @@
+//! Lava lamp. A few soft blobs of colour drift slowly up and down the strip, merging where they
+//! overlap. Positions are kept in fixed point with a smooth falloff, so each frame only moves a
+//! fraction of a pixel. That keeps it looking smooth at the low frame rates and brightness
+//! levels of a wearable.
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::{ANIMATION_UPDATE, LED_STRING_SIZE};
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// Number of blobs in the lamp
+pub const BLOBS: usize = 3;
+
+/// Fixed point scale for positions and velocities
+const ONE: i32 = 256;
+
+/// Blob radius in fixed point pixels
+const RADIUS: i32 = 4 * ONE;
+
+/// Fastest a blob may drift, in fixed point pixels per frame
+const MAX_SPEED: i32 = ONE / 4;
+
+/// Length of the strip in fixed point pixels
+const LENGTH: i32 = (LED_STRING_SIZE as i32 - 1) * ONE;
+
+#[derive(Clone, Copy)]
+struct Blob {
+    colour: RGB8,
+    position: i32,
+    velocity: i32,
+}
+
+#[derive(Clone)]
+pub struct LavaAnimation {
+    blobs: [Blob; BLOBS],
+    rng: fastrand::Rng,
+    pub(super) expires: Option<Instant>,
+}
+
+impl LavaAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(ANIMATION_UPDATE);
+
+    /// Create a lava lamp animation
+    ///
+    /// # Arguments
+    /// * `colours` - The colour of each blob
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colours: [RGB8; BLOBS], ttl: Option<Duration>) -> Self {
+        Self::with_seed(colours, ttl, Instant::now().as_ticks())
+    }
+
+    /// Create a lava lamp animation that always drifts the same way, for tests
+    ///
+    /// # Arguments
+    /// * `colours` - The colour of each blob
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    /// * `seed` - Seed for the blob positions and drift
+    pub fn with_seed(colours: [RGB8; BLOBS], ttl: Option<Duration>, seed: u64) -> Self {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let blobs = colours.map(|colour| Blob {
+            colour,
+            position: rng.i32(0..=LENGTH),
+            velocity: rng.i32(-MAX_SPEED..=MAX_SPEED),
+        });
+        Self {
+            blobs,
+            rng,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.blobs[0].colour
+    }
+
+    /// Brightness contribution of a blob at a pixel. Falls off smoothly to zero at [RADIUS].
+    fn intensity(blob: &Blob, pixel: usize) -> u8 {
+        let d = (pixel as i32 * ONE - blob.position).abs();
+        if d >= RADIUS {
+            0
+        } else {
+            (255 - 255 * d * d / (RADIUS * RADIUS)) as u8
+        }
+    }
+}
+
+impl Interruptable for LavaAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for LavaAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        for blob in self.blobs.iter_mut() {
+            // Wander a little so the blobs don't move like clockwork, bouncing off the ends
+            blob.velocity = (blob.velocity + self.rng.i32(-2..=2)).clamp(-MAX_SPEED, MAX_SPEED);
+            blob.position += blob.velocity;
+            if !(0..=LENGTH).contains(&blob.position) {
+                blob.velocity = -blob.velocity;
+                blob.position = blob.position.clamp(0, LENGTH);
+            }
+        }
+        let mut buffer = LedBuffer::default();
+        for (idx, led) in buffer.iter_mut().enumerate() {
+            for blob in &self.blobs {
+                let c = set_brightness(Self::intensity(blob, idx), blob.colour);
+                led.r = led.r.saturating_add(c.r);
+                led.g = led.g.saturating_add(c.g);
+                led.b = led.b.saturating_add(c.b);
+            }
+        }
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_blobs_stay_on_the_strip() {
+        let mut lava = LavaAnimation::new([RGB8::new(255, 0, 0); BLOBS], None);
+        for _ in 0..1000 {
+            lava.next().unwrap();
+            assert!(lava.blobs.iter().all(|b| (0..=LENGTH).contains(&b.position)));
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Morse code beacon. Blinks a message, normally our advertised name, on the whole strip in
+//! Morse code and then repeats it. A bit of a geek flourish, but it also lets someone identify
+//! us when BLE is not getting through.
+//!
+//! One frame is one Morse unit: a dot is one unit on, a dash three. Symbols in a letter are
+//! separated by one unit off, letters by three and words by seven. Characters without a Morse
+//! code are skipped.
+
+use super::{Interruptable, has_expired};
+use crate::colour::LedBuffer;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// Units off between letters
+const LETTER_GAP: u8 = 3;
+
+/// Units off between words and before the message repeats
+const WORD_GAP: u8 = 7;
+
+/// The Morse code for a character as dots and dashes, or None if it has no code
+fn code(c: u8) -> Option<&'static [u8]> {
+    let code: &'static [u8] = match c.to_ascii_uppercase() {
+        b'A' => b".-",
+        b'B' => b"-...",
+        b'C' => b"-.-.",
+        b'D' => b"-..",
+        b'E' => b".",
+        b'F' => b"..-.",
+        b'G' => b"--.",
+        b'H' => b"....",
+        b'I' => b"..",
+        b'J' => b".---",
+        b'K' => b"-.-",
+        b'L' => b".-..",
+        b'M' => b"--",
+        b'N' => b"-.",
+        b'O' => b"---",
+        b'P' => b".--.",
+        b'Q' => b"--.-",
+        b'R' => b".-.",
+        b'S' => b"...",
+        b'T' => b"-",
+        b'U' => b"..-",
+        b'V' => b"...-",
+        b'W' => b".--",
+        b'X' => b"-..-",
+        b'Y' => b"-.--",
+        b'Z' => b"--..",
+        b'0' => b"-----",
+        b'1' => b".----",
+        b'2' => b"..---",
+        b'3' => b"...--",
+        b'4' => b"....-",
+        b'5' => b".....",
+        b'6' => b"-....",
+        b'7' => b"--...",
+        b'8' => b"---..",
+        b'9' => b"----.",
+        _ => return None,
+    };
+    Some(code)
+}
+
+#[derive(Clone)]
+pub struct MorseAnimation {
+    colour: RGB8,
+    message: &'static str,
+    /// Index of the character being sent, None if the message has nothing we can send
+    char: Option<usize>,
+    /// Index of the symbol being sent within the character
+    symbol: usize,
+    /// True while a symbol is lit, false during the gaps
+    on: bool,
+    /// Frames left before we move on to the next symbol or gap
+    units_left: u8,
+    pub(super) expires: Option<Instant>,
+}
+
+impl MorseAnimation {
+    /// One Morse unit
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(150);
+
+    /// Create a Morse code animation
+    ///
+    /// # Arguments
+    /// * `colour` - The colour to blink with
+    /// * `message` - The message to send
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colour: RGB8, message: &'static str, ttl: Option<Duration>) -> Self {
+        let mut morse = Self {
+            colour,
+            message,
+            char: None,
+            symbol: 0,
+            on: false,
+            units_left: 0,
+            expires: ttl.map(|t| Instant::now() + t),
+        };
+        morse.char = morse.next_char(0).map(|(c, _)| c);
+        morse
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+
+    /// Find the next character we can send, starting at `from` and wrapping around to the start.
+    /// Also returns true if a word gap comes before it.
+    fn next_char(&self, from: usize) -> Option<(usize, bool)> {
+        let bytes = self.message.as_bytes();
+        let len = bytes.len();
+        let mut word_gap = false;
+        for i in from..from + len {
+            // Pause before repeating the message
+            if i == len {
+                word_gap = true;
+            }
+            match code(bytes[i % len]) {
+                Some(_) => return Some((i % len, word_gap)),
+                None => word_gap |= bytes[i % len] == b' ',
+            }
+        }
+        None
+    }
+
+    /// Move on to the next symbol or gap once the current one is done
+    fn advance(&mut self, char: usize) {
+        let symbols = code(self.message.as_bytes()[char]).unwrap_or_default();
+        if !self.on {
+            self.on = true;
+            self.units_left = if symbols.get(self.symbol) == Some(&b'-') { 3 } else { 1 };
+            return;
+        }
+        self.on = false;
+        self.symbol += 1;
+        if self.symbol < symbols.len() {
+            self.units_left = 1;
+            return;
+        }
+        // The letter is done, so gap before the next one
+        self.symbol = 0;
+        let (next, word_gap) = self.next_char(char + 1).unwrap_or((char, true));
+        self.char = Some(next);
+        self.units_left = if word_gap { WORD_GAP } else { LETTER_GAP };
+    }
+}
+
+impl Interruptable for MorseAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for MorseAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        let mut buffer = LedBuffer::default();
+        if let Some(char) = self.char {
+            if self.units_left == 0 {
+                self.advance(char);
+            }
+            self.units_left -= 1;
+            if self.on {
+                buffer.fill(self.colour);
+            }
+        }
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use heapless::Vec;
+
+    /// Run the animation for a number of frames, recording which ones were lit
+    fn blinks(morse: &mut MorseAnimation, frames: usize) -> Vec<bool, 32> {
+        morse.take(frames).map(|b| b[0] != RGB8::default()).collect()
+    }
+
+    #[test]
+    pub fn if_it_sends_morse() {
+        let colour = RGB8::new(255, 0, 0);
+        // E is a dot, T is a dash, then a word gap before the message repeats
+        let mut morse = MorseAnimation::new(colour, "e t", None);
+        let mut expected: Vec<bool, 32> = Vec::new();
+        expected.push(true).unwrap();
+        expected.extend_from_slice(&[false; WORD_GAP as usize]).unwrap();
+        expected.extend_from_slice(&[true; 3]).unwrap();
+        expected.extend_from_slice(&[false; WORD_GAP as usize]).unwrap();
+        expected.push(true).unwrap();
+        assert_eq!(blinks(&mut morse, expected.len()), expected);
+        // Nothing to send just stays dark
+        let mut morse = MorseAnimation::new(colour, "?!", None);
+        assert!(blinks(&mut morse, 5).iter().all(|on| !on));
+    }
+}
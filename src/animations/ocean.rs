@@ -0,0 +1,103 @@
+//! Ocean. A few layers of gentle waves in blues and greens roll along the strip at different
+//! speeds, adding up to a calm, low-stimulation pattern for chilled areas.
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, set_brightness};
+use crate::math::sin8;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// A single wave layer
+#[derive(Clone, Copy)]
+struct Layer {
+    colour: RGB8,
+    /// Phase change from one pixel to the next, which sets the wavelength
+    spread: u8,
+    /// Phase change per frame, which sets the speed
+    speed: u8,
+}
+
+const LAYERS: [Layer; 3] = [
+    Layer {
+        colour: RGB8::new(0, 20, 120),
+        spread: 11,
+        speed: 1,
+    },
+    Layer {
+        colour: RGB8::new(0, 80, 100),
+        spread: 17,
+        speed: 2,
+    },
+    Layer {
+        colour: RGB8::new(0, 100, 40),
+        spread: 7,
+        speed: 3,
+    },
+];
+
+#[derive(Clone)]
+pub struct OceanAnimation {
+    /// Current phase of each layer
+    phases: [u8; LAYERS.len()],
+    pub(super) expires: Option<Instant>,
+}
+
+impl OceanAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Create an ocean animation
+    ///
+    /// # Arguments
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            // Start the layers out of step with each other
+            phases: [0, 85, 170],
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+}
+
+impl Interruptable for OceanAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for OceanAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        let mut buffer = LedBuffer::default();
+        for (idx, led) in buffer.iter_mut().enumerate() {
+            for (layer, phase) in LAYERS.iter().zip(self.phases) {
+                let b = sin8(phase.wrapping_add((idx as u8).wrapping_mul(layer.spread)));
+                let c = set_brightness(b, layer.colour);
+                led.r = led.r.saturating_add(c.r);
+                led.g = led.g.saturating_add(c.g);
+                led.b = led.b.saturating_add(c.b);
+            }
+        }
+        for (layer, phase) in LAYERS.iter().zip(self.phases.iter_mut()) {
+            *phase = phase.wrapping_add(layer.speed);
+        }
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_the_waves_roll() {
+        let mut ocean = OceanAnimation::new(None);
+        let first = ocean.next().unwrap();
+        let second = ocean.next().unwrap();
+        assert!(first != second);
+        assert!(first.iter().all(|c| c.r == 0));
+    }
+}
@@ -0,0 +1,79 @@
+//! Pulse on the beat. The whole strip flashes our colour on every beat of the music and fades
+//! away before the next one, see [tempo](crate::tempo).
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, set_brightness};
+use crate::tempo::beat;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+#[derive(Clone)]
+pub struct PulseAnimation {
+    colour: RGB8,
+    pub(super) expires: Option<Instant>,
+}
+
+impl PulseAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Create a pulse animation
+    ///
+    /// # Arguments
+    /// * `colour` - Colour of the pulse
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colour: RGB8, ttl: Option<Duration>) -> Self {
+        Self {
+            colour,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+
+    /// Draw the pulse
+    ///
+    /// # Arguments
+    /// * `phase` - How far through the beat we are, from 0 (on the beat) to 255
+    fn draw(&self, phase: u8) -> LedBuffer {
+        // Fade fast at first so the beat stands out, then tail off
+        let left = 255 - phase as u16;
+        let mut buffer = LedBuffer::default();
+        buffer.fill(set_brightness((left * left / 255) as u8, self.colour));
+        buffer
+    }
+}
+
+impl Interruptable for PulseAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for PulseAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        let (_, phase) = beat(Instant::now());
+        Some(self.draw(phase))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_flashes_on_the_beat() {
+        let colour = RGB8::new(255, 0, 0);
+        let pulse = PulseAnimation::new(colour, None);
+        assert!(pulse.draw(0).iter().all(|&p| p == colour));
+        let half = pulse.draw(128)[0].r;
+        assert!(half > 0 && half < 128);
+        assert_eq!(pulse.draw(255)[0], RGB8::default());
+    }
+}
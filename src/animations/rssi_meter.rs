@@ -0,0 +1,231 @@
+//! Signal strength meter for range-testing antennas and transmitter power settings in the field.
+//! The strip shows the smoothed RSSI of the strongest soul we can hear as a bar graph, from red
+//! at the weak end to green at the strong end. The last pixel blinks the RSSI in dB as a simple
+//! code: the tens and then the units, each digit as that many short blinks, with a zero as one
+//! long blink. A soul at -67 dBm blinks six times, pauses and blinks seven times.
+//!
+//! The display task feeds the meter with every beacon it receives. It sticks with the soul it
+//! is measuring unless another is clearly stronger, so the reading doesn't jump about between
+//! souls that are about as close as each other.
+
+use super::Interruptable;
+use crate::colour::LedBuffer;
+use crate::configuration::LED_STRING_SIZE;
+use embassy_time::Duration;
+use smart_leds::RGB8;
+
+/// RSSI (dBm) shown as an empty bar
+const RSSI_FLOOR: i32 = -100;
+
+/// RSSI (dBm) shown as a full bar
+const RSSI_CEILING: i32 = -30;
+
+/// How much stronger (dB) another soul must be before we measure it instead
+const SWITCH_MARGIN: i32 = 6;
+
+/// Pixels in the bar. The last pixel is for the blink code.
+const BAR_PIXELS: usize = LED_STRING_SIZE - 1;
+
+/// Frames a short blink is on for, and off for after it
+const BLINK_FRAMES: usize = 2;
+
+/// Frames the long blink for a zero is on for
+const LONG_BLINK_FRAMES: usize = 6;
+
+/// Frames between the two digits
+const DIGIT_GAP_FRAMES: usize = 6;
+
+/// Frames between one reading of the code and the next
+const CODE_GAP_FRAMES: usize = 15;
+
+/// The colour of the blink code
+const CODE_COLOUR: RGB8 = RGB8::new(128, 128, 128);
+
+#[derive(Clone)]
+pub struct RssiMeterAnimation {
+    /// Tracker key of the soul we are measuring, None if we can't hear anybody
+    target: Option<u32>,
+    /// Moving average of the RSSI of the target in sixteenths of a dBm
+    smoothed: i32,
+    /// The RSSI being blinked, latched at the start of each reading so it doesn't change part way
+    code: u8,
+    /// Frame within the current reading of the code
+    frame: usize,
+}
+
+impl Default for RssiMeterAnimation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RssiMeterAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new() -> Self {
+        Self {
+            target: None,
+            smoothed: 0,
+            code: 0,
+            frame: 0,
+        }
+    }
+
+    /// Take in a beacon
+    ///
+    /// # Arguments
+    /// * `key` - Tracker key of the sender
+    /// * `rssi` - The signal strength it was received with in dBm
+    pub fn update(&mut self, key: u32, rssi: i8) {
+        let rssi = rssi as i32 * 16;
+        match self.target {
+            Some(target) if target == key => self.smoothed += (rssi - self.smoothed) / 4,
+            Some(_) if rssi <= self.smoothed + SWITCH_MARGIN * 16 => (),
+            _ => {
+                self.target = Some(key);
+                self.smoothed = rssi;
+            }
+        }
+    }
+
+    /// The soul we are measuring, if any
+    pub fn target(&self) -> Option<u32> {
+        self.target
+    }
+
+    /// The soul we were measuring has gone, so measure whoever we hear next
+    pub fn lost(&mut self) {
+        self.target = None;
+    }
+
+    /// The smoothed RSSI in dBm, None if we can't hear anybody
+    pub fn rssi(&self) -> Option<i32> {
+        self.target.map(|_| self.smoothed / 16)
+    }
+}
+
+/// Whether the code pixel is lit on a frame of one reading of the blink code
+///
+/// # Arguments
+/// * `value` - The number being blinked, up to 99
+/// * `frame` - The frame within the reading
+///
+/// # Returns
+/// None once the reading is over
+fn code_lit(value: u8, mut frame: usize) -> Option<bool> {
+    for digit in [value / 10, value % 10] {
+        let (length, lit) = match digit as usize {
+            0 => (LONG_BLINK_FRAMES + BLINK_FRAMES, frame < LONG_BLINK_FRAMES),
+            n => (n * 2 * BLINK_FRAMES, frame % (2 * BLINK_FRAMES) < BLINK_FRAMES),
+        };
+        if frame < length {
+            return Some(lit);
+        }
+        frame -= length;
+        if frame < DIGIT_GAP_FRAMES {
+            return Some(false);
+        }
+        frame -= DIGIT_GAP_FRAMES;
+    }
+    (frame < CODE_GAP_FRAMES).then_some(false)
+}
+
+impl Interruptable for RssiMeterAnimation {
+    /// The meter runs until it is switched off
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for RssiMeterAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = LedBuffer::default();
+        let Some(rssi) = self.rssi() else {
+            self.frame = 0;
+            return Some(buffer);
+        };
+        let level = (rssi.clamp(RSSI_FLOOR, RSSI_CEILING) - RSSI_FLOOR) as usize;
+        let lit = level * BAR_PIXELS / (RSSI_CEILING - RSSI_FLOOR) as usize;
+        for (i, pixel) in buffer[..lit].iter_mut().enumerate() {
+            let green = (i * 255 / (BAR_PIXELS - 1)) as u8;
+            *pixel = RGB8::new(255 - green, green, 0);
+        }
+        if self.frame == 0 {
+            self.code = rssi.unsigned_abs().min(99) as u8;
+        }
+        match code_lit(self.code, self.frame) {
+            Some(on) => {
+                if on {
+                    buffer[BAR_PIXELS] = CODE_COLOUR;
+                }
+                self.frame += 1;
+            }
+            None => self.frame = 0,
+        }
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Count the blinks in a reading of the code, long blinks as zero
+    fn digits(value: u8) -> [usize; 2] {
+        let mut digits = [0; 2];
+        let mut digit = 0;
+        let (mut on, mut off) = (0, 0);
+        let mut frame = 0;
+        while let Some(lit) = code_lit(value, frame) {
+            if lit {
+                if on == 0 && off >= DIGIT_GAP_FRAMES && frame > 0 {
+                    digit += 1;
+                }
+                on += 1;
+                off = 0;
+            } else {
+                if on > 0 && on < LONG_BLINK_FRAMES {
+                    digits[digit] += 1;
+                }
+                on = 0;
+                off += 1;
+            }
+            frame += 1;
+        }
+        digits
+    }
+
+    #[test]
+    pub fn if_it_blinks_the_digits() {
+        assert_eq!(digits(67), [6, 7]);
+        assert_eq!(digits(90), [9, 0]);
+        assert_eq!(digits(5), [0, 5]);
+    }
+
+    #[test]
+    pub fn if_it_sticks_with_the_strongest_soul() {
+        let mut meter = RssiMeterAnimation::new();
+        assert!(meter.next().unwrap().iter().all(|p| *p == RGB8::default()));
+        meter.update(1, -70);
+        assert_eq!(meter.rssi(), Some(-70));
+        // A little stronger isn't enough to switch, a lot stronger is
+        meter.update(2, -66);
+        assert_eq!(meter.target(), Some(1));
+        meter.update(2, -50);
+        assert_eq!(meter.target(), Some(2));
+        // A strong signal fills more of the bar than a weak one
+        let strong = meter.next().unwrap()[..BAR_PIXELS]
+            .iter()
+            .filter(|p| **p != RGB8::default())
+            .count();
+        meter.lost();
+        meter.update(3, -90);
+        let weak = meter.next().unwrap()[..BAR_PIXELS]
+            .iter()
+            .filter(|p| **p != RGB8::default())
+            .count();
+        assert!(strong > weak);
+    }
+}
@@ -0,0 +1,111 @@
+//! Larson scanner, as made famous by KITT in Knight Rider. A bright dot bounces from one end of
+//! the strip to the other, leaving an exponentially fading tail behind it.
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::LED_STRING_SIZE;
+use crate::tuning::Tuning;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// Number of positions in one full sweep there and back
+const SWEEP_LENGTH: usize = 2 * (LED_STRING_SIZE - 1);
+
+#[derive(Clone)]
+pub struct ScannerAnimation {
+    colour: RGB8,
+    /// Number of pixels the dot moves each frame
+    speed: usize,
+    /// Position in the sweep. The first half moves up the strip, the second half back down.
+    position: usize,
+    /// Brightness of each pixel, which decays every frame to form the tail
+    trail: [u8; LED_STRING_SIZE],
+    /// How much of each pixel is kept every frame, out of 256
+    tail: u8,
+    pub(super) expires: Option<Instant>,
+}
+
+impl ScannerAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+    /// Create a scanner animation
+    ///
+    /// # Arguments
+    /// * `colour` - Colour of the dot and its tail
+    /// * `speed` - Number of pixels the dot moves each frame. It is at least one.
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colour: RGB8, speed: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            colour,
+            speed: speed.max(1),
+            position: 0,
+            trail: [0; LED_STRING_SIZE],
+            tail: 128,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+
+    /// Take the colour and tail length from the tuning
+    pub(super) fn tune(&mut self, tuning: &Tuning) {
+        self.colour = tuning.palette.unwrap_or(self.colour);
+        self.tail = tuning.tail;
+    }
+
+    /// The pixel the dot is on for a position in the sweep
+    fn pixel(position: usize) -> usize {
+        if position < LED_STRING_SIZE {
+            position
+        } else {
+            SWEEP_LENGTH - position
+        }
+    }
+}
+
+impl Interruptable for ScannerAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for ScannerAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        // Decay everything to get the exponential tail. By default it halves.
+        for t in self.trail.iter_mut() {
+            *t = ((*t as u16 * self.tail as u16) >> 8) as u8;
+        }
+        // Light every pixel we pass over so faster speeds don't leave gaps
+        for _ in 0..self.speed {
+            self.trail[Self::pixel(self.position)] = 255;
+            self.position = (self.position + 1) % SWEEP_LENGTH;
+        }
+        let mut buffer = LedBuffer::default();
+        for (led, b) in buffer.iter_mut().zip(self.trail) {
+            *led = set_brightness(b, self.colour);
+        }
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_bounces() {
+        let mut scanner = ScannerAnimation::new(RGB8::new(255, 0, 0), 1, None);
+        let brightest = |b: LedBuffer| b.iter().enumerate().max_by_key(|(_, p)| p.r).map(|(i, _)| i).unwrap();
+        assert_eq!(brightest(scanner.next().unwrap()), 0);
+        let end = scanner.by_ref().take(LED_STRING_SIZE - 1).last().unwrap();
+        assert_eq!(brightest(end), LED_STRING_SIZE - 1);
+        assert_eq!(brightest(scanner.next().unwrap()), LED_STRING_SIZE - 2);
+    }
+}
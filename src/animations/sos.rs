@@ -0,0 +1,64 @@
+//! Emergency SOS. Blinks ··· −−− ··· in white on the whole strip, over and over until it is
+//! cancelled. The display task shows it at the highest brightness the power limiter allows, even
+//! in night mode.
+//!
+//! The Morse unit is a bit longer than for the [MorseAnimation] so that the dots stay below
+//! three flashes a second.
+
+use super::{Interruptable, MorseAnimation};
+use crate::colour::LedBuffer;
+use embassy_time::Duration;
+use smart_leds::RGB8;
+
+const SOS_COLOUR: RGB8 = RGB8::new(255, 255, 255);
+
+#[derive(Clone)]
+pub struct SosAnimation {
+    morse: MorseAnimation,
+}
+
+impl SosAnimation {
+    /// One Morse unit
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(200);
+
+    pub fn new() -> Self {
+        Self {
+            morse: MorseAnimation::new(SOS_COLOUR, "SOS", None),
+        }
+    }
+}
+
+impl Default for SosAnimation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interruptable for SosAnimation {
+    /// Runs until it is cancelled
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for SosAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.morse.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_calls_for_help() {
+        let lit: heapless::Vec<bool, 16> = SosAnimation::new().take(16).map(|b| b[0] == SOS_COLOUR).collect();
+        // Three dots, a letter gap and the first dash
+        let expected =
+            [true, false, true, false, true, false, false, false, true, true, true, false, true, true, true, false];
+        assert_eq!(lit.as_slice(), expected);
+    }
+}
@@ -0,0 +1,148 @@
+//! Spectrum analyser. The strip is split into a region each for the bass, middle and treble
+//! from the microphone (see [fft](crate::fft)) and each region lights a bar in proportion to its
+//! band. The bass is in our colour and the other two are a third and two thirds of the way round
+//! the colour wheel from it. Each bar has a peak marker that holds the highest recent level for
+//! a moment and then slowly falls back.
+
+use super::{Interruptable, has_expired};
+use crate::audio::spectrum;
+use crate::colour::{LedBuffer, rgb2hsv, set_brightness};
+use crate::configuration::LED_STRING_SIZE;
+use crate::fft::BANDS;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+use smart_leds::hsv::hsv2rgb;
+
+/// Frames the peak marker holds still before it starts to fall
+const PEAK_HOLD_FRAMES: u8 = 12;
+
+/// The peak marker drops by one pixel every this many frames once it is falling
+const PEAK_DECAY_FRAMES: u8 = 3;
+
+/// Brightness of the bars relative to the peak markers
+const BAR_BRIGHTNESS: u8 = 96;
+
+/// Pixels in each band's region
+const REGION: usize = LED_STRING_SIZE / BANDS;
+
+#[derive(Clone)]
+pub struct SpectrumAnimation {
+    colour: RGB8,
+    /// The colour of each band
+    colours: [RGB8; BANDS],
+    /// Position of each band's peak marker
+    peaks: [usize; BANDS],
+    /// Frames since each peak marker last moved
+    peak_ages: [u8; BANDS],
+    pub(super) expires: Option<Instant>,
+}
+
+impl SpectrumAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+    /// Create a spectrum analyser animation
+    ///
+    /// # Arguments
+    /// * `colour` - Colour of the bass, the other bands are spread round the colour wheel from it
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colour: RGB8, ttl: Option<Duration>) -> Self {
+        let mut hsv = rgb2hsv(colour);
+        let colours = core::array::from_fn(|band| {
+            if band == 0 {
+                return colour;
+            }
+            hsv.hue = hsv.hue.wrapping_add((256 / BANDS) as u8);
+            hsv2rgb(hsv)
+        });
+        Self {
+            colour,
+            colours,
+            peaks: [0; BANDS],
+            peak_ages: [0; BANDS],
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+
+    /// Draw the bands and move the peak markers on by a frame
+    ///
+    /// # Arguments
+    /// * `levels` - The level of each band, from 0 to 255
+    fn draw(&mut self, levels: [u8; BANDS]) -> LedBuffer {
+        let mut buffer = LedBuffer::default();
+        for band in 0..BANDS {
+            let lit = levels[band] as usize * REGION / 255;
+            let (peak, age) = (&mut self.peaks[band], &mut self.peak_ages[band]);
+            if lit >= *peak {
+                *peak = lit;
+                *age = 0;
+            } else {
+                *age += 1;
+                if *age >= PEAK_HOLD_FRAMES + PEAK_DECAY_FRAMES {
+                    *peak -= 1;
+                    *age = PEAK_HOLD_FRAMES;
+                }
+            }
+            let region = &mut buffer[band * REGION..(band + 1) * REGION];
+            let colour = self.colours[band];
+            region[..lit].fill(set_brightness(BAR_BRIGHTNESS, colour));
+            if *peak > 0 {
+                region[*peak - 1] = colour;
+            }
+        }
+        buffer
+    }
+}
+
+impl Interruptable for SpectrumAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for SpectrumAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        Some(self.draw(spectrum()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_each_band_has_its_region() {
+        let colour = RGB8::new(255, 0, 0);
+        let mut spectrum = SpectrumAnimation::new(colour, None);
+        let b = spectrum.draw([255, 0, 128]);
+        assert_eq!(b[REGION - 1], colour);
+        assert_eq!(b[REGION], RGB8::default());
+        assert_ne!(b[2 * REGION], RGB8::default());
+        assert_eq!(b[3 * REGION - 1], RGB8::default());
+        // The treble is a different colour to the bass
+        assert_ne!(b[2 * REGION + REGION / 2 - 1], colour);
+    }
+
+    #[test]
+    pub fn if_the_peaks_hold_then_fall() {
+        let mut spectrum = SpectrumAnimation::new(RGB8::new(0, 255, 0), None);
+        spectrum.draw([255; BANDS]);
+        let b = (0..PEAK_HOLD_FRAMES).map(|_| spectrum.draw([0; BANDS])).last().unwrap();
+        assert_ne!(b[REGION - 1], RGB8::default());
+        let b = (0..PEAK_DECAY_FRAMES)
+            .map(|_| spectrum.draw([0; BANDS]))
+            .last()
+            .unwrap();
+        assert_eq!(b[REGION - 1], RGB8::default());
+        assert_ne!(b[REGION - 2], RGB8::default());
+        assert_eq!(b[0], RGB8::default());
+    }
+}
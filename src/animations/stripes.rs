@@ -0,0 +1,81 @@
+//! Stripes. The strip is split into equal bands of colour, like a flag, which slowly scroll
+//! along. The bands for each soul can be set in `souls.toml`.
+
+use super::{Interruptable, has_expired};
+use crate::colour::{LedBuffer, is_whiteish};
+use crate::configuration::LED_STRING_SIZE;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smart_leds::RGB8;
+
+/// The most bands we can display. Any more are ignored.
+pub const MAX_STRIPES: usize = 8;
+
+#[derive(Clone)]
+pub struct StripesAnimation {
+    bands: Vec<RGB8, MAX_STRIPES>,
+    /// How far the bands have scrolled along the strip
+    offset: usize,
+    pub(super) expires: Option<Instant>,
+}
+
+impl StripesAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Create a stripes animation
+    ///
+    /// # Arguments
+    /// * `bands` - The colour of each band, in order. Only the first [MAX_STRIPES] are used.
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(bands: impl IntoIterator<Item = RGB8>, ttl: Option<Duration>) -> Self {
+        Self {
+            bands: bands.into_iter().take(MAX_STRIPES).collect(),
+            offset: 0,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    /// True if any of the bands is white-ish
+    pub(super) fn has_white(&self) -> bool {
+        self.bands.iter().any(|b| is_whiteish(*b))
+    }
+}
+
+impl Interruptable for StripesAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for StripesAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) || self.bands.is_empty() {
+            return None;
+        }
+        let n = self.bands.len();
+        let mut buffer = LedBuffer::default();
+        for (idx, led) in buffer.iter_mut().enumerate() {
+            let pos = (idx + self.offset) % LED_STRING_SIZE;
+            *led = self.bands[pos * n / LED_STRING_SIZE];
+        }
+        self.offset = (self.offset + 1) % LED_STRING_SIZE;
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_draws_equal_bands() {
+        let red = RGB8::new(255, 0, 0);
+        let blue = RGB8::new(0, 0, 255);
+        let buffer = StripesAnimation::new([red, blue], None).next().unwrap();
+        assert_eq!(buffer.iter().filter(|p| **p == red).count(), LED_STRING_SIZE / 2);
+        assert_eq!(buffer[0], red);
+        assert_eq!(buffer[LED_STRING_SIZE - 1], blue);
+    }
+}
@@ -0,0 +1,104 @@
+//! Strobe for peak party moments.
+//!
+//! Flashing lights can trigger seizures in people with photosensitive epilepsy. The usual
+//! guidance is to stay at or below three flashes a second, so the flash rate is capped at
+//! [MAX_FLASH_HZ] no matter what is asked for. Strobing also has to be explicitly enabled in
+//! the configuration.
+
+use super::{Interruptable, has_expired};
+use crate::colour::LedBuffer;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// The fastest we will ever flash
+pub const MAX_FLASH_HZ: u64 = 3;
+
+/// Longest fraction of a period, in percent, that the strobe stays on
+const MAX_DUTY: u8 = 50;
+
+#[derive(Clone)]
+pub struct StrobeAnimation {
+    colour: RGB8,
+    /// Frames in a full on/off period
+    period: u32,
+    /// Frames the strobe is on for in each period
+    on: u32,
+    /// Frames since the animation started
+    frame: u32,
+    pub(super) expires: Option<Instant>,
+}
+
+impl StrobeAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(25);
+
+    /// Create a strobe animation. The period is rounded up to a whole number of frames and is
+    /// never shorter than one flash every 1/[MAX_FLASH_HZ] seconds.
+    ///
+    /// # Arguments
+    /// * `colour` - The colour to flash
+    /// * `period` - Time between the start of each flash
+    /// * `duty` - Percentage of the period the strobe is lit for, from 1 to 50
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colour: RGB8, period: Duration, duty: u8, ttl: Option<Duration>) -> Self {
+        let min_period = Duration::from_millis(1000 / MAX_FLASH_HZ + 1);
+        let period = period
+            .max(min_period)
+            .as_millis()
+            .div_ceil(Self::FRAME_INTERVAL.as_millis()) as u32;
+        let on = (period * duty.clamp(1, MAX_DUTY) as u32 / 100).max(1);
+        Self {
+            colour,
+            period,
+            on,
+            frame: 0,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+}
+
+impl Interruptable for StrobeAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for StrobeAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        let mut buffer = LedBuffer::default();
+        if self.frame % self.period < self.on {
+            buffer.fill(self.colour);
+        }
+        self.frame = self.frame.wrapping_add(1);
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_never_flashes_too_fast() {
+        let strobe = StrobeAnimation::new(RGB8::new(255, 255, 255), Duration::from_millis(10), 100, None);
+        let frames_per_second = (1000 / StrobeAnimation::FRAME_INTERVAL.as_millis()) as usize;
+        let mut flashes = 0;
+        let mut was_on = false;
+        for b in strobe.take(frames_per_second) {
+            let on = b[0] != RGB8::default();
+            if on && !was_on {
+                flashes += 1;
+            }
+            was_on = on;
+        }
+        assert!(flashes <= MAX_FLASH_HZ);
+    }
+}
@@ -0,0 +1,75 @@
+//! A quick swirl of colour to confirm a gesture, e.g. shaking the star to shuffle the default
+//! animation. A bright head with a fading tail races twice round the strip and is gone.
+
+use super::Interruptable;
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::LED_STRING_SIZE;
+use embassy_time::Duration;
+use smart_leds::RGB8;
+
+/// Times the head goes round the strip
+const LAPS: usize = 2;
+
+/// Pixels in the tail behind the head
+const TAIL: usize = 6;
+
+#[derive(Clone)]
+pub struct SwirlAnimation {
+    colour: RGB8,
+    frame: usize,
+}
+
+impl SwirlAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Create the swirl
+    ///
+    /// # Arguments
+    /// * `colour` - The colour to swirl in
+    pub fn new(colour: RGB8) -> Self {
+        Self { colour, frame: 0 }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+}
+
+impl Interruptable for SwirlAnimation {
+    /// It is short, so let it finish
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+impl Iterator for SwirlAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame >= LAPS * LED_STRING_SIZE {
+            return None;
+        }
+        let mut buffer = LedBuffer::default();
+        // The tail can't be longer than the strip or the head would land on it
+        for behind in 0..TAIL.min(LED_STRING_SIZE).min(self.frame + 1) {
+            let brightness = (255 - behind * 255 / TAIL) as u8;
+            buffer[(self.frame - behind) % LED_STRING_SIZE] = set_brightness(brightness, self.colour);
+        }
+        self.frame += 1;
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_swirls_and_stops() {
+        let colour = RGB8::new(0, 255, 0);
+        let mut swirl = SwirlAnimation::new(colour);
+        assert_eq!(swirl.next().unwrap()[0], colour);
+        assert_eq!(swirl.next().unwrap()[1], colour);
+        assert_eq!(swirl.count(), LAPS * LED_STRING_SIZE - 2);
+    }
+}
@@ -0,0 +1,125 @@
+//! VU meter. Lights up a bar in proportion to the audio envelope from the microphone with a
+//! peak marker that holds the highest recent level and slowly falls back. On a ring, the bar
+//! grows up both sides from the first LED.
+
+use super::{Interruptable, has_expired};
+use crate::audio::envelope;
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::{LED_STRING_SIZE, LedLayout};
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// The peak marker drops by one pixel every this many frames
+const PEAK_DECAY_FRAMES: u8 = 4;
+
+/// Brightness of the bar relative to the peak marker
+const BAR_BRIGHTNESS: u8 = 96;
+
+#[derive(Clone)]
+pub struct VuMeterAnimation {
+    colour: RGB8,
+    layout: LedLayout,
+    /// Position of the peak marker along the meter
+    peak: usize,
+    /// Frames since the peak marker last moved
+    peak_age: u8,
+    pub(super) expires: Option<Instant>,
+}
+
+impl VuMeterAnimation {
+    pub const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+    /// Create a VU meter animation
+    ///
+    /// # Arguments
+    /// * `colour` - Colour of the bar and the peak marker
+    /// * `layout` - How the LEDs are arranged
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    pub fn new(colour: RGB8, layout: LedLayout, ttl: Option<Duration>) -> Self {
+        Self {
+            colour,
+            layout,
+            peak: 0,
+            peak_age: 0,
+            expires: ttl.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub(super) fn colour(&self) -> RGB8 {
+        self.colour
+    }
+
+    /// Number of positions along the meter
+    fn length(&self) -> usize {
+        match self.layout {
+            LedLayout::Strip | LedLayout::Matrix { .. } => LED_STRING_SIZE,
+            LedLayout::Ring => LED_STRING_SIZE / 2,
+        }
+    }
+
+    /// Set the pixel(s) for a position along the meter
+    fn set(&self, buffer: &mut LedBuffer, position: usize, colour: RGB8) {
+        buffer[position] = colour;
+        if self.layout == LedLayout::Ring {
+            buffer[LED_STRING_SIZE - 1 - position] = colour;
+        }
+    }
+}
+
+impl Interruptable for VuMeterAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+impl Iterator for VuMeterAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if has_expired(self.expires) {
+            return None;
+        }
+        let length = self.length();
+        let lit = envelope() as usize * length / 255;
+        if lit >= self.peak {
+            self.peak = lit;
+            self.peak_age = 0;
+        } else {
+            self.peak_age += 1;
+            if self.peak_age >= PEAK_DECAY_FRAMES {
+                self.peak -= 1;
+                self.peak_age = 0;
+            }
+        }
+        let mut buffer = LedBuffer::default();
+        for position in 0..lit {
+            self.set(&mut buffer, position, set_brightness(BAR_BRIGHTNESS, self.colour));
+        }
+        if self.peak > 0 {
+            self.set(&mut buffer, self.peak - 1, self.colour);
+        }
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::set_envelope;
+
+    #[test]
+    pub fn if_the_peak_holds_then_falls() {
+        let colour = RGB8::new(0, 255, 0);
+        let mut vu = VuMeterAnimation::new(colour, LedLayout::Strip, None);
+        set_envelope(255);
+        let b = vu.next().unwrap();
+        assert_eq!(b[LED_STRING_SIZE - 1], colour);
+        set_envelope(0);
+        let b = vu.next().unwrap();
+        assert_eq!(b[LED_STRING_SIZE - 1], colour);
+        assert_eq!(b[0], RGB8::default());
+        let b = vu.by_ref().take(PEAK_DECAY_FRAMES as usize).last().unwrap();
+        assert_eq!(b[LED_STRING_SIZE - 1], RGB8::default());
+        assert_eq!(b[LED_STRING_SIZE - 2], colour);
+    }
+}
@@ -0,0 +1,61 @@
+//! Audio levels shared between the microphone task and the animations that react to sound.
+//!
+//! The microphone task publishes the envelope of the audio signal here and animations read it
+//! when they draw a frame. Only the latest value matters, so a single atomic is all we need.
+//! The levels of the bass, middle and treble (see [fft](crate::fft)) are kept together so they
+//! always match.
+
+use crate::configuration::MIC_FULL_SCALE;
+use crate::fft::BANDS;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+/// The most recent audio envelope, from 0 (silence) to 255 (as loud as it gets)
+static ENVELOPE: AtomicU8 = AtomicU8::new(0);
+
+/// Publish the latest audio envelope
+pub fn set_envelope(level: u8) {
+    ENVELOPE.store(level, Ordering::Relaxed);
+}
+
+/// The latest audio envelope. It stays at zero if there is no microphone.
+pub fn envelope() -> u8 {
+    ENVELOPE.load(Ordering::Relaxed)
+}
+
+/// The envelope of a block of samples, their average size scaled so that [MIC_FULL_SCALE] is 255
+pub fn envelope_of(samples: &[i16]) -> u8 {
+    let sum: u32 = samples.iter().map(|s| s.unsigned_abs() as u32).sum();
+    let average = sum / samples.len().max(1) as u32;
+    (average * 255 / MIC_FULL_SCALE as u32).min(255) as u8
+}
+
+/// The latest levels of the bass, middle and treble, each from 0 to 255
+static SPECTRUM: Mutex<CriticalSectionRawMutex, Cell<[u8; BANDS]>> = Mutex::new(Cell::new([0; BANDS]));
+
+/// Publish the latest levels of the bass, middle and treble
+pub fn set_spectrum(levels: [u8; BANDS]) {
+    SPECTRUM.lock(|s| s.set(levels));
+}
+
+/// The latest levels of the bass, middle and treble. They stay at zero if there is no microphone.
+pub fn spectrum() -> [u8; BANDS] {
+    SPECTRUM.lock(|s| s.get())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_the_envelope_follows_the_level() {
+        assert_eq!(envelope_of(&[]), 0);
+        assert_eq!(envelope_of(&[0; 16]), 0);
+        let full = MIC_FULL_SCALE as i16;
+        assert_eq!(envelope_of(&[full, -full]), 255);
+        assert_eq!(envelope_of(&[i16::MIN, i16::MAX]), 255);
+        assert_eq!(envelope_of(&[full / 2, -full / 2]), 127);
+    }
+}
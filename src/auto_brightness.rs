@@ -0,0 +1,76 @@
+//! Ambient light driven auto-brightness.
+//!
+//! The light level is split into the bands in [AUTO_BRIGHTNESS_BANDS], each with its own
+//! brightness, so the star is at full brightness in the sun and dim on a dark dancefloor. The
+//! light has to get [AUTO_BRIGHTNESS_HYSTERESIS] past the edge of a band before we move to the
+//! next one, so a light level that hovers around an edge at dusk doesn't make the display flicker.
+
+use crate::configuration::{AUTO_BRIGHTNESS_BANDS, AUTO_BRIGHTNESS_HYSTERESIS};
+
+/// Picks a display brightness for the ambient light level
+#[derive(Clone, Copy, Default)]
+pub struct AutoBrightness {
+    /// Index into [AUTO_BRIGHTNESS_BANDS] of the current band, None until the first reading
+    band: Option<usize>,
+}
+
+impl AutoBrightness {
+    pub const fn new() -> Self {
+        Self { band: None }
+    }
+
+    /// Take a new light reading
+    ///
+    /// # Parameters
+    /// * `light` - The ambient light level, where larger is brighter
+    ///
+    /// # Returns
+    /// The new brightness if it has changed
+    pub fn update(&mut self, light: u16) -> Option<u8> {
+        let band = match self.band {
+            None => band_for(light),
+            Some(band) => {
+                let up = band_for(light.saturating_sub(AUTO_BRIGHTNESS_HYSTERESIS));
+                let down = band_for(light.saturating_add(AUTO_BRIGHTNESS_HYSTERESIS));
+                if up > band {
+                    up
+                } else if down < band {
+                    down
+                } else {
+                    return None;
+                }
+            }
+        };
+        self.band = Some(band);
+        Some(AUTO_BRIGHTNESS_BANDS[band].1)
+    }
+}
+
+/// The band the light level falls in
+fn band_for(light: u16) -> usize {
+    AUTO_BRIGHTNESS_BANDS
+        .iter()
+        .rposition(|&(threshold, _)| light >= threshold)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_follows_the_light_without_flickering() {
+        let mut auto = AutoBrightness::new();
+        let (edge, bright) = AUTO_BRIGHTNESS_BANDS[1];
+        let dim = AUTO_BRIGHTNESS_BANDS[0].1;
+        assert_eq!(auto.update(0), Some(dim));
+        // Hovering around the edge of the band changes nothing
+        assert_eq!(auto.update(edge), None);
+        assert_eq!(auto.update(edge + AUTO_BRIGHTNESS_HYSTERESIS - 1), None);
+        assert_eq!(auto.update(edge + AUTO_BRIGHTNESS_HYSTERESIS), Some(bright));
+        assert_eq!(auto.update(edge - 1), None);
+        assert_eq!(auto.update(edge - AUTO_BRIGHTNESS_HYSTERESIS - 1), Some(dim));
+        // Full sun
+        assert_eq!(auto.update(u16::MAX), Some(255));
+    }
+}
@@ -0,0 +1,152 @@
+//! Backpressure between the BLE side and the display task.
+//!
+//! Souls advertise far more often than the display needs to hear about them, so when the
+//! display channel backs up, we hold on to the latest sighting of each soul instead of dropping
+//! it. A newer sighting of the same soul simply replaces the pending one. Anything that still
+//! has to be dropped is counted so it shows up in the display status.
+
+use crate::payload::Sighting;
+use crate::telemetry::{Counter, increment};
+use core::sync::atomic::{AtomicU32, Ordering};
+use defmt::Format;
+use heapless::index_map::FnvIndexMap;
+
+/// The kinds of messages that can be dropped or coalesced
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum DropKind {
+    /// Raw advertisement reports dropped because the parser fell behind
+    RawReport,
+    /// Presence updates replaced by a newer one for the same soul
+    PresenceCoalesced,
+    /// Presence updates dropped because too many souls were waiting
+    PresenceDropped,
+    /// Animations dropped from a full animation queue
+    Animation,
+}
+
+const DROP_KINDS: usize = 4;
+
+static DROPS: [AtomicU32; DROP_KINDS] = [const { AtomicU32::new(0) }; DROP_KINDS];
+
+/// Count a dropped or coalesced message. They all add up to the [Counter::QueueDrops] telemetry.
+pub fn record_drop(kind: DropKind) {
+    DROPS[kind as usize].fetch_add(1, Ordering::Relaxed);
+    increment(Counter::QueueDrops);
+}
+
+/// The number of messages dropped or coalesced since start-up
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Format)]
+pub struct DropCounts {
+    pub raw_reports: u32,
+    pub presence_coalesced: u32,
+    pub presence_dropped: u32,
+    pub animations: u32,
+}
+
+/// Snapshot of the drop counters
+pub fn drop_counts() -> DropCounts {
+    let count = |kind: DropKind| DROPS[kind as usize].load(Ordering::Relaxed);
+    DropCounts {
+        raw_reports: count(DropKind::RawReport),
+        presence_coalesced: count(DropKind::PresenceCoalesced),
+        presence_dropped: count(DropKind::PresenceDropped),
+        animations: count(DropKind::Animation),
+    }
+}
+
+/// The latest sighting of each soul that is waiting for room in the display channel. N must be
+/// a power of two.
+pub struct PendingSightings<const N: usize> {
+    pending: FnvIndexMap<u32, Sighting, N>,
+}
+
+impl<const N: usize> Default for PendingSightings<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PendingSightings<N> {
+    pub fn new() -> Self {
+        Self {
+            pending: FnvIndexMap::new(),
+        }
+    }
+
+    /// Hold on to a sighting until there is room for it, replacing any older one of the same
+    /// soul. Replaced and dropped sightings are counted.
+    pub fn hold(&mut self, sighting: Sighting) {
+        match self.pending.insert(sighting.key, sighting) {
+            Ok(Some(_)) => record_drop(DropKind::PresenceCoalesced),
+            Ok(None) => (),
+            Err(_) => record_drop(DropKind::PresenceDropped),
+        }
+    }
+
+    /// True if we are holding a sighting of this soul
+    pub fn contains(&self, key: u32) -> bool {
+        self.pending.contains_key(&key)
+    }
+
+    /// The next sighting to send, which stays pending until [PendingSightings::sent] is called
+    pub fn peek(&self) -> Option<Sighting> {
+        self.pending.values().next().copied()
+    }
+
+    /// Forget a sighting once it has been sent
+    pub fn sent(&mut self, sighting: &Sighting) {
+        self.pending.remove(&sighting.key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use embassy_time::Instant;
+    use smart_leds::RGB8;
+
+    fn sighting(key: u32, rssi: i8) -> Sighting {
+        Sighting {
+            key,
+            colour: RGB8::new(1, 2, 3),
+            rssi,
+            tx_power: 0,
+            last_seen: Instant::from_secs(0),
+            favourite: false,
+            emergency: false,
+            wave: false,
+            role: Default::default(),
+            lead: None,
+            command: None,
+            report: None,
+            reception: Default::default(),
+            pixel: None,
+        }
+    }
+
+    #[test]
+    pub fn if_it_coalesces_each_soul() {
+        let before = drop_counts();
+        let mut pending: PendingSightings<2> = PendingSightings::new();
+        pending.hold(sighting(1, -50));
+        pending.hold(sighting(1, -40));
+        pending.hold(sighting(2, -60));
+        pending.hold(sighting(3, -70));
+        assert_eq!(pending.len(), 2);
+        let first = pending.peek().unwrap();
+        assert_eq!((first.key, first.rssi), (1, -40));
+        pending.sent(&first);
+        assert_eq!(pending.peek().unwrap().key, 2);
+        let after = drop_counts();
+        assert!(after.presence_coalesced > before.presence_coalesced);
+        assert!(after.presence_dropped > before.presence_dropped);
+    }
+}
@@ -0,0 +1,220 @@
+//! Badges the wearer earns over a night out, e.g. for meeting lots of souls.
+//!
+//! The display task feeds the [BadgeEngine] with what it sees and celebrates each badge as it is
+//! unlocked. The unlocked badges are published here as a bit set so that they can be read over
+//! GATT (see `soul_link.rs`). They are saved with the settings, so a badge once earned stays
+//! earned across restarts until a factory reset.
+
+use crate::configuration::{BADGE_DARK_LEVEL, BADGE_DAWN_LEVEL, BADGE_FRIEND_TIME, BADGE_NIGHT_TIME, BADGE_SOULS_MET};
+use crate::intervals::flush_age;
+use core::sync::atomic::{AtomicU8, Ordering};
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::index_map::FnvIndexMap;
+use heapless::index_set::FnvIndexSet;
+use smart_leds::RGB8;
+
+/// The most souls we count towards [Badge::SocialButterfly]
+const MAX_SOULS_MET: usize = 32;
+
+/// The most friends we time at once for [Badge::Companion]
+const MAX_FRIENDS: usize = 8;
+
+/// The badges there are to earn
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum Badge {
+    /// Met [BADGE_SOULS_MET] different souls
+    SocialButterfly,
+    /// Stayed near a favourite soul for [BADGE_FRIEND_TIME] seconds
+    Companion,
+    /// Was still going when the sun came up
+    NightOwl,
+}
+
+impl Badge {
+    /// The colour the badge is celebrated in
+    pub fn colour(self) -> RGB8 {
+        match self {
+            Badge::SocialButterfly => RGB8::new(255, 0, 192),
+            Badge::Companion => RGB8::new(255, 192, 0),
+            Badge::NightOwl => RGB8::new(64, 96, 255),
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The unlocked badges, one bit per [Badge]
+static UNLOCKED: AtomicU8 = AtomicU8::new(0);
+
+/// The badges unlocked so far, one bit per [Badge]
+pub fn unlocked_badges() -> u8 {
+    UNLOCKED.load(Ordering::Relaxed)
+}
+
+/// Put back the badges saved before a restart
+///
+/// # Arguments
+/// * `badges` - The saved badges, one bit per [Badge]
+pub fn restore_badges(badges: u8) {
+    UNLOCKED.fetch_or(badges, Ordering::Relaxed);
+}
+
+/// Works out when badges are earned
+#[derive(Default)]
+pub struct BadgeEngine {
+    /// The badges this engine has unlocked
+    unlocked: u8,
+    /// The souls we have met, up to [BADGE_SOULS_MET]
+    met: FnvIndexSet<u32, MAX_SOULS_MET>,
+    /// When each nearby favourite arrived and was last seen
+    friends: FnvIndexMap<u32, (Instant, Instant), MAX_FRIENDS>,
+    /// When it got dark, None while it is light
+    dark_since: Option<Instant>,
+}
+
+impl BadgeEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An engine that carries on from the badges earned before a restart, so they aren't
+    /// celebrated again
+    ///
+    /// # Arguments
+    /// * `badges` - The badges already earned, one bit per [Badge]
+    pub fn with_unlocked(badges: u8) -> Self {
+        Self {
+            unlocked: badges,
+            ..Self::default()
+        }
+    }
+
+    /// A soul we weren't tracking has come into range
+    ///
+    /// # Returns
+    /// The badge that this unlocked, if any
+    pub fn soul_joined(&mut self, key: u32) -> Option<Badge> {
+        if self.met.len() < BADGE_SOULS_MET.min(MAX_SOULS_MET) {
+            // There is room, checked above
+            let _ = self.met.insert(key);
+        }
+        if self.met.len() >= BADGE_SOULS_MET.min(MAX_SOULS_MET) {
+            self.unlock(Badge::SocialButterfly)
+        } else {
+            None
+        }
+    }
+
+    /// A favourite soul has been seen. They count as having stayed near as long as they don't
+    /// drop out of the tracker.
+    ///
+    /// # Returns
+    /// The badge that this unlocked, if any
+    pub fn friend_seen(&mut self, key: u32, now: Instant) -> Option<Badge> {
+        let gone = flush_age();
+        let (arrived, last_seen) = match self.friends.get(&key) {
+            Some(&(arrived, last_seen)) if now - last_seen <= gone => (arrived, now),
+            _ => (now, now),
+        };
+        // Forget friends who have left to make room for this one
+        if self.friends.is_full() && !self.friends.contains_key(&key) {
+            self.friends.retain(|_, &mut (_, seen)| now - seen <= gone);
+        }
+        let _ = self.friends.insert(key, (arrived, last_seen));
+        if now - arrived >= Duration::from_secs(BADGE_FRIEND_TIME) {
+            self.unlock(Badge::Companion)
+        } else {
+            None
+        }
+    }
+
+    /// A new ambient light reading. Dawn after a long dark night earns a badge.
+    ///
+    /// # Returns
+    /// The badge that this unlocked, if any
+    pub fn ambient_light(&mut self, light: u16, now: Instant) -> Option<Badge> {
+        match self.dark_since {
+            None if light < BADGE_DARK_LEVEL => self.dark_since = Some(now),
+            Some(dark) if light >= BADGE_DAWN_LEVEL => {
+                self.dark_since = None;
+                if now - dark >= Duration::from_secs(BADGE_NIGHT_TIME) {
+                    return self.unlock(Badge::NightOwl);
+                }
+            }
+            _ => (),
+        }
+        None
+    }
+
+    fn unlock(&mut self, badge: Badge) -> Option<Badge> {
+        if self.unlocked & badge.bit() != 0 {
+            return None;
+        }
+        self.unlocked |= badge.bit();
+        UNLOCKED.fetch_or(badge.bit(), Ordering::Relaxed);
+        Some(badge)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::TRACKER_FLUSH_AGE;
+
+    #[test]
+    pub fn if_meeting_souls_earns_a_badge() {
+        let mut engine = BadgeEngine::new();
+        for key in 0..BADGE_SOULS_MET as u32 - 1 {
+            assert_eq!(engine.soul_joined(key), None);
+            // Meeting the same soul again doesn't count
+            assert_eq!(engine.soul_joined(key), None);
+        }
+        assert_eq!(engine.soul_joined(1000), Some(Badge::SocialButterfly));
+        assert_eq!(engine.soul_joined(1001), None);
+        assert!(unlocked_badges() & Badge::SocialButterfly.bit() != 0);
+    }
+
+    #[test]
+    pub fn if_a_friend_has_to_stay() {
+        let mut engine = BadgeEngine::new();
+        let start = Instant::from_secs(100);
+        let step = Duration::from_secs(TRACKER_FLUSH_AGE / 2);
+        let mut now = start;
+        assert_eq!(engine.friend_seen(1, now), None);
+        // Leaving for a while starts the clock again
+        now += Duration::from_secs(TRACKER_FLUSH_AGE * 2);
+        assert_eq!(engine.friend_seen(1, now), None);
+        let arrived = now;
+        while now - arrived < Duration::from_secs(BADGE_FRIEND_TIME) {
+            assert_eq!(engine.friend_seen(1, now), None);
+            now += step;
+        }
+        assert_eq!(engine.friend_seen(1, now), Some(Badge::Companion));
+    }
+
+    #[test]
+    pub fn if_dawn_follows_a_long_night() {
+        let mut engine = BadgeEngine::new();
+        let dusk = Instant::from_secs(1000);
+        assert_eq!(engine.ambient_light(BADGE_DARK_LEVEL - 1, dusk), None);
+        // A short dark spell is just a tent
+        let early = dusk + Duration::from_secs(60);
+        assert_eq!(engine.ambient_light(BADGE_DAWN_LEVEL, early), None);
+        assert_eq!(engine.ambient_light(BADGE_DARK_LEVEL - 1, early), None);
+        let dawn = early + Duration::from_secs(BADGE_NIGHT_TIME);
+        assert_eq!(engine.ambient_light(BADGE_DAWN_LEVEL - 1, dawn), None);
+        assert_eq!(engine.ambient_light(BADGE_DAWN_LEVEL, dawn), Some(Badge::NightOwl));
+    }
+
+    #[test]
+    pub fn if_saved_badges_are_not_earned_again() {
+        let mut engine = BadgeEngine::with_unlocked(Badge::SocialButterfly.bit());
+        for key in 0..BADGE_SOULS_MET as u32 {
+            assert_eq!(engine.soul_joined(key), None);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Battery level estimation.
+//!
+//! A single LiPo cell is the usual power source. Its voltage is a poor but serviceable guide
+//! to what is left: it sags quickly at the top, sits on a long plateau around 3.7V and falls off
+//! a cliff at the end. We interpolate along a rough discharge curve to get a percentage that
+//! moves at about the right rate. The latest level is kept here so the beacon and the Battery
+//! Service can report it.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Cell voltage (millivolts) at which each charge level is reached, emptiest first
+const DISCHARGE_CURVE: &[(u16, u8)] = &[
+    (3300, 0),
+    (3500, 5),
+    (3600, 10),
+    (3700, 30),
+    (3750, 45),
+    (3800, 55),
+    (3900, 70),
+    (4000, 80),
+    (4100, 90),
+    (4200, 100),
+];
+
+/// Marks a battery level we haven't measured yet
+const UNKNOWN: u8 = 0xFF;
+
+/// The most recent battery level in percent
+static BATTERY_LEVEL: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// The charge left in percent for a cell voltage
+///
+/// # Parameters
+/// * `millivolts` - The cell voltage
+pub fn battery_percent(millivolts: u16) -> u8 {
+    let mut below = DISCHARGE_CURVE[0];
+    for &(mv, percent) in DISCHARGE_CURVE {
+        if millivolts < mv {
+            let (low_mv, low_percent) = below;
+            if millivolts <= low_mv {
+                return low_percent;
+            }
+            let span = (percent - low_percent) as u32 * (millivolts - low_mv) as u32;
+            return low_percent + (span / (mv - low_mv) as u32) as u8;
+        }
+        below = (mv, percent);
+    }
+    100
+}
+
+/// Record the latest battery level in percent
+pub fn set_battery_level(percent: u8) {
+    BATTERY_LEVEL.store(percent.min(100), Ordering::Relaxed);
+}
+
+/// The latest battery level in percent, None if it hasn't been measured
+pub fn battery_level() -> Option<u8> {
+    match BATTERY_LEVEL.load(Ordering::Relaxed) {
+        UNKNOWN => None,
+        percent => Some(percent),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_follows_the_curve() {
+        assert_eq!(battery_percent(0), 0);
+        assert_eq!(battery_percent(3300), 0);
+        assert_eq!(battery_percent(3650), 20);
+        assert_eq!(battery_percent(3700), 30);
+        assert_eq!(battery_percent(4200), 100);
+        assert_eq!(battery_percent(4350), 100);
+        let mut last = 0;
+        for mv in (3000..4400).step_by(10) {
+            let percent = battery_percent(mv);
+            assert!(percent >= last);
+            last = percent;
+        }
+    }
+}
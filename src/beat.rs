@@ -0,0 +1,157 @@
+//! Finding the beat in the music the microphone hears.
+//!
+//! The microphone task (see `microphone.rs`) hands each block of samples to a [BeatDetector].
+//! The block is split into three bands, bass, middle and treble, with a pair of one pole filters,
+//! and the energy in each band is compared with the block before. A beat is a sudden rise in
+//! energy (the spectral flux) well above the running average of the rises, which is mostly a
+//! kick drum, so the bass counts double. After a beat nothing else counts until a beat at
+//! [MAX_BPM] would be due, so the off-beats don't get in.
+//!
+//! Every beat is published in [audio](crate::audio) for the animations that flash on the beat.
+//! The gaps between beats are averaged like taps on the tempo button (see [tempo](crate::tempo))
+//! and, once the beat is steady, they set the tempo so [PulseAnimation] and [ChaseAnimation]
+//! lock to the music without anybody tapping along.
+//!
+//! [PulseAnimation]: crate::animations::PulseAnimation
+//! [ChaseAnimation]: crate::animations::ChaseAnimation
+
+use crate::configuration::{BEAT_FLOOR, BEAT_SENSITIVITY, MAX_BPM};
+use crate::tempo::TapTempo;
+use embassy_time::{Duration, Instant};
+
+/// The bass filter's shift. At 16kHz it lets through about 160Hz and below.
+const BASS_SHIFT: u32 = 4;
+
+/// The treble filter's shift. At 16kHz everything above about 2kHz is treble.
+const TREBLE_SHIFT: u32 = 1;
+
+/// How many blocks the average flux is taken over, roughly
+const FLUX_BLOCKS: u32 = 16;
+
+/// A beat heard in the music
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Beat {
+    /// When the beat was heard
+    pub at: Instant,
+    /// The tempo in beats per minute, once the beat has been steady for a few beats
+    pub bpm: Option<u16>,
+}
+
+/// Finds beats in blocks of microphone samples
+#[derive(Default)]
+pub struct BeatDetector {
+    /// The bass filter's output, scaled up by 256
+    bass: i32,
+    /// The filter below the treble, scaled up by 256
+    low: i32,
+    /// The energy in the bass, middle and treble in the previous block
+    energy: [u32; 3],
+    /// A running sum of the flux, decaying by one [FLUX_BLOCKS]th each block
+    average: u32,
+    /// When the last beat was heard
+    last: Option<Instant>,
+    /// Works the tempo out from the gaps between beats
+    tempo: TapTempo,
+}
+
+impl BeatDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a block of samples
+    ///
+    /// # Parameters
+    /// * `samples` - The samples, at 16kHz
+    /// * `now` - The time of the end of the block
+    ///
+    /// # Returns
+    /// The beat, if one starts in this block
+    pub fn update(&mut self, samples: &[i16], now: Instant) -> Option<Beat> {
+        let energy = self.energy(samples);
+        let flux: u32 = energy
+            .iter()
+            .zip(self.energy)
+            .zip([2, 1, 1])
+            .map(|((&after, before), weight)| after.saturating_sub(before) * weight)
+            .sum();
+        self.energy = energy;
+        let average = self.average / FLUX_BLOCKS;
+        self.average = self.average - average + flux;
+        let gap = Duration::from_micros(60_000_000 / MAX_BPM as u64);
+        if flux < BEAT_FLOOR
+            || flux <= average * BEAT_SENSITIVITY / 100
+            || self.last.is_some_and(|last| now.saturating_duration_since(last) < gap)
+        {
+            return None;
+        }
+        self.last = Some(now);
+        Some(Beat {
+            at: now,
+            bpm: self.tempo.tap(now),
+        })
+    }
+
+    /// The average size of the samples in the bass, middle and treble
+    fn energy(&mut self, samples: &[i16]) -> [u32; 3] {
+        let mut sums = [0u32; 3];
+        for &sample in samples {
+            let sample = (sample as i32) << 8;
+            self.bass += (sample - self.bass) >> BASS_SHIFT;
+            self.low += (sample - self.low) >> TREBLE_SHIFT;
+            let bands = [self.bass, self.low - self.bass, sample - self.low];
+            for (sum, band) in sums.iter_mut().zip(bands) {
+                *sum += band.unsigned_abs() >> 8;
+            }
+        }
+        sums.map(|sum| sum / samples.len().max(1) as u32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Samples in each block, 10ms at 16kHz
+    const BLOCK: usize = 160;
+
+    /// Play `seconds` of a kick drum every `every` blocks to a new detector
+    ///
+    /// # Returns
+    /// The beats it heard
+    fn listen(every: usize, seconds: usize) -> heapless::Vec<Beat, 64> {
+        let mut detector = BeatDetector::new();
+        let mut beats = heapless::Vec::new();
+        for block in 0..seconds * 100 {
+            let mut samples = [0i16; BLOCK];
+            // A tenth of a second of a 62.5Hz square wave
+            if block % every < 10 {
+                for (i, sample) in samples.iter_mut().enumerate() {
+                    *sample = if (block * BLOCK + i) % 256 < 128 { 8000 } else { -8000 };
+                }
+            }
+            let at = Instant::from_secs(10) + Duration::from_millis(10 * (block as u64 + 1));
+            if let Some(beat) = detector.update(&samples, at) {
+                beats.push(beat).unwrap();
+            }
+        }
+        beats
+    }
+
+    #[test]
+    pub fn if_it_hears_the_beat() {
+        // A kick every half second is 120 BPM
+        let beats = listen(50, 4);
+        assert_eq!(beats.len(), 8);
+        assert_eq!(beats[1].at - beats[0].at, Duration::from_millis(500));
+        assert_eq!(beats[1].bpm, None);
+        assert!(beats[2..].iter().all(|beat| beat.bpm == Some(120)));
+    }
+
+    #[test]
+    pub fn if_silence_has_no_beat() {
+        let mut detector = BeatDetector::new();
+        assert_eq!(detector.update(&[0; BLOCK], Instant::from_secs(1)), None);
+        assert_eq!(detector.update(&[3; BLOCK], Instant::from_secs(2)), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Brownout protection.
+//!
+//! A tired cell sags a long way under a full white torch, and if it sags far enough the
+//! regulator drops out and the chip reboots in the middle of the song. We would much rather be a
+//! bit dimmer. The battery voltage is watched every
+//! [BROWNOUT_INTERVAL](crate::configuration::BROWNOUT_INTERVAL) milliseconds and, while
+//! it is below [BROWNOUT_MILLIVOLTS], the brightness ceiling is stepped down by [BROWNOUT_STEP]
+//! at a time until the voltage recovers or we reach [BROWNOUT_MIN_BRIGHTNESS]. Once the voltage
+//! has been [BROWNOUT_HYSTERESIS] clear of the threshold for [BROWNOUT_RECOVERY] seconds, the
+//! ceiling is stepped back up again, one step at a time.
+//!
+//! The ceiling applies to everything sent to the strip, the torch included.
+
+use crate::configuration::{
+    BROWNOUT_HYSTERESIS, BROWNOUT_MILLIVOLTS, BROWNOUT_MIN_BRIGHTNESS, BROWNOUT_RECOVERY, BROWNOUT_STEP,
+};
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_time::{Duration, Instant};
+
+/// The highest brightness the battery can currently support
+static BRIGHTNESS_CEILING: AtomicU8 = AtomicU8::new(u8::MAX);
+
+/// The highest brightness the battery can currently support
+pub fn brightness_ceiling() -> u8 {
+    BRIGHTNESS_CEILING.load(Ordering::Relaxed)
+}
+
+/// Record a new brightness ceiling
+pub fn set_brightness_ceiling(ceiling: u8) {
+    BRIGHTNESS_CEILING.store(ceiling, Ordering::Relaxed);
+}
+
+/// Steps the brightness ceiling down while the battery sags, and back up once it has recovered
+pub struct BrownoutGuard {
+    ceiling: u8,
+    /// When the voltage last came clear of the threshold, None while it is sagging
+    clear_since: Option<Instant>,
+}
+
+impl Default for BrownoutGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BrownoutGuard {
+    pub const fn new() -> Self {
+        Self {
+            ceiling: u8::MAX,
+            clear_since: None,
+        }
+    }
+
+    /// Take a new battery reading
+    ///
+    /// # Parameters
+    /// * `millivolts` - The cell voltage
+    /// * `now` - The time of the reading
+    ///
+    /// # Returns
+    /// The new brightness ceiling if it has changed
+    pub fn update(&mut self, millivolts: u16, now: Instant) -> Option<u8> {
+        let ceiling = if millivolts < BROWNOUT_MILLIVOLTS {
+            self.clear_since = None;
+            self.ceiling.saturating_sub(BROWNOUT_STEP).max(BROWNOUT_MIN_BRIGHTNESS)
+        } else if millivolts >= BROWNOUT_MILLIVOLTS + BROWNOUT_HYSTERESIS {
+            let since = *self.clear_since.get_or_insert(now);
+            if now.saturating_duration_since(since) < Duration::from_secs(BROWNOUT_RECOVERY) {
+                return None;
+            }
+            // Give the next step up as long to prove itself
+            self.clear_since = Some(now);
+            self.ceiling.saturating_add(BROWNOUT_STEP)
+        } else {
+            self.clear_since = None;
+            return None;
+        };
+        if ceiling == self.ceiling {
+            return None;
+        }
+        self.ceiling = ceiling;
+        Some(ceiling)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_steps_down_and_recovers() {
+        let mut guard = BrownoutGuard::new();
+        let at = |s| Instant::from_secs(s);
+        let sagging = BROWNOUT_MILLIVOLTS - 1;
+        let clear = BROWNOUT_MILLIVOLTS + BROWNOUT_HYSTERESIS;
+        assert_eq!(guard.update(clear, at(0)), None);
+        assert_eq!(guard.update(sagging, at(1)), Some(u8::MAX - BROWNOUT_STEP));
+        assert_eq!(guard.update(sagging, at(2)), Some(u8::MAX - 2 * BROWNOUT_STEP));
+        // Never goes below the floor
+        for s in 3..20 {
+            guard.update(sagging, at(s));
+        }
+        assert_eq!(guard.update(sagging, at(20)), None);
+        assert_eq!(guard.ceiling, BROWNOUT_MIN_BRIGHTNESS);
+        // Just above the threshold isn't enough to recover
+        assert_eq!(guard.update(BROWNOUT_MILLIVOLTS, at(100)), None);
+        assert_eq!(guard.update(clear, at(101)), None);
+        assert_eq!(guard.update(clear, at(100 + BROWNOUT_RECOVERY)), None);
+        assert_eq!(guard.update(clear, at(101 + BROWNOUT_RECOVERY)), Some(BROWNOUT_MIN_BRIGHTNESS + BROWNOUT_STEP));
+        assert_eq!(guard.update(clear, at(102 + BROWNOUT_RECOVERY)), None);
+        // Sagging again interrupts the recovery
+        assert_eq!(guard.update(sagging, at(103 + BROWNOUT_RECOVERY)), Some(BROWNOUT_MIN_BRIGHTNESS));
+    }
+}
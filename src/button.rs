@@ -1,8 +1,106 @@
-use embassy_time::{Duration, Timer};
+//! Buttons wired straight to GPIO pins, and the channel that button presses from every backend
+//! are delivered on.
+
+use crate::display_task::DisplayChannelSender;
+use crate::display_task::DisplayState::FactoryReset;
+use crate::settings_store::erase_settings;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use esp_hal::gpio::Input;
+use soulstar::buttons::{Button, ButtonPress, ButtonPull, GpioButton, is_held, set_held};
+use soulstar::configuration::MAX_GPIO_BUTTONS;
+use soulstar::factory_reset::{ResetChord, ResetEvent};
+use soulstar::log;
+
+/// How often the factory reset chord is checked (milliseconds)
+const RESET_POLL_INTERVAL: u64 = 100;
+
+/// Button presses from all the buttons, wherever they are wired
+pub static BUTTON_PRESSES: Channel<CriticalSectionRawMutex, ButtonPress, 8> = Channel::new();
+
+/// Whether a button is down right now, e.g. to see if it was held at power up
+///
+/// # Parameters
+/// * `input` - The pin the button is on
+/// * `pull` - Which way the pin is pulled while the button is up
+pub fn is_down(input: &Input<'_>, pull: ButtonPull) -> bool {
+    match pull {
+        ButtonPull::Up => input.is_low(),
+        ButtonPull::Down => input.is_high(),
+    }
+}
 
-/// Simple debounced button press detection
-pub async fn wait_for_press(button: &mut Input<'_>) {
-    button.wait_for_rising_edge().await;
+/// Simple debounced button press detection. Completes when the button is released.
+///
+/// # Parameters
+/// * `input` - The pin the button is on
+/// * `wiring` - How the button is wired, and what it is so we can record when it is being held
+///
+/// # Returns
+/// How long the button was held down for, so that long presses can be told apart
+pub async fn wait_for_press(input: &mut Input<'_>, wiring: GpioButton) -> Duration {
+    // A button that is already down counts as pressed from now
+    match wiring.pull {
+        ButtonPull::Up => input.wait_for_low().await,
+        ButtonPull::Down => input.wait_for_high().await,
+    }
+    let pressed = Instant::now();
+    set_held(wiring.button, true);
     Timer::after(Duration::from_millis(100)).await; // debounce
+    match wiring.pull {
+        ButtonPull::Up => input.wait_for_high().await,
+        ButtonPull::Down => input.wait_for_low().await,
+    }
+    set_held(wiring.button, false);
+    Timer::after(Duration::from_millis(100)).await; // debounce
+    pressed.elapsed()
+}
+
+/// Watch a button on a GPIO pin and deliver its presses to [BUTTON_PRESSES]
+///
+/// # Parameters
+/// * `input` - The pin the button is on
+/// * `wiring` - How the button is wired and what it does
+#[embassy_executor::task(pool_size = MAX_GPIO_BUTTONS)]
+pub async fn gpio_button_task(mut input: Input<'static>, wiring: GpioButton) {
+    let button = wiring.button;
+    loop {
+        let held = wait_for_press(&mut input, wiring).await;
+        BUTTON_PRESSES.send(ButtonPress { button, held }).await;
+    }
+}
+
+/// Watch for both brightness buttons being held and restart the star with its defaults if they
+/// are held for long enough, see [factory_reset](soulstar::factory_reset). The display counts
+/// down in red meanwhile.
+///
+/// # Parameters
+/// * `display` - Display channel sender for the countdown
+#[embassy_executor::task]
+pub async fn factory_reset_task(display: DisplayChannelSender) {
+    let mut chord = ResetChord::new();
+    let mut ticker = Ticker::every(Duration::from_millis(RESET_POLL_INTERVAL));
+    loop {
+        ticker.next().await;
+        let held = is_held(Button::Increase) && is_held(Button::Decrease);
+        match chord.update(held, Instant::now()) {
+            Some(ResetEvent::Armed(left)) => {
+                log!(Main, warn, "MAIN: Factory reset in {}ms unless a button is let go", left.as_millis());
+                display.send(FactoryReset(Some(left)).into()).await;
+            }
+            Some(ResetEvent::Cancelled) => {
+                log!(Main, info, "MAIN: Factory reset cancelled");
+                display.send(FactoryReset(None).into()).await;
+            }
+            Some(ResetEvent::Reset) => {
+                log!(Main, warn, "MAIN: Factory reset");
+                erase_settings().await;
+                // Give the log a moment to get out
+                Timer::after(Duration::from_millis(100)).await;
+                esp_hal::system::software_reset();
+            }
+            None => (),
+        }
+    }
 }
@@ -0,0 +1,214 @@
+//! Button events, whichever way the buttons are wired up.
+//!
+//! Buttons can be on GPIO pins or, for builds that have run out of pins, on an I2C GPIO expander.
+//! Either way, each press is turned into a [ButtonPress] when the button is released, so that the
+//! main loop can tell long presses from short ones. The backends also keep track of which buttons
+//! are being held down right now, which is what chords are made of.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+/// What a button does
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+#[repr(u8)]
+pub enum Button {
+    /// Torch on, brighter and off
+    Torch,
+    /// Increase the brightness
+    Increase,
+    /// Decrease the brightness
+    Decrease,
+    /// Cycle the default animation or torch colour
+    Mode,
+    /// Toggle night mode. Only fitted to builds with more than four buttons.
+    NightMode,
+    /// Call for help. Only fitted to builds with more than four buttons.
+    Sos,
+    /// Tap in time with the music to set the tempo, see [tempo](crate::tempo). Only fitted to
+    /// builds with more than four buttons.
+    Tempo,
+}
+
+impl Button {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A completed press of a button
+#[derive(Clone, Copy, Debug, Format)]
+pub struct ButtonPress {
+    pub button: Button,
+    /// How long the button was held down for
+    pub held: Duration,
+}
+
+/// The I2C GPIO expanders we can read buttons from
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum Expander {
+    /// 8 quasi-bidirectional lines
+    Pcf8574,
+    /// 16 lines, of which we use port A
+    Mcp23017,
+}
+
+/// Which way the pin of a button wired straight to a GPIO is pulled while the button is up
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum ButtonPull {
+    /// Pulled up, with the button shorting the pin to ground
+    Up,
+    /// Pulled down, with the button connecting the pin to 3.3V
+    Down,
+}
+
+/// A button wired straight to a GPIO pin
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub struct GpioButton {
+    /// The GPIO number
+    pub pin: u8,
+    /// Which way the pin is pulled while the button is up, and so which level means pressed
+    pub pull: ButtonPull,
+    /// What the button does
+    pub button: Button,
+}
+
+/// The GPIOs the firmware already drives (see `main.rs`), or that the board needs for USB. Keep in
+/// step with `RESERVED_GPIOS` in `build.rs`, which checks the LED power pin.
+const RESERVED_GPIOS: &[u8] = &[0, 1, 5, 6, 7, 10, 12, 13, 18, 19, 20, 22, 23];
+
+/// GPIO24 to GPIO30 are wired to the flash, and there is no GPIO past that
+const FIRST_FLASH_GPIO: u8 = 24;
+
+/// Check that every button is on a pin of its own that nothing else uses, so that it is safe to
+/// take the pins over at start-up. Meant for a const assertion on the board configuration.
+///
+/// # Arguments
+/// * `buttons` - The buttons wired straight to GPIO pins
+pub const fn valid_wiring(buttons: &[GpioButton]) -> bool {
+    let mut i = 0;
+    while i < buttons.len() {
+        let pin = buttons[i].pin;
+        if pin >= FIRST_FLASH_GPIO {
+            return false;
+        }
+        let mut r = 0;
+        while r < RESERVED_GPIOS.len() {
+            if RESERVED_GPIOS[r] == pin {
+                return false;
+            }
+            r += 1;
+        }
+        let mut j = i + 1;
+        while j < buttons.len() {
+            if buttons[j].pin == pin {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The buttons being held down right now, one bit per [Button]
+static HELD: AtomicU8 = AtomicU8::new(0);
+
+/// Record a button going down or coming back up
+pub fn set_held(button: Button, held: bool) {
+    if held {
+        HELD.fetch_or(button.bit(), Ordering::Relaxed);
+    } else {
+        HELD.fetch_and(!button.bit(), Ordering::Relaxed);
+    }
+}
+
+/// True if the button is being held down. Used to detect chords, where one button is held
+/// while another is pressed.
+pub fn is_held(button: Button) -> bool {
+    HELD.load(Ordering::Relaxed) & button.bit() != 0
+}
+
+/// Turns regular samples of a set of input lines into button presses, for backends that have to
+/// poll their buttons. Presses shorter than the debounce time are ignored.
+pub struct ButtonSampler<const N: usize> {
+    /// The button on each input line, if any
+    lines: [Option<Button>; N],
+    /// When the button on each line went down, None if it is up
+    pressed_at: [Option<Instant>; N],
+    debounce: Duration,
+}
+
+impl<const N: usize> ButtonSampler<N> {
+    /// Create a sampler
+    ///
+    /// # Parameters
+    /// * `lines` - The button on each input line, starting with bit 0
+    /// * `debounce` - The shortest press that counts
+    pub fn new(lines: [Option<Button>; N], debounce: Duration) -> Self {
+        Self {
+            lines,
+            pressed_at: [None; N],
+            debounce,
+        }
+    }
+
+    /// Take a sample of the input lines
+    ///
+    /// # Parameters
+    /// * `pressed` - A bit for each line, set if the button on it is down
+    /// * `now` - When the sample was taken
+    ///
+    /// # Returns
+    /// The buttons that have been released since the last sample
+    pub fn sample(&mut self, pressed: u16, now: Instant) -> Vec<ButtonPress, N> {
+        let mut presses = Vec::new();
+        for (line, (button, pressed_at)) in self.lines.iter().zip(self.pressed_at.iter_mut()).enumerate() {
+            let Some(button) = *button else {
+                continue;
+            };
+            let down = pressed & (1 << line) != 0;
+            match *pressed_at {
+                None if down => {
+                    *pressed_at = Some(now);
+                    set_held(button, true);
+                }
+                Some(at) if !down => {
+                    *pressed_at = None;
+                    set_held(button, false);
+                    let held = now - at;
+                    if held >= self.debounce {
+                        // There is room for a press from every line
+                        let _ = presses.push(ButtonPress { button, held });
+                    }
+                }
+                _ => (),
+            }
+        }
+        presses
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_reports_presses_on_release() {
+        let debounce = Duration::from_millis(50);
+        let mut sampler = ButtonSampler::new([Some(Button::NightMode), None, Some(Button::Sos)], debounce);
+        let start = Instant::from_millis(1000);
+        assert!(sampler.sample(0b101, start).is_empty());
+        assert!(is_held(Button::NightMode) && is_held(Button::Sos));
+        // A bounce on the first line is too short to count
+        let presses = sampler.sample(0b100, start + Duration::from_millis(20));
+        assert!(presses.is_empty());
+        assert!(!is_held(Button::NightMode));
+        let presses = sampler.sample(0b000, start + Duration::from_millis(1500));
+        assert_eq!(presses.len(), 1);
+        assert_eq!(presses[0].button, Button::Sos);
+        assert_eq!(presses[0].held, Duration::from_millis(1500));
+        assert!(!is_held(Button::Sos));
+    }
+}
@@ -0,0 +1,51 @@
+//! The shared clock, so that souls near each other can animate in step.
+//!
+//! Every beacon carries the sender's shared clock in milliseconds. Whenever we hear a clock that
+//! is ahead of ours we jump forward to it, so a group settles on the clock of whoever has been
+//! running longest without any one soul being in charge. A beacon is a little out of date by the
+//! time it is heard, so the clocks agree to within the beacon refresh interval plus a scan or two.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_time::Instant;
+
+/// A clock has to be this far ahead of ours before we adopt it (ms). Stops us chasing clocks that
+/// are only ahead because of rounding.
+const ADOPT_THRESHOLD: u32 = 20;
+
+/// What we add to our own uptime to get the shared time (ms)
+static OFFSET: AtomicU32 = AtomicU32::new(0);
+
+/// The shared time in milliseconds. It wraps after about 49 days.
+pub fn shared_now() -> u32 {
+    (Instant::now().as_millis() as u32).wrapping_add(OFFSET.load(Ordering::Relaxed))
+}
+
+/// Consider a clock heard in somebody's beacon, adopting it if it is ahead of ours
+///
+/// # Returns
+/// True if we jumped forward
+pub fn observe(remote: u32) -> bool {
+    let ahead = remote.wrapping_sub(shared_now());
+    // Anything more than half way round is behind us
+    if ahead > ADOPT_THRESHOLD && ahead < u32::MAX / 2 {
+        OFFSET.fetch_add(ahead, Ordering::Relaxed);
+        true
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_only_moves_forward() {
+        let now = shared_now();
+        assert!(!observe(now.wrapping_sub(10_000)));
+        assert!(!observe(now + 5));
+        assert!(observe(now + 60_000));
+        assert!(shared_now().wrapping_sub(now) >= 60_000);
+        assert!(!observe(now));
+    }
+}
@@ -1,6 +1,35 @@
+use crate::configuration::{
+    COLOUR_COLLISION_DISTANCE, COLOUR_HUE_NUDGE, LED_STRING_SIZE, PRESENCE_FAR_LOSS, PRESENCE_MIN_BRIGHTNESS,
+    PRESENCE_NEAR_LOSS,
+};
 use core::default::Default;
 use smart_leds::RGB8;
-use crate::utils::clip;
+use smart_leds::hsv::{Hsv, hsv2rgb};
+
+/// Convenience type so we speak the same language when dealing with animations etc.
+pub type LedBuffer = [RGB8; LED_STRING_SIZE];
+
+/// The colours a soul's animations are drawn with
+#[derive(Clone, Copy)]
+pub struct Theme {
+    /// The soul's own colour
+    pub colour: RGB8,
+    /// Bands of colour for the stripes animation. May be empty.
+    pub stripes: &'static [[u8; 3]],
+    /// The name we advertise, blinked out by the Morse animation
+    pub name: &'static str,
+    /// Our soul ID, which picks our part in phase-locked animations. It comes from the factory MAC
+    /// address rather than the random one we advertise from, so it doesn't change on a restart.
+    pub id: u32,
+}
+
+/// Colours with all channels above this are considered to be white-ish
+const WHITE_THRESHOLD: u8 = 128;
+
+/// True if the colour is close enough to white to ruin everybody's night vision
+pub fn is_whiteish(colour: RGB8) -> bool {
+    colour.r.min(colour.g).min(colour.b) > WHITE_THRESHOLD
+}
 
 #[allow(unused)]
 pub fn set_brightness(brightness: u8, pixel: RGB8) -> RGB8 {
@@ -19,14 +48,123 @@ pub fn set_brightness(brightness: u8, pixel: RGB8) -> RGB8 {
 }
 
 /// Clip to a minimum value
-#[allow(unused)]
 pub fn clip_min(v: i16, min: u8) -> u8 {
     if v < min as i16 { min } else { v as u8 }
 }
 
-#[allow(unused)]
-pub fn adjust_brightness_for_rssi(colour: RGB8, rssi: i8, brightness: u8) -> RGB8 {
-    // Map -100 -> -40 dBm to a scale of 0-128
-    let brightness = ((brightness as i16) * (rssi as i16 + 100) * 3) / 255;
-    set_brightness(clip(brightness), colour)
+/// Dim a soul's colour the further away it is, so nearby friends glow bright. Distant souls never
+/// drop below [PRESENCE_MIN_BRIGHTNESS] so they stay visible.
+///
+/// # Parameters
+/// * `colour` - The soul's colour
+/// * `tx_loss` - Path loss to the soul in dB
+/// * `brightness` - The brightness for a soul right next to us
+pub fn adjust_brightness_for_loss(colour: RGB8, tx_loss: i32, brightness: u8) -> RGB8 {
+    // Map PRESENCE_FAR_LOSS -> PRESENCE_NEAR_LOSS dB to a scale of 0 - brightness
+    let loss = tx_loss.clamp(PRESENCE_NEAR_LOSS, PRESENCE_FAR_LOSS);
+    let scaled = brightness as i32 * (PRESENCE_FAR_LOSS - loss) / (PRESENCE_FAR_LOSS - PRESENCE_NEAR_LOSS);
+    set_brightness(clip_min(scaled as i16, PRESENCE_MIN_BRIGHTNESS.min(brightness)), colour)
+}
+
+/// Colours with a saturation below this have no hue worth turning, so they are dimmed instead
+const GREY_SATURATION: u8 = 32;
+
+/// Give up looking for a free colour after this many nudges
+const MAX_NUDGES: u8 = 8;
+
+/// Roughly how different two colours look, as the sum of the channel differences
+pub fn colour_distance(a: RGB8, b: RGB8) -> u16 {
+    a.r.abs_diff(b.r) as u16 + a.g.abs_diff(b.g) as u16 + a.b.abs_diff(b.b) as u16
+}
+
+/// Convert to hue, saturation and value on the same 0 - 255 scales as [hsv2rgb]
+pub fn rgb2hsv(colour: RGB8) -> Hsv {
+    let max = colour.r.max(colour.g).max(colour.b);
+    let min = colour.r.min(colour.g).min(colour.b);
+    let delta = (max - min) as i32;
+    if delta == 0 {
+        return Hsv {
+            hue: 0,
+            sat: 0,
+            val: max,
+        };
+    }
+    // Each third of the hue circle is 85 steps wide, centred on the dominant channel
+    let (base, a, b) = if max == colour.r {
+        (0, colour.g, colour.b)
+    } else if max == colour.g {
+        (85, colour.b, colour.r)
+    } else {
+        (170, colour.r, colour.g)
+    };
+    let hue = base + 43 * (a as i32 - b as i32) / delta;
+    Hsv {
+        hue: hue.rem_euclid(256) as u8,
+        sat: (delta * 255 / max as i32) as u8,
+        val: max,
+    }
+}
+
+/// Nudge a colour until it no longer collides with any of the colours already taken, so that
+/// friends who picked the same colour can still be told apart. Colours are turned around the hue
+/// circle, alternating either side of the original. Greys have no hue so they are dimmed in steps
+/// instead. If nothing free turns up, the original colour is used anyway.
+///
+/// # Parameters
+/// * `colour` - The colour the soul asked for
+/// * `taken` - The colours of the souls that have already been placed
+pub fn distinct_colour(colour: RGB8, taken: &[RGB8]) -> RGB8 {
+    let collides = |c: RGB8| taken.iter().any(|&t| colour_distance(c, t) < COLOUR_COLLISION_DISTANCE);
+    if !collides(colour) {
+        return colour;
+    }
+    let hsv = rgb2hsv(colour);
+    (1..=MAX_NUDGES)
+        .map(|n| {
+            if hsv.sat < GREY_SATURATION {
+                set_brightness(255 - n * (255 / (MAX_NUDGES + 1)), colour)
+            } else {
+                // 1, -1, 2, -2... nudges either side of the original hue
+                let step = (n.div_ceil(2) * COLOUR_HUE_NUDGE) as i16;
+                let offset = if n % 2 == 1 { step } else { -step };
+                hsv2rgb(Hsv {
+                    hue: (hsv.hue as i16 + offset).rem_euclid(256) as u8,
+                    ..hsv
+                })
+            }
+        })
+        .find(|&c| !collides(c))
+        .unwrap_or(colour)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_distant_souls_are_dimmer() {
+        let colour = RGB8::new(200, 100, 0);
+        assert_eq!(adjust_brightness_for_loss(colour, PRESENCE_NEAR_LOSS - 10, 255), colour);
+        let far = adjust_brightness_for_loss(colour, PRESENCE_FAR_LOSS + 10, 255);
+        assert_eq!(far, set_brightness(PRESENCE_MIN_BRIGHTNESS, colour));
+        let middle = adjust_brightness_for_loss(colour, (PRESENCE_NEAR_LOSS + PRESENCE_FAR_LOSS) / 2, 255);
+        assert!(middle.r < colour.r && middle.r > far.r);
+    }
+
+    #[test]
+    pub fn if_colliding_colours_are_nudged_apart() {
+        let red = RGB8::new(255, 0, 0);
+        let blue = RGB8::new(0, 0, 255);
+        assert_eq!(distinct_colour(red, &[blue]), red);
+        // Two more reds each get their own colour
+        let second = distinct_colour(red, &[red, blue]);
+        assert!(colour_distance(second, red) >= COLOUR_COLLISION_DISTANCE);
+        let third = distinct_colour(red, &[red, blue, second]);
+        assert!(colour_distance(third, red) >= COLOUR_COLLISION_DISTANCE);
+        assert!(colour_distance(third, second) >= COLOUR_COLLISION_DISTANCE);
+        // White has no hue, so it gets dimmer
+        let white = RGB8::new(200, 200, 200);
+        let grey = distinct_colour(white, &[white]);
+        assert!(grey.r < white.r && grey.r == grey.g && grey.g == grey.b);
+    }
 }
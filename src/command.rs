@@ -0,0 +1,178 @@
+//! Controller commands, which push one-shot effects to every soul in the group that is in range.
+//!
+//! A command goes in the controller's beacon for [COMMAND_BROADCAST] seconds. It carries the
+//! [shared time](crate::clock) it was issued at and a tag made with the group key, so souls
+//! outside the group can't make us do anything. The tag is a SipHash-2-4 of the command cut down
+//! to 32 bits, which is plenty to stop pranks but is not meant to keep out a determined attacker.
+//! Each soul carries out a command once, ignores anything older than [COMMAND_WINDOW] seconds and
+//! never more than one every [COMMAND_MIN_INTERVAL] seconds. Souls that would rather not be
+//! commanded can turn off [COMMANDS_ENABLED](crate::configuration::COMMANDS_ENABLED).
+
+use crate::clock::shared_now;
+use crate::configuration::{COMMAND_BROADCAST, COMMAND_MIN_INTERVAL, COMMAND_WINDOW};
+use core::cell::Cell;
+use core::hash::Hasher;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// Size of an encoded [Command], tag included
+pub const COMMAND_SIZE: usize = 12;
+
+/// The group key that tags commands
+pub type GroupKey = [u8; 16];
+
+/// What a command makes everybody do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Effect {
+    /// Flash the whole strip in a colour
+    Flash(RGB8),
+}
+
+/// A command from a controller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Command {
+    pub effect: Effect,
+    /// The shared time at which the command was issued
+    pub issued: u32,
+}
+
+impl Command {
+    /// Encode the command and tag it with the group key
+    pub fn encode(&self, key: &GroupKey) -> [u8; COMMAND_SIZE] {
+        let mut data = [0; COMMAND_SIZE];
+        let Effect::Flash(colour) = self.effect;
+        data[..4].copy_from_slice(&[1, colour.r, colour.g, colour.b]);
+        data[4..8].copy_from_slice(&self.issued.to_le_bytes());
+        let tag = tag(key, &data[..8]);
+        data[8..].copy_from_slice(&tag);
+        data
+    }
+
+    /// Decode a command, None if it isn't tagged with our group key or we don't know the effect
+    pub fn decode(data: &[u8], key: &GroupKey) -> Option<Self> {
+        let [effect, r, g, b, i0, i1, i2, i3, ref received @ ..] = *data else {
+            return None;
+        };
+        if *received != tag(key, &data[..8]) {
+            return None;
+        }
+        let effect = match effect {
+            1 => Effect::Flash(RGB8::new(r, g, b)),
+            _ => return None,
+        };
+        Some(Self {
+            effect,
+            issued: u32::from_le_bytes([i0, i1, i2, i3]),
+        })
+    }
+}
+
+/// The tag for a command body
+#[allow(deprecated)] // SipHasher is deprecated for hash tables, but it is still SipHash-2-4
+pub(crate) fn tag(key: &GroupKey, body: &[u8]) -> [u8; 4] {
+    let half = |i: usize| u64::from_le_bytes(core::array::from_fn(|j| key[i * 8 + j]));
+    let mut hasher = core::hash::SipHasher::new_with_keys(half(0), half(1));
+    hasher.write(body);
+    let [a, b, c, d, ..] = hasher.finish().to_le_bytes();
+    [a, b, c, d]
+}
+
+/// Decides which of the commands we hear get carried out
+#[derive(Default)]
+pub struct CommandFilter {
+    /// When the last command we carried out was issued
+    last_issued: Option<u32>,
+    /// When we carried it out
+    last_run: Option<Instant>,
+}
+
+impl CommandFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// True if the command should be carried out. The command is beaconed over and over, so
+    /// this only says yes once for each.
+    ///
+    /// # Arguments
+    /// * `command` - The command we heard
+    /// * `shared` - The shared time now
+    /// * `now` - Our own time now, for the rate limit
+    pub fn accept(&mut self, command: &Command, shared: u32, now: Instant) -> bool {
+        let age = shared.wrapping_sub(command.issued);
+        // Commands from slightly ahead of us are fine, the clocks are never quite in step
+        let fresh = age <= COMMAND_WINDOW * 1000 || age > u32::MAX - COMMAND_WINDOW * 1000;
+        let seen = self.last_issued == Some(command.issued);
+        let limited = self
+            .last_run
+            .is_some_and(|t| now < t + Duration::from_secs(COMMAND_MIN_INTERVAL as u64));
+        if !fresh || seen || limited {
+            return false;
+        }
+        self.last_issued = Some(command.issued);
+        self.last_run = Some(now);
+        true
+    }
+}
+
+/// The command we are broadcasting, if any
+static SENDING: Mutex<CriticalSectionRawMutex, Cell<Option<Command>>> = Mutex::new(Cell::new(None));
+
+/// Broadcast an effect to the group for the next [COMMAND_BROADCAST] seconds
+pub fn send_command(effect: Effect) {
+    SENDING.lock(|s| {
+        s.set(Some(Command {
+            effect,
+            issued: shared_now(),
+        }))
+    });
+}
+
+/// The command to put in our beacon, None once it has been broadcast for long enough
+pub fn sending() -> Option<Command> {
+    SENDING
+        .lock(|s| s.get())
+        .filter(|c| shared_now().wrapping_sub(c.issued) < COMMAND_BROADCAST * 1000)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: GroupKey = [7; 16];
+
+    fn command(issued: u32) -> Command {
+        Command {
+            effect: Effect::Flash(RGB8::new(255, 170, 0)),
+            issued,
+        }
+    }
+
+    #[test]
+    pub fn if_only_the_group_can_command() {
+        let data = command(1234).encode(&KEY);
+        assert_eq!(Command::decode(&data, &KEY), Some(command(1234)));
+        assert_eq!(Command::decode(&data, &[8; 16]), None);
+        let mut forged = data;
+        forged[1] ^= 1;
+        assert_eq!(Command::decode(&forged, &KEY), None);
+        assert_eq!(Command::decode(&data[..8], &KEY), None);
+    }
+
+    #[test]
+    pub fn if_commands_are_rate_limited() {
+        let mut filter = CommandFilter::new();
+        let now = Instant::from_secs(100);
+        assert!(filter.accept(&command(50_000), 51_000, now));
+        // Repeats of the same beacon
+        assert!(!filter.accept(&command(50_000), 51_500, now));
+        // Too soon
+        assert!(!filter.accept(&command(52_000), 52_500, now + Duration::from_secs(1)));
+        // Too old
+        let later = now + Duration::from_secs(COMMAND_MIN_INTERVAL as u64);
+        assert!(!filter.accept(&command(52_000), 52_000 + COMMAND_WINDOW * 1000 + 1, later));
+        assert!(filter.accept(&command(90_000), 90_100, later));
+    }
+}
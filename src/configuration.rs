@@ -1,12 +1,27 @@
+use crate::buttons::{Button, ButtonPull, Expander, GpioButton, valid_wiring};
+use crate::logging::LogLevel;
+use crate::trigger::{TriggerEvent, TriggerMode};
 use trouble_host::prelude::TxPower;
 
-/// The display animation update interval in milliseconds
+/// The default display animation update interval in milliseconds. Animations may choose a faster rate.
 pub const ANIMATION_UPDATE: u64 = 200;
 
+/// The most frames an animation is stepped forward by to make up for the display being held up,
+/// see [frame_clock](crate::frame_clock)
+pub const FRAME_CATCH_UP: u32 = 4;
+
 /// If a soul has not been seen for more than this many seconds, they are flushed
 /// from the presence list
 pub const TRACKER_FLUSH_AGE: u64 = 15;
 
+/// A soul whose recent path loss is this much (dB) below or above its longer term average is
+/// approaching or receding
+pub const MOVEMENT_THRESHOLD: i32 = 6;
+
+/// The shortest time between two approach hints for the same soul (seconds), so somebody pacing
+/// about nearby doesn't fill the animation queue
+pub const APPROACH_HINT_INTERVAL: u64 = 30;
+
 /// The presence register will be flushed at this interval (seconds)
 pub const PRESENCE_REGISTER_FLUSH_INTERVAL: u64 = 1;
 
@@ -19,11 +34,551 @@ pub const MAX_SOULS_TRACKED: usize = 16;
 /// Transmission power for the advertisement beacon. Generally, the bigger, the longer the range
 pub const TX_POWER: TxPower = TxPower::Plus20dBm;
 
-/// A global company ID that we set here so we can filter beacons for only SoulStar devices
+/// The company ID we tag our beacons with so we can filter beacons for only SoulStar devices.
+/// It is not an assigned ID, so it can be overridden in the `[network]` section of souls.toml.
 pub const COMPANY_ID: u16 = 0xBEEF;
 
+/// The company ID used by development units on the test network, so they don't light up
+/// everybody's stars. It can also be overridden in souls.toml.
+pub const TEST_COMPANY_ID: u16 = 0xBEEE;
+
 /// The number of LEDs in the string we are driving
 pub const LED_STRING_SIZE: usize = 24;
 
+/// How the LEDs are physically arranged, which matters for animations like the VU meter
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LedLayout {
+    /// A straight strip, drawn from one end to the other
+    Strip,
+    /// A ring, drawn symmetrically up both sides from the first LED
+    Ring,
+    /// A grid, wired row by row from the top left. With the accelerometer fitted the frame is
+    /// turned to stay upright, see [orientation](crate::orientation).
+    Matrix { width: usize, height: usize },
+}
+
+/// The layout of our LEDs
+pub const LED_LAYOUT: LedLayout = LedLayout::Ring;
+
+const _: () = assert!(
+    match LED_LAYOUT {
+        LedLayout::Matrix { width, height } => width * height <= LED_STRING_SIZE,
+        _ => true,
+    },
+    "The matrix has more LEDs than the string"
+);
+
+/// How long an animation requested to play immediately runs for (seconds)
+pub const PLAY_NOW_DURATION: u64 = 10;
+
 /// The maximum number of pending animations in the animation queue
 pub const MAX_PENDING_ANIMATIONS: usize = 20;
+
+/// How long a sender waits to hear whether the display task carried out its command
+/// (milliseconds). It is kept short as GATT connections wait on it.
+pub const DISPLAY_ACK_TIMEOUT: u64 = 200;
+
+/// The log level every module starts with. It can be changed per module at runtime, see
+/// [logging](crate::logging).
+pub const DEFAULT_LOG_LEVEL: LogLevel = LogLevel::Info;
+
+/// Interval at which the diagnostics task reports heap and stack usage (seconds)
+pub const DIAGNOSTICS_INTERVAL: u64 = 30;
+
+/// The pixel reserved for status indicators (BLE connected, low battery etc.)
+pub const STATUS_PIXEL: usize = 0;
+
+/// Brightness of the status pixel, which is kept apart from the animation brightness so the
+/// status can still be seen on a dimmed strip. Night mode still caps it.
+pub const STATUS_BRIGHTNESS: u8 = 255;
+
+/// Brightness changes fade in over this many steps
+pub const BRIGHTNESS_FADE_STEPS: u8 = 15;
+
+/// Interval between brightness fade steps in milliseconds
+pub const BRIGHTNESS_FADE_INTERVAL: u64 = 20;
+
+/// Brightness ceiling applied to everything, including the torch, while in night mode
+pub const NIGHT_MODE_BRIGHTNESS: u8 = 24;
+
+/// Set if an ambient light sensor is fitted. The display brightness then follows the light
+/// until it is changed with the buttons.
+pub const AUTO_BRIGHTNESS_ENABLED: bool = false;
+
+/// How often the light level is reported to the display task (milliseconds)
+pub const AUTO_BRIGHTNESS_INTERVAL: u64 = 1000;
+
+/// Light levels (raw 12 bit ADC readings) at which each display brightness starts, darkest first
+pub const AUTO_BRIGHTNESS_BANDS: &[(u16, u8)] = &[(0, 16), (400, 48), (1200, 128), (2800, 255)];
+
+/// How far past the edge of a band the light must get before the brightness changes
+pub const AUTO_BRIGHTNESS_HYSTERESIS: u16 = 100;
+
+/// Set if the battery voltage is wired to GPIO0 through a divider, so that we can report the
+/// battery level over the Battery Service and in our beacon
+pub const BATTERY_ENABLED: bool = false;
+
+/// How much the divider on the battery pin divides the cell voltage by
+pub const BATTERY_DIVIDER: u16 = 2;
+
+/// How often the battery voltage is measured (seconds)
+pub const BATTERY_INTERVAL: u64 = 60;
+
+/// Battery level (percent) at or below which the status pixel warns that it needs charging
+pub const BATTERY_LOW: u8 = 15;
+
+/// How often the battery voltage is checked for a sag under load, when the battery is wired up
+/// (milliseconds)
+pub const BROWNOUT_INTERVAL: u64 = 250;
+
+/// Cell voltage (millivolts) below which the brightness is stepped down to save the regulator
+pub const BROWNOUT_MILLIVOLTS: u16 = 3400;
+
+/// How far (millivolts) above [BROWNOUT_MILLIVOLTS] the cell must recover before the brightness
+/// is allowed back up
+pub const BROWNOUT_HYSTERESIS: u16 = 150;
+
+/// How long the cell must stay recovered before each step back up (seconds)
+pub const BROWNOUT_RECOVERY: u64 = 10;
+
+/// How much the brightness ceiling moves by in each step
+pub const BROWNOUT_STEP: u8 = 32;
+
+/// The brightness ceiling never goes below this, however much the cell sags
+pub const BROWNOUT_MIN_BRIGHTNESS: u8 = 32;
+
+/// Current drawn by a single LED colour channel at full drive (milliamps)
+pub const LED_CHANNEL_CURRENT: u32 = 20;
+
+/// How long the strip's power rail or level shifter takes to come up once enabled, before we
+/// send it any data (milliseconds)
+pub const LED_POWER_SETTLE: u64 = 2;
+
+/// How long the strip must have been dark before its power is cut, on builds that can cut it
+/// (milliseconds)
+pub const LED_POWER_IDLE: u64 = 2000;
+
+/// The most current the strip may draw (milliamps). Brighter frames are dimmed to fit.
+pub const MAX_LED_CURRENT: u32 = 1000;
+
+/// A button held for at least this long (milliseconds) is a long press
+pub const LONG_PRESS: u64 = 1000;
+
+/// How long both brightness buttons must be held for a factory reset (seconds)
+pub const FACTORY_RESET_HOLD: u64 = 10;
+
+/// Where the settings and encounters are kept in flash. This is the NVS partition of the
+/// standard ESP-IDF partition table, which nothing else uses.
+pub const STORE_OFFSET: u32 = 0x9000;
+
+/// The number of 4kB sectors the store is spread over to share out the wear. The NVS partition
+/// has room for six.
+pub const STORE_SECTORS: u32 = 4;
+
+/// How long the settings must stop changing before they are written to flash (seconds)
+pub const SETTINGS_SAVE_DELAY: u64 = 5;
+
+/// The buttons wired straight to GPIO pins. A board with a different arrangement only has to
+/// change this table. The pins must not be used for anything else.
+pub const GPIO_BUTTONS: &[GpioButton] = &[
+    GpioButton {
+        pin: 2,
+        pull: ButtonPull::Up,
+        button: Button::Torch,
+    },
+    GpioButton {
+        pin: 3,
+        pull: ButtonPull::Up,
+        button: Button::Increase,
+    },
+    GpioButton {
+        pin: 15,
+        pull: ButtonPull::Up,
+        button: Button::Decrease,
+    },
+    GpioButton {
+        pin: 4,
+        pull: ButtonPull::Up,
+        button: Button::Mode,
+    },
+];
+
+/// The most buttons that can be wired straight to GPIO pins
+pub const MAX_GPIO_BUTTONS: usize = 8;
+
+const _: () = assert!(GPIO_BUTTONS.len() <= MAX_GPIO_BUTTONS, "Too many GPIO buttons");
+const _: () = assert!(valid_wiring(GPIO_BUTTONS), "A GPIO button shares a pin, or is on one we can't use");
+
+/// The I2C GPIO expander the extra buttons are on, None if there isn't one
+pub const EXPANDER: Option<Expander> = None;
+
+/// The I2C address of the button expander. Both supported expanders default to 0x20.
+pub const EXPANDER_ADDRESS: u8 = 0x20;
+
+/// The button on each expander line, starting with line 0
+pub const EXPANDER_BUTTONS: [Option<Button>; 8] =
+    [Some(Button::NightMode), Some(Button::Sos), Some(Button::Tempo), None, None, None, None, None];
+
+/// How often the expander buttons are read (milliseconds)
+pub const BUTTON_POLL_INTERVAL: u64 = 20;
+
+/// The shortest press of an expander button that counts (milliseconds)
+pub const BUTTON_DEBOUNCE: u64 = 50;
+
+/// The tempo the beat-aware animations keep until the tempo button is tapped (beats per minute)
+pub const DEFAULT_BPM: u16 = 120;
+
+/// The slowest tempo we follow (beats per minute). A longer gap between taps starts again.
+pub const MIN_BPM: u16 = 60;
+
+/// The fastest tempo we follow (beats per minute)
+pub const MAX_BPM: u16 = 200;
+
+/// How many of the latest taps the tempo is averaged over
+pub const TEMPO_TAPS: usize = 8;
+
+/// How long a tapped tempo wins over the one the microphone hears (seconds). A tempo saved from an
+/// earlier tap counts as tapped at start up.
+pub const TAPPED_TEMPO_HOLD: u64 = 600;
+
+/// Set if a LIS3DH accelerometer is fitted on the same I2C bus as the button expander. It picks
+/// up gestures, see [motion](crate::motion).
+pub const IMU_ENABLED: bool = false;
+
+/// The I2C address of the accelerometer, 0x19 with SDO pulled up or 0x18 with it grounded
+pub const IMU_ADDRESS: u8 = 0x19;
+
+/// How often the accelerometer is read (milliseconds)
+pub const IMU_INTERVAL: u64 = 10;
+
+/// The change in acceleration from one reading to the next (milli-g, summed over the axes) that
+/// counts as a tap on the enclosure
+pub const TAP_THRESHOLD: u32 = 1500;
+
+/// The longest a tap lasts (milliseconds). Anything longer is the wearer moving about.
+pub const TAP_MAX_DURATION: u64 = 60;
+
+/// The shortest gap between the two taps of a double tap (milliseconds). Anything closer is the
+/// enclosure ringing from the first tap.
+pub const TAP_MIN_GAP: u64 = 80;
+
+/// The longest gap between the two taps of a double tap (milliseconds)
+pub const TAP_MAX_GAP: u64 = 400;
+
+/// The average change in acceleration from one reading to the next (milli-g, summed over the
+/// axes) that counts as shaking the star
+pub const SHAKE_LEVEL: u32 = 500;
+
+/// The shortest time between two shakes (milliseconds), so one long shake only counts once
+pub const SHAKE_COOLDOWN: u64 = 2000;
+
+/// How much of gravity (milli-g) must pull along a matrix panel's rows or columns before we
+/// decide which way up it is. Over 707 means only one axis can be over it at a time.
+pub const ORIENTATION_THRESHOLD: u32 = 750;
+
+/// How long a matrix panel must stay the new way up before the display turns (milliseconds)
+pub const ORIENTATION_SETTLE: u64 = 500;
+
+/// The change in acceleration from one reading to the next (milli-g, summed over the axes) that
+/// is just the jitter of a star sitting still, which the dance meter ignores
+pub const DANCE_NOISE: u32 = 60;
+
+/// The movement that fills the dance meter (milli-g). Dancing moves the star by a few hundred
+/// milli-g every reading, so this is a couple of hours of it.
+pub const DANCE_FULL: u32 = 200_000_000;
+
+/// How often the dance meter starts again from empty (hours of the shared clock)
+pub const DANCE_DAY: u64 = 24;
+
+/// How often the dance meter is saved to flash while it is changing (seconds)
+pub const DANCE_SAVE_INTERVAL: u64 = 600;
+
+/// The change in acceleration from one reading to the next (milli-g, summed over the axes) that
+/// counts as the wearer moving, for the stillness auto-dim
+pub const STILL_THRESHOLD: u32 = 150;
+
+/// How long the wearer must keep still before the display dims (seconds)
+pub const STILL_TIMEOUT: u64 = 600;
+
+/// The glow the display dims to while the wearer keeps still
+pub const STILL_BRIGHTNESS: u8 = 8;
+
+/// The stillness dim fades out over this many steps, see [BRIGHTNESS_FADE_INTERVAL]
+pub const STILL_FADE_STEPS: u8 = 150;
+
+/// How long we wave for after a double tap (seconds), see [wave](crate::wave)
+pub const WAVE_DURATION: u64 = 3;
+
+/// How often the BLE task checks for a change of radio mode, e.g. do-not-disturb (milliseconds)
+pub const RADIO_MODE_POLL_INTERVAL: u64 = 250;
+
+/// Set if a vibration motor is fitted. It pulses when a favourite soul comes into range.
+pub const HAPTIC_ENABLED: bool = false;
+
+/// How long the vibration motor runs for each pulse (milliseconds)
+pub const HAPTIC_PULSE: u64 = 300;
+
+/// Starts a command line on the USB serial port, e.g. for exporting the encounter history
+pub const CONSOLE_ENABLED: bool = true;
+
+/// Set if an I2S MEMS microphone (e.g. an INMP441) is wired to GPIO18 (clock), GPIO19 (word
+/// select) and GPIO20 (data), for the VU meter and finding the beat in the music
+pub const MIC_ENABLED: bool = false;
+
+/// The microphone sample rate (Hz). The beat detector's filters are tuned for 16kHz.
+pub const MIC_SAMPLE_RATE: u32 = 16_000;
+
+/// The samples handed on at a time, 16ms at 16kHz
+pub const MIC_BLOCK: usize = 256;
+
+/// The average sample size that shows as a full VU meter
+pub const MIC_FULL_SCALE: u16 = 8000;
+
+/// A rise in energy this much bigger than the average rise is a beat (percent)
+pub const BEAT_SENSITIVITY: u32 = 150;
+
+/// Rises in energy smaller than this are never a beat, so quiet rooms don't find beats in the
+/// background noise
+pub const BEAT_FLOOR: u32 = 500;
+
+/// Set if something is wired to the auxiliary trigger output (GPIO7), e.g. an EL-wire driver,
+/// a relay or a sound module
+pub const TRIGGER_ENABLED: bool = false;
+
+/// The events that fire the trigger output
+pub const TRIGGER_EVENTS: &[TriggerEvent] = &[TriggerEvent::SoulJoined, TriggerEvent::FavouriteNear];
+
+/// Whether the trigger output pulses or stays on while events keep happening
+pub const TRIGGER_MODE: TriggerMode = TriggerMode::Pulse;
+
+/// Set if the trigger output is high when triggered. Clear for active low relay boards.
+pub const TRIGGER_ACTIVE_HIGH: bool = true;
+
+/// How long a trigger pulse lasts (milliseconds)
+pub const TRIGGER_PULSE: u64 = 500;
+
+/// The trigger output resets once there have been no events for this long (seconds)
+pub const TRIGGER_HOLD: u64 = 5;
+
+/// Set to send every frame out as DMX512 on GPIO10, through an RS-485 transceiver, so the star
+/// can drive venue fixtures as well as its own LEDs, see [dmx](crate::dmx)
+pub const DMX_ENABLED: bool = false;
+
+/// The DMX channel of the red of the first pixel, from 1 to 512. Each pixel takes three channels.
+pub const DMX_START_ADDRESS: u16 = 1;
+
+/// How often the DMX packet is sent (milliseconds). DMX is sent over and over whether or not
+/// anything has changed, and fixtures go dark if it stops for too long.
+pub const DMX_REFRESH: u64 = 25;
+
+/// Set to join the venue Wi-Fi (set in souls.toml) and take frames from a lighting desk over
+/// Art-Net or sACN, see [light_show](crate::light_show)
+pub const LIGHT_SHOW_ENABLED: bool = false;
+
+/// The Art-Net universe (port address) the desk sends our pixels in. Art-Net counts from 0.
+pub const ARTNET_UNIVERSE: u16 = 0;
+
+/// The sACN universe the desk sends our pixels in. sACN counts from 1.
+pub const SACN_UNIVERSE: u16 = 1;
+
+/// The channel of the red of our first pixel in the desk's universe. Each pixel takes three
+/// channels.
+pub const LIGHT_SHOW_ADDRESS: u16 = 1;
+
+/// Our own animations come back once the desk has sent nothing for this long (seconds)
+pub const LIGHT_SHOW_TIMEOUT: u64 = 5;
+
+/// Meeting this many different souls earns a badge
+pub const BADGE_SOULS_MET: usize = 10;
+
+/// Staying near a favourite soul for this long earns a badge (seconds)
+pub const BADGE_FRIEND_TIME: u64 = 3600;
+
+/// Seeing the sun come up after a night at least this long earns a badge (seconds). Needs the
+/// ambient light sensor.
+pub const BADGE_NIGHT_TIME: u64 = 4 * 3600;
+
+/// Ambient light below this level counts as night, in the same units as [AUTO_BRIGHTNESS_BANDS]
+pub const BADGE_DARK_LEVEL: u16 = 100;
+
+/// Ambient light at or above this level after a night counts as sunrise
+pub const BADGE_DAWN_LEVEL: u16 = 600;
+
+/// How long the red alert stays up after the last SOS beacon from a friend (seconds)
+pub const ALERT_DURATION: u64 = 10;
+
+/// Strobe (party) mode must be explicitly enabled as flashing lights can trigger seizures
+pub const STROBE_ENABLED: bool = false;
+
+/// Time between strobe flashes in milliseconds. It is capped to stay below 3 flashes a second.
+pub const STROBE_PERIOD: u64 = 500;
+
+/// Percentage of the strobe period that the LEDs are lit
+pub const STROBE_DUTY: u8 = 20;
+
+/// How long party mode runs for before reverting to normal (seconds)
+pub const STROBE_DURATION: u64 = 30;
+
+/// The minimum time between presence updates from the same soul (milliseconds). Reports that
+/// arrive sooner are dropped by the scan handler.
+pub const SCAN_MIN_UPDATE_INTERVAL: u64 = 1000;
+
+/// Feed the display scripted fake souls as well as the real ones, for working on the presence
+/// animations without a second star. See [simulation](crate::simulation). Never ship with this set.
+pub const SIMULATION_ENABLED: bool = false;
+
+/// How many times each start-up step is tried before the star carries on without it
+pub const STARTUP_ATTEMPTS: u8 = 5;
+
+/// Delay before the first retry of a failed start-up step (milliseconds). It doubles on every failure.
+pub const STARTUP_RETRY_DELAY: u64 = 100;
+
+/// First delay before restarting a failed BLE stack (seconds). It doubles on every failure.
+pub const BLE_RESTART_BACKOFF_MIN: u64 = 1;
+
+/// Longest delay before restarting a failed BLE stack (seconds)
+pub const BLE_RESTART_BACKOFF_MAX: u64 = 60;
+
+/// A BLE stack that ran for this long before failing starts again with the shortest back off (seconds)
+pub const BLE_STABLE_TIME: u64 = 300;
+
+/// The number of received advertisements that can wait to be parsed
+pub const RAW_REPORT_QUEUE_SIZE: usize = 16;
+
+/// Shortest advertising interval, used when we are on our own (milliseconds)
+pub const ADVERTISING_INTERVAL_MIN: u64 = 150;
+
+/// The advertising interval grows by this much for every soul we can see (milliseconds)
+pub const ADVERTISING_INTERVAL_STEP: u64 = 50;
+
+/// Longest advertising interval, however big the crowd (milliseconds)
+pub const ADVERTISING_INTERVAL_MAX: u64 = 1000;
+
+/// The controller may advertise anywhere between the interval and this much longer (milliseconds)
+pub const ADVERTISING_SPREAD: u64 = 300;
+
+/// How often we check if the crowd has changed enough to change the advertising interval (seconds)
+pub const ADVERTISING_REVIEW_INTERVAL: u64 = 10;
+
+/// How often we refresh the shared clock in our beacon (seconds). The clocks of a group agree to
+/// within about this, so shorter keeps phase-locked animations tighter at the cost of rewriting
+/// the advertisement more often.
+pub const CLOCK_REFRESH_INTERVAL: u64 = 2;
+
+/// Enables the soul-link exchange, where souls that come very close connect and swap profiles.
+/// It makes our advertisement connectable.
+pub const EXCHANGE_ENABLED: bool = false;
+
+/// Souls closer than this path loss (dB) are close enough for a soul-link exchange
+pub const EXCHANGE_LOSS: i32 = 50;
+
+/// How long we give a soul-link connection and profile read before giving up (seconds)
+pub const EXCHANGE_TIMEOUT: u64 = 5;
+
+/// How long before we try again with a soul we failed to link with (seconds)
+pub const EXCHANGE_RETRY: u64 = 300;
+
+/// The number of soul-link encounters we remember
+pub const MAX_ENCOUNTERS: usize = 16;
+
+/// Seeing a soul we have linked with again counts as meeting it again once it has been away this long (seconds)
+pub const ENCOUNTER_GAP: u64 = 600;
+
+/// How often shuffle mode picks a new default animation (seconds)
+pub const SHUFFLE_INTERVAL: u64 = 180;
+
+/// Switch to a lively animation when the crowd gets big, see [crowd](crate::crowd)
+pub const CROWD_MOOD_ENABLED: bool = true;
+
+/// A crowd of at least this many souls is lively
+pub const LIVELY_CROWD: usize = 8;
+
+/// A lively crowd calms down again when it drops below this many souls
+pub const CALM_CROWD: usize = 5;
+
+/// Let the souls we have seen in the last minute, rather than the souls we can see right now, set
+/// the advertising interval and the crowd mood. A busy thoroughfare then counts as a crowd even
+/// though only a few souls are in range at any moment. See [crowd](crate::crowd).
+pub const CROWD_BY_RATE: bool = false;
+
+/// The most different souls a minute we can count. Must be a power of two because of the heapless
+/// crate. It can be bigger than [MAX_SOULS_TRACKED] as only the keys are kept.
+pub const MAX_SOULS_PER_MINUTE: usize = 64;
+
+/// Adopt the animation of a soul with our group key that is leading, see [leader](crate::leader)
+pub const FOLLOW_ENABLED: bool = false;
+
+/// How long a leader can go unheard before we follow somebody else (seconds)
+pub const FOLLOW_TIMEOUT: u64 = 30;
+
+/// The most frames a follower fast-forwards to catch up with the leader
+pub const FOLLOW_CATCH_UP: u32 = 50;
+
+/// Help our crew (the souls with our group key) find the soul they are playing hot/cold with, and
+/// let them help us, see [crew](crate::crew)
+pub const CREW_FINDER_ENABLED: bool = false;
+
+/// The most crew members whose reports we keep while hunting
+pub const CREW_SIZE: usize = 8;
+
+/// Crew hunts and reports are forgotten after this long without hearing them again (seconds)
+pub const CREW_REPORT_TIMEOUT: u64 = 10;
+
+/// A crew member must be this much nearer (dB) to the soul we are hunting than we are before
+/// the hot/cold game points us at them
+pub const CREW_MARGIN: i32 = 6;
+
+/// Carry out the commands of controllers in our group, see [command](crate::command)
+pub const COMMANDS_ENABLED: bool = true;
+
+/// How long a controller broadcasts each command (seconds)
+pub const COMMAND_BROADCAST: u32 = 3;
+
+/// Commands older than this are ignored (seconds)
+pub const COMMAND_WINDOW: u32 = 10;
+
+/// The shortest time between two commands we carry out (seconds)
+pub const COMMAND_MIN_INTERVAL: u32 = 30;
+
+/// The colour a controller tells the group to flash in
+pub const COMMAND_FLASH_COLOUR: [u8; 3] = [255, 170, 0];
+
+/// Path loss (dB) at 1 m that the loss thresholds are written for, see [path_loss](crate::path_loss)
+pub const PATH_LOSS_REFERENCE: i32 = 45;
+
+/// Path-loss exponent (tenths) that the loss thresholds are written for. 20 is free space.
+pub const PATH_LOSS_EXPONENT: u8 = 20;
+
+/// The number of beacons averaged for each calibration step
+pub const CALIBRATION_SAMPLES: u32 = 10;
+
+/// Path loss (dB) at or below which a soul is drawn at full brightness in the presence animation
+pub const PRESENCE_NEAR_LOSS: i32 = 40;
+
+/// Path loss (dB) at or above which a soul is drawn at [PRESENCE_MIN_BRIGHTNESS]
+pub const PRESENCE_FAR_LOSS: i32 = 100;
+
+/// The dimmest a distant soul is drawn in the presence animation
+pub const PRESENCE_MIN_BRIGHTNESS: u8 = 24;
+
+/// Path loss (dB) at which the hot/cold meter is full, i.e. the soul is right next to us
+pub const HOT_COLD_NEAR_LOSS: i32 = 45;
+
+/// Path loss (dB) at which the hot/cold meter is at its coldest
+pub const HOT_COLD_FAR_LOSS: i32 = 95;
+
+/// Show our friends on pixels of their own rather than in the rotating presence layout, and put
+/// our stable ID in our beacon so that they can do the same with us. The ID needs a group key,
+/// see [constellation](crate::constellation).
+pub const CONSTELLATION_ENABLED: bool = false;
+
+/// The heatmap animation is at its hottest with this many souls right next to us. Souls further
+/// away count for less, see [HeatmapAnimation](crate::animations::HeatmapAnimation).
+pub const HEATMAP_FULL_CROWD: u32 = 8;
+
+/// Souls whose colours differ by less than this (sum of the channel differences) look the same
+/// on the strip, so the presence display nudges one of them
+pub const COLOUR_COLLISION_DISTANCE: u16 = 60;
+
+/// How far (out of 256) the hue of a colliding colour is turned on each attempt to find a free one
+pub const COLOUR_HUE_NUDGE: u8 = 24;
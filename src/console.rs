@@ -0,0 +1,254 @@
+//! A command line over the USB serial port, for getting things off the star without a phone.
+//!
+//! Connect a terminal to the star's USB port and type a command followed by enter:
+//!
+//! * `help` lists the commands
+//! * `encounters` dumps the encounter history as CSV, with a header line
+//! * `encounters json` dumps it as JSON lines instead
+//! * `status` shows the brightness, whether the animations are running, the torch, the animation
+//!   showing and how many are waiting, see [DisplayStatus]
+//! * `stats` shows the heap and stack use from the last [diagnostics](crate::diagnostics) report
+//! * `colour ff8000` changes our colour, given as RGB in hex, everywhere at once
+//! * `interval update 100` changes one of the [intervals](soulstar::intervals)
+//! * `mood 4` puts a mood in our beacon, whatever it means to the wearer, and `mood none` takes
+//!   it out again
+//! * `friend ff00ff Dr Strange` shows a friend in a colour of our own, see
+//!   [overrides](soulstar::overrides), and `friend none Dr Strange` in their own again
+//!
+//! Each encounter has the soul's tracker key, its name and contact token, when we first and last
+//! met it and how many times we have met. Times are the start they happened in and the seconds
+//! after it, see [Moment](soulstar::encounters::Moment). The log goes over RTT rather than the
+//! serial port, so the two don't get mixed up. The console is only started if
+//! [CONSOLE_ENABLED](soulstar::configuration::CONSOLE_ENABLED) is set.
+
+use crate::diagnostics::latest_stats;
+use crate::display_task::{CommandResult, DisplayChannelSender, DisplayState, DisplayStatus, change_colour};
+use crate::presence::update_beacon;
+use crate::settings_store::{change_interval, save_override};
+use crate::soul_link::encounter;
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{Read, Write};
+use esp_hal::Async;
+use esp_hal::usb_serial_jtag::{UsbSerialJtagRx, UsbSerialJtagTx};
+use heapless::String;
+use smart_leds::RGB8;
+use soulstar::configuration::{DIAGNOSTICS_INTERVAL, DISPLAY_ACK_TIMEOUT};
+use soulstar::encounters::CSV_HEADER;
+use soulstar::intervals::parse_interval;
+use soulstar::log;
+use soulstar::overrides::{name_key, set_override};
+
+/// Longest command we take, long enough for a friend's colour and name
+const MAX_COMMAND: usize = 48;
+
+/// Big enough for the longest encounter, with every character escaped
+const MAX_LINE: usize = 256;
+
+/// Where the display task tells us how a command went
+static DISPLAY_ACK: Signal<CriticalSectionRawMutex, CommandResult> = Signal::new();
+
+/// Where the display task answers the `status` command
+static DISPLAY_STATUS: Signal<CriticalSectionRawMutex, DisplayStatus> = Signal::new();
+
+const HELP: &str = "help              this list\n\
+                    encounters        the encounter history as CSV\n\
+                    encounters json   the encounter history as JSON lines\n\
+                    status            what the display is doing\n\
+                    stats             heap and stack use\n\
+                    colour rrggbb     change our colour, e.g. colour ff8000\n\
+                    interval name n   change update (ms), flush or age (s), e.g. interval update 100\n\
+                    mood n            put a mood from 0 to 255 in our beacon, none for no mood\n\
+                    friend rrggbb nm  show a friend in our colour for them, none for their own\n";
+
+/// Read commands from the USB serial port and answer them
+///
+/// # Parameters
+/// * `rx` - The receiving half of the USB serial port
+/// * `tx` - The sending half of the USB serial port
+/// * `display` - Where a new colour is sent for the animations
+#[embassy_executor::task]
+pub async fn console_task(
+    mut rx: UsbSerialJtagRx<'static, Async>,
+    mut tx: UsbSerialJtagTx<'static, Async>,
+    display: DisplayChannelSender,
+) {
+    log!(Main, info, "CONSOLE: Ready");
+    let mut command: String<MAX_COMMAND> = String::new();
+    // Set when the line is longer than any command, so it isn't run cut short
+    let mut overflow = false;
+    let mut byte = [0u8; 1];
+    loop {
+        if rx.read_exact(&mut byte).await.is_err() {
+            continue;
+        }
+        match byte[0] {
+            b'\r' | b'\n' => {
+                let answered = if overflow {
+                    tx.write_all(b"Command too long\n").await.map_err(|_| ())
+                } else if !command.is_empty() {
+                    answer(command.trim(), &mut tx, &display).await
+                } else {
+                    Ok(())
+                };
+                if answered.is_err() {
+                    log!(Main, warn, "CONSOLE: Could not answer {}", command.as_str());
+                }
+                command.clear();
+                overflow = false;
+            }
+            // Anything that isn't text can't be a command, so let it fail as one
+            c => {
+                overflow |= command.push(if c.is_ascii() { c as char } else { '?' }).is_err();
+            }
+        }
+    }
+}
+
+/// Answer a command
+async fn answer(
+    command: &str,
+    tx: &mut UsbSerialJtagTx<'static, Async>,
+    display: &DisplayChannelSender,
+) -> Result<(), ()> {
+    let json = match command.split_once(' ') {
+        None if command == "help" => return tx.write_all(HELP.as_bytes()).await.map_err(|_| ()),
+        None if command == "status" => return status(tx, display).await,
+        None if command == "stats" => return stats(tx).await,
+        None if command == "encounters" => false,
+        Some(("encounters", "csv")) => false,
+        Some(("encounters", "json")) => true,
+        Some(("colour", hex)) => {
+            let reply: &[u8] = match parse_colour(hex.trim()) {
+                Some(colour) => match change_colour(display, colour, &DISPLAY_ACK).await {
+                    CommandResult::Applied => b"OK\n",
+                    CommandResult::QueueFull => b"Saved, but the display is busy so the animations change on restart\n",
+                    CommandResult::Rejected => b"Saved, but the display won't change the animations until restart\n",
+                },
+                None => b"Give the colour as six hex digits, e.g. colour ff8000\n",
+            };
+            return tx.write_all(reply).await.map_err(|_| ());
+        }
+        Some(("interval", setting)) => {
+            let reply: &[u8] = match setting
+                .trim()
+                .split_once(' ')
+                .and_then(|(n, v)| parse_interval(n, v.trim()))
+            {
+                Some((id, value)) => {
+                    change_interval(id, value);
+                    b"OK\n"
+                }
+                None => b"Give update, flush or age and a value in range, see src/intervals.rs\n",
+            };
+            return tx.write_all(reply).await.map_err(|_| ());
+        }
+        Some(("mood", mood)) => {
+            let mood = match mood.trim() {
+                "none" => Some(None),
+                mood => mood.parse().ok().map(Some),
+            };
+            let reply: &[u8] = match mood {
+                Some(mood) => {
+                    update_beacon(|i| i.mood = mood);
+                    b"OK\n"
+                }
+                None => b"Give the mood as a number from 0 to 255, or none\n",
+            };
+            return tx.write_all(reply).await.map_err(|_| ());
+        }
+        Some(("friend", rest)) => {
+            let reply: &[u8] = match rest.trim().split_once(' ') {
+                Some((colour, name)) if colour == "none" || parse_colour(colour).is_some() => {
+                    let (key, colour) = (name_key(name.trim()), parse_colour(colour));
+                    if set_override(key, colour) {
+                        save_override(key, colour);
+                        b"OK\n"
+                    } else {
+                        b"No room for another friend, give one of them none first\n"
+                    }
+                }
+                _ => b"Give the colour as six hex digits or none, then the name, e.g. friend ff00ff Dr Strange\n",
+            };
+            return tx.write_all(reply).await.map_err(|_| ());
+        }
+        _ => return tx.write_all(b"Unknown command, try help\n").await.map_err(|_| ()),
+    };
+    if !json {
+        tx.write_all(CSV_HEADER.as_bytes()).await.map_err(|_| ())?;
+        tx.write_all(b"\n").await.map_err(|_| ())?;
+    }
+    let mut line: String<MAX_LINE> = String::new();
+    // The history can change while we are sending it, which at worst skips or repeats one
+    for e in (0..).map_while(encounter) {
+        line.clear();
+        let written = if json {
+            e.write_json(&mut line)
+        } else {
+            e.write_csv(&mut line)
+        };
+        written.map_err(|_| ())?;
+        tx.write_all(line.as_bytes()).await.map_err(|_| ())?;
+    }
+    tx.flush().await.map_err(|_| ())
+}
+
+/// Ask the display task what it is doing and send the answer
+async fn status(tx: &mut UsbSerialJtagTx<'static, Async>, display: &DisplayChannelSender) -> Result<(), ()> {
+    // Forget an answer that came too late last time
+    DISPLAY_STATUS.reset();
+    let status = if display
+        .try_send(DisplayState::GetStatus(&DISPLAY_STATUS).into())
+        .is_ok()
+    {
+        with_timeout(Duration::from_millis(DISPLAY_ACK_TIMEOUT), DISPLAY_STATUS.wait())
+            .await
+            .ok()
+    } else {
+        None
+    };
+    let mut line: String<MAX_LINE> = String::new();
+    match status {
+        Some(s) => writeln!(
+            line,
+            "Brightness {}, running {}, torch {}, animation {}, {} waiting",
+            s.brightness, s.running, s.torch, s.animation, s.queue_depth
+        ),
+        None => writeln!(line, "The display is busy, try again"),
+    }
+    .map_err(|_| ())?;
+    tx.write_all(line.as_bytes()).await.map_err(|_| ())
+}
+
+/// Send the heap and stack use from the last diagnostics report
+async fn stats(tx: &mut UsbSerialJtagTx<'static, Async>) -> Result<(), ()> {
+    let mut line: String<MAX_LINE> = String::new();
+    match latest_stats() {
+        Some(s) => writeln!(
+            line,
+            "Heap {}/{} bytes used ({} free, high-water {}), stack {}/{} bytes used ({} headroom)",
+            s.heap_used,
+            s.heap_size,
+            s.heap_free,
+            s.heap_high_water,
+            s.stack_high_water,
+            s.stack_size,
+            s.stack_headroom()
+        ),
+        None => writeln!(line, "No report yet, try again in {} seconds", DIAGNOSTICS_INTERVAL),
+    }
+    .map_err(|_| ())?;
+    tx.write_all(line.as_bytes()).await.map_err(|_| ())
+}
+
+/// A colour written as six hex digits, with or without a leading #
+fn parse_colour(hex: &str) -> Option<RGB8> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |at: usize| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok();
+    Some(RGB8::new(channel(0)?, channel(2)?, channel(4)?))
+}
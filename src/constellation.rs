@@ -0,0 +1,104 @@
+//! Constellation mode, where every friend has a pixel of their own. Rather than the rotating
+//! presence layout, each friend always lights the same pixel in their colour while they are
+//! around, so you can tell at a glance who is here.
+//!
+//! A friend's pixel is either given in [souls.toml](../../souls.toml) by advertised name or
+//! hashed from the stable ID in their beacon. Our Bluetooth address changes at every restart, so
+//! with [CONSTELLATION_ENABLED](crate::configuration::CONSTELLATION_ENABLED) set and a group key
+//! we put a [stable_id] in our beacon. It is a hash of the factory MAC address keyed with the group
+//! key, so it doesn't give the address away, and it is [masked](mask) with the group key and our
+//! current address before it goes out. The masked ID changes along with the address, so only our
+//! group can tell that it is still us after a restart.
+
+use crate::command::{GroupKey, tag};
+use crate::configuration::LED_STRING_SIZE;
+use trouble_host::prelude::BdAddr;
+
+/// Our stable ID, a hash of the factory MAC address keyed with the group key. It stays the same
+/// across restarts, but without the key there is no getting back to the address.
+///
+/// # Parameters
+/// * `mac` - The base MAC address burned into the efuses
+/// * `key` - Our group key
+pub fn stable_id(mac: &[u8; 6], key: &GroupKey) -> u32 {
+    let [a, b, c, d, e, f] = *mac;
+    u32::from_le_bytes(tag(key, &[b'I', a, b, c, d, e, f]))
+}
+
+/// Mask a stable ID for our beacon, or unmask one from somebody else's. The mask is made from the
+/// group key and the address the beacon is sent from, so it is different after every restart.
+///
+/// # Parameters
+/// * `stable_id` - The stable ID, or the masked one from a beacon
+/// * `address` - The address of the soul the ID belongs to
+/// * `key` - Our group key
+pub fn mask(stable_id: u32, address: &BdAddr, key: &GroupKey) -> u32 {
+    let mut body = [b'M'; 7];
+    body[1..].copy_from_slice(address.raw());
+    stable_id ^ u32::from_le_bytes(tag(key, &body))
+}
+
+/// The pixel for a friend without one of their own in the configuration
+///
+/// # Parameters
+/// * `stable_id` - The stable ID from the friend's beacon
+pub fn hashed_pixel(stable_id: u32) -> u16 {
+    // Stir the ID first, so that IDs that only differ in their low bits still spread out
+    (stable_id.wrapping_mul(0x9E37_79B1) >> 16) as u16 % LED_STRING_SIZE as u16
+}
+
+/// Find the pixel a friend is drawn on. Two friends can hash to the same pixel, in which case
+/// the later one moves along to the next free pixel. None if the strip is already full.
+///
+/// # Parameters
+/// * `pixel` - The friend's own pixel
+/// * `taken` - The pixels that already have somebody on them
+pub fn place(pixel: u16, taken: &[bool; LED_STRING_SIZE]) -> Option<usize> {
+    (0..LED_STRING_SIZE)
+        .map(|offset| (pixel as usize + offset) % LED_STRING_SIZE)
+        .find(|&p| !taken[p])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_stable_ids_are_stable() {
+        let key = [7; 16];
+        let mac = [0x40, 0x4C, 0xCA, 0x01, 0xAB, 0xFF];
+        assert_eq!(stable_id(&mac, &key), stable_id(&mac, &key));
+        assert_ne!(stable_id(&mac, &key), stable_id(&[0x40, 0x4C, 0xCA, 0x01, 0xAB, 0xFE], &key));
+        // Another group can't work it out
+        assert_ne!(stable_id(&mac, &key), stable_id(&mac, &[8; 16]));
+        // Neighbouring IDs don't all land on the same pixel
+        let pixels: heapless::Vec<u16, 8> = (0..8).map(hashed_pixel).collect();
+        assert!(pixels.iter().any(|&p| p != pixels[0]));
+        assert!(pixels.iter().all(|&p| (p as usize) < LED_STRING_SIZE));
+    }
+
+    #[test]
+    pub fn if_only_the_group_can_unmask_the_id() {
+        let key = [7; 16];
+        let id = stable_id(&[0x40, 0x4C, 0xCA, 0x01, 0xAB, 0xFF], &key);
+        let before = BdAddr::new([0xC1, 2, 3, 4, 5, 6]);
+        let after = BdAddr::new([0xC9, 8, 7, 6, 5, 4]);
+        // The ID looks different after a restart
+        assert_ne!(mask(id, &before, &key), mask(id, &after, &key));
+        // But the group can still see it is the same soul
+        assert_eq!(mask(mask(id, &before, &key), &before, &key), id);
+        assert_eq!(mask(mask(id, &after, &key), &after, &key), id);
+        assert_ne!(mask(mask(id, &after, &key), &after, &[8; 16]), id);
+    }
+
+    #[test]
+    pub fn if_friends_share_a_pixel_one_moves_along() {
+        let mut taken = [false; LED_STRING_SIZE];
+        assert_eq!(place(3, &taken), Some(3));
+        taken[3] = true;
+        assert_eq!(place(3, &taken), Some(4));
+        taken[LED_STRING_SIZE - 1] = true;
+        assert_eq!(place(LED_STRING_SIZE as u16 - 1, &taken), Some(0));
+        assert_eq!(place(0, &[true; LED_STRING_SIZE]), None);
+    }
+}
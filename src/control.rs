@@ -0,0 +1,106 @@
+//! Writes to the control GATT service (see `soul_link.rs`), which only the souls and phones with
+//! our group key may make.
+//!
+//! We never bond, so the link isn't encrypted and anybody in range could connect and write. Each
+//! connection is given a random [nonce](CONTROL_NONCE_SIZE) to read first, and every value
+//! written must be followed by a tag made with the group key over the characteristic, the nonce
+//! and the value. Anything else is ignored, as is every write when we have no group key. A write
+//! overheard on one connection is no good on the next. Like [commands](crate::command), the tag
+//! stops pranks rather than a determined attacker.
+
+use crate::command::{GroupKey, tag};
+use heapless::Vec;
+
+/// Size of the tag after each value
+pub const CONTROL_TAG_SIZE: usize = 4;
+
+/// Size of the nonce each connection is given
+pub const CONTROL_NONCE_SIZE: usize = 4;
+
+/// The longest value a control characteristic takes
+const MAX_VALUE: usize = 8;
+
+/// The control characteristics that can be written, numbered like their UUIDs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ControlWrite {
+    /// Log level for a module, see [apply_log_command](crate::logging::apply_log_command)
+    LogLevel = 0x04,
+    /// Animation speed, see [TuningParam::Speed](crate::tuning::TuningParam::Speed)
+    Speed = 0x06,
+    /// Sparkle density, see [TuningParam::Density](crate::tuning::TuningParam::Density)
+    Density = 0x07,
+    /// Tail length, see [TuningParam::Tail](crate::tuning::TuningParam::Tail)
+    Tail = 0x08,
+    /// Colour for the animations, see [TuningParam::Palette](crate::tuning::TuningParam::Palette)
+    Palette = 0x09,
+    /// One of the intervals, see [interval_command](crate::intervals::interval_command)
+    Intervals = 0x0a,
+    /// Our own colour, see `change_colour` in `display_task.rs`
+    Colour = 0x0b,
+}
+
+/// The tag for a value written to a control characteristic
+///
+/// # Arguments
+/// * `key` - Our group key
+/// * `characteristic` - The characteristic written to
+/// * `nonce` - The nonce given to the connection
+/// * `value` - The value written
+pub fn control_tag(
+    key: &GroupKey,
+    characteristic: ControlWrite,
+    nonce: &[u8; CONTROL_NONCE_SIZE],
+    value: &[u8],
+) -> [u8; CONTROL_TAG_SIZE] {
+    let mut body: Vec<u8, { 2 + CONTROL_NONCE_SIZE + MAX_VALUE }> = Vec::new();
+    let _ = body.push(b'W');
+    let _ = body.push(characteristic as u8);
+    let _ = body.extend_from_slice(nonce);
+    // A longer value is never accepted, see control_value
+    let _ = body.extend_from_slice(value);
+    tag(key, &body)
+}
+
+/// Check the tag on a write to a control characteristic
+///
+/// # Arguments
+/// * `data` - What was written, the value followed by its tag
+/// * `characteristic` - The characteristic written to
+/// * `nonce` - The nonce given to the connection
+/// * `key` - Our group key, None if we don't have one
+///
+/// # Returns
+/// The value written, or None if it wasn't tagged with our group key
+pub fn control_value<'a>(
+    data: &'a [u8],
+    characteristic: ControlWrite,
+    nonce: &[u8; CONTROL_NONCE_SIZE],
+    key: Option<&GroupKey>,
+) -> Option<&'a [u8]> {
+    let (value, received) = data.split_at_checked(data.len().checked_sub(CONTROL_TAG_SIZE)?)?;
+    (value.len() <= MAX_VALUE && *received == control_tag(key?, characteristic, nonce, value)).then_some(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_only_the_group_can_write() {
+        let key = [7; 16];
+        let nonce = [1, 2, 3, 4];
+        let mut data = [150, 0, 0, 0, 0];
+        data[1..].copy_from_slice(&control_tag(&key, ControlWrite::Speed, &nonce, &[150]));
+        assert_eq!(control_value(&data, ControlWrite::Speed, &nonce, Some(&key)), Some(&[150][..]));
+        // Not without the key, on another connection or to another characteristic
+        assert_eq!(control_value(&data, ControlWrite::Speed, &nonce, Some(&[8; 16])), None);
+        assert_eq!(control_value(&data, ControlWrite::Speed, &nonce, None), None);
+        assert_eq!(control_value(&data, ControlWrite::Speed, &[1, 2, 3, 5], Some(&key)), None);
+        assert_eq!(control_value(&data, ControlWrite::Density, &nonce, Some(&key)), None);
+        // Nor with the value changed or the tag cut short
+        data[0] = 255;
+        assert_eq!(control_value(&data, ControlWrite::Speed, &nonce, Some(&key)), None);
+        assert_eq!(control_value(&data[..3], ControlWrite::Speed, &nonce, Some(&key)), None);
+    }
+}
@@ -0,0 +1,354 @@
+//! Crew friend finder, so the whole crew helps whoever is playing hot/cold find their soul.
+//!
+//! The hunter puts the tracker key of the soul it is looking for in its beacon. Crew members who
+//! hear that and can see the soul themselves answer with a [Report] of their path loss to it in
+//! their own beacons. Tracker keys come from the Bluetooth address, which everybody hears the
+//! same, so every member means the same soul by a key. The hunter combines the reports with its
+//! own reading into a [Zone]: the soul is nearest to us, nearest to one of the crew, or nobody
+//! can hear it. The hot/cold game then points the way, to the crew member in whose zone the soul
+//! is until it is in ours (see [hot_cold](crate::animations::HotColdAnimation)).
+//!
+//! The crew is the souls with our group key. Their beacons carry a [group_id] made from it, and
+//! hunts and reports are tagged with the key and the address of the member that sent them, like
+//! [commands](crate::command). Anybody else can copy the group ID, but not the tags, so their
+//! hunts and reports are ignored. The finder is off unless
+//! [CREW_FINDER_ENABLED](crate::configuration::CREW_FINDER_ENABLED) is set.
+
+use crate::command::{GroupKey, tag};
+use crate::configuration::{CREW_MARGIN, CREW_REPORT_TIMEOUT, CREW_SIZE};
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smart_leds::RGB8;
+use trouble_host::prelude::BdAddr;
+
+/// Size of an encoded hunt, tag included
+pub const HUNT_SIZE: usize = 8;
+
+/// Size of an encoded [Report], tag included
+pub const REPORT_SIZE: usize = 9;
+
+/// A crew member's path loss to the soul the crew is looking for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// Tracker key of the soul
+    pub target: u32,
+    /// Path loss to it in dB, mapped onto the nominal path-loss model
+    pub loss: u8,
+}
+
+impl Report {
+    /// The target, little endian, then the loss and the tag
+    ///
+    /// # Parameters
+    /// * `key` - Our group key
+    /// * `sender` - Our address
+    pub fn encode(&self, key: &GroupKey, sender: &BdAddr) -> [u8; REPORT_SIZE] {
+        let [a, b, c, d] = self.target.to_le_bytes();
+        let body = [a, b, c, d, self.loss];
+        let [e, f, g, h] = crew_tag(key, FieldKind::Report, &body, sender);
+        [a, b, c, d, self.loss, e, f, g, h]
+    }
+
+    /// Decode a report, None if it is the wrong size or wasn't tagged by a member of our crew
+    ///
+    /// # Parameters
+    /// * `data` - The report from the beacon
+    /// * `key` - Our group key
+    /// * `sender` - The address of the soul that sent it
+    pub fn decode(data: &[u8], key: &GroupKey, sender: &BdAddr) -> Option<Self> {
+        let [a, b, c, d, loss, ref received @ ..] = *data else {
+            return None;
+        };
+        (*received == crew_tag(key, FieldKind::Report, &data[..5], sender)).then(|| Self {
+            target: u32::from_le_bytes([a, b, c, d]),
+            loss,
+        })
+    }
+}
+
+/// What a crew tag is for, so a hunt can't be passed off as a report
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum FieldKind {
+    Hunt = 1,
+    Report = 2,
+}
+
+/// The tag for a hunt or a report. It covers the sender's address, so a hunt or report heard from
+/// one member can't be sent on as somebody else's.
+fn crew_tag(key: &GroupKey, kind: FieldKind, body: &[u8], sender: &BdAddr) -> [u8; 4] {
+    let mut data: Vec<u8, 12> = Vec::new();
+    // At most 12 bytes, as the body is never more than 5
+    let _ = data.push(kind as u8);
+    let _ = data.extend_from_slice(body);
+    let _ = data.extend_from_slice(sender.raw());
+    tag(key, &data)
+}
+
+/// Encode the soul we are hunting for our beacon, tagged with the group key
+///
+/// # Parameters
+/// * `target` - Tracker key of the soul
+/// * `key` - Our group key
+/// * `sender` - Our address
+pub fn encode_hunt(target: u32, key: &GroupKey, sender: &BdAddr) -> [u8; HUNT_SIZE] {
+    let [a, b, c, d] = target.to_le_bytes();
+    let [e, f, g, h] = crew_tag(key, FieldKind::Hunt, &[a, b, c, d], sender);
+    [a, b, c, d, e, f, g, h]
+}
+
+/// Decode the soul a crew member is hunting, None if it wasn't tagged by a member of our crew
+///
+/// # Parameters
+/// * `data` - The hunt from the beacon
+/// * `key` - Our group key
+/// * `sender` - The address of the soul that sent it
+pub fn decode_hunt(data: &[u8], key: &GroupKey, sender: &BdAddr) -> Option<u32> {
+    let [a, b, c, d, ref received @ ..] = *data else {
+        return None;
+    };
+    (*received == crew_tag(key, FieldKind::Hunt, &data[..4], sender)).then(|| u32::from_le_bytes([a, b, c, d]))
+}
+
+/// The group ID our crew's beacons carry. It is made from the group key so that it says nothing
+/// about the key itself.
+pub fn group_id(key: &GroupKey) -> u16 {
+    let [a, b, ..] = tag(key, b"crew");
+    u16::from_le_bytes([a, b])
+}
+
+/// The soul we are hunting, for our beacon
+static HUNT: Mutex<CriticalSectionRawMutex, Cell<Option<u32>>> = Mutex::new(Cell::new(None));
+
+/// What the crew has asked us to look out for
+#[derive(Clone, Copy)]
+struct Request {
+    target: u32,
+    asked: Instant,
+    /// Our latest path loss to the target and when we measured it
+    heard: Option<(u8, Instant)>,
+}
+
+/// The latest soul the crew asked us to look out for
+static REQUEST: Mutex<CriticalSectionRawMutex, Cell<Option<Request>>> = Mutex::new(Cell::new(None));
+
+/// Set the soul we are hunting, None once we stop
+pub fn set_hunt(target: Option<u32>) {
+    HUNT.lock(|h| h.set(target));
+}
+
+/// The soul we are hunting, for our beacon
+pub fn hunt() -> Option<u32> {
+    HUNT.lock(|h| h.get())
+}
+
+/// A crew member asked for help finding a soul
+///
+/// # Parameters
+/// * `target` - Tracker key of the soul
+/// * `now` - When we heard the request
+pub fn asked(target: u32, now: Instant) {
+    REQUEST.lock(|r| {
+        let heard = r.get().filter(|r| r.target == target).and_then(|r| r.heard);
+        r.set(Some(Request {
+            target,
+            asked: now,
+            heard,
+        }));
+    });
+}
+
+/// Note our path loss to a soul we heard, in case it is the one the crew is looking for
+///
+/// # Parameters
+/// * `key` - Tracker key of the soul
+/// * `loss` - Path loss to it in dB, mapped onto the nominal path-loss model
+/// * `now` - When we heard it
+pub fn heard(key: u32, loss: i32, now: Instant) {
+    REQUEST.lock(|r| {
+        if let Some(mut request) = r.get()
+            && request.target == key
+        {
+            request.heard = Some((loss.clamp(0, u8::MAX as i32) as u8, now));
+            r.set(Some(request));
+        }
+    });
+}
+
+/// Our report for the crew, None unless we were asked and have heard the soul lately
+pub fn our_report(now: Instant) -> Option<Report> {
+    let request = REQUEST.lock(|r| r.get())?;
+    let (loss, at) = request.heard?;
+    let recent = |then: Instant| now.saturating_duration_since(then) <= Duration::from_secs(CREW_REPORT_TIMEOUT);
+    (recent(request.asked) && recent(at)).then_some(Report {
+        target: request.target,
+        loss,
+    })
+}
+
+/// Where the soul being hunted is, as best the crew can tell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zone {
+    /// Nobody can hear it
+    Lost,
+    /// It is nearer to us than to anybody else in the crew
+    Ours,
+    /// It is nearest to this crew member
+    Member { key: u32, colour: RGB8 },
+}
+
+/// The latest report from a crew member
+#[derive(Clone, Copy, Debug)]
+struct MemberReport {
+    key: u32,
+    colour: RGB8,
+    loss: u8,
+    at: Instant,
+}
+
+/// Gathers the crew's reports on one soul and works out which zone it is in
+#[derive(Clone, Debug)]
+pub struct CrewFinder {
+    target: u32,
+    reports: Vec<MemberReport, CREW_SIZE>,
+}
+
+impl CrewFinder {
+    /// Start looking for a soul
+    ///
+    /// # Arguments
+    /// * `target` - Tracker key of the soul
+    pub fn new(target: u32) -> Self {
+        Self {
+            target,
+            reports: Vec::new(),
+        }
+    }
+
+    /// Take in a report from a crew member. Reports on other souls are ignored.
+    ///
+    /// # Arguments
+    /// * `member` - Tracker key of the crew member
+    /// * `colour` - The crew member's colour
+    /// * `report` - What it reported
+    /// * `now` - When the report arrived
+    pub fn hear(&mut self, member: u32, colour: RGB8, report: Report, now: Instant) {
+        if report.target != self.target {
+            return;
+        }
+        let fresh = MemberReport {
+            key: member,
+            colour,
+            loss: report.loss,
+            at: now,
+        };
+        if let Some(known) = self.reports.iter_mut().find(|r| r.key == member) {
+            *known = fresh;
+        } else if let Err(fresh) = self.reports.push(fresh)
+            && let Some(stalest) = self.reports.iter_mut().min_by_key(|r| r.at)
+        {
+            *stalest = fresh;
+        }
+    }
+
+    /// Which zone the soul is in. A crew member has to be [CREW_MARGIN] dB nearer to it than we
+    /// are to take it out of our zone, so the hint doesn't flick back and forth.
+    ///
+    /// # Arguments
+    /// * `own` - Our own path loss to the soul, None if we can't hear it
+    /// * `now` - The time now. Reports older than [CREW_REPORT_TIMEOUT] seconds are ignored.
+    pub fn zone(&self, own: Option<i32>, now: Instant) -> Zone {
+        let timeout = Duration::from_secs(CREW_REPORT_TIMEOUT);
+        let nearest = self
+            .reports
+            .iter()
+            .filter(|r| now.saturating_duration_since(r.at) <= timeout)
+            .min_by_key(|r| r.loss);
+        match (own, nearest) {
+            (Some(own), Some(member)) if (member.loss as i32) + CREW_MARGIN <= own => Zone::Member {
+                key: member.key,
+                colour: member.colour,
+            },
+            (Some(_), _) => Zone::Ours,
+            (None, Some(member)) => Zone::Member {
+                key: member.key,
+                colour: member.colour,
+            },
+            (None, None) => Zone::Lost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report(target: u32, loss: u8) -> Report {
+        Report { target, loss }
+    }
+
+    #[test]
+    pub fn if_reports_round_trip() {
+        let key = [7; 16];
+        let member = BdAddr::new([1, 2, 3, 4, 5, 6]);
+        let r = report(0xDEADBEEF, 72);
+        assert_eq!(Report::decode(&r.encode(&key, &member), &key, &member), Some(r));
+        assert_eq!(Report::decode(&r.encode(&key, &member)[..4], &key, &member), None);
+        assert_eq!(decode_hunt(&encode_hunt(0xDEADBEEF, &key, &member), &key, &member), Some(0xDEADBEEF));
+    }
+
+    #[test]
+    pub fn if_only_the_crew_can_hunt_and_report() {
+        let key = [7; 16];
+        let member = BdAddr::new([1, 2, 3, 4, 5, 6]);
+        let stranger = BdAddr::new([6, 5, 4, 3, 2, 1]);
+        let r = report(0xDEADBEEF, 72);
+        let hunt = encode_hunt(0xDEADBEEF, &key, &member);
+        // Somebody without the key
+        assert_eq!(Report::decode(&r.encode(&[8; 16], &member), &key, &member), None);
+        assert_eq!(decode_hunt(&encode_hunt(0xDEADBEEF, &[8; 16], &member), &key, &member), None);
+        // Somebody sending on what they heard from a member
+        assert_eq!(Report::decode(&r.encode(&key, &member), &key, &stranger), None);
+        assert_eq!(decode_hunt(&hunt, &key, &stranger), None);
+        // A hunt is not a report
+        let mut forged = [0; REPORT_SIZE];
+        forged[..HUNT_SIZE].copy_from_slice(&hunt);
+        assert_eq!(Report::decode(&forged, &key, &member), None);
+    }
+
+    #[test]
+    pub fn if_the_nearest_member_has_the_zone() {
+        let now = Instant::from_secs(100);
+        let (red, green) = (RGB8::new(255, 0, 0), RGB8::new(0, 255, 0));
+        let mut finder = CrewFinder::new(7);
+        assert_eq!(finder.zone(None, now), Zone::Lost);
+        assert_eq!(finder.zone(Some(80), now), Zone::Ours);
+        finder.hear(1, red, report(7, 70), now);
+        finder.hear(2, green, report(7, 60), now);
+        finder.hear(3, green, report(8, 40), now);
+        assert_eq!(finder.zone(Some(80), now), Zone::Member { key: 2, colour: green });
+        assert_eq!(finder.zone(None, now), Zone::Member { key: 2, colour: green });
+        // Only a member that is clearly nearer takes it out of our zone
+        assert_eq!(finder.zone(Some(60 + CREW_MARGIN - 1), now), Zone::Ours);
+        // Old reports are forgotten
+        let later = now + Duration::from_secs(CREW_REPORT_TIMEOUT + 1);
+        finder.hear(1, red, report(7, 70), later);
+        assert_eq!(finder.zone(None, later), Zone::Member { key: 1, colour: red });
+    }
+
+    #[test]
+    pub fn if_we_only_report_when_asked() {
+        let now = Instant::from_secs(100);
+        heard(5, 60, now);
+        assert_eq!(our_report(now), None);
+        asked(5, now);
+        assert_eq!(our_report(now), None);
+        heard(5, 300, now);
+        assert_eq!(our_report(now), Some(report(5, 255)));
+        let later = now + Duration::from_secs(CREW_REPORT_TIMEOUT + 1);
+        assert_eq!(our_report(later), None);
+    }
+}
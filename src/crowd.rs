@@ -0,0 +1,136 @@
+//! Crowd-adaptive advertising. In a dense crowd the channel is already busy with everybody's
+//! beacons, so we advertise less often to save airtime and battery. When we are alone we
+//! advertise more often so that we are discovered quickly when someone turns up.
+//!
+//! The display task publishes the number of souls it is tracking here and the BLE task picks
+//! it up. Only the latest value matters, so a single atomic is all we need. It publishes the
+//! number of different souls we see a minute the same way. With
+//! [CROWD_BY_RATE](crate::configuration::CROWD_BY_RATE) set, that is what measures the crowd,
+//! see [crowd_density].
+//!
+//! The display task also reads the room with a [MoodDetector]: a big enough crowd switches us to
+//! livelier animations until it has thinned out again.
+
+use crate::configuration::{
+    ADVERTISING_INTERVAL_MAX, ADVERTISING_INTERVAL_MIN, ADVERTISING_INTERVAL_STEP, ADVERTISING_SPREAD, CALM_CROWD,
+    CROWD_BY_RATE, LIVELY_CROWD,
+};
+use crate::telemetry::{Gauge, set_gauge};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use defmt::Format;
+use embassy_time::Duration;
+
+/// The number of souls we can currently see
+static CROWD_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Publish the number of souls we can see
+pub fn set_crowd_size(souls: usize) {
+    CROWD_SIZE.store(souls, Ordering::Relaxed);
+    set_gauge(Gauge::SoulsTracked, souls as u32);
+}
+
+/// The number of souls we can see
+pub fn crowd_size() -> usize {
+    CROWD_SIZE.load(Ordering::Relaxed)
+}
+
+/// The number of different souls we have been seeing a minute
+static SOULS_PER_MINUTE: AtomicUsize = AtomicUsize::new(0);
+
+/// Publish the number of different souls we are seeing a minute
+pub fn set_souls_per_minute(souls: usize) {
+    SOULS_PER_MINUTE.store(souls, Ordering::Relaxed);
+    set_gauge(Gauge::SoulsPerMinute, souls as u32);
+}
+
+/// The number of different souls we are seeing a minute
+pub fn souls_per_minute() -> usize {
+    SOULS_PER_MINUTE.load(Ordering::Relaxed)
+}
+
+/// How big the crowd is for the crowd-adaptive features: the souls we can see right now, or the
+/// souls we are seeing a minute if [CROWD_BY_RATE] is set
+pub fn crowd_density() -> usize {
+    if CROWD_BY_RATE {
+        souls_per_minute()
+    } else {
+        crowd_size()
+    }
+}
+
+/// The advertising interval range to use for the size of the crowd. It grows by
+/// [ADVERTISING_INTERVAL_STEP] for every soul we can see, up to [ADVERTISING_INTERVAL_MAX].
+///
+/// # Parameters
+/// * `souls` - The number of souls we can see
+///
+/// # Returns
+/// The minimum and maximum advertising interval
+pub fn advertising_interval(souls: usize) -> (Duration, Duration) {
+    let min = (ADVERTISING_INTERVAL_MIN + ADVERTISING_INTERVAL_STEP * souls as u64).min(ADVERTISING_INTERVAL_MAX);
+    (Duration::from_millis(min), Duration::from_millis(min + ADVERTISING_SPREAD))
+}
+
+/// How lively the crowd around us is
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Format)]
+pub enum CrowdMood {
+    #[default]
+    Calm,
+    Lively,
+}
+
+/// Turns the crowd size into a [CrowdMood]. The crowd turns lively at [LIVELY_CROWD] souls and
+/// only calms down again below [CALM_CROWD], so a crowd hovering around the threshold doesn't
+/// flip us back and forth.
+#[derive(Default)]
+pub struct MoodDetector {
+    mood: CrowdMood,
+}
+
+impl MoodDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the latest crowd size into account
+    ///
+    /// # Returns
+    /// The new mood if it changed
+    pub fn update(&mut self, souls: usize) -> Option<CrowdMood> {
+        let mood = match self.mood {
+            CrowdMood::Calm if souls >= LIVELY_CROWD => CrowdMood::Lively,
+            CrowdMood::Lively if souls < CALM_CROWD => CrowdMood::Calm,
+            mood => mood,
+        };
+        (mood != self.mood).then(|| {
+            self.mood = mood;
+            mood
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_the_mood_has_hysteresis() {
+        let mut moods = MoodDetector::new();
+        assert_eq!(moods.update(LIVELY_CROWD - 1), None);
+        assert_eq!(moods.update(LIVELY_CROWD), Some(CrowdMood::Lively));
+        assert_eq!(moods.update(LIVELY_CROWD + 3), None);
+        assert_eq!(moods.update(CALM_CROWD), None);
+        assert_eq!(moods.update(CALM_CROWD - 1), Some(CrowdMood::Calm));
+    }
+
+    #[test]
+    pub fn if_crowds_slow_us_down() {
+        let (alone, _) = advertising_interval(0);
+        let (crowded, crowded_max) = advertising_interval(5);
+        assert_eq!(alone, Duration::from_millis(ADVERTISING_INTERVAL_MIN));
+        assert!(crowded > alone);
+        assert!(crowded_max > crowded);
+        let (packed, _) = advertising_interval(1000);
+        assert_eq!(packed, Duration::from_millis(ADVERTISING_INTERVAL_MAX));
+    }
+}
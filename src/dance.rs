@@ -0,0 +1,113 @@
+//! The dance meter, a fun personal metric of how much the wearer has moved about today.
+//!
+//! The IMU task (see `imu.rs`) adds up how much the acceleration changes from one reading to the
+//! next, ignoring the jitter of a star sitting still, and the dance meter animation (see
+//! [dance_meter](crate::animations::DanceMeterAnimation)) fills up along the strip as it grows.
+//! It is full after about [DANCE_FULL] milli-g of movement, a couple of hours of solid dancing.
+//!
+//! A day is [DANCE_DAY] hours of the [shared clock](crate::clock) rather than of our own uptime,
+//! so a restart doesn't start the day again and the meters of a group empty together. The meter
+//! is saved with the [settings](crate::settings) every [DANCE_SAVE_INTERVAL] seconds, and a saved
+//! meter carries on as long as the shared clock is still in the same day.
+//!
+//! [DANCE_SAVE_INTERVAL]: crate::configuration::DANCE_SAVE_INTERVAL
+
+use crate::clock::shared_now;
+use crate::configuration::{DANCE_DAY, DANCE_FULL, DANCE_NOISE};
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+/// How much the wearer has moved in one day
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DanceMeter {
+    /// The day of the shared clock, see [day]
+    pub day: u8,
+    /// The movement so far that day (milli-g)
+    pub energy: u32,
+}
+
+impl DanceMeter {
+    pub const fn new() -> Self {
+        Self { day: 0, energy: 0 }
+    }
+
+    /// Add the movement in one reading, starting again from empty if the day has moved on
+    ///
+    /// # Parameters
+    /// * `jerk` - How far the reading is from the last one, see [Acceleration::jerk]
+    /// * `day` - The day of the reading, see [day]
+    ///
+    /// [Acceleration::jerk]: crate::motion::Acceleration::jerk
+    pub fn add(&mut self, jerk: u32, day: u8) {
+        if day != self.day {
+            *self = Self { day, energy: 0 };
+        }
+        self.energy = self.energy.saturating_add(jerk.saturating_sub(DANCE_NOISE));
+    }
+
+    /// How full the meter is, from 0 (not moved at all today) to 255 (danced out)
+    pub fn level(&self) -> u8 {
+        (self.energy.min(DANCE_FULL) as u64 * 255 / DANCE_FULL as u64) as u8
+    }
+}
+
+/// The day of the shared clock a shared time falls in. The shared clock wraps after about 49
+/// days, which still fits.
+pub fn day(shared: u32) -> u8 {
+    (shared / (DANCE_DAY as u32 * 3_600_000)) as u8
+}
+
+/// Our dance meter
+static METER: Mutex<CriticalSectionRawMutex, Cell<DanceMeter>> = Mutex::new(Cell::new(DanceMeter::new()));
+
+/// Add the movement in one reading to our meter, see [DanceMeter::add]
+pub fn add_movement(jerk: u32) {
+    let today = day(shared_now());
+    METER.lock(|m| {
+        let mut meter = m.get();
+        meter.add(jerk, today);
+        m.set(meter);
+    });
+}
+
+/// How full our dance meter is, see [DanceMeter::level]
+pub fn dance_level() -> u8 {
+    METER.lock(|m| m.get()).level()
+}
+
+/// Our dance meter as it is now, for saving
+pub fn dance_meter() -> DanceMeter {
+    METER.lock(|m| m.get())
+}
+
+/// Put back the dance meter that was saved before the restart
+pub fn restore_dance_meter(meter: DanceMeter) {
+    METER.lock(|m| m.set(meter));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_fills_up_and_starts_again_each_day() {
+        let mut meter = DanceMeter::new();
+        // Sitting still doesn't count
+        meter.add(DANCE_NOISE, 0);
+        assert_eq!(meter.level(), 0);
+        meter.add(DANCE_NOISE + DANCE_FULL / 2, 0);
+        assert_eq!(meter.level(), 127);
+        meter.add(DANCE_FULL, 0);
+        assert_eq!(meter.level(), 255);
+        meter.add(0, day(DANCE_DAY as u32 * 3_600_000));
+        assert_eq!(meter.level(), 0);
+    }
+
+    #[test]
+    pub fn if_days_follow_the_shared_clock() {
+        assert_eq!(day(0), 0);
+        assert_eq!(day(DANCE_DAY as u32 * 3_600_000 - 1), 0);
+        assert_eq!(day(DANCE_DAY as u32 * 3_600_000), 1);
+    }
+}
@@ -0,0 +1,35 @@
+//! What we tell a BLE browser about ourselves through the Device Information Service, so that
+//! a fleet can be inventoried and a misbehaving star identified without a custom app.
+
+use heapless::String;
+
+/// The firmware version from the Cargo metadata
+pub const FIRMWARE_REVISION: &str = env!("CARGO_PKG_VERSION");
+
+/// Length of a serial number, two hex digits per byte of the MAC
+pub const SERIAL_NUMBER_SIZE: usize = 12;
+
+/// The serial number for a chip, which is its factory MAC address in hex. We advertise with a
+/// random address, so this is the only thing that stays the same across restarts.
+///
+/// # Parameters
+/// * `mac` - The base MAC address burned into the efuses
+pub fn serial_number(mac: [u8; 6]) -> String<SERIAL_NUMBER_SIZE> {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut serial = String::new();
+    for byte in mac {
+        let _ = serial.push(HEX[(byte >> 4) as usize] as char);
+        let _ = serial.push(HEX[(byte & 0x0F) as usize] as char);
+    }
+    serial
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_serial_is_the_mac_in_hex() {
+        assert_eq!(serial_number([0x40, 0x4C, 0xCA, 0x01, 0xAB, 0xFF]).as_str(), "404CCA01ABFF");
+    }
+}
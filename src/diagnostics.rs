@@ -0,0 +1,158 @@
+//! Heap and stack usage monitoring.
+//!
+//! Periodically reports how much of the `esp-alloc` heap is in use (including the high-water
+//! mark) and how deep the main stack has been. All the Embassy tasks run from the one executor
+//! on the main stack, so the stack headroom reported here covers every task we spawn. The stack
+//! is measured by painting the unused part with a known pattern at start-up and then looking
+//! for the deepest word that has been overwritten.
+//!
+//! The render times of each animation are reported alongside, see [profiling](soulstar::profiling).
+
+use core::cell::Cell;
+use defmt::Format;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Ticker};
+use heapless::Vec;
+use soulstar::configuration::DIAGNOSTICS_INTERVAL;
+use soulstar::log;
+use soulstar::profiling::{MAX_PROFILED, with_profiles};
+use soulstar::telemetry::telemetry;
+
+/// Pattern we fill the unused stack with
+const STACK_PAINT: u32 = 0xA5A5_A5A5;
+
+/// Leave the bottom of the stack alone as esp-hal places its stack guard there
+const STACK_GUARD_MARGIN: usize = 256;
+
+/// Space to leave below the stack pointer while painting
+const STACK_PAINT_MARGIN: usize = 64;
+
+unsafe extern "C" {
+    // Stack boundaries for the main stack as defined by the esp-hal linker scripts
+    static _stack_start_cpu0: u32;
+    static _stack_end_cpu0: u32;
+}
+
+/// Snapshot of our memory usage. All values are in bytes.
+#[derive(Clone, Copy, Format)]
+pub struct MemoryStats {
+    /// Total size of the heap
+    pub heap_size: usize,
+    /// Heap currently in use
+    pub heap_used: usize,
+    /// Heap currently available
+    pub heap_free: usize,
+    /// The most heap that has ever been in use
+    pub heap_high_water: usize,
+    /// Total size of the main stack
+    pub stack_size: usize,
+    /// The deepest the stack has been since start-up
+    pub stack_high_water: usize,
+}
+
+impl MemoryStats {
+    /// How much stack has never been touched
+    pub fn stack_headroom(&self) -> usize {
+        self.stack_size.saturating_sub(self.stack_high_water)
+    }
+}
+
+/// The most recent report so others can query it without waiting for the next report
+static LATEST_STATS: Mutex<CriticalSectionRawMutex, Cell<Option<MemoryStats>>> = Mutex::new(Cell::new(None));
+
+/// Returns the most recent memory statistics or None if no report has been made yet
+pub fn latest_stats() -> Option<MemoryStats> {
+    LATEST_STATS.lock(|s| s.get())
+}
+
+/// Diagnostics task. Paints the stack and then reports memory usage, the telemetry and the
+/// animation render times every [DIAGNOSTICS_INTERVAL] seconds.
+#[embassy_executor::task]
+pub async fn diagnostics_task() {
+    paint_stack();
+    let mut ticker = Ticker::every(Duration::from_secs(DIAGNOSTICS_INTERVAL));
+    loop {
+        ticker.next().await;
+        let stats = memory_stats();
+        log!(
+            Diagnostics,
+            info,
+            "DIAGNOSTICS: Heap {}/{} bytes used ({} free, high-water {}). Stack {}/{} bytes used ({} headroom)",
+            stats.heap_used,
+            stats.heap_size,
+            stats.heap_free,
+            stats.heap_high_water,
+            stats.stack_high_water,
+            stats.stack_size,
+            stats.stack_headroom()
+        );
+        log!(Diagnostics, info, "DIAGNOSTICS: {}", telemetry());
+        // Take a copy so we aren't logging inside the critical section
+        let profiles: Vec<_, MAX_PROFILED> = with_profiles(|p| Vec::from_slice(p).unwrap_or_default());
+        for p in profiles {
+            log!(
+                Diagnostics,
+                info,
+                "DIAGNOSTICS: {} renders in {}us (max {}us) and sends in {}us (max {}us) over {} frames",
+                p.name,
+                p.render.mean(),
+                p.render.max,
+                p.transmit.mean(),
+                p.transmit.max,
+                p.render.frames
+            );
+        }
+        LATEST_STATS.lock(|s| s.set(Some(stats)));
+    }
+}
+
+/// Collect the current heap and stack statistics
+fn memory_stats() -> MemoryStats {
+    let heap = esp_alloc::HEAP.stats();
+    let (bottom, top) = stack_bounds();
+    MemoryStats {
+        heap_size: heap.size,
+        heap_used: heap.current_usage,
+        heap_free: esp_alloc::HEAP.free(),
+        heap_high_water: heap.max_usage,
+        stack_size: top - bottom,
+        stack_high_water: top - deepest_stack_use(bottom + STACK_GUARD_MARGIN, top),
+    }
+}
+
+/// Lowest and highest addresses of the main stack
+fn stack_bounds() -> (usize, usize) {
+    let start = &raw const _stack_start_cpu0 as usize;
+    let end = &raw const _stack_end_cpu0 as usize;
+    (start.min(end), start.max(end))
+}
+
+/// Fill everything between the stack guard and the current stack pointer with [STACK_PAINT].
+/// Interrupt handlers run on this stack, so we must not be interrupted while painting.
+fn paint_stack() {
+    let (bottom, _) = stack_bounds();
+    critical_section::with(|_| {
+        let sp: usize;
+        // SAFETY: Just reads the stack pointer
+        unsafe { core::arch::asm!("mv {}, sp", out(reg) sp) };
+        let mut p = (bottom + STACK_GUARD_MARGIN) as *mut u32;
+        while (p as usize) < sp - STACK_PAINT_MARGIN {
+            // SAFETY: The memory lies below the stack pointer inside the stack region so nothing uses it
+            unsafe {
+                p.write_volatile(STACK_PAINT);
+                p = p.add(1);
+            }
+        }
+    });
+}
+
+/// Scan up from the bottom of the painted region for the first word that has been overwritten
+fn deepest_stack_use(from: usize, top: usize) -> usize {
+    let mut p = from as *const u32;
+    // SAFETY: Only reads words inside the stack region
+    while (p as usize) < top && unsafe { p.read_volatile() } == STACK_PAINT {
+        p = unsafe { p.add(1) };
+    }
+    p as usize
+}
@@ -1,14 +1,79 @@
-use crate::animations::{Animation, PresenceAnimation, SparkleAnimation, is_interruptable, next_buffer};
-use crate::configuration::*;
-use crate::led_driver::{LedBuffer, LedDriver};
-use crate::presence::PresenceMessage;
-use crate::tracker::Tracker;
-use defmt::{debug, info};
-use embassy_futures::select::{Either3::*, select3};
+use crate::dmx_output;
+use crate::haptic;
+use crate::led_driver::LedDriver;
+use crate::presence::update_beacon;
+use crate::settings_store::update_settings;
+use crate::trigger_output;
+use core::cell::Cell;
+use defmt::{Debug2Format, Format};
+use embassy_futures::select::{Either4::*, select4};
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::{Channel, Receiver, Sender};
-use embassy_time::{Duration, Ticker};
-use heapless::spsc::Queue;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker, Timer, with_timeout};
+use smart_leds::RGB8;
+use soulstar::animation_queue::AnimationQueue;
+use soulstar::animations::{
+    AlertAnimation, Animation, AnimationKind, ApproachAnimation, BadgeAnimation, BootAnimation, CountdownAnimation,
+    FavouriteAnimation, HotColdAnimation, LIVELY, PresenceAnimation, RssiMeterAnimation, SHUFFLE, SosAnimation,
+    SparkleAnimation, StrobeAnimation, SwirlAnimation, frame_interval, is_interruptable, is_white_heavy, kind, name,
+    next_buffer, postpone, tune,
+};
+use soulstar::auto_brightness::AutoBrightness;
+use soulstar::backpressure::{DropCounts, DropKind, drop_counts, record_drop};
+use soulstar::badges::{Badge, BadgeEngine, unlocked_badges};
+use soulstar::clock::shared_now;
+use soulstar::colour::{LedBuffer, Theme};
+use soulstar::command::{CommandFilter, Effect};
+use soulstar::configuration::*;
+use soulstar::crew::set_hunt;
+use soulstar::crowd::{CrowdMood, MoodDetector, crowd_density, set_crowd_size, set_souls_per_minute};
+use soulstar::fader::Fader;
+use soulstar::frame_clock::FrameClock;
+use soulstar::intervals::{flush_interval, scaled};
+use soulstar::layers::LayerBrightness;
+use soulstar::leader::{Lead, LeadFilter, leading, set_lead};
+use soulstar::log;
+use soulstar::orientation::{orientation, rotate};
+use soulstar::path_loss::{Calibration, CalibrationStep, path_loss, set_path_loss};
+use soulstar::payload::Sighting;
+use soulstar::profiling::record_frame;
+use soulstar::status::StatusFlags;
+use soulstar::throttle::UpdateThrottle;
+use soulstar::torch::{TorchIntensity, TorchPreset};
+use soulstar::tracker::Tracker;
+use soulstar::trigger::TriggerEvent;
+use soulstar::tuning::{Tuning, TuningParam};
+use soulstar::wave::WaveFilter;
+
+/// A snapshot of the display state, returned in response to [DisplayState::GetStatus]
+#[derive(Clone, Copy, Format)]
+pub struct DisplayStatus {
+    /// The brightness we are fading to (or are at)
+    pub brightness: u8,
+    /// True if animations are being updated
+    pub running: bool,
+    /// True if the torch is on
+    pub torch: bool,
+    /// The current torch colour
+    pub torch_preset: TorchPreset,
+    /// The current torch intensity
+    pub torch_intensity: TorchIntensity,
+    /// True if night mode is on
+    pub night_mode: bool,
+    /// True if the brightness follows the ambient light
+    pub auto_brightness: bool,
+    /// Name of the animation currently being displayed
+    pub animation: &'static str,
+    /// Number of animations waiting in the queue
+    pub queue_depth: usize,
+    /// Messages dropped or coalesced on their way to the display since start-up
+    pub drops: DropCounts,
+}
+
+/// Where the display task sends its reply to a [DisplayState::GetStatus] request
+pub type StatusResponder = &'static Signal<CriticalSectionRawMutex, DisplayStatus>;
 
 /// Manage the display state by sending it messages of this type. If anyone asks why I like Rust,
 /// this is one of the many reasons
@@ -22,19 +87,177 @@ pub enum DisplayState {
     Off,
     /// Start the animation again
     On,
-    /// Enable/disable torch function
-    Torch(bool),
-    /// Set the display brightness
+    /// Enable/disable torch function, optionally changing the torch colour. The colour
+    /// is remembered for the next time the torch is switched on.
+    Torch(bool, Option<TorchPreset>),
+    /// Set the torch intensity. It is remembered for the next time the torch is switched on.
+    /// The torch ignores the display brightness.
+    TorchLevel(TorchIntensity),
+    /// Set the display brightness. This turns auto-brightness off.
     Brightness(u8),
+    /// Enable/disable auto-brightness, where the brightness follows the ambient light
+    AutoBrightness(bool),
+    /// The latest ambient light level from the light sensor
+    AmbientLight(u16),
+    /// The wearer has kept still for a while, or has started moving again. The display fades to
+    /// a glow while they keep still and back to where it was when they move.
+    Still(bool),
+    /// Enable/disable night mode. This clamps the brightness of everything to a low ceiling
+    /// and suppresses white-heavy animations and torch colours.
+    NightMode(bool),
     /// Update the presence with a newly received BLE advertisement
-    PresenceUpdate(PresenceMessage),
+    PresenceUpdate(Sighting),
+    /// Change the animation shown when there is nothing else to display
+    SetDefault(AnimationKind),
+    /// Our colour has changed, see [change_colour]. The built-in animations take it up.
+    Colour(RGB8),
+    /// Immediately play an animation for [PLAY_NOW_DURATION] seconds, after which the
+    /// display carries on as before
+    PlayNow(AnimationKind),
+    /// Start or stop party mode, which strobes our colour for [STROBE_DURATION] seconds. It is
+    /// rejected unless [STROBE_ENABLED] is set and never runs in night mode.
+    Party(bool),
+    /// Start or stop the hot/cold friend finder game. The strip shows how close we are to the
+    /// first soul we can see. It is rejected if there is nobody around to find.
+    HotCold(bool),
+    /// Hunt for the next visible soul in the hot/cold game
+    NextSoul,
+    /// Start or stop the signal strength meter, which shows the RSSI of the strongest soul we
+    /// can hear for range-testing antennas and transmitter power settings
+    RssiMeter(bool),
+    /// Enable/disable shuffle mode, which picks a random built-in default animation every
+    /// [SHUFFLE_INTERVAL] seconds while nothing else is being displayed
+    Shuffle(bool),
+    /// Start or stop calling for help. The SOS is flashed in white at the highest brightness
+    /// the power limiter allows, even in night mode, until it is stopped.
+    Sos(bool),
+    /// Enable/disable beacon-only mode. We stop scanning, so the souls we know about are
+    /// forgotten and only the default animation is shown.
+    BeaconOnly(bool),
+    /// Live-tune the animations. The tuning applies to the current and default animations and
+    /// lasts until the next restart.
+    Tune(TuningParam),
+    /// Calibrate the path-loss model from the beacons of the nearest soul, see
+    /// [path_loss](soulstar::path_loss)
+    Calibrate(CalibrationStep),
+    /// Start counting down to a factory reset, or stop if the countdown was cancelled
+    FactoryReset(Option<Duration>),
+    /// We have just waved at the souls around us, see [wave](soulstar::wave). Our colour is
+    /// flashed to show the wave went out.
+    Waving,
+    /// The star has been shaken. A new default animation is picked the same way as in shuffle
+    /// mode and a swirl of our colour confirms it.
+    Shaken,
+    /// A frame from a lighting desk, see [light_show](soulstar::light_show). It is shown as it
+    /// is and our own animations are held off until the desk goes quiet.
+    LightShow(LedBuffer),
+    /// Request a snapshot of the display state. It is signalled on the responder.
+    GetStatus(StatusResponder),
+}
+
+/// The outcome of a command sent to the display task
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum CommandResult {
+    /// The command was carried out
+    Applied,
+    /// The animation queue was full so something had to be dropped
+    QueueFull,
+    /// The command is not allowed in the current state, e.g. a white-heavy animation in night mode
+    Rejected,
+}
+
+/// Where the display task sends the outcome of a command if the sender asked for it
+pub type AckResponder = &'static Signal<CriticalSectionRawMutex, CommandResult>;
+
+/// What actually goes down the display channel. Most senders don't care about the outcome, so
+/// a [DisplayState] can be turned into a command without an acknowledgement using `into()`.
+pub struct DisplayCommand {
+    /// The state change to apply
+    pub state: DisplayState,
+    /// Optionally signalled with the outcome once the command has been handled
+    pub ack: Option<AckResponder>,
+}
+
+impl DisplayCommand {
+    /// Create a command whose outcome is signalled on the responder
+    pub fn with_ack(state: DisplayState, ack: AckResponder) -> Self {
+        Self { state, ack: Some(ack) }
+    }
+}
+
+impl From<DisplayState> for DisplayCommand {
+    fn from(state: DisplayState) -> Self {
+        Self { state, ack: None }
+    }
 }
 
 const DISPLAY_QUEUE_SIZE: usize = 10;
+
+/// The colour of the factory reset countdown
+const RESET_COLOUR: RGB8 = RGB8::new(255, 0, 0);
 /// Channel types for the display task.
-pub type DisplayChannel = Channel<CriticalSectionRawMutex, DisplayState, DISPLAY_QUEUE_SIZE>;
-pub type DisplayChannelSender = Sender<'static, CriticalSectionRawMutex, DisplayState, DISPLAY_QUEUE_SIZE>;
-pub type DisplayChannelReceiver = Receiver<'static, CriticalSectionRawMutex, DisplayState, DISPLAY_QUEUE_SIZE>;
+pub type DisplayChannel = Channel<CriticalSectionRawMutex, DisplayCommand, DISPLAY_QUEUE_SIZE>;
+pub type DisplayChannelSender = Sender<'static, CriticalSectionRawMutex, DisplayCommand, DISPLAY_QUEUE_SIZE>;
+pub type DisplayChannelReceiver = Receiver<'static, CriticalSectionRawMutex, DisplayCommand, DISPLAY_QUEUE_SIZE>;
+
+/// Send a command and wait to hear how it went. This never waits for room on the channel and
+/// only waits [DISPLAY_ACK_TIMEOUT] milliseconds for the outcome, so it can be used from a GATT
+/// connection.
+///
+/// # Parameters
+/// * `display` - Where the command is sent
+/// * `state` - The command
+/// * `ack` - Where the outcome is signalled, which must not be shared with another sender
+///
+/// # Returns
+/// The outcome, [CommandResult::QueueFull] if the display was too busy to take the command
+pub async fn send_acked(display: &DisplayChannelSender, state: DisplayState, ack: AckResponder) -> CommandResult {
+    // Forget the outcome of an earlier command that we gave up waiting for
+    ack.reset();
+    if display.try_send(DisplayCommand::with_ack(state, ack)).is_err() {
+        return CommandResult::QueueFull;
+    }
+    with_timeout(Duration::from_millis(DISPLAY_ACK_TIMEOUT), ack.wait())
+        .await
+        .unwrap_or(CommandResult::QueueFull)
+}
+
+/// Change our colour everywhere at once: the built-in animations, the saved settings, our beacon
+/// and the profile we hand out over a soul-link. Nothing needs a restart. If the display doesn't
+/// take it, the animations take the new colour up on the next restart instead.
+///
+/// # Parameters
+/// * `display` - Where the new colour is sent for the animations
+/// * `colour` - Our new colour
+/// * `ack` - Where the display signals the outcome, see [send_acked]
+///
+/// # Returns
+/// Whether the animations took the new colour
+pub async fn change_colour(display: &DisplayChannelSender, colour: RGB8, ack: AckResponder) -> CommandResult {
+    update_settings(|s| s.colour = Some(colour));
+    update_beacon(|i| i.colour = colour);
+    let result = send_acked(display, DisplayState::Colour(colour), ack).await;
+    if result != CommandResult::Applied {
+        log!(Display, warn, "DISPLAY_TASK: The animations keep the old colour for now: {}", result);
+    }
+    result
+}
+
+/// Status indicators drawn over the animations. These sit outside the display channel so that
+/// status updates never compete with animations or control messages.
+static STATUS: Mutex<CriticalSectionRawMutex, Cell<StatusFlags>> = Mutex::new(Cell::new(StatusFlags::new()));
+
+/// Update the status indicators. They are picked up by the display task on the next frame.
+///
+/// # Parameters
+/// * `update` - Closure that modifies the current status flags
+pub fn update_status(update: impl FnOnce(&mut StatusFlags)) {
+    STATUS.lock(|s| {
+        let mut status = s.get();
+        update(&mut status);
+        s.set(status);
+    });
+}
 
 /// Display driver main task.
 /// The display is fully managed from this task. It contains the state and responds to messages
@@ -43,141 +266,841 @@ pub type DisplayChannelReceiver = Receiver<'static, CriticalSectionRawMutex, Dis
 /// # Parameters
 /// * `channel` - Channel receiver for display state messages
 /// * `led` - LED driver instance for controlling the LED strip
-/// * `default` - Default animation type to use when no other animation is queued. T
-///
+/// * `default` - Default animation type to use when no other animation is queued.
+/// * `theme` - Our own colours, used for the built-in animations
+/// * `seed` - Random seed for shuffle mode
 #[embassy_executor::task]
 pub async fn display_task(
     channel: &'static DisplayChannelReceiver,
     led: &'static mut LedDriver<'static>,
-    default: &'static Animation,
+    default: AnimationKind,
+    mut theme: Theme,
+    seed: u64,
 ) {
-    let mut animation = Ticker::every(Duration::from_millis(ANIMATION_UPDATE));
-    let mut flusher = Ticker::every(Duration::from_secs(PRESENCE_REGISTER_FLUSH_INTERVAL));
+    let mut flush_every = flush_interval();
+    let mut flusher = Ticker::every(flush_every);
     let mut running = true;
+    // When the display was paused, so we can suspend the animation clocks
+    let mut paused_at: Option<Instant> = None;
     let mut tracker: Tracker<MAX_SOULS_TRACKED> = Tracker::new();
-    let mut animation_queue: Queue<Animation, MAX_PENDING_ANIMATIONS> = Queue::new();
-    let mut current_animation = default.clone();
-    let mut brightness: u8 = 128;
+    let mut animation_queue: AnimationQueue<MAX_PENDING_ANIMATIONS> = AnimationQueue::new();
+    let mut default_kind = default;
+    let mut default = default_kind.animation(&theme, None);
+    let mut tuning = Tuning::default();
+    set_lead(Lead::new(default_kind, tuning.speed));
+    let mut leads = LeadFilter::new();
+    let mut commands = CommandFilter::new();
+    let mut waves = WaveFilter::new();
+    let mut moods = MoodDetector::new();
+    // The default to go back to once a lively crowd calms down, None while the crowd is calm
+    let mut calm_kind: Option<AnimationKind> = None;
+    let mut calibration: Option<Calibration> = None;
+    let mut hints: UpdateThrottle<MAX_SOULS_TRACKED> = UpdateThrottle::new(Duration::from_secs(APPROACH_HINT_INTERVAL));
+    // Play the boot sequence first. The default takes over once it is done.
+    let mut current_animation = Animation::Boot(BootAnimation::new(theme.colour));
+    let mut interval = scaled(frame_interval(&current_animation));
+    let mut animation = FrameClock::new(interval, Instant::now());
+    let mut fader = Fader::new(128);
+    // The last frame we displayed, before brightness was applied, so we can redraw it while fading
+    let mut last_frame = LedBuffer::default();
     let mut torch = false;
+    let mut torch_preset = TorchPreset::default();
+    let mut torch_intensity = TorchIntensity::default();
+    let mut night_mode = false;
+    let mut beacon_only = false;
+    let mut sos = false;
+    let mut auto_brightness: Option<AutoBrightness> = None;
+    // The brightness to go back to when the wearer moves again, None unless dimmed for stillness
+    let mut resting: Option<u8> = None;
+    let mut badges = BadgeEngine::with_unlocked(unlocked_badges());
+    let mut frame: u32 = 0;
+    // When shuffle mode next picks a new default, None if shuffle is off
+    let mut next_shuffle: Option<Instant> = None;
+    // When the last frame from a lighting desk arrived
+    let mut desk_heard: Option<Instant> = None;
+    let mut rng = fastrand::Rng::with_seed(seed);
 
-    info!("DISPLAY_TASK: Task started. Waiting for messages...");
+    log!(Display, info, "DISPLAY_TASK: Task started. Waiting for messages...");
     loop {
+        // Our beacon asks the crew for help for as long as the hot/cold game is on, however it ends
+        set_hunt(match &current_animation {
+            Animation::HotCold(game) => Some(game.target()),
+            _ => None,
+        });
+        // Retarget the frame clock if the current animation wants a different frame rate
+        if tuning.frame_interval(scaled(frame_interval(&current_animation))) != interval {
+            interval = tuning.frame_interval(scaled(frame_interval(&current_animation)));
+            log!(
+                Display,
+                debug,
+                "DISPLAY_TASK: Frame interval now {}ms for {}",
+                interval.as_millis(),
+                current_animation
+            );
+            animation.set_interval(interval, Instant::now());
+        }
+        // The flush interval can be changed at runtime
+        if flush_interval() != flush_every {
+            flush_every = flush_interval();
+            flusher = Ticker::every(flush_every);
+        }
+        // Only wake up for brightness fades while we are actually fading
+        let fading = fader.is_fading();
+        let fade = async move {
+            if fading {
+                Timer::after(Duration::from_millis(BRIGHTNESS_FADE_INTERVAL)).await
+            } else {
+                core::future::pending().await
+            }
+        };
         // Wait for one of our futures to become ready
-        match select3(animation.next(), channel.receive(), flusher.next()).await {
+        match select4(Timer::at(animation.deadline()), channel.receive(), flusher.next(), fade).await {
             // Animation update timer
             First(_) => {
-                // The ticker woke us up
-                if running {
+                // The frame clock woke us up. If we were held up, step over the frames we missed so
+                // the animation keeps its pace.
+                let due = animation.due(Instant::now());
+                // A lighting desk takes over from our animations, but never from an SOS
+                let desk = !sos && desk_heard.is_some_and(|at| at.elapsed() < Duration::from_secs(LIGHT_SHOW_TIMEOUT));
+                if running && !desk {
+                    for _ in 1..due {
+                        if draw(&mut current_animation, &tracker).is_none() {
+                            break;
+                        }
+                    }
+                    // Shuffle to a new default, but only while we are idling on the current one
+                    if let Some(at) = next_shuffle
+                        && Instant::now() >= at
+                        && kind(&current_animation) == Some(default_kind)
+                        && is_interruptable(&current_animation)
+                    {
+                        let set = if calm_kind.is_some() { LIVELY } else { SHUFFLE };
+                        let old_kind = default_kind;
+                        default_kind = default_kind.shuffle_from(set, &mut rng);
+                        log!(Display, info, "DISPLAY_TASK: Shuffled to {}", default_kind);
+                        renew_default(
+                            &mut current_animation,
+                            &mut default,
+                            old_kind,
+                            default_kind,
+                            &theme,
+                            &tuning,
+                            None,
+                        );
+                        next_shuffle = Some(at + Duration::from_secs(SHUFFLE_INTERVAL));
+                    }
+                    let render_start = Instant::now();
                     // Look at our state and return something that we can display.
                     // Note we must peek into animation_queue because if we are interruptable, we must
                     // leave the next animation in the queue until the current animation terminates.
                     let mut new_buf: Option<LedBuffer> = match (
-                        next_buffer(&mut current_animation),
+                        draw(&mut current_animation, &tracker),
                         animation_queue.peek(),
                         is_interruptable(&current_animation),
                     ) {
                         // A new animation and the current one is interruptable, set up the new one.
                         (_, Some(animation), true) => {
-                            debug!("DISPLAY_TASK: Animation {} replaced by updated {}", current_animation, animation);
+                            log!(
+                                Display,
+                                debug,
+                                "DISPLAY_TASK: Animation {} replaced by updated {}",
+                                current_animation,
+                                animation
+                            );
                             current_animation = animation.clone();
                             animation_queue.dequeue().unwrap(); // Infallible drop because the peek was Some()
-                            next_buffer(&mut current_animation)
+                            draw(&mut current_animation, &tracker)
                         }
                         // Just one animation running, so let it roll
                         (Some(buf), None, _) => {
-                            debug!("DISPLAY_TASK: Animation continuing with {}", current_animation);
+                            log!(Display, debug, "DISPLAY_TASK: Animation continuing with {}", current_animation);
                             Some(buf)
                         }
                         // A new animation available but we are not interruptable, return the current animation next buffer
                         (Some(buf), Some(animation), false) => {
-                            debug!(
+                            log!(
+                                Display,
+                                debug,
                                 "DISPLAY_TASK: Uninterruptible animation {} updated with pending animation {}",
-                                current_animation, animation
+                                current_animation,
+                                animation
                             );
                             Some(buf)
                         }
                         // Current animation terminates, no new animation so revert to default
                         (None, None, _) => {
-                            debug!("DISPLAY_TASK: No animations found. Reverting to the default");
+                            log!(Display, debug, "DISPLAY_TASK: No animations found. Reverting to the default");
                             current_animation = default.clone();
-                            next_buffer(&mut current_animation)
+                            draw(&mut current_animation, &tracker)
                         }
                         // No new buffer and a pending animation
                         (None, Some(animation), _) => {
-                            debug!("DISPLAY_TASK: No current animation with a pending animation {}", animation);
+                            log!(
+                                Display,
+                                debug,
+                                "DISPLAY_TASK: No current animation with a pending animation {}",
+                                animation
+                            );
                             current_animation = animation.clone();
                             animation_queue.dequeue().unwrap(); // Infallible drop because the peek was Some()
-                            next_buffer(&mut current_animation)
+                            draw(&mut current_animation, &tracker)
                         }
                     };
+                    // White-heavy animations are blanked out in night mode, except for an SOS
+                    if night_mode && is_white_heavy(&current_animation) && !sos {
+                        log!(Display, debug, "DISPLAY_TASK: Suppressing {} in night mode", current_animation);
+                        new_buf = new_buf.map(|_| LedBuffer::default());
+                    }
                     // The buffer is still wrapped in an option, so grab it. It will never be None
                     if let Some(ref mut b) = new_buf {
-                        led.update_from_buffer(b, brightness).await;
+                        frame = frame.wrapping_add(1);
+                        last_frame = *b;
+                        // An SOS goes out at full brightness, even in night mode
+                        let (brightness, night) = if sos {
+                            (255, false)
+                        } else {
+                            (fader.brightness(), night_mode)
+                        };
+                        let rendered = Instant::now();
+                        show(led, b, brightness, night, frame).await;
+                        record_frame(name(&current_animation), rendered - render_start, rendered.elapsed(), interval);
                     } // Just let the default animation pick this one up if we don't have a new buffer
                 }
             }
             // Control message from our channel
-            Second(message) => {
+            Second(DisplayCommand { state, ack }) => {
                 // We received a message
                 use DisplayState::*;
-                match message {
-                    Stop => running = false,
-                    Start => running = true,
+                let mut result = CommandResult::Applied;
+                match state {
+                    Stop => {
+                        running = false;
+                        paused_at.get_or_insert_with(Instant::now);
+                    }
+                    Start => {
+                        running = true;
+                        resume(&mut paused_at, &mut current_animation, &mut animation_queue);
+                    }
                     Off => {
                         led.all_off().await;
+                        led.power_off();
                         running = false;
+                        paused_at.get_or_insert_with(Instant::now);
                     }
                     On => {
                         running = true;
+                        resume(&mut paused_at, &mut current_animation, &mut animation_queue);
                     }
                     Brightness(b) => {
-                        brightness = b;
+                        if auto_brightness.take().is_some() {
+                            log!(Display, info, "DISPLAY_TASK: Brightness set by hand, auto-brightness off");
+                        }
+                        // Somebody pressing buttons isn't asleep
+                        resting = None;
+                        // The fade timer takes care of redrawing at the new brightness
+                        fader.set_target(b, BRIGHTNESS_FADE_STEPS);
+                    }
+                    AutoBrightness(on) => {
+                        log!(Display, info, "DISPLAY_TASK: Auto-brightness {}", on);
+                        auto_brightness = on.then(AutoBrightness::new);
+                    }
+                    AmbientLight(light) => {
+                        let badge = badges.ambient_light(light, Instant::now());
+                        celebrate(&mut animation_queue, badge);
+                        if let Some(b) = auto_brightness.as_mut().and_then(|a| a.update(light)) {
+                            log!(Display, debug, "DISPLAY_TASK: Ambient light {} sets brightness {}", light, b);
+                            match resting.as_mut() {
+                                Some(r) => *r = b,
+                                None => fader.set_target(b, BRIGHTNESS_FADE_STEPS),
+                            }
+                        }
+                    }
+                    Still(true) => {
+                        if resting.is_none() {
+                            log!(Display, info, "DISPLAY_TASK: The wearer is keeping still, dimming");
+                            resting = Some(fader.target());
+                            fader.set_target(fader.target().min(STILL_BRIGHTNESS), STILL_FADE_STEPS);
+                        }
+                    }
+                    Still(false) => {
+                        if let Some(b) = resting.take() {
+                            log!(Display, info, "DISPLAY_TASK: The wearer is moving again, back to {}", b);
+                            fader.set_target(b, BRIGHTNESS_FADE_STEPS);
+                        }
+                    }
+                    NightMode(on) => {
+                        log!(Display, info, "DISPLAY_TASK: Night mode {}", on);
+                        night_mode = on;
                         if torch {
-                            led.torch(brightness).await;
+                            led.torch(
+                                torch_colour(torch_preset, night_mode),
+                                torch_brightness(torch_preset, torch_intensity, night_mode),
+                            )
+                            .await;
+                        } else if running {
+                            show(led, &last_frame, fader.brightness(), night_mode, frame).await;
                         }
                     }
-                    Torch(on) => {
+                    Torch(on, preset) => {
+                        if let Some(p) = preset {
+                            torch_preset = p;
+                        }
                         if on {
                             running = false;
+                            paused_at.get_or_insert_with(Instant::now);
                             torch = true;
-                            led.torch(brightness).await;
+                            led.torch(
+                                torch_colour(torch_preset, night_mode),
+                                torch_brightness(torch_preset, torch_intensity, night_mode),
+                            )
+                            .await;
                         } else {
                             running = true;
+                            resume(&mut paused_at, &mut current_animation, &mut animation_queue);
                             torch = false;
                         };
                     }
+                    TorchLevel(intensity) => {
+                        log!(Display, info, "DISPLAY_TASK: Torch intensity {}", intensity);
+                        torch_intensity = intensity;
+                        if torch {
+                            led.torch(
+                                torch_colour(torch_preset, night_mode),
+                                torch_brightness(torch_preset, torch_intensity, night_mode),
+                            )
+                            .await;
+                        }
+                    }
+                    // Reports still in flight when scanning stopped are ignored
+                    PresenceUpdate(_) if beacon_only => (),
                     PresenceUpdate(message) => {
+                        // A friend calling for help takes over the display for as long as their SOS
+                        // keeps arriving, unless we are calling for help ourselves
+                        if message.emergency {
+                            trigger_output::fire(TriggerEvent::Emergency);
+                            let ttl = Duration::from_secs(ALERT_DURATION);
+                            match current_animation {
+                                Animation::Alert(ref mut alert) => alert.extend(ttl),
+                                Animation::Sos(_) => (),
+                                _ => {
+                                    log!(Display, warn, "DISPLAY_TASK: Soul {:x} is calling for help", message.key);
+                                    current_animation = Animation::Alert(AlertAnimation::new(ttl));
+                                }
+                            }
+                        }
+                        // Keep the hot/cold meter up to date with every advertisement from its target,
+                        // and with what the crew can see of it
+                        if let Animation::HotCold(ref mut game) = current_animation {
+                            if game.target() == message.key {
+                                game.update(Some(message.tx_loss()));
+                            }
+                            if let Some(report) = message.report {
+                                game.crew_report(message.key, message.colour, report);
+                            }
+                        }
+                        if let Animation::RssiMeter(ref mut meter) = current_animation {
+                            meter.update(message.key, message.rssi);
+                        }
+                        if let Some(ref mut c) = calibration
+                            && let Some(model) = c.sample(message.key, message.raw_loss(), path_loss())
+                        {
+                            log!(Display, info, "DISPLAY_TASK: Calibrated {}", model);
+                            set_path_loss(model);
+                            update_settings(|s| s.path_loss = Some(model));
+                            calibration = None;
+                            enqueue(
+                                &mut animation_queue,
+                                Animation::Sparkle(SparkleAnimation::new(theme.colour, Some(Duration::from_secs(1)))),
+                            );
+                        }
+                        // Adopt the leader's animation whenever it picks a new one
+                        if let Some(lead) = message.lead
+                            && FOLLOW_ENABLED
+                            && !leading()
+                            && leads.follow(message.key, lead, Instant::now())
+                        {
+                            log!(Display, info, "DISPLAY_TASK: Following soul {:x} with {}", message.key, lead.kind);
+                            let old_kind = default_kind;
+                            default_kind = lead.kind;
+                            tuning.set(TuningParam::Speed(lead.speed));
+                            renew_default(
+                                &mut current_animation,
+                                &mut default,
+                                old_kind,
+                                default_kind,
+                                &theme,
+                                &tuning,
+                                Some(&lead),
+                            );
+                        }
+                        // One-shot effects from a controller in our group
+                        if let Some(command) = message.command
+                            && COMMANDS_ENABLED
+                            && commands.accept(&command, shared_now(), Instant::now())
+                        {
+                            let Effect::Flash(colour) = command.effect;
+                            log!(Display, info, "DISPLAY_TASK: Soul {:x} told us to flash", message.key);
+                            if !enqueue(&mut animation_queue, Animation::Favourite(FavouriteAnimation::new(colour))) {
+                                result = CommandResult::QueueFull;
+                            }
+                        }
+                        // A soul waving at us gets a flash in their colour, once for each wave
+                        if message.wave && waves.accept(message.key, Instant::now()) {
+                            log!(Display, info, "DISPLAY_TASK: Soul {:x} is waving at us", message.key);
+                            if !enqueue(
+                                &mut animation_queue,
+                                Animation::Favourite(FavouriteAnimation::new(message.colour)),
+                            ) {
+                                result = CommandResult::QueueFull;
+                            }
+                        }
+                        if message.favourite {
+                            trigger_output::fire(TriggerEvent::FavouriteNear);
+                            let badge = badges.friend_seen(message.key, message.last_seen);
+                            celebrate(&mut animation_queue, badge);
+                        }
                         // Only update if there was a change to the presence list. The update()
-                        // method returns true if there was an update.
+                        // method returns true if there was an update. Changes in signal strength
+                        // are picked up by the presence animation through the tracker generation.
+                        let joined = tracker.soul(message.key).await.is_none();
                         if tracker.update(&message).await {
-                            info!("DISPLAY_TASK: Presence update message received!");
-                            let souls = tracker.get_soul_summary().await;
-                            // Send sparkle animation for new user. There can only be one
-                            animation_queue
-                                .enqueue(Animation::Sparkle(SparkleAnimation::new(
-                                    message.colour,
-                                    Some(Duration::from_secs(NEW_SOUL_ANIMATION)),
-                                )))
-                                .unwrap_or(());
-                            // Silently drop an animation if the queue is full
-                            animation_queue
-                                .enqueue(Animation::Presence(PresenceAnimation::new(&souls)))
-                                .unwrap_or(());
+                            if joined {
+                                trigger_output::fire(TriggerEvent::SoulJoined);
+                                let badge = badges.soul_joined(message.key);
+                                celebrate(&mut animation_queue, badge);
+                            }
+                            set_crowd_size(tracker.summary().len());
+                            // A favourite gets a fanfare straight away, everybody else a sparkle in
+                            // turn. Either is followed by the updated soul layout. The queue replaces
+                            // any stale layout with this one.
+                            let celebrated = if message.favourite {
+                                log!(Display, info, "DISPLAY_TASK: A favourite soul has arrived");
+                                haptic::pulse();
+                                let fanfare = Animation::Favourite(FavouriteAnimation::new(message.colour));
+                                // Never cut short the boot sequence, a game, another fanfare or an emergency
+                                if matches!(
+                                    current_animation,
+                                    Animation::Boot(_)
+                                        | Animation::HotCold(_)
+                                        | Animation::RssiMeter(_)
+                                        | Animation::Favourite(_)
+                                        | Animation::Sos(_)
+                                        | Animation::Alert(_)
+                                ) {
+                                    enqueue(&mut animation_queue, fanfare)
+                                } else {
+                                    current_animation = fanfare;
+                                    true
+                                }
+                            } else {
+                                enqueue(
+                                    &mut animation_queue,
+                                    Animation::Sparkle(SparkleAnimation::new(
+                                        message.colour,
+                                        Some(Duration::from_secs(NEW_SOUL_ANIMATION)),
+                                    )),
+                                )
+                            };
+                            let laid_out = enqueue(&mut animation_queue, Animation::Presence(PresenceAnimation::new()));
+                            if !(celebrated && laid_out) {
+                                result = CommandResult::QueueFull;
+                            }
                         };
+                        // A subtle hint when somebody is coming closer or walking away
+                        if let Some(movement) = tracker.trend(&message)
+                            && hints.allow(message.key, Instant::now())
+                        {
+                            log!(Display, debug, "DISPLAY_TASK: Soul {:x} is {}", message.key, movement);
+                            enqueue(
+                                &mut animation_queue,
+                                Animation::Approach(ApproachAnimation::new(message.colour, movement)),
+                            );
+                            enqueue(&mut animation_queue, Animation::Presence(PresenceAnimation::new()));
+                        }
+                    }
+                    SetDefault(new_kind) => {
+                        log!(Display, info, "DISPLAY_TASK: Default animation set to {}", new_kind);
+                        renew_default(
+                            &mut current_animation,
+                            &mut default,
+                            default_kind,
+                            new_kind,
+                            &theme,
+                            &tuning,
+                            None,
+                        );
+                        default_kind = new_kind;
+                        // The wearer's choice sticks, even once a lively crowd calms down
+                        calm_kind = None;
+                    }
+                    Colour(colour) => {
+                        log!(Display, info, "DISPLAY_TASK: Our colour is now {:?}", Debug2Format(&colour));
+                        theme.colour = colour;
+                        renew_default(
+                            &mut current_animation,
+                            &mut default,
+                            default_kind,
+                            default_kind,
+                            &theme,
+                            &tuning,
+                            None,
+                        );
+                    }
+                    PlayNow(play) => {
+                        let animation = play.animation(&theme, Some(Duration::from_secs(PLAY_NOW_DURATION)));
+                        if night_mode && is_white_heavy(&animation) {
+                            log!(Display, info, "DISPLAY_TASK: Not playing {} in night mode", play);
+                            result = CommandResult::Rejected;
+                        } else {
+                            log!(Display, info, "DISPLAY_TASK: Playing {} now", play);
+                            current_animation = animation;
+                        }
+                    }
+                    Party(false) => {
+                        if matches!(current_animation, Animation::Strobe(_)) {
+                            log!(Display, info, "DISPLAY_TASK: Party is over");
+                            current_animation = default.clone();
+                        }
+                    }
+                    Party(true) => {
+                        if !STROBE_ENABLED || night_mode {
+                            log!(Display, info, "DISPLAY_TASK: Party mode is not allowed");
+                            result = CommandResult::Rejected;
+                        } else {
+                            log!(Display, info, "DISPLAY_TASK: Party time!");
+                            current_animation = Animation::Strobe(StrobeAnimation::new(
+                                theme.colour,
+                                Duration::from_millis(STROBE_PERIOD),
+                                STROBE_DUTY,
+                                Some(Duration::from_secs(STROBE_DURATION)),
+                            ));
+                        }
+                    }
+                    HotCold(false) => {
+                        if matches!(current_animation, Animation::HotCold(_)) {
+                            log!(Display, info, "DISPLAY_TASK: Hot/cold game over");
+                            current_animation = default.clone();
+                        }
+                    }
+                    HotCold(true) => {
+                        result = hunt(&tracker, None, &mut current_animation).await;
+                    }
+                    NextSoul => {
+                        if let Animation::HotCold(ref game) = current_animation {
+                            result = hunt(&tracker, Some(game.target()), &mut current_animation).await;
+                        } else {
+                            result = CommandResult::Rejected;
+                        }
+                    }
+                    RssiMeter(false) => {
+                        if matches!(current_animation, Animation::RssiMeter(_)) {
+                            log!(Display, info, "DISPLAY_TASK: Signal strength meter off");
+                            current_animation = default.clone();
+                        }
+                    }
+                    RssiMeter(true) => {
+                        log!(Display, info, "DISPLAY_TASK: Signal strength meter on");
+                        current_animation = Animation::RssiMeter(RssiMeterAnimation::new());
+                    }
+                    Shuffle(on) => {
+                        log!(Display, info, "DISPLAY_TASK: Shuffle mode {}", on);
+                        next_shuffle = on.then(|| Instant::now() + Duration::from_secs(SHUFFLE_INTERVAL));
+                    }
+                    Sos(true) => {
+                        log!(Display, warn, "DISPLAY_TASK: Calling for help");
+                        sos = true;
+                        current_animation = Animation::Sos(SosAnimation::new());
+                    }
+                    Sos(false) => {
+                        log!(Display, info, "DISPLAY_TASK: SOS cancelled");
+                        sos = false;
+                        if matches!(current_animation, Animation::Sos(_)) {
+                            current_animation = default.clone();
+                        }
+                    }
+                    BeaconOnly(on) => {
+                        log!(Display, info, "DISPLAY_TASK: Beacon-only mode {}", on);
+                        beacon_only = on;
+                        if on {
+                            tracker.clear().await;
+                            set_crowd_size(0);
+                            animation_queue.clear();
+                            if matches!(
+                                current_animation,
+                                Animation::Presence(_)
+                                    | Animation::Sparkle(_)
+                                    | Animation::HotCold(_)
+                                    | Animation::RssiMeter(_)
+                                    | Animation::Favourite(_)
+                            ) {
+                                current_animation = default.clone();
+                            }
+                        }
+                    }
+                    Tune(param) => {
+                        log!(Display, info, "DISPLAY_TASK: Tuning {:?}", Debug2Format(&param));
+                        tuning.set(param);
+                        tune(&mut default, &tuning);
+                        tune(&mut current_animation, &tuning);
+                        set_lead(Lead::new(default_kind, tuning.speed));
+                    }
+                    Calibrate(step) => {
+                        log!(Display, info, "DISPLAY_TASK: Calibrating the path loss {}", step);
+                        calibration = Some(Calibration::new(step));
+                    }
+                    FactoryReset(Some(left)) => {
+                        log!(Display, warn, "DISPLAY_TASK: Counting down to a factory reset");
+                        current_animation = Animation::Countdown(CountdownAnimation::new(RESET_COLOUR, left));
+                    }
+                    FactoryReset(None) => {
+                        if matches!(current_animation, Animation::Countdown(_)) {
+                            current_animation = default.clone();
+                        }
+                    }
+                    Waving => {
+                        if !enqueue(&mut animation_queue, Animation::Favourite(FavouriteAnimation::new(theme.colour))) {
+                            result = CommandResult::QueueFull;
+                        }
+                    }
+                    Shaken => {
+                        let set = if calm_kind.is_some() { LIVELY } else { SHUFFLE };
+                        let old_kind = default_kind;
+                        default_kind = default_kind.shuffle_from(set, &mut rng);
+                        log!(Display, info, "DISPLAY_TASK: Shaken, shuffled to {}", default_kind);
+                        renew_default(
+                            &mut current_animation,
+                            &mut default,
+                            old_kind,
+                            default_kind,
+                            &theme,
+                            &tuning,
+                            None,
+                        );
+                        // Give the new default a full turn before shuffle mode moves on
+                        if next_shuffle.is_some() {
+                            next_shuffle = Some(Instant::now() + Duration::from_secs(SHUFFLE_INTERVAL));
+                        }
+                        if !enqueue(&mut animation_queue, Animation::Swirl(SwirlAnimation::new(theme.colour))) {
+                            result = CommandResult::QueueFull;
+                        }
+                    }
+                    LightShow(buffer) => {
+                        if !desk_heard.is_some_and(|at| at.elapsed() < Duration::from_secs(LIGHT_SHOW_TIMEOUT)) {
+                            log!(Display, info, "DISPLAY_TASK: A lighting desk has taken over");
+                        }
+                        desk_heard = Some(Instant::now());
+                        if running && !sos {
+                            last_frame = buffer;
+                            show(led, &buffer, fader.brightness(), night_mode, frame).await;
+                        }
+                    }
+                    GetStatus(responder) => {
+                        responder.signal(DisplayStatus {
+                            brightness: resting.unwrap_or(fader.target()),
+                            running,
+                            torch,
+                            torch_preset,
+                            torch_intensity,
+                            night_mode,
+                            auto_brightness: auto_brightness.is_some(),
+                            animation: name(&current_animation),
+                            queue_depth: animation_queue.len(),
+                            drops: drop_counts(),
+                        });
                     }
                 }
+                if let Some(ack) = ack {
+                    ack.signal(result);
+                }
             }
             // Flush stale presence messages timer
             Third(_) => {
                 if tracker.flush().await {
                     // Someone disappeared so update the animation
-                    info!("DISPLAY_TASK: A soul disappeared");
-                    let souls = tracker.get_soul_summary().await;
-                    animation_queue
-                        .enqueue(Animation::Presence(PresenceAnimation::new(&souls)))
-                        .unwrap_or(());
+                    log!(Display, info, "DISPLAY_TASK: A soul disappeared");
+                    set_crowd_size(tracker.summary().len());
+                    if let Animation::HotCold(ref mut game) = current_animation {
+                        game.update(tracker.soul(game.target()).await.map(|s| s.tx_loss));
+                    }
+                    if let Animation::RssiMeter(ref mut meter) = current_animation
+                        && let Some(target) = meter.target()
+                        && tracker.soul(target).await.is_none()
+                    {
+                        meter.lost();
+                    }
+                    enqueue(&mut animation_queue, Animation::Presence(PresenceAnimation::new()));
+                }
+                set_souls_per_minute(tracker.souls_per_minute(Instant::now()));
+                // Read the room, switching to something livelier while the crowd is big
+                if CROWD_MOOD_ENABLED && let Some(mood) = moods.update(crowd_density()) {
+                    log!(Display, info, "DISPLAY_TASK: The crowd is {}", mood);
+                    let old_kind = default_kind;
+                    default_kind = match mood {
+                        CrowdMood::Lively => {
+                            calm_kind = Some(default_kind);
+                            default_kind.shuffle_from(LIVELY, &mut rng)
+                        }
+                        CrowdMood::Calm => calm_kind.take().unwrap_or(default_kind),
+                    };
+                    renew_default(&mut current_animation, &mut default, old_kind, default_kind, &theme, &tuning, None);
+                }
+            }
+            // Brightness fade step. The torch has its own intensity, so it is left alone.
+            Fourth(_) => {
+                if let Some(b) = fader.next()
+                    && running
+                {
+                    show(led, &last_frame, b, night_mode, frame).await;
                 }
             }
         };
     }
 }
+
+/// Resume after a pause. The animation clocks are pushed back by the time we were paused for so
+/// that timed animations carry on exactly where they left off.
+fn resume(
+    paused_at: &mut Option<Instant>,
+    current: &mut Animation,
+    queue: &mut AnimationQueue<MAX_PENDING_ANIMATIONS>,
+) {
+    if let Some(paused) = paused_at.take() {
+        let paused_for = Instant::now() - paused;
+        log!(Display, debug, "DISPLAY_TASK: Resuming after {}ms", paused_for.as_millis());
+        postpone(current, paused_for);
+        queue.postpone(paused_for);
+    }
+}
+
+/// Send a frame to the strip. A matrix is turned the right way up first, so that the status pixel
+/// stays put, and then the status is drawn over the animation, blinking along with the `count` of
+/// frames shown. The animation is dimmed to `brightness` and the status pixel, while it is showing
+/// anything, to [STATUS_BRIGHTNESS]. Night mode then caps the whole frame.
+async fn show(led: &mut LedDriver<'_>, frame: &LedBuffer, brightness: u8, night_mode: bool, count: u32) {
+    let mut buffer = *frame;
+    if let LedLayout::Matrix { width, height } = LED_LAYOUT {
+        buffer = rotate(&buffer, orientation(), width, height);
+    }
+    let status = STATUS.lock(|s| s.get());
+    status.apply(&mut buffer, count);
+    let layers = LayerBrightness {
+        background: brightness,
+        overlay: STATUS_BRIGHTNESS,
+    };
+    let overlay: &[usize] = if status.is_active() { &[STATUS_PIXEL] } else { &[] };
+    layers.apply(&mut buffer, overlay);
+    if DMX_ENABLED {
+        dmx_output::publish(&buffer, limit_brightness(255, night_mode));
+    }
+    led.update_from_buffer(&mut buffer, limit_brightness(255, night_mode))
+        .await;
+}
+
+/// Clamp the brightness to the night mode ceiling if night mode is on
+fn limit_brightness(brightness: u8, night_mode: bool) -> u8 {
+    if night_mode {
+        brightness.min(NIGHT_MODE_BRIGHTNESS)
+    } else {
+        brightness
+    }
+}
+
+/// The torch brightness to use. The preset may cap the intensity and night mode caps everything.
+fn torch_brightness(preset: TorchPreset, intensity: TorchIntensity, night_mode: bool) -> u8 {
+    limit_brightness(intensity.brightness().min(preset.max_brightness()), night_mode)
+}
+
+/// The torch colour to use. White-heavy presets are replaced by red in night mode.
+fn torch_colour(preset: TorchPreset, night_mode: bool) -> RGB8 {
+    if night_mode && preset.is_white_heavy() {
+        TorchPreset::Red.colour()
+    } else {
+        preset.colour()
+    }
+}
+
+/// Build the default animation afresh after its kind, our colour or the tuning changed, and swap
+/// straight over to it if we were idling on the old one. Unless we are following somebody else's
+/// lead, we lead with the new default.
+///
+/// # Parameters
+/// * `current` - The animation being shown
+/// * `default` - The default animation, replaced by the new one
+/// * `old_kind` - The kind of the old default
+/// * `new_kind` - The kind of the new default
+/// * `theme` - Our colours
+/// * `tuning` - Our tuning
+/// * `following` - The lead the new default catches up with, None if we picked it ourselves
+fn renew_default(
+    current: &mut Animation,
+    default: &mut Animation,
+    old_kind: AnimationKind,
+    new_kind: AnimationKind,
+    theme: &Theme,
+    tuning: &Tuning,
+    following: Option<&Lead>,
+) {
+    let showing_default = kind(current) == Some(old_kind) && is_interruptable(current);
+    *default = new_kind.animation(theme, None);
+    tune(default, tuning);
+    match following {
+        Some(lead) => lead.catch_up(default, tuning),
+        None => set_lead(Lead::new(new_kind, tuning.speed)),
+    }
+    if showing_default {
+        *current = default.clone();
+    }
+}
+
+/// Draw the next frame of the animation. Presence and heatmap animations are brought up to date
+/// with the tracker first.
+fn draw(animation: &mut Animation, tracker: &Tracker<MAX_SOULS_TRACKED>) -> Option<LedBuffer> {
+    match animation {
+        Animation::Presence(presence) => presence.refresh(tracker.summary(), tracker.generation()),
+        Animation::Heatmap(heatmap) => heatmap.refresh(tracker.summary()),
+        _ => (),
+    }
+    next_buffer(animation)
+}
+
+/// Point the hot/cold game at the next soul we can see
+///
+/// # Parameters
+/// * `tracker` - The souls we can see
+/// * `after` - The soul currently being hunted, None to start with the first one
+/// * `current` - The current animation, replaced by the game
+async fn hunt(tracker: &Tracker<MAX_SOULS_TRACKED>, after: Option<u32>, current: &mut Animation) -> CommandResult {
+    match tracker.next_soul(after).await {
+        Some((key, soul)) => {
+            log!(Display, info, "DISPLAY_TASK: Hot/cold hunting for soul {:x}", key);
+            *current = Animation::HotCold(HotColdAnimation::new(key, soul.colour, Some(soul.tx_loss)));
+            CommandResult::Applied
+        }
+        None => {
+            log!(Display, info, "DISPLAY_TASK: Nobody to play hot/cold with");
+            CommandResult::Rejected
+        }
+    }
+}
+
+/// Queue up a celebration for a newly unlocked badge, if there is one, and save it so that it
+/// survives a restart
+fn celebrate(queue: &mut AnimationQueue<MAX_PENDING_ANIMATIONS>, badge: Option<Badge>) {
+    if let Some(badge) = badge {
+        log!(Display, info, "DISPLAY_TASK: Unlocked the {} badge", badge);
+        update_settings(|s| s.badges = Some(unlocked_badges()));
+        enqueue(queue, Animation::Badge(BadgeAnimation::new(badge)));
+    }
+}
+
+/// Queue an animation, logging anything that had to be dropped to make space for it.
+/// Returns false if something was dropped.
+fn enqueue(queue: &mut AnimationQueue<MAX_PENDING_ANIMATIONS>, animation: Animation) -> bool {
+    if let Some(dropped) = queue.enqueue(animation) {
+        log!(Display, warn, "DISPLAY_TASK: Animation queue full, dropped {}", dropped);
+        record_drop(DropKind::Animation);
+        false
+    } else {
+        true
+    }
+}
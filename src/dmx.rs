@@ -0,0 +1,76 @@
+//! DMX512 packets, so the presence and animation engine can drive venue fixtures.
+//!
+//! Each pixel of the frame becomes three DMX channels, red, green and blue, starting at
+//! [DMX_START_ADDRESS]. The pixels go through the same gamma correction and brightness as the
+//! LED strip, so an RGB fixture looks like an LED would. A packet is the start code followed by
+//! only as many channels as we use, which DMX allows and which lets it refresh faster. The UART
+//! that sends it lives in `dmx_output.rs`.
+
+use crate::colour::LedBuffer;
+use crate::configuration::{DMX_START_ADDRESS, LED_STRING_SIZE};
+use crate::gamma::brightness_lut;
+
+/// The start code for dimmer data
+const START_CODE: u8 = 0;
+
+/// The most channels in a DMX universe
+pub const DMX_CHANNELS: usize = 512;
+
+/// The number of slots we send, the start code and every channel up to our last one
+pub const DMX_SLOTS: usize = 1 + channels_used();
+
+// The first pixel must at least start in the universe, or the packet would have no room for it
+const _: () = assert!(
+    DMX_START_ADDRESS >= 1 && DMX_START_ADDRESS as usize <= DMX_CHANNELS,
+    "DMX_START_ADDRESS must be a channel from 1 to 512"
+);
+
+/// The channels up to and including our last one. Pixels that don't fit in the universe are
+/// dropped.
+const fn channels_used() -> usize {
+    let used = DMX_START_ADDRESS as usize - 1 + LED_STRING_SIZE * 3;
+    if used > DMX_CHANNELS { DMX_CHANNELS } else { used }
+}
+
+/// A DMX packet ready to send after the break
+pub type DmxPacket = [u8; DMX_SLOTS];
+
+/// Build the DMX packet for a frame
+///
+/// # Parameters
+/// * `frame` - The frame as drawn, before gamma correction
+/// * `brightness` - Global brightness level from 0 (off) to 255 (max brightness)
+pub fn dmx_packet(frame: &LedBuffer, brightness: u8) -> DmxPacket {
+    let lut = brightness_lut(brightness);
+    let mut packet = [0u8; DMX_SLOTS];
+    packet[0] = START_CODE;
+    let channels = frame.iter().flat_map(|p| [p.r, p.g, p.b]).map(|v| lut[v as usize]);
+    for (slot, value) in packet[DMX_START_ADDRESS as usize..].iter_mut().zip(channels) {
+        *slot = value;
+    }
+    packet
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use smart_leds::RGB8;
+
+    #[test]
+    pub fn if_each_pixel_is_three_channels() {
+        let mut frame = LedBuffer::default();
+        frame[0] = RGB8::new(255, 0, 0);
+        frame[LED_STRING_SIZE - 1] = RGB8::new(0, 0, 255);
+        let packet = dmx_packet(&frame, 255);
+        assert_eq!(packet[0], START_CODE);
+        // The channels before the start address are left at zero
+        assert!(packet[1..DMX_START_ADDRESS as usize].iter().all(|&c| c == 0));
+        let first = DMX_START_ADDRESS as usize;
+        assert_eq!(&packet[first..first + 3], &[255, 0, 0]);
+        assert_eq!(packet[DMX_SLOTS - 1], 255);
+        // Dimmed, with gamma correction
+        assert_eq!(dmx_packet(&frame, 127)[first], 127);
+        frame[0] = RGB8::new(128, 0, 0);
+        assert!(dmx_packet(&frame, 255)[first] < 64);
+    }
+}
@@ -0,0 +1,81 @@
+//! Optional DMX512 output on a UART, see [dmx](soulstar::dmx).
+//!
+//! The display publishes every frame it shows and the DMX task sends the latest one every
+//! [DMX_REFRESH] milliseconds, whether or not it has changed, as DMX requires. The task is only
+//! started if [DMX_ENABLED](soulstar::configuration::DMX_ENABLED) is set.
+//!
+//! DMX runs at 250kbaud with two stop bits and each packet starts with a break of at least 88µs
+//! followed by a mark. The UART can't send a break on its own, so it is slowed down to send a
+//! single zero byte, which holds the line low for 100µs, and its stop bit makes the mark.
+
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Ticker};
+use esp_hal::Async;
+use esp_hal::uart::{Config, StopBits, Uart};
+use smart_leds::RGB8;
+use soulstar::colour::LedBuffer;
+use soulstar::configuration::{DMX_REFRESH, LED_STRING_SIZE};
+use soulstar::dmx::dmx_packet;
+use soulstar::log;
+
+/// The DMX bit rate
+const DMX_BAUD: u32 = 250_000;
+
+/// A zero byte at this rate holds the line low for 100µs, long enough for a break
+const BREAK_BAUD: u32 = 90_000;
+
+/// The latest frame and the brightness to show it at
+static FRAME: Mutex<CriticalSectionRawMutex, Cell<(LedBuffer, u8)>> =
+    Mutex::new(Cell::new(([RGB8::new(0, 0, 0); LED_STRING_SIZE], 0)));
+
+/// Hand the DMX output the frame being shown
+///
+/// # Parameters
+/// * `frame` - The frame as drawn, before gamma correction
+/// * `brightness` - Global brightness level from 0 (off) to 255 (max brightness)
+pub fn publish(frame: &LedBuffer, brightness: u8) {
+    FRAME.lock(|f| f.set((*frame, brightness)));
+}
+
+/// Send the frames out as DMX512
+///
+/// # Parameters
+/// * `uart` - The UART wired to the RS-485 transceiver
+#[embassy_executor::task]
+pub async fn dmx_task(mut uart: Uart<'static, Async>) {
+    let data = Config::default().with_baudrate(DMX_BAUD).with_stop_bits(StopBits::_2);
+    let mark = Config::default().with_baudrate(BREAK_BAUD);
+    let mut ticker = Ticker::every(Duration::from_millis(DMX_REFRESH));
+    log!(Outputs, info, "DMX: Sending every {}ms", DMX_REFRESH);
+    loop {
+        ticker.next().await;
+        let (frame, brightness) = FRAME.lock(|f| f.get());
+        // A lost packet is soon replaced by the next one, so there is nothing more to do about it
+        if !send(&mut uart, &dmx_packet(&frame, brightness), &data, &mark).await {
+            log!(Outputs, warn, "DMX: Could not send a packet");
+        }
+    }
+}
+
+/// Send one packet, break and all
+///
+/// # Returns
+/// False if the UART failed along the way
+async fn send(uart: &mut Uart<'static, Async>, packet: &[u8], data: &Config, mark: &Config) -> bool {
+    if uart.apply_config(mark).is_err() || uart.write_async(&[0]).await.is_err() || uart.flush_async().await.is_err() {
+        return false;
+    }
+    if uart.apply_config(data).is_err() {
+        return false;
+    }
+    let mut rest = packet;
+    while !rest.is_empty() {
+        match uart.write_async(rest).await {
+            Ok(written) => rest = &rest[written..],
+            Err(_) => return false,
+        }
+    }
+    uart.flush_async().await.is_ok()
+}
@@ -0,0 +1,468 @@
+//! Soul-link profiles and the history of the souls we have swapped them with.
+//!
+//! When two SoulStars get very close they can connect and exchange a richer profile than fits in
+//! the beacon: the full name, a favourite palette and a contact token. The profile travels as a
+//! simple length prefixed blob:
+//!
+//! | Bytes     | Contents                                  |
+//! |-----------|-------------------------------------------|
+//! | 1         | Length of the name                        |
+//! | n         | The name as UTF-8                         |
+//! | 1         | Number of palette colours                 |
+//! | 3 * n     | Palette colours as RGB triples            |
+//! | 1         | Length of the contact token               |
+//! | n         | The contact token as UTF-8                |
+//!
+//! Encounters are saved across restarts (see `settings_store.rs`) as the profile followed by when
+//! we first and last met the soul and how many times we have met it. There is no wall clock, so
+//! those are [Moment]s: the boot they happened in and how long after it. The history can be
+//! exported as CSV or JSON lines over the console (see `console.rs`).
+
+use crate::configuration::ENCOUNTER_GAP;
+use core::fmt::{self, Write};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU16, Ordering};
+use embassy_time::Instant;
+use heapless::{String, Vec};
+use smart_leds::RGB8;
+
+/// Most colours in a profile palette
+pub const MAX_PALETTE: usize = 4;
+
+/// Longest name in a profile
+pub const MAX_PROFILE_NAME: usize = 24;
+
+/// Longest contact token in a profile
+pub const MAX_CONTACT: usize = 32;
+
+/// Largest encoded profile
+pub const PROFILE_SIZE: usize = 3 + MAX_PROFILE_NAME + 3 * MAX_PALETTE + MAX_CONTACT;
+
+/// Bytes saved after the profile: the first and last [Moment] and the count
+const HISTORY_SIZE: usize = 14;
+
+/// Largest encoded encounter
+pub const ENCOUNTER_SIZE: usize = PROFILE_SIZE + HISTORY_SIZE;
+
+/// The first line of the CSV export, naming the columns
+pub const CSV_HEADER: &str = "soul,name,contact,first_boot,first_seconds,last_boot,last_seconds,count";
+
+/// The number of times we have started, see [set_boot]
+static BOOT: AtomicU16 = AtomicU16::new(0);
+
+/// Set the number of times we have started. This is counted in the store, so it is set once the
+/// store has been opened.
+pub fn set_boot(boot: u16) {
+    BOOT.store(boot, Ordering::Relaxed);
+}
+
+/// What a soul tells us about itself over a soul-link
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    /// The full name of the soul
+    pub name: String<MAX_PROFILE_NAME>,
+    /// The soul's favourite colours
+    pub palette: Vec<RGB8, MAX_PALETTE>,
+    /// Whatever the soul wants to be contacted by, e.g. a handle
+    pub contact: String<MAX_CONTACT>,
+}
+
+impl Profile {
+    /// Encode the profile into the buffer
+    ///
+    /// # Returns
+    /// The number of bytes used
+    pub fn encode(&self, buffer: &mut [u8; PROFILE_SIZE]) -> usize {
+        let mut len = put_str(buffer, 0, &self.name);
+        buffer[len] = self.palette.len() as u8;
+        len += 1;
+        for c in &self.palette {
+            buffer[len..len + 3].copy_from_slice(&[c.r, c.g, c.b]);
+            len += 3;
+        }
+        len + put_str(buffer, len, &self.contact)
+    }
+
+    /// Decode a received profile. Returns None if it is malformed or does not fit.
+    ///
+    /// # Parameters
+    /// * `data` - The encoded profile
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut rest = data;
+        let name = take_str(&mut rest)?;
+        let count = take(&mut rest, 1)?[0] as usize;
+        if count > MAX_PALETTE {
+            return None;
+        }
+        let palette = take(&mut rest, 3 * count)?
+            .chunks(3)
+            .map(|c| RGB8::new(c[0], c[1], c[2]))
+            .collect();
+        let contact = take_str(&mut rest)?;
+        Some(Self { name, palette, contact })
+    }
+
+    /// The number of bytes [encode](Self::encode) uses
+    fn encoded_len(&self) -> usize {
+        3 + self.name.len() + 3 * self.palette.len() + self.contact.len()
+    }
+}
+
+/// Write a length prefixed string, returning the number of bytes used
+fn put_str(buffer: &mut [u8], at: usize, s: &str) -> usize {
+    buffer[at] = s.len() as u8;
+    buffer[at + 1..at + 1 + s.len()].copy_from_slice(s.as_bytes());
+    1 + s.len()
+}
+
+/// Split `n` bytes off the front of the data
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if rest.len() < n {
+        return None;
+    }
+    let (head, tail) = rest.split_at(n);
+    *rest = tail;
+    Some(head)
+}
+
+/// Split a length prefixed string off the front of the data
+fn take_str<const N: usize>(rest: &mut &[u8]) -> Option<String<N>> {
+    let len = take(rest, 1)?[0] as usize;
+    let bytes = take(rest, len)?;
+    String::from_str(str::from_utf8(bytes).ok()?).ok()
+}
+
+/// When something happened, as near as we can tell without a wall clock
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Moment {
+    /// The boot it happened in, see [set_boot]
+    pub boot: u16,
+    /// Seconds since that boot
+    pub seconds: u32,
+}
+
+impl Moment {
+    /// The moment it is now, in this boot
+    pub fn now() -> Self {
+        Self {
+            boot: BOOT.load(Ordering::Relaxed),
+            seconds: Instant::now().as_secs() as u32,
+        }
+    }
+
+    /// True if this is at least [ENCOUNTER_GAP] seconds after `earlier`, or in a later boot
+    fn is_apart_from(&self, earlier: &Moment) -> bool {
+        self.boot != earlier.boot || self.seconds.saturating_sub(earlier.seconds) as u64 >= ENCOUNTER_GAP
+    }
+}
+
+/// A soul we have exchanged profiles with
+#[derive(Clone, Debug, PartialEq)]
+pub struct Encounter {
+    /// Tracker key of the soul, see [crate::tracker::addr_to_key]
+    pub key: u32,
+    /// The profile it sent us
+    pub profile: Profile,
+    /// When we exchanged profiles
+    pub first: Moment,
+    /// When we last saw the soul close up
+    pub last: Moment,
+    /// The number of times we have met. Seeing the soul again only counts once it has been away
+    /// for [ENCOUNTER_GAP] seconds or we have restarted.
+    pub count: u16,
+}
+
+impl Encounter {
+    /// Encode the encounter into the buffer for saving
+    ///
+    /// # Returns
+    /// The number of bytes used
+    pub fn encode(&self, buffer: &mut [u8; ENCOUNTER_SIZE]) -> usize {
+        let mut profile = [0u8; PROFILE_SIZE];
+        let len = self.profile.encode(&mut profile);
+        buffer[..len].copy_from_slice(&profile[..len]);
+        let history = &mut buffer[len..len + HISTORY_SIZE];
+        history[0..2].copy_from_slice(&self.first.boot.to_le_bytes());
+        history[2..6].copy_from_slice(&self.first.seconds.to_le_bytes());
+        history[6..8].copy_from_slice(&self.last.boot.to_le_bytes());
+        history[8..12].copy_from_slice(&self.last.seconds.to_le_bytes());
+        history[12..14].copy_from_slice(&self.count.to_le_bytes());
+        len + HISTORY_SIZE
+    }
+
+    /// Decode a saved encounter. Encounters saved by older firmware are only the profile, so
+    /// they are taken to have been met once, in the first boot. Returns None if it is malformed.
+    ///
+    /// # Parameters
+    /// * `key` - Tracker key of the soul
+    /// * `data` - The encoded encounter
+    pub fn decode(key: u32, data: &[u8]) -> Option<Self> {
+        let profile = Profile::decode(data)?;
+        let mut encounter = Self {
+            key,
+            first: Moment::default(),
+            last: Moment::default(),
+            count: 1,
+            profile,
+        };
+        if let Some(h) = data.get(encounter.profile.encoded_len()..)
+            && h.len() >= HISTORY_SIZE
+        {
+            let u16_at = |i: usize| u16::from_le_bytes([h[i], h[i + 1]]);
+            let u32_at = |i: usize| u32::from_le_bytes([h[i], h[i + 1], h[i + 2], h[i + 3]]);
+            encounter.first = Moment {
+                boot: u16_at(0),
+                seconds: u32_at(2),
+            };
+            encounter.last = Moment {
+                boot: u16_at(6),
+                seconds: u32_at(8),
+            };
+            encounter.count = u16_at(12);
+        }
+        Some(encounter)
+    }
+
+    /// Write the encounter as a line of CSV, with the columns in [CSV_HEADER]
+    pub fn write_csv(&self, out: &mut impl Write) -> fmt::Result {
+        write!(out, "{:08x},", self.key)?;
+        write_csv_field(out, &self.profile.name)?;
+        out.write_char(',')?;
+        write_csv_field(out, &self.profile.contact)?;
+        writeln!(
+            out,
+            ",{},{},{},{},{}",
+            self.first.boot, self.first.seconds, self.last.boot, self.last.seconds, self.count
+        )
+    }
+
+    /// Write the encounter as a line of JSON
+    pub fn write_json(&self, out: &mut impl Write) -> fmt::Result {
+        write!(out, "{{\"soul\":\"{:08x}\",\"name\":", self.key)?;
+        write_json_string(out, &self.profile.name)?;
+        out.write_str(",\"contact\":")?;
+        write_json_string(out, &self.profile.contact)?;
+        writeln!(
+            out,
+            ",\"first\":{{\"boot\":{},\"seconds\":{}}},\"last\":{{\"boot\":{},\"seconds\":{}}},\"count\":{}}}",
+            self.first.boot, self.first.seconds, self.last.boot, self.last.seconds, self.count
+        )
+    }
+}
+
+/// Write a CSV field, quoting it if it has anything in it that needs quoting
+fn write_csv_field(out: &mut impl Write, s: &str) -> fmt::Result {
+    if !s.contains([',', '"', '\n', '\r']) {
+        return out.write_str(s);
+    }
+    out.write_char('"')?;
+    for c in s.chars() {
+        if c == '"' {
+            out.write_char('"')?;
+        }
+        out.write_char(c)?;
+    }
+    out.write_char('"')
+}
+
+/// Write a JSON string, escaping it as needed
+fn write_json_string(out: &mut impl Write, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// The most recent encounters, oldest first. Meeting a soul again moves it to the end.
+pub struct EncounterHistory<const N: usize> {
+    encounters: Vec<Encounter, N>,
+}
+
+impl<const N: usize> Default for EncounterHistory<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> EncounterHistory<N> {
+    pub const fn new() -> Self {
+        Self { encounters: Vec::new() }
+    }
+
+    /// Remember an exchange, forgetting the oldest encounter if the history is full
+    ///
+    /// # Parameters
+    /// * `key` - Tracker key of the soul
+    /// * `profile` - The profile it sent us
+    /// * `now` - When we exchanged profiles
+    ///
+    /// # Returns
+    /// The encounter to save, and the key of the encounter that was forgotten to make room if one was
+    pub fn record(&mut self, key: u32, profile: Profile, now: Moment) -> (Encounter, Option<u32>) {
+        let encounter = match self.take(key) {
+            Some(known) => Encounter {
+                profile,
+                last: now,
+                count: known.count.saturating_add(1),
+                ..known
+            },
+            None => Encounter {
+                key,
+                profile,
+                first: now,
+                last: now,
+                count: 1,
+            },
+        };
+        (encounter.clone(), self.restore(encounter))
+    }
+
+    /// Put back an encounter that was saved before the restart
+    ///
+    /// # Returns
+    /// The key of the encounter that was forgotten to make room, if one was
+    pub fn restore(&mut self, encounter: Encounter) -> Option<u32> {
+        self.take(encounter.key);
+        let forgotten = if self.encounters.is_full() {
+            Some(self.encounters.remove(0).key)
+        } else {
+            None
+        };
+        let _ = self.encounters.push(encounter);
+        forgotten
+    }
+
+    /// Note that we have seen a soul we exchanged profiles with close up again
+    ///
+    /// # Parameters
+    /// * `key` - Tracker key of the soul
+    /// * `now` - When we saw it
+    ///
+    /// # Returns
+    /// The encounter to save if this counts as meeting it again
+    pub fn seen(&mut self, key: u32, now: Moment) -> Option<Encounter> {
+        let index = self.encounters.iter().position(|e| e.key == key)?;
+        let encounter = &mut self.encounters[index];
+        let again = now.is_apart_from(&encounter.last);
+        encounter.last = now;
+        if !again {
+            return None;
+        }
+        encounter.count = encounter.count.saturating_add(1);
+        let encounter = self.encounters.remove(index);
+        let _ = self.encounters.push(encounter.clone());
+        Some(encounter)
+    }
+
+    /// Take an encounter out of the history
+    fn take(&mut self, key: u32) -> Option<Encounter> {
+        let index = self.encounters.iter().position(|e| e.key == key)?;
+        Some(self.encounters.remove(index))
+    }
+
+    /// True if we have already exchanged profiles with this soul
+    pub fn contains(&self, key: u32) -> bool {
+        self.encounters.iter().any(|e| e.key == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Encounter> {
+        self.encounters.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.encounters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.encounters.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile(name: &str) -> Profile {
+        Profile {
+            name: String::from_str(name).unwrap(),
+            palette: Vec::from_slice(&[RGB8::new(1, 2, 3), RGB8::new(4, 5, 6)]).unwrap(),
+            contact: String::from_str("@who").unwrap(),
+        }
+    }
+
+    #[test]
+    pub fn if_profiles_round_trip() {
+        let mut buffer = [0u8; PROFILE_SIZE];
+        let p = profile("Dr Who");
+        let len = p.encode(&mut buffer);
+        assert_eq!(Profile::decode(&buffer[..len]), Some(p));
+        assert!(Profile::decode(&buffer[..len - 1]).is_none());
+        buffer[0] = 200;
+        assert!(Profile::decode(&buffer[..len]).is_none());
+    }
+
+    fn at(boot: u16, seconds: u32) -> Moment {
+        Moment { boot, seconds }
+    }
+
+    #[test]
+    pub fn if_the_history_keeps_the_latest() {
+        let mut history: EncounterHistory<2> = EncounterHistory::new();
+        history.record(1, profile("One"), at(1, 0));
+        history.record(2, profile("Two"), at(1, 0));
+        assert_eq!(history.record(1, profile("One again"), at(1, 5)).1, None);
+        assert_eq!(history.record(3, profile("Three"), at(1, 5)).1, Some(2));
+        assert_eq!(history.len(), 2);
+        assert!(!history.contains(2));
+        let names: Vec<&str, 2> = history.iter().map(|e| e.profile.name.as_str()).collect();
+        assert_eq!(names.as_slice(), &["One again", "Three"]);
+    }
+
+    #[test]
+    pub fn if_meetings_are_counted() {
+        let mut history: EncounterHistory<2> = EncounterHistory::new();
+        history.record(1, profile("One"), at(1, 100));
+        assert!(history.seen(1, at(1, 200)).is_none());
+        assert!(history.seen(2, at(1, 200)).is_none());
+        let again = history.seen(1, at(1, 200 + ENCOUNTER_GAP as u32)).unwrap();
+        assert_eq!((again.first, again.count), (at(1, 100), 2));
+        // A restart is always a new meeting
+        assert_eq!(history.seen(1, at(2, 10)).map(|e| e.count), Some(3));
+    }
+
+    #[test]
+    pub fn if_encounters_round_trip() {
+        let mut history: EncounterHistory<2> = EncounterHistory::new();
+        let (encounter, _) = history.record(7, profile("Dr Who"), at(3, 42));
+        let mut buffer = [0u8; ENCOUNTER_SIZE];
+        let len = encounter.encode(&mut buffer);
+        assert_eq!(Encounter::decode(7, &buffer[..len]), Some(encounter));
+        // Older firmware only saved the profile
+        let mut old = [0u8; PROFILE_SIZE];
+        let len = profile("Dr Who").encode(&mut old);
+        let decoded = Encounter::decode(7, &old[..len]).unwrap();
+        assert_eq!((decoded.first, decoded.count), (Moment::default(), 1));
+    }
+
+    #[test]
+    pub fn if_the_export_is_escaped() {
+        let mut history: EncounterHistory<2> = EncounterHistory::new();
+        let (encounter, _) = history.record(0xab, profile("Who, \"Dr\""), at(1, 2));
+        let mut line: String<256> = String::new();
+        encounter.write_csv(&mut line).unwrap();
+        assert_eq!(line.as_str(), "000000ab,\"Who, \"\"Dr\"\"\",@who,1,2,1,2,1\n");
+        line.clear();
+        encounter.write_json(&mut line).unwrap();
+        assert_eq!(
+            line.as_str(),
+            "{\"soul\":\"000000ab\",\"name\":\"Who, \\\"Dr\\\"\",\"contact\":\"@who\",\
+             \"first\":{\"boot\":1,\"seconds\":2},\"last\":{\"boot\":1,\"seconds\":2},\"count\":1}\n"
+        );
+    }
+}
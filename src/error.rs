@@ -0,0 +1,21 @@
+//! The things that can go wrong in a way somebody further up might want to act on.
+//!
+//! Drivers and tasks report failures as a [SoulStarError] rather than panicking, so whoever
+//! called them can decide whether to retry, carry on without the part that failed or tell the
+//! wearer. The details of the underlying driver error are logged where it happens, as they are
+//! rarely of any use to the caller and the driver error types don't travel well.
+
+use defmt::Format;
+
+/// What went wrong
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum SoulStarError {
+    /// The BLE controller or host stack failed
+    Ble,
+    /// The RMT peripheral couldn't send a frame to the LED strip
+    Rmt,
+    /// The flash couldn't be read or written
+    Storage,
+    /// The configuration can't work, e.g. a value too big for the store
+    Config,
+}
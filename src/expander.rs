@@ -0,0 +1,63 @@
+//! Buttons on an I2C GPIO expander, for wearable builds that have run out of pins. The PCF8574
+//! and the MCP23017 (port A only) are supported.
+//!
+//! The expander lines are pulled up, so a pressed button reads low, just like the GPIO buttons.
+//! They are polled every [BUTTON_POLL_INTERVAL] milliseconds and the presses are delivered to the
+//! same channel as the GPIO buttons.
+
+use crate::button::BUTTON_PRESSES;
+use crate::i2c_bus::I2cBus;
+use defmt::Debug2Format;
+use embassy_time::{Duration, Instant, Ticker};
+use soulstar::buttons::{ButtonSampler, Expander};
+use soulstar::configuration::{BUTTON_DEBOUNCE, BUTTON_POLL_INTERVAL, EXPANDER_ADDRESS, EXPANDER_BUTTONS};
+use soulstar::log;
+
+/// MCP23017 register holding the pull-ups for port A
+const MCP23017_GPPUA: u8 = 0x0C;
+
+/// MCP23017 register holding the levels on port A
+const MCP23017_GPIOA: u8 = 0x12;
+
+/// Poll the buttons on the expander
+///
+/// # Parameters
+/// * `bus` - The I2C bus the expander is on
+/// * `expander` - The kind of expander
+#[embassy_executor::task]
+pub async fn expander_task(bus: &'static I2cBus, expander: Expander) {
+    log!(Main, info, "EXPANDER: Reading buttons from a {} at {:x}", expander, EXPANDER_ADDRESS);
+    // All lines are inputs after reset. Pull them up so released buttons read high.
+    let mut i2c = bus.lock().await;
+    let setup = match expander {
+        // Writing a one makes a line a weakly pulled up input
+        Expander::Pcf8574 => i2c.write_async(EXPANDER_ADDRESS, &[0xFF]).await,
+        Expander::Mcp23017 => i2c.write_async(EXPANDER_ADDRESS, &[MCP23017_GPPUA, 0xFF]).await,
+    };
+    if let Err(e) = setup {
+        log!(Main, warn, "EXPANDER: Could not set up the expander: {:?}", Debug2Format(&e));
+    }
+    drop(i2c);
+    let mut sampler = ButtonSampler::new(EXPANDER_BUTTONS, Duration::from_millis(BUTTON_DEBOUNCE));
+    let mut ticker = Ticker::every(Duration::from_millis(BUTTON_POLL_INTERVAL));
+    loop {
+        ticker.next().await;
+        let mut levels = [0u8; 1];
+        let mut i2c = bus.lock().await;
+        let read = match expander {
+            Expander::Pcf8574 => i2c.read_async(EXPANDER_ADDRESS, &mut levels).await,
+            Expander::Mcp23017 => {
+                i2c.write_read_async(EXPANDER_ADDRESS, &[MCP23017_GPIOA], &mut levels)
+                    .await
+            }
+        };
+        drop(i2c);
+        if let Err(e) = read {
+            log!(Main, warn, "EXPANDER: Could not read the buttons: {:?}", Debug2Format(&e));
+            continue;
+        }
+        for press in sampler.sample(!levels[0] as u16, Instant::now()) {
+            BUTTON_PRESSES.send(press).await;
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Factory reset from a button chord.
+//!
+//! Only the wearer's [settings](crate::settings), which include the badges and the path-loss
+//! calibration, and the encounter history are kept in flash, and the reset erases them.
+//! Everything else we learn, tuning and log levels, lives in RAM, and we never bond with anybody,
+//! so there are no keys to forget. A restart then puts everything back to its defaults. The reset
+//! is guarded so it can't happen by accident: both brightness buttons must be held for
+//! [FACTORY_RESET_HOLD] seconds, with the strip counting down in red once they have been held
+//! for a long press.
+
+use crate::configuration::{FACTORY_RESET_HOLD, LONG_PRESS};
+use embassy_time::{Duration, Instant};
+
+/// What a change in the chord means
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetEvent {
+    /// The chord has been held long enough to start counting down. This is how long is left.
+    Armed(Duration),
+    /// The chord was let go of before the countdown ran out
+    Cancelled,
+    /// The countdown ran out, so it is time to reset
+    Reset,
+}
+
+/// Follows the reset chord
+#[derive(Default)]
+pub struct ResetChord {
+    /// When the chord was first held, None if it isn't being held
+    since: Option<Instant>,
+    /// The countdown has started
+    armed: bool,
+}
+
+impl ResetChord {
+    pub const fn new() -> Self {
+        Self {
+            since: None,
+            armed: false,
+        }
+    }
+
+    /// Take a sample of the chord
+    ///
+    /// # Parameters
+    /// * `held` - True if both brightness buttons are being held
+    /// * `now` - The time of the sample
+    ///
+    /// # Returns
+    /// Something to act on, if anything has changed
+    pub fn update(&mut self, held: bool, now: Instant) -> Option<ResetEvent> {
+        let hold = Duration::from_secs(FACTORY_RESET_HOLD);
+        let Some(since) = self.since.filter(|_| held) else {
+            self.since = held.then_some(now);
+            let cancelled = self.armed;
+            self.armed = false;
+            return cancelled.then_some(ResetEvent::Cancelled);
+        };
+        let held_for = now.saturating_duration_since(since);
+        if held_for >= hold {
+            self.since = None;
+            self.armed = false;
+            Some(ResetEvent::Reset)
+        } else if !self.armed && held_for >= Duration::from_millis(LONG_PRESS) {
+            self.armed = true;
+            Some(ResetEvent::Armed(hold - held_for))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_resets_after_a_long_hold() {
+        let mut chord = ResetChord::new();
+        let at = |ms| Instant::from_millis(ms);
+        assert_eq!(chord.update(false, at(0)), None);
+        assert_eq!(chord.update(true, at(100)), None);
+        assert_eq!(chord.update(true, at(500)), None);
+        assert_eq!(
+            chord.update(true, at(1100)),
+            Some(ResetEvent::Armed(Duration::from_secs(FACTORY_RESET_HOLD) - Duration::from_millis(1000)))
+        );
+        assert_eq!(chord.update(true, at(5000)), None);
+        assert_eq!(chord.update(true, at(100 + FACTORY_RESET_HOLD * 1000)), Some(ResetEvent::Reset));
+    }
+
+    #[test]
+    pub fn if_letting_go_cancels() {
+        let mut chord = ResetChord::new();
+        let at = |ms| Instant::from_millis(ms);
+        chord.update(true, at(0));
+        // A quick chord never starts the countdown
+        assert_eq!(chord.update(false, at(300)), None);
+        chord.update(true, at(1000));
+        assert!(matches!(chord.update(true, at(2500)), Some(ResetEvent::Armed(_))));
+        assert_eq!(chord.update(false, at(3000)), Some(ResetEvent::Cancelled));
+        // Holding again starts from scratch
+        assert_eq!(chord.update(true, at(4000)), None);
+        assert!(matches!(chord.update(true, at(4000 + FACTORY_RESET_HOLD * 1000 - 1)), Some(ResetEvent::Armed(_))));
+        assert_eq!(chord.update(true, at(4000 + FACTORY_RESET_HOLD * 1000)), Some(ResetEvent::Reset));
+    }
+}
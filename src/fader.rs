@@ -0,0 +1,93 @@
+//! Smooth brightness changes.
+//!
+//! A fader ramps the effective brightness towards a target over a fixed number of steps so that
+//! brightness changes fade in rather than jump. Each call to `next()` moves one step closer to
+//! the target, returning None once the target has been reached.
+
+/// Brightness fade state
+#[derive(Clone, Copy)]
+pub struct Fader {
+    brightness: u8,
+    target: u8,
+    step: u8,
+}
+
+impl Fader {
+    /// Create a fader that starts out at the given brightness with nothing to do
+    pub const fn new(brightness: u8) -> Self {
+        Self {
+            brightness,
+            target: brightness,
+            step: 1,
+        }
+    }
+
+    /// Set a new brightness target and fade to it over the given number of steps.
+    ///
+    /// # Parameters
+    /// * `target` - The brightness to fade to
+    /// * `steps` - The number of steps to take to get there. Zero jumps straight to the target
+    pub fn set_target(&mut self, target: u8, steps: u8) {
+        self.target = target;
+        if steps == 0 {
+            self.brightness = target;
+        } else {
+            self.step = self.brightness.abs_diff(target).div_ceil(steps).max(1);
+        }
+    }
+
+    /// The current effective brightness
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// The brightness we are fading to
+    pub fn target(&self) -> u8 {
+        self.target
+    }
+
+    /// True while we have not yet reached the target
+    pub fn is_fading(&self) -> bool {
+        self.brightness != self.target
+    }
+}
+
+impl Iterator for Fader {
+    type Item = u8;
+
+    /// Next brightness value on the way to the target
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.is_fading() {
+            return None;
+        }
+        self.brightness = if self.brightness < self.target {
+            self.brightness.saturating_add(self.step).min(self.target)
+        } else {
+            self.brightness.saturating_sub(self.step).max(self.target)
+        };
+        Some(self.brightness)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_fades_up_and_down() {
+        let mut f = Fader::new(32);
+        f.set_target(48, 4);
+        assert_eq!(f.by_ref().collect::<heapless::Vec<u8, 8>>().as_slice(), &[36, 40, 44, 48]);
+        f.set_target(0, 3);
+        assert_eq!(f.by_ref().last(), Some(0));
+        assert_eq!(f.count(), 0);
+    }
+
+    #[test]
+    pub fn if_it_jumps_without_steps() {
+        let mut f = Fader::new(32);
+        f.set_target(200, 0);
+        assert_eq!(f.brightness(), 200);
+        assert!(!f.is_fading());
+    }
+}
@@ -0,0 +1,149 @@
+//! The spectrum of the music the microphone hears, for the spectrum analyser.
+//!
+//! The RISC-V core has no FPU, so this is a fixed-point radix-2 FFT over [FFT_SIZE] samples with
+//! the twiddle factors in Q15. Every stage halves its outputs so nothing can overflow, which
+//! leaves the result scaled down by [FFT_SIZE].
+//!
+//! The microphone task (see `microphone.rs`) hands the latest samples to [bands]. They are
+//! halved to 8kHz first, so each bin is 125Hz wide and the spectrum reaches up to 4kHz, and
+//! shaped with a Hann window so a loud bass note doesn't leak into the treble. The bins are then
+//! gathered into [BANDS] bands, bass, middle and treble, and published in [audio](crate::audio).
+
+use crate::configuration::MIC_FULL_SCALE;
+use crate::math::cos8;
+
+/// The number of samples the FFT is taken over
+pub const FFT_SIZE: usize = 64;
+
+/// The bands the spectrum is gathered into: bass, middle and treble
+pub const BANDS: usize = 3;
+
+/// The first bin of each band, and the end of the last one. Bins are 125Hz wide, so the bass is
+/// up to 375Hz and the middle up to 2kHz. Bin 0 is the DC offset of the microphone.
+const BAND_EDGES: [usize; BANDS + 1] = [1, 3, 16, FFT_SIZE / 2];
+
+/// A quarter cycle of a sine wave over [FFT_SIZE] steps, in Q15
+const SINE_Q15: [i32; FFT_SIZE / 4 + 1] =
+    [0, 3212, 6393, 9512, 12539, 15446, 18204, 20787, 23170, 25329, 27245, 28898, 30273, 31356, 32137, 32609, 32767];
+
+/// The cosine and sine of `k` steps of [FFT_SIZE] round the circle, for the first half cycle
+fn twiddle(k: usize) -> (i32, i32) {
+    let quarter = FFT_SIZE / 4;
+    if k <= quarter {
+        (SINE_Q15[quarter - k], SINE_Q15[k])
+    } else {
+        (-SINE_Q15[k - quarter], SINE_Q15[2 * quarter - k])
+    }
+}
+
+/// Transform the samples into their spectrum, in place. The result is scaled down by [FFT_SIZE].
+///
+/// # Parameters
+/// * `re` - The samples going in, the real part of the spectrum coming out
+/// * `im` - Zeros going in, the imaginary part of the spectrum coming out
+pub fn fft(re: &mut [i32; FFT_SIZE], im: &mut [i32; FFT_SIZE]) {
+    let bits = FFT_SIZE.trailing_zeros();
+    for i in 0..FFT_SIZE {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut size = 2;
+    while size <= FFT_SIZE {
+        let half = size / 2;
+        for start in (0..FFT_SIZE).step_by(size) {
+            for k in 0..half {
+                let (cos, sin) = twiddle(k * FFT_SIZE / size);
+                let (a, b) = (start + k, start + k + half);
+                // Multiply by e^-jθ
+                let tr = (re[b] * cos + im[b] * sin) >> 15;
+                let ti = (im[b] * cos - re[b] * sin) >> 15;
+                (re[b], im[b]) = ((re[a] - tr) >> 1, (im[a] - ti) >> 1);
+                (re[a], im[a]) = ((re[a] + tr) >> 1, (im[a] + ti) >> 1);
+            }
+        }
+        size *= 2;
+    }
+}
+
+/// The size of a complex number, near enough: the bigger part plus half the smaller one
+fn magnitude(re: i32, im: i32) -> u32 {
+    let (re, im) = (re.unsigned_abs(), im.unsigned_abs());
+    re.max(im) + re.min(im) / 2
+}
+
+/// The level of the bass, middle and treble in the latest samples
+///
+/// # Parameters
+/// * `samples` - At least twice [FFT_SIZE] samples at 16kHz, only the latest are used
+///
+/// # Returns
+/// The loudest bin in each band, scaled so that a tone [MIC_FULL_SCALE] high is 255
+pub fn bands(samples: &[i16]) -> [u8; BANDS] {
+    let latest = &samples[samples.len().saturating_sub(2 * FFT_SIZE)..];
+    let mut re = [0i32; FFT_SIZE];
+    let mut im = [0i32; FFT_SIZE];
+    for (n, (sample, &[a, b])) in re.iter_mut().zip(latest.as_chunks::<2>().0).enumerate() {
+        // The window is 0 at the ends and 255 in the middle
+        let window = 255 - cos8((n * 256 / FFT_SIZE) as u8) as i32;
+        *sample = (a as i32 + b as i32) / 2 * window / 256;
+    }
+    fft(&mut re, &mut im);
+    let mut levels = [0u8; BANDS];
+    for (level, edges) in levels.iter_mut().zip(BAND_EDGES.windows(2)) {
+        let loudest = (edges[0]..edges[1])
+            .map(|bin| magnitude(re[bin], im[bin]))
+            .max()
+            .unwrap_or(0);
+        // The FFT halves a tone's height, and so does the window
+        *level = (loudest * 4 * 255 / MIC_FULL_SCALE as u32).min(255) as u8;
+    }
+    levels
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A tone `cycles` times round in every [FFT_SIZE] samples, about 12700 high
+    fn tone(cycles: usize, n: usize) -> i32 {
+        (cos8((n * cycles * 256 / FFT_SIZE) as u8) as i32 - 128) * 100
+    }
+
+    #[test]
+    pub fn if_a_tone_lands_in_its_bin() {
+        let mut re = [0i32; FFT_SIZE];
+        let mut im = [0i32; FFT_SIZE];
+        for (n, sample) in re.iter_mut().enumerate() {
+            *sample = tone(4, n);
+        }
+        fft(&mut re, &mut im);
+        let loudest = (1..FFT_SIZE / 2)
+            .max_by_key(|&bin| magnitude(re[bin], im[bin]))
+            .unwrap();
+        assert_eq!(loudest, 4);
+        // Half the height, from each side of the spectrum
+        assert!((6000..6700).contains(&magnitude(re[4], im[4])));
+        assert!(magnitude(re[10], im[10]) < 100);
+    }
+
+    #[test]
+    pub fn if_the_bands_follow_the_pitch() {
+        // 125Hz, 1kHz and 3kHz at 16kHz, each sample repeated to make the halving easy to follow
+        let at = |hz: usize| {
+            let mut samples = [0i16; 2 * FFT_SIZE];
+            for (n, pair) in samples.as_chunks_mut::<2>().0.iter_mut().enumerate() {
+                pair.fill(tone(hz * FFT_SIZE / 8000, n) as i16);
+            }
+            bands(&samples)
+        };
+        let [bass, middle, treble] = at(125);
+        assert!(bass > 200 && middle < 100 && treble < 20);
+        let [bass, middle, treble] = at(1000);
+        assert!(bass < 20 && middle > 200 && treble < 100);
+        let [bass, middle, treble] = at(3000);
+        assert!(bass < 20 && middle < 100 && treble > 200);
+    }
+}
@@ -0,0 +1,391 @@
+//! Typed fields in the manufacturer specific data of our beacon.
+//!
+//! The first beacons carried just the colour and an optional flags byte. Newer ones carry a list
+//! of fields, each an ID byte, a length byte and the data, so that fields can be added without
+//! upsetting the souls that don't know about them yet. Decoding is tolerant: unknown fields and
+//! known fields of the wrong length are skipped, and a field that runs off the end stops the
+//! decoding. Old style payloads are three or four bytes long and never look like a field list,
+//! which always starts with the five byte colour field.
+
+use crate::command::COMMAND_SIZE;
+use crate::crew::{HUNT_SIZE, REPORT_SIZE};
+use crate::leader::LEAD_SIZE;
+use heapless::Vec;
+use smart_leds::RGB8;
+
+/// Set in the flags when the sender is calling for help
+pub const EMERGENCY_FLAG: u8 = 0x01;
+
+/// Set in the flags when the sender is a development unit on the test network
+pub const TEST_FLAG: u8 = 0x02;
+
+/// Set in the flags while the sender is waving at the souls around it
+pub const WAVE_FLAG: u8 = 0x04;
+
+/// The most manufacturer data the fields can take up
+pub const MAX_FIELDS_SIZE: usize = 92;
+
+/// The fields we know about. New ones must take a new ID and never change the meaning of an old one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FieldId {
+    /// Our preferred colour as RGB
+    Colour = 0x01,
+    /// Flags such as [EMERGENCY_FLAG], [TEST_FLAG] and [WAVE_FLAG]
+    Flags = 0x02,
+    /// Battery charge in percent
+    Battery = 0x03,
+    /// The wearer's mood, meaning up to the wearer
+    Mood = 0x04,
+    /// The group the soul belongs to, little endian
+    Group = 0x05,
+    /// An ID that survives the random address changing, little endian
+    StableId = 0x06,
+    /// Firmware version as major, minor and patch
+    FirmwareVersion = 0x07,
+    /// The soul's [Role] at an event
+    Role = 0x08,
+    /// The sender's [shared clock](crate::clock) in milliseconds, little endian
+    Clock = 0x09,
+    // 0x0A was a lead without a tag, which is no longer believed
+    /// A controller [Command](crate::command::Command), tagged with the group key
+    Command = 0x0B,
+    // 0x0C and 0x0D were hunts and reports without a tag, which are no longer believed
+    /// The soul the sender wants its [crew](crate::crew) to help find, tagged with the group key
+    Hunt = 0x0E,
+    /// The sender's path loss to the soul its crew is hunting, tagged with the group key, see
+    /// [Report](crate::crew::Report)
+    Report = 0x0F,
+    /// The animation a leader wants its followers to show, tagged with the group key, see
+    /// [Lead](crate::leader::Lead)
+    Lead = 0x10,
+}
+
+/// What a soul does at an event. Event staff are picked out on the strip with a halo pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Role {
+    #[default]
+    Regular,
+    Organiser,
+    Medic,
+    BarCrew,
+}
+
+impl Role {
+    /// The role with the given number, None if we don't know it
+    pub fn from_u8(role: u8) -> Option<Self> {
+        [Self::Regular, Self::Organiser, Self::Medic, Self::BarCrew]
+            .get(role as usize)
+            .copied()
+    }
+
+    /// The colour of the halo pixel shown beside the soul's own colour, None for regulars
+    pub fn halo(self) -> Option<RGB8> {
+        match self {
+            Role::Regular => None,
+            Role::Organiser => Some(RGB8::new(255, 255, 255)),
+            Role::Medic => Some(RGB8::new(0, 255, 96)),
+            Role::BarCrew => Some(RGB8::new(255, 128, 0)),
+        }
+    }
+}
+
+/// The version of this firmware, for the [FieldId::FirmwareVersion] field
+pub const FIRMWARE_VERSION: [u8; 3] = [
+    version_part(env!("CARGO_PKG_VERSION_MAJOR")),
+    version_part(env!("CARGO_PKG_VERSION_MINOR")),
+    version_part(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+const fn version_part(part: &str) -> u8 {
+    match u8::from_str_radix(part, 10) {
+        Ok(v) => v,
+        Err(_) => 0,
+    }
+}
+
+/// The fields of a beacon. Everything but the colour is optional.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BeaconFields {
+    pub colour: RGB8,
+    /// The sender is calling for help
+    pub emergency: bool,
+    /// The sender is on the test network, see [Network](crate::payload::Network)
+    pub test: bool,
+    /// The sender is waving at us, see [wave](crate::wave)
+    pub wave: bool,
+    pub battery: Option<u8>,
+    pub mood: Option<u8>,
+    pub group: Option<u16>,
+    pub stable_id: Option<u32>,
+    pub firmware: Option<[u8; 3]>,
+    /// Regulars are left out of the beacon
+    pub role: Role,
+    pub clock: Option<u32>,
+    /// Only sent while leading. It is checked against the group key later.
+    pub lead: Option<[u8; LEAD_SIZE]>,
+    /// Only sent by a controller. It is checked against the group key later.
+    pub command: Option<[u8; COMMAND_SIZE]>,
+    /// Only sent while playing hot/cold with the crew finder on. It is checked against the group
+    /// key later.
+    pub hunt: Option<[u8; HUNT_SIZE]>,
+    /// Only sent while helping the crew hunt. It is checked against the group key later.
+    pub report: Option<[u8; REPORT_SIZE]>,
+}
+
+impl BeaconFields {
+    /// Encode the fields, leaving out the ones we don't have
+    pub fn encode(&self) -> Vec<u8, MAX_FIELDS_SIZE> {
+        let mut data = Vec::new();
+        let mut field = |id: FieldId, value: &[u8]| {
+            // Every field we know fits, see if_all_the_fields_fit
+            let _ = data.push(id as u8);
+            let _ = data.push(value.len() as u8);
+            let _ = data.extend_from_slice(value);
+        };
+        field(FieldId::Colour, &[self.colour.r, self.colour.g, self.colour.b]);
+        if self.emergency || self.test || self.wave {
+            let flag = |set: bool, flag: u8| if set { flag } else { 0 };
+            let flags = flag(self.emergency, EMERGENCY_FLAG) | flag(self.test, TEST_FLAG) | flag(self.wave, WAVE_FLAG);
+            field(FieldId::Flags, &[flags]);
+        }
+        if let Some(battery) = self.battery {
+            field(FieldId::Battery, &[battery]);
+        }
+        if let Some(mood) = self.mood {
+            field(FieldId::Mood, &[mood]);
+        }
+        if let Some(group) = self.group {
+            field(FieldId::Group, &group.to_le_bytes());
+        }
+        if let Some(id) = self.stable_id {
+            field(FieldId::StableId, &id.to_le_bytes());
+        }
+        if let Some(version) = self.firmware {
+            field(FieldId::FirmwareVersion, &version);
+        }
+        if self.role != Role::Regular {
+            field(FieldId::Role, &[self.role as u8]);
+        }
+        if let Some(clock) = self.clock {
+            field(FieldId::Clock, &clock.to_le_bytes());
+        }
+        if let Some(lead) = self.lead {
+            field(FieldId::Lead, &lead);
+        }
+        if let Some(command) = self.command {
+            field(FieldId::Command, &command);
+        }
+        if let Some(hunt) = self.hunt {
+            field(FieldId::Hunt, &hunt);
+        }
+        if let Some(report) = self.report {
+            field(FieldId::Report, &report);
+        }
+        data
+    }
+
+    /// Leave out the least important optional field that is still there, for a beacon that is too
+    /// big. The colour, the flags and any command are always kept.
+    ///
+    /// # Returns
+    /// False if there was nothing left to leave out
+    pub fn shed(&mut self) -> bool {
+        self.stable_id.take().is_some()
+            || self.mood.take().is_some()
+            || self.firmware.take().is_some()
+            || self.battery.take().is_some()
+            || core::mem::take(&mut self.role) != Role::Regular
+            || self.clock.take().is_some()
+            || self.report.take().is_some()
+            || self.hunt.take().is_some()
+            || self.group.take().is_some()
+            || self.lead.take().is_some()
+    }
+
+    /// Decode the manufacturer data following the company ID, in either the old or the new
+    /// format. Returns None if there is no colour, as then it can't be one of ours.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        match *data {
+            // The old format: colour and maybe flags
+            [r, g, b] => Some(Self::legacy(RGB8::new(r, g, b), 0)),
+            [r, g, b, flags] => Some(Self::legacy(RGB8::new(r, g, b), flags)),
+            _ => Self::decode_fields(data),
+        }
+    }
+
+    fn legacy(colour: RGB8, flags: u8) -> Self {
+        Self {
+            colour,
+            emergency: flags & EMERGENCY_FLAG != 0,
+            test: flags & TEST_FLAG != 0,
+            wave: flags & WAVE_FLAG != 0,
+            ..Default::default()
+        }
+    }
+
+    fn decode_fields(mut data: &[u8]) -> Option<Self> {
+        let mut colour = None;
+        let mut fields = Self::default();
+        while let [id, len, ref rest @ ..] = *data {
+            let Some((value, next)) = rest.split_at_checked(len as usize) else {
+                break;
+            };
+            data = next;
+            match (id, value) {
+                (id, &[r, g, b]) if id == FieldId::Colour as u8 => colour = Some(RGB8::new(r, g, b)),
+                (id, &[flags]) if id == FieldId::Flags as u8 => {
+                    fields.emergency = flags & EMERGENCY_FLAG != 0;
+                    fields.test = flags & TEST_FLAG != 0;
+                    fields.wave = flags & WAVE_FLAG != 0;
+                }
+                (id, &[battery]) if id == FieldId::Battery as u8 => fields.battery = Some(battery),
+                (id, &[mood]) if id == FieldId::Mood as u8 => fields.mood = Some(mood),
+                (id, &[lo, hi]) if id == FieldId::Group as u8 => fields.group = Some(u16::from_le_bytes([lo, hi])),
+                (id, &[a, b, c, d]) if id == FieldId::StableId as u8 => {
+                    fields.stable_id = Some(u32::from_le_bytes([a, b, c, d]))
+                }
+                (id, &[major, minor, patch]) if id == FieldId::FirmwareVersion as u8 => {
+                    fields.firmware = Some([major, minor, patch])
+                }
+                // Roles we don't know yet are treated as regulars
+                (id, &[role]) if id == FieldId::Role as u8 => fields.role = Role::from_u8(role).unwrap_or_default(),
+                (id, &[a, b, c, d]) if id == FieldId::Clock as u8 => {
+                    fields.clock = Some(u32::from_le_bytes([a, b, c, d]))
+                }
+                (id, lead) if id == FieldId::Lead as u8 => fields.lead = lead.try_into().ok(),
+                (id, command) if id == FieldId::Command as u8 => fields.command = command.try_into().ok(),
+                (id, hunt) if id == FieldId::Hunt as u8 => fields.hunt = hunt.try_into().ok(),
+                (id, report) if id == FieldId::Report as u8 => fields.report = report.try_into().ok(),
+                // Something from a newer soul, or garbage
+                _ => (),
+            }
+        }
+        colour.map(|colour| Self { colour, ..fields })
+    }
+}
+
+#[cfg(test)]
+impl BeaconFields {
+    /// Any set of fields a soul could send, for the property tests
+    pub(crate) fn arbitrary(rng: &mut fastrand::Rng) -> Self {
+        let roles = (0..=u8::MAX).map_while(Role::from_u8).count() as u8;
+        Self {
+            colour: RGB8::new(rng.u8(..), rng.u8(..), rng.u8(..)),
+            emergency: rng.bool(),
+            test: rng.bool(),
+            wave: rng.bool(),
+            battery: rng.bool().then(|| rng.u8(..)),
+            mood: rng.bool().then(|| rng.u8(..)),
+            group: rng.bool().then(|| rng.u16(..)),
+            stable_id: rng.bool().then(|| rng.u32(..)),
+            firmware: rng.bool().then(|| [rng.u8(..), rng.u8(..), rng.u8(..)]),
+            role: Role::from_u8(rng.u8(..roles)).unwrap(),
+            clock: rng.bool().then(|| rng.u32(..)),
+            lead: rng.bool().then(|| {
+                let mut lead = [0; LEAD_SIZE];
+                rng.fill(&mut lead);
+                lead
+            }),
+            command: rng.bool().then(|| {
+                let mut command = [0; COMMAND_SIZE];
+                rng.fill(&mut command);
+                command
+            }),
+            hunt: rng.bool().then(|| {
+                let mut hunt = [0; HUNT_SIZE];
+                rng.fill(&mut hunt);
+                hunt
+            }),
+            report: rng.bool().then(|| {
+                let mut report = [0; REPORT_SIZE];
+                rng.fill(&mut report);
+                report
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_all_the_fields_fit() {
+        let fields = BeaconFields {
+            colour: RGB8::new(1, 2, 3),
+            emergency: true,
+            test: true,
+            wave: true,
+            battery: Some(87),
+            mood: Some(4),
+            group: Some(0x1234),
+            stable_id: Some(0xDEADBEEF),
+            firmware: Some(FIRMWARE_VERSION),
+            role: Role::Medic,
+            clock: Some(123_456),
+            lead: Some([4; LEAD_SIZE]),
+            command: Some([9; COMMAND_SIZE]),
+            hunt: Some([5; HUNT_SIZE]),
+            report: Some([6; REPORT_SIZE]),
+        };
+        let data = fields.encode();
+        assert_eq!(data.len(), 5 + 3 + 3 + 3 + 4 + 6 + 5 + 3 + 6 + 12 + 14 + 10 + 11);
+        assert_eq!(BeaconFields::decode(&data), Some(fields));
+    }
+
+    #[test]
+    pub fn if_it_reads_old_beacons() {
+        let fields = BeaconFields::decode(&[1, 2, 3]).unwrap();
+        assert_eq!(fields.colour, RGB8::new(1, 2, 3));
+        assert!(!fields.emergency);
+        let fields = BeaconFields::decode(&[1, 2, 3, EMERGENCY_FLAG]).unwrap();
+        assert!(fields.emergency);
+        assert_eq!(fields.battery, None);
+    }
+
+    #[test]
+    pub fn if_it_skips_what_it_does_not_know() {
+        // A field from the future, a battery of the wrong size and a truncated mood
+        let data = [0x42, 2, 9, 9, 0x01, 3, 1, 2, 3, 0x03, 2, 50, 50, 0x04, 5, 1];
+        let fields = BeaconFields::decode(&data).unwrap();
+        assert_eq!(fields.colour, RGB8::new(1, 2, 3));
+        assert_eq!(fields.battery, None);
+        assert_eq!(fields.mood, None);
+        // No colour, no soul
+        assert_eq!(BeaconFields::decode(&[0x03, 1, 50, 0x04, 1, 2]), None);
+        assert_eq!(BeaconFields::decode(&[]), None);
+    }
+
+    #[test]
+    pub fn if_arbitrary_fields_round_trip() {
+        let mut rng = fastrand::Rng::with_seed(0xF1E1D);
+        for _ in 0..10_000 {
+            let fields = BeaconFields::arbitrary(&mut rng);
+            assert_eq!(BeaconFields::decode(&fields.encode()), Some(fields));
+        }
+    }
+
+    #[test]
+    pub fn if_it_survives_garbage() {
+        let mut rng = fastrand::Rng::with_seed(0x6A4B);
+        let mut data = [0u8; MAX_FIELDS_SIZE];
+        for _ in 0..10_000 {
+            let len = rng.usize(0..=MAX_FIELDS_SIZE);
+            rng.fill(&mut data[..len]);
+            // Some garbage looks like a beacon, and whatever we made of it must survive being sent on
+            if let Some(fields) = BeaconFields::decode(&data[..len]) {
+                assert_eq!(BeaconFields::decode(&fields.encode()), Some(fields));
+            }
+        }
+        // Well formed fields with the odd byte changed
+        for _ in 0..10_000 {
+            let mut data = BeaconFields::arbitrary(&mut rng).encode();
+            let i = rng.usize(..data.len());
+            data[i] = rng.u8(..);
+            let len = rng.usize(..=data.len());
+            BeaconFields::decode(&data[..len]);
+        }
+    }
+}
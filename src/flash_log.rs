@@ -0,0 +1,530 @@
+//! A small wear-levelled key-value store in flash, for the [settings](crate::settings) and the
+//! history of the souls we have swapped profiles with.
+//!
+//! Rewriting one sector every time something changes would erase it hundreds of times over a
+//! season of festivals. Instead the store is a log spread over a ring of sectors. Every change
+//! is a new record appended to the newest sector, so nothing is erased until that sector fills
+//! up. Then the records that are still live, the latest one for each key, are copied into the
+//! next sector round the ring and the log carries on there. Each sector is erased once a lap.
+//!
+//! Every sector starts with a header holding a sequence number, which goes up by one for each
+//! new sector, so the newest is the one with the highest. Each record is
+//!
+//! | Bytes    | Contents                                              |
+//! |----------|-------------------------------------------------------|
+//! | 1        | The [Tag], what sort of record it is                  |
+//! | 1        | The length of the value, zero when the key is removed |
+//! | 2        | Unused                                                |
+//! | 4        | The key, little endian                                |
+//! | n        | The value, padded to a multiple of four bytes         |
+//! | 4        | A CRC-32 over everything but the padding              |
+//!
+//! Erased flash reads as all ones, so a tag of 0xFF marks the end of the records. A record
+//! that was cut short by a power cut fails its CRC, and the sector is treated as full from there
+//! so the next write moves on to a fresh one. The header of a new sector is written after the
+//! records are copied into it, so if the copy is cut short the old sector is still the newest.
+//!
+//! All the live records have to fit in one sector, which they do with room to spare as there
+//! are at most [LOG_KEYS] of them and a profile is small.
+
+use crate::configuration::MAX_ENCOUNTERS;
+use crate::encounters::ENCOUNTER_SIZE;
+use crate::error::SoulStarError;
+use crate::log;
+use crate::overrides::MAX_OVERRIDES;
+use crate::settings::SETTINGS_SIZE;
+use crate::utils::crc32;
+use defmt::Debug2Format;
+use embedded_storage::nor_flash::NorFlash;
+use heapless::Vec;
+
+/// Marks a sector that belongs to the log
+const SECTOR_MAGIC: [u8; 4] = *b"SLOG";
+
+/// The magic and the sequence number at the start of every sector
+const SECTOR_HEADER: u32 = 8;
+
+/// The tag, length, padding and key at the start of every record
+const RECORD_HEADER: usize = 8;
+
+/// The CRC at the end of every record
+const RECORD_CRC: usize = 4;
+
+/// Records start on a multiple of this. The flash must write in units this size or smaller.
+const ALIGN: usize = 4;
+
+/// The largest value, padded
+const MAX_VALUE: usize = 256;
+
+/// The most keys the log keeps, one for each encounter and friend colour, the settings and the
+/// number of boots
+pub const LOG_KEYS: usize = MAX_ENCOUNTERS + MAX_OVERRIDES + 2;
+
+const _: () = assert!(
+    SECTOR_HEADER
+        + MAX_ENCOUNTERS as u32 * record_size(ENCOUNTER_SIZE)
+        + MAX_OVERRIDES as u32 * record_size(3)
+        + record_size(SETTINGS_SIZE)
+        + record_size(2)
+        <= 4096,
+    "The live records don't fit in one 4kB sector"
+);
+
+/// What a record holds. New ones must take a new tag and never change the meaning of an old one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Tag {
+    /// The encoded [Settings](crate::settings::Settings), under key 0
+    Settings = 0x01,
+    /// A soul we have swapped profiles with, under its tracker key
+    Encounter = 0x02,
+    /// The number of times we have started, under key 0, see [set_boot](crate::encounters::set_boot)
+    Boots = 0x03,
+    /// A colour given to a friend at runtime, under its [name_key](crate::overrides::name_key)
+    ColourOverride = 0x04,
+}
+
+/// Where the latest record for a key is
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    tag: u8,
+    key: u32,
+    len: u8,
+    /// The flash address of the start of the record
+    address: u32,
+}
+
+/// The bytes a value takes up once it is padded
+const fn padded(len: usize) -> usize {
+    len.div_ceil(ALIGN) * ALIGN
+}
+
+/// The bytes a record takes up
+const fn record_size(len: usize) -> u32 {
+    (RECORD_HEADER + padded(len) + RECORD_CRC) as u32
+}
+
+/// The store. It owns the flash it is kept in.
+pub struct FlashLog<F> {
+    flash: F,
+    /// The flash address of the first sector
+    offset: u32,
+    /// The number of sectors in the ring
+    sectors: u32,
+    /// The newest sector, which records are appended to
+    head: u32,
+    /// The sequence number of the newest sector
+    sequence: u32,
+    /// Where the next record goes in the newest sector
+    end: u32,
+}
+
+impl<F: NorFlash> FlashLog<F> {
+    /// Open the log, starting a new one if there isn't one there
+    ///
+    /// # Parameters
+    /// * `flash` - The flash the log is kept in
+    /// * `offset` - The flash address of the first sector, which must be on a sector boundary
+    /// * `sectors` - The number of sectors in the ring, at least two
+    pub fn mount(flash: F, offset: u32, sectors: u32) -> Result<Self, SoulStarError> {
+        let mut log = Self {
+            flash,
+            offset,
+            sectors: sectors.max(2),
+            head: 0,
+            sequence: 0,
+            end: SECTOR_HEADER,
+        };
+        let mut newest: Option<(u32, u32)> = None;
+        for sector in 0..log.sectors {
+            let mut header = [0u8; SECTOR_HEADER as usize];
+            log.read(log.address(sector), &mut header)?;
+            let Some(sequence) = header.strip_prefix(&SECTOR_MAGIC) else {
+                continue;
+            };
+            let sequence = u32::from_le_bytes([sequence[0], sequence[1], sequence[2], sequence[3]]);
+            if newest.is_none_or(|(_, newest)| sequence > newest) {
+                newest = Some((sector, sequence));
+            }
+        }
+        match newest {
+            Some((sector, sequence)) => {
+                (log.head, log.sequence) = (sector, sequence);
+                log.end = log.scan(|_| ())?;
+            }
+            None => {
+                log!(Main, info, "STORE: Starting a new log");
+                log.erase(0)?;
+                log.write_header(0, 1)?;
+                log.sequence = 1;
+            }
+        }
+        Ok(log)
+    }
+
+    /// Read the value for a key
+    ///
+    /// # Parameters
+    /// * `tag` - What sort of record it is
+    /// * `key` - The key
+    /// * `value` - Where to put the value
+    ///
+    /// # Returns
+    /// The length of the value, None if there isn't one
+    pub fn get(&mut self, tag: Tag, key: u32, value: &mut [u8]) -> Result<Option<usize>, SoulStarError> {
+        let found = self.index()?.into_iter().find(|e| e.tag == tag as u8 && e.key == key);
+        match found {
+            Some(entry) => self.value(&entry, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Visit every key with the tag, oldest change first
+    ///
+    /// # Parameters
+    /// * `tag` - What sort of record to visit
+    /// * `visit` - Called with each key and its value
+    pub fn for_each(&mut self, tag: Tag, mut visit: impl FnMut(u32, &[u8])) -> Result<(), SoulStarError> {
+        for entry in self.index()?.iter().filter(|e| e.tag == tag as u8) {
+            let mut value = [0u8; MAX_VALUE];
+            let len = self.value(entry, &mut value)?;
+            visit(entry.key, &value[..len]);
+        }
+        Ok(())
+    }
+
+    /// Store a value, replacing whatever the key had before
+    ///
+    /// # Parameters
+    /// * `tag` - What sort of record it is
+    /// * `key` - The key
+    /// * `value` - The value, at most 255 bytes
+    pub fn put(&mut self, tag: Tag, key: u32, value: &[u8]) -> Result<(), SoulStarError> {
+        if value.len() >= MAX_VALUE {
+            return Err(SoulStarError::Config);
+        }
+        let index = self.index()?;
+        let known = index.iter().any(|e| e.tag == tag as u8 && e.key == key);
+        if !value.is_empty() && !known && index.is_full() {
+            log!(Main, error, "STORE: No room for another key");
+            return Err(SoulStarError::Storage);
+        }
+        let size = record_size(value.len());
+        if self.end + size > self.sector_size() {
+            self.compact()?;
+        }
+        if self.end + size > self.sector_size() {
+            log!(Main, error, "STORE: The live records don't leave room for more");
+            return Err(SoulStarError::Storage);
+        }
+        self.write_record(self.address(self.head) + self.end, tag as u8, key, value)?;
+        self.end += size;
+        Ok(())
+    }
+
+    /// Forget a key. Nothing is written if it isn't there.
+    pub fn remove(&mut self, tag: Tag, key: u32) -> Result<(), SoulStarError> {
+        if self.index()?.iter().any(|e| e.tag == tag as u8 && e.key == key) {
+            self.put(tag, key, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Forget everything, erasing every sector
+    pub fn clear(&mut self) -> Result<(), SoulStarError> {
+        for sector in 0..self.sectors {
+            self.erase(sector)?;
+        }
+        // Carry on round the ring so the first sector doesn't take all the wear
+        let next = (self.head + 1) % self.sectors;
+        self.write_header(next, self.sequence + 1)?;
+        (self.head, self.sequence, self.end) = (next, self.sequence + 1, SECTOR_HEADER);
+        Ok(())
+    }
+
+    fn sector_size(&self) -> u32 {
+        F::ERASE_SIZE as u32
+    }
+
+    /// The flash address of a sector
+    fn address(&self, sector: u32) -> u32 {
+        self.offset + sector * self.sector_size()
+    }
+
+    /// Copy the live records into the next sector round the ring and carry on there
+    fn compact(&mut self) -> Result<(), SoulStarError> {
+        let live = self.index()?;
+        let next = (self.head + 1) % self.sectors;
+        self.erase(next)?;
+        let mut end = SECTOR_HEADER;
+        for entry in &live {
+            let mut value = [0u8; MAX_VALUE];
+            let len = self.value(entry, &mut value)?;
+            if end + record_size(len) > self.sector_size() {
+                log!(Main, error, "STORE: Lost key {:x} as the live records don't fit", entry.key);
+                continue;
+            }
+            self.write_record(self.address(next) + end, entry.tag, entry.key, &value[..len])?;
+            end += record_size(len);
+        }
+        self.write_header(next, self.sequence + 1)?;
+        (self.head, self.sequence, self.end) = (next, self.sequence + 1, end);
+        log!(Main, debug, "STORE: Moved {} records on to sector {}", live.len(), next);
+        Ok(())
+    }
+
+    /// The latest record for every live key, oldest change first
+    fn index(&mut self) -> Result<Vec<Entry, LOG_KEYS>, SoulStarError> {
+        let mut index: Vec<Entry, LOG_KEYS> = Vec::new();
+        self.scan(|entry| {
+            index.retain(|e| e.tag != entry.tag || e.key != entry.key);
+            // A removed key has an empty value, and put keeps the number of keys in bounds
+            if entry.len > 0 {
+                let _ = index.push(entry);
+            }
+        })?;
+        Ok(index)
+    }
+
+    /// Visit every good record in the newest sector
+    ///
+    /// # Returns
+    /// Where the next record can go, which is the end of the sector if a damaged record was found
+    fn scan(&mut self, mut visit: impl FnMut(Entry)) -> Result<u32, SoulStarError> {
+        let start = self.address(self.head);
+        let mut end = SECTOR_HEADER;
+        loop {
+            if end + record_size(0) > self.sector_size() {
+                return Ok(end);
+            }
+            let mut header = [0u8; RECORD_HEADER];
+            self.read(start + end, &mut header)?;
+            if header[0] == 0xFF {
+                return Ok(end);
+            }
+            let entry = Entry {
+                tag: header[0],
+                key: u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+                len: header[1],
+                address: start + end,
+            };
+            let size = record_size(entry.len as usize);
+            if end + size > self.sector_size() {
+                break;
+            }
+            let mut value = [0u8; MAX_VALUE];
+            let mut crc = [0u8; RECORD_CRC];
+            self.value(&entry, &mut value)?;
+            self.read(start + end + size - RECORD_CRC as u32, &mut crc)?;
+            if u32::from_le_bytes(crc) != record_crc(&header, &value[..entry.len as usize]) {
+                break;
+            }
+            visit(entry);
+            end += size;
+        }
+        log!(Main, warn, "STORE: Damaged record in sector {}, moving on", self.head);
+        Ok(self.sector_size())
+    }
+
+    /// Read the value of a record
+    ///
+    /// # Returns
+    /// The length of the value, cut short if it doesn't fit
+    fn value(&mut self, entry: &Entry, value: &mut [u8]) -> Result<usize, SoulStarError> {
+        let mut buffer = [0u8; MAX_VALUE];
+        self.read(entry.address + RECORD_HEADER as u32, &mut buffer[..padded(entry.len as usize)])?;
+        let len = value.len().min(entry.len as usize);
+        value[..len].copy_from_slice(&buffer[..len]);
+        Ok(len)
+    }
+
+    fn write_record(&mut self, address: u32, tag: u8, key: u32, value: &[u8]) -> Result<(), SoulStarError> {
+        let mut record = [0xFFu8; RECORD_HEADER + MAX_VALUE + RECORD_CRC];
+        record[0] = tag;
+        record[1] = value.len() as u8;
+        record[4..RECORD_HEADER].copy_from_slice(&key.to_le_bytes());
+        record[RECORD_HEADER..RECORD_HEADER + value.len()].copy_from_slice(value);
+        let size = record_size(value.len()) as usize;
+        let crc = record_crc(&record[..RECORD_HEADER], value);
+        record[size - RECORD_CRC..size].copy_from_slice(&crc.to_le_bytes());
+        self.flash.write(address, &record[..size]).map_err(|e| {
+            log!(Main, error, "STORE: Flash write failed: {:?}", Debug2Format(&e));
+            SoulStarError::Storage
+        })
+    }
+
+    fn write_header(&mut self, sector: u32, sequence: u32) -> Result<(), SoulStarError> {
+        let mut header = [0u8; SECTOR_HEADER as usize];
+        header[..4].copy_from_slice(&SECTOR_MAGIC);
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+        self.flash.write(self.address(sector), &header).map_err(|e| {
+            log!(Main, error, "STORE: Flash write failed: {:?}", Debug2Format(&e));
+            SoulStarError::Storage
+        })
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), SoulStarError> {
+        let from = self.address(sector);
+        self.flash.erase(from, from + self.sector_size()).map_err(|e| {
+            log!(Main, error, "STORE: Flash erase failed: {:?}", Debug2Format(&e));
+            SoulStarError::Storage
+        })
+    }
+
+    fn read(&mut self, address: u32, bytes: &mut [u8]) -> Result<(), SoulStarError> {
+        self.flash.read(address, bytes).map_err(|e| {
+            log!(Main, warn, "STORE: Flash read failed: {:?}", Debug2Format(&e));
+            SoulStarError::Storage
+        })
+    }
+}
+
+/// The CRC of a record, over the header and the value but not the padding
+fn record_crc(header: &[u8], value: &[u8]) -> u32 {
+    let mut bytes = [0u8; RECORD_HEADER + MAX_VALUE];
+    bytes[..RECORD_HEADER].copy_from_slice(&header[..RECORD_HEADER]);
+    bytes[RECORD_HEADER..RECORD_HEADER + value.len()].copy_from_slice(value);
+    crc32(&bytes[..RECORD_HEADER + value.len()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    const SECTOR: usize = 4096;
+    const SECTORS: u32 = 4;
+
+    /// Flash in RAM that behaves like NOR flash: writes can only clear bits
+    struct RamFlash<'a> {
+        bytes: &'a mut [u8],
+        erases: &'a mut [u32],
+    }
+
+    impl ErrorType for RamFlash<'_> {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for RamFlash<'_> {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::READ_SIZE) || !bytes.len().is_multiple_of(Self::READ_SIZE) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for RamFlash<'_> {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.bytes[from as usize..to as usize].fill(0xFF);
+            self.erases[from as usize / SECTOR] += 1;
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::WRITE_SIZE) || !bytes.len().is_multiple_of(Self::WRITE_SIZE) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            for (old, new) in self.bytes[offset..offset + bytes.len()].iter_mut().zip(bytes) {
+                *old &= new;
+            }
+            Ok(())
+        }
+    }
+
+    fn get(log: &mut FlashLog<RamFlash<'_>>, tag: Tag, key: u32) -> Option<heapless::Vec<u8, 256>> {
+        let mut value = [0u8; 256];
+        let len = log.get(tag, key, &mut value).unwrap()?;
+        Some(heapless::Vec::from_slice(&value[..len]).unwrap())
+    }
+
+    #[test]
+    pub fn if_values_survive_a_restart() {
+        let mut bytes = [0xFFu8; SECTOR * SECTORS as usize];
+        let mut erases = [0u32; SECTORS as usize];
+        {
+            let flash = RamFlash {
+                bytes: &mut bytes,
+                erases: &mut erases,
+            };
+            let mut log = FlashLog::mount(flash, 0, SECTORS).unwrap();
+            assert_eq!(get(&mut log, Tag::Settings, 0), None);
+            log.put(Tag::Settings, 0, b"first").unwrap();
+            log.put(Tag::Encounter, 7, b"seven").unwrap();
+            log.put(Tag::Encounter, 8, b"eight").unwrap();
+            log.put(Tag::Settings, 0, b"second").unwrap();
+            log.remove(Tag::Encounter, 7).unwrap();
+        }
+        let flash = RamFlash {
+            bytes: &mut bytes,
+            erases: &mut erases,
+        };
+        let mut log = FlashLog::mount(flash, 0, SECTORS).unwrap();
+        assert_eq!(get(&mut log, Tag::Settings, 0).unwrap().as_slice(), b"second");
+        assert_eq!(get(&mut log, Tag::Encounter, 7), None);
+        let mut keys = heapless::Vec::<u32, 4>::new();
+        log.for_each(Tag::Encounter, |key, _| keys.push(key).unwrap()).unwrap();
+        assert_eq!(keys.as_slice(), &[8]);
+        log.clear().unwrap();
+        assert_eq!(get(&mut log, Tag::Encounter, 8), None);
+    }
+
+    #[test]
+    pub fn if_the_wear_is_spread_out() {
+        let mut bytes = [0xFFu8; SECTOR * SECTORS as usize];
+        let mut erases = [0u32; SECTORS as usize];
+        let flash = RamFlash {
+            bytes: &mut bytes,
+            erases: &mut erases,
+        };
+        let mut log = FlashLog::mount(flash, 0, SECTORS).unwrap();
+        log.put(Tag::Encounter, 1, b"an old friend").unwrap();
+        for save in 0..2000u32 {
+            log.put(Tag::Settings, 0, &save.to_le_bytes()).unwrap();
+        }
+        assert_eq!(get(&mut log, Tag::Settings, 0).unwrap().as_slice(), &1999u32.to_le_bytes());
+        assert_eq!(get(&mut log, Tag::Encounter, 1).unwrap().as_slice(), b"an old friend");
+        // 2000 saves would have been 2000 erases of the one sector. Each sector takes about 250.
+        assert!(erases.iter().all(|&e| (1..=3).contains(&e)), "{erases:?}");
+    }
+
+    #[test]
+    pub fn if_a_torn_write_is_left_behind() {
+        let mut bytes = [0xFFu8; SECTOR * SECTORS as usize];
+        let mut erases = [0u32; SECTORS as usize];
+        {
+            let flash = RamFlash {
+                bytes: &mut bytes,
+                erases: &mut erases,
+            };
+            let mut log = FlashLog::mount(flash, 0, SECTORS).unwrap();
+            log.put(Tag::Settings, 0, b"good").unwrap();
+            log.put(Tag::Settings, 0, b"torn").unwrap();
+        }
+        // The power went before the CRC of the second record was written
+        let crc = SECTOR_HEADER as usize + 2 * record_size(4) as usize - RECORD_CRC;
+        bytes[crc..crc + RECORD_CRC].fill(0xFF);
+        let flash = RamFlash {
+            bytes: &mut bytes,
+            erases: &mut erases,
+        };
+        let mut log = FlashLog::mount(flash, 0, SECTORS).unwrap();
+        assert_eq!(get(&mut log, Tag::Settings, 0).unwrap().as_slice(), b"good");
+        // The next write moves on to a fresh sector, taking the good record with it
+        log.put(Tag::Encounter, 3, b"three").unwrap();
+        assert_eq!(get(&mut log, Tag::Settings, 0).unwrap().as_slice(), b"good");
+        assert_eq!(get(&mut log, Tag::Encounter, 3).unwrap().as_slice(), b"three");
+    }
+}
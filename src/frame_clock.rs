@@ -0,0 +1,94 @@
+//! Frame scheduling.
+//!
+//! Frames are due on a fixed grid, one every frame interval from when the animation started, no
+//! matter how long each one took to draw and send. A frame that is drawn late doesn't push the
+//! ones after it back, so the animation keeps its pace. When something holds up the display
+//! task for longer than a frame, like a burst of BLE traffic or a flash write, the missed frames
+//! are reported so the animation can be stepped forward to where it should be, rather than
+//! bursting through them to catch up or quietly falling behind. At most [FRAME_CATCH_UP] frames
+//! are made up, so a long stall doesn't turn into a long burst of work.
+
+use crate::configuration::FRAME_CATCH_UP;
+use embassy_time::{Duration, Instant};
+
+/// Keeps the animation frames on their grid
+pub struct FrameClock {
+    interval: Duration,
+    /// When the next frame is due
+    next: Instant,
+}
+
+impl FrameClock {
+    /// Start a clock with the first frame due one interval from now
+    ///
+    /// # Parameters
+    /// * `interval` - The frame interval
+    /// * `now` - The current time
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            next: now + interval,
+        }
+    }
+
+    /// When the next frame is due
+    pub fn deadline(&self) -> Instant {
+        self.next
+    }
+
+    /// Change the frame interval. The next frame is due no later than one new interval from now.
+    ///
+    /// # Parameters
+    /// * `interval` - The new frame interval
+    /// * `now` - The current time
+    pub fn set_interval(&mut self, interval: Duration, now: Instant) {
+        self.interval = interval;
+        self.next = self.next.min(now + interval);
+    }
+
+    /// Take the frames that are due and move the deadline on to the next frame on the grid
+    ///
+    /// # Parameters
+    /// * `now` - The current time
+    ///
+    /// # Returns
+    /// The frames that have come due, none if we are early. Any more than one were missed and
+    /// should be stepped over, up to [FRAME_CATCH_UP] of them.
+    pub fn due(&mut self, now: Instant) -> u32 {
+        if now < self.next {
+            return 0;
+        }
+        let interval = self.interval.as_ticks().max(1);
+        let frames = 1 + (now - self.next).as_ticks() / interval;
+        self.next += Duration::from_ticks(frames * interval);
+        frames.min(FRAME_CATCH_UP as u64 + 1) as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_keeps_to_the_grid() {
+        let ms = Duration::from_millis;
+        let at = Instant::from_millis;
+        let mut clock = FrameClock::new(ms(20), at(0));
+        assert_eq!(clock.deadline(), at(20));
+        assert_eq!(clock.due(at(19)), 0);
+        // Waking a little late doesn't push the next frame back
+        assert_eq!(clock.due(at(23)), 1);
+        assert_eq!(clock.deadline(), at(40));
+        // Two frames missed
+        assert_eq!(clock.due(at(85)), 3);
+        assert_eq!(clock.deadline(), at(100));
+        // A long stall only makes up so many
+        assert_eq!(clock.due(at(2000)), FRAME_CATCH_UP + 1);
+        assert_eq!(clock.deadline(), at(2020));
+        // A faster frame rate takes over straight away
+        clock.set_interval(ms(5), at(2001));
+        assert_eq!(clock.deadline(), at(2006));
+        clock.set_interval(ms(50), at(2002));
+        assert_eq!(clock.deadline(), at(2006));
+    }
+}
@@ -0,0 +1,54 @@
+//! Lookup tables for getting colours onto the LEDs.
+//!
+//! LEDs respond linearly to their drive level but our eyes don't, so every pixel is gamma
+//! corrected and then scaled by the global brightness before it is sent to the strip. Rather
+//! than doing that arithmetic for every channel of every pixel on every frame, we fold both
+//! steps into a single 256 entry table that only has to be rebuilt when the brightness changes.
+
+/// Gamma correction with a gamma of 2.8. This is the same table that `smart_leds::gamma` uses.
+pub const GAMMA: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8,
+    9, 9, 9, 10, 10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21,
+    21, 22, 22, 23, 24, 24, 25, 25, 26, 27, 27, 28, 29, 29, 30, 31, 32, 32, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41,
+    42, 43, 44, 45, 46, 47, 48, 49, 50, 50, 51, 52, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 66, 67, 68, 69, 70, 72,
+    73, 74, 75, 77, 78, 79, 81, 82, 83, 85, 86, 87, 89, 90, 92, 93, 95, 96, 98, 99, 101, 102, 104, 105, 107, 109, 110,
+    112, 114, 115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137, 138, 140, 142, 144, 146, 148, 150, 152,
+    154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175, 177, 180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203,
+    205, 208, 210, 213, 215, 218, 220, 223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
+];
+
+/// Build the combined gamma and brightness table for a brightness level. The brightness is
+/// applied the same way as `smart_leds::brightness` so the colours don't change.
+///
+/// # Parameters
+/// * `brightness` - Global brightness level from 0 (off) to 255 (max brightness)
+pub const fn brightness_lut(brightness: u8) -> [u8; 256] {
+    let mut lut = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        lut[i] = (GAMMA[i] as u16 * (brightness as u16 + 1) / 256) as u8;
+        i += 1;
+    }
+    lut
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use smart_leds::RGB8;
+
+    #[test]
+    pub fn if_it_matches_smart_leds() {
+        for brightness in [0, 1, 32, 128, 255] {
+            let lut = brightness_lut(brightness);
+            for v in 0..=255u8 {
+                let pixel = RGB8::new(v, v, v);
+                let expected = smart_leds::brightness(smart_leds::gamma([pixel].into_iter()), brightness)
+                    .next()
+                    .unwrap();
+                assert_eq!(lut[v as usize], expected.r);
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Optional vibration motor, for alerts that should be felt as well as seen.
+//!
+//! Anybody can ask for a pulse without knowing whether there is a motor fitted. The haptic task
+//! is only started if [HAPTIC_ENABLED](soulstar::configuration::HAPTIC_ENABLED) is set, otherwise
+//! the requests go nowhere.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::Output;
+use soulstar::configuration::HAPTIC_PULSE;
+use soulstar::log;
+
+/// Pulses waiting to be played. Requests that arrive during a pulse are merged into the next one.
+static PULSE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Ask for a pulse of the vibration motor
+pub fn pulse() {
+    PULSE.signal(());
+}
+
+/// Drive the vibration motor for [HAPTIC_PULSE] milliseconds whenever a pulse is asked for
+///
+/// # Parameters
+/// * `motor` - The output pin switching the motor
+#[embassy_executor::task]
+pub async fn haptic_task(mut motor: Output<'static>) {
+    loop {
+        PULSE.wait().await;
+        log!(Outputs, debug, "HAPTIC: Pulse");
+        motor.set_high();
+        Timer::after(Duration::from_millis(HAPTIC_PULSE)).await;
+        motor.set_low();
+    }
+}
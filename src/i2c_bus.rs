@@ -0,0 +1,20 @@
+//! The I2C bus, shared between the button expander and the accelerometer.
+//!
+//! Both are polled from their own tasks, so each transaction takes the bus for as long as it
+//! lasts and hands it back.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use esp_hal::Async;
+use esp_hal::i2c::master::I2c;
+use static_cell::StaticCell;
+
+/// The shared I2C bus
+pub type I2cBus = Mutex<CriticalSectionRawMutex, I2c<'static, Async>>;
+
+static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+
+/// Share the I2C bus between the tasks that use it. Can only be called once.
+pub fn share(i2c: I2c<'static, Async>) -> &'static I2cBus {
+    I2C_BUS.init(Mutex::new(i2c))
+}
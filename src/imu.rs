@@ -0,0 +1,123 @@
+//! A LIS3DH accelerometer on the I2C bus, for gestures. The readings are handed to the
+//! detectors in [motion](soulstar::motion) and whatever they find is acted on here.
+//!
+//! The accelerometer runs at 100Hz in high resolution mode with a ±2g range, which gives one
+//! milli-g per count. It is read every [IMU_INTERVAL] milliseconds.
+
+use crate::display_task::DisplayChannelSender;
+use crate::display_task::DisplayState::{Shaken, Still, Waving};
+use crate::i2c_bus::I2cBus;
+use crate::settings_store::update_settings;
+use defmt::Debug2Format;
+use embassy_time::{Duration, Instant, Ticker};
+use soulstar::configuration::{DANCE_SAVE_INTERVAL, IMU_ADDRESS, IMU_INTERVAL};
+use soulstar::dance::{add_movement, dance_meter};
+use soulstar::log;
+use soulstar::motion::{Acceleration, ShakeDetector, StillnessDetector, TapDetector};
+use soulstar::orientation::{OrientationTracker, set_orientation};
+use soulstar::wave::wave;
+
+/// Register holding the chip ID
+const WHO_AM_I: u8 = 0x0F;
+
+/// What [WHO_AM_I] reads on a LIS3DH
+const LIS3DH_ID: u8 = 0x33;
+
+/// Data rate, power mode and the axes enabled
+const CTRL_REG1: u8 = 0x20;
+
+/// 100Hz, normal power, all three axes
+const CTRL_REG1_100HZ_XYZ: u8 = 0x57;
+
+/// Block data update, range and resolution
+const CTRL_REG4: u8 = 0x23;
+
+/// Block data update, ±2g and high resolution
+const CTRL_REG4_BDU_HR: u8 = 0x88;
+
+/// The first of the six output registers, X low byte first
+const OUT_X_L: u8 = 0x28;
+
+/// Set in a register address to read several registers in one go
+const AUTO_INCREMENT: u8 = 0x80;
+
+/// Read the accelerometer and act on the gestures it picks up. A double tap waves at the souls
+/// around us and flashes our colour to show it went out. A shake shuffles the default animation.
+/// Gravity keeps track of which way up a matrix panel is, see [orientation](soulstar::orientation),
+/// and all the movement goes into the dance meter, see [dance](soulstar::dance), which is saved
+/// every [DANCE_SAVE_INTERVAL] seconds. The display dims while the wearer keeps still.
+///
+/// # Parameters
+/// * `bus` - The I2C bus the accelerometer is on
+/// * `display` - Display channel sender for the flash
+#[embassy_executor::task]
+pub async fn imu_task(bus: &'static I2cBus, display: DisplayChannelSender) {
+    let mut id = [0u8];
+    let setup = {
+        let mut i2c = bus.lock().await;
+        match i2c.write_read_async(IMU_ADDRESS, &[WHO_AM_I], &mut id).await {
+            Ok(()) => {
+                let rate = i2c.write_async(IMU_ADDRESS, &[CTRL_REG1, CTRL_REG1_100HZ_XYZ]).await;
+                let range = i2c.write_async(IMU_ADDRESS, &[CTRL_REG4, CTRL_REG4_BDU_HR]).await;
+                rate.and(range)
+            }
+            Err(e) => Err(e),
+        }
+    };
+    match setup {
+        Ok(()) if id[0] == LIS3DH_ID => log!(Main, info, "IMU: Found a LIS3DH at {:x}", IMU_ADDRESS),
+        Ok(()) => log!(Main, warn, "IMU: Expected a LIS3DH at {:x} but found chip {:x}", IMU_ADDRESS, id[0]),
+        Err(e) => {
+            log!(Main, error, "IMU: Could not set up the accelerometer: {:?}", Debug2Format(&e));
+            return;
+        }
+    }
+    let mut taps = TapDetector::new();
+    let mut shakes = ShakeDetector::new();
+    let mut upright = OrientationTracker::new();
+    let mut stillness = StillnessDetector::new();
+    let mut last: Option<Acceleration> = None;
+    let mut saved = (Instant::now(), dance_meter());
+    let mut ticker = Ticker::every(Duration::from_millis(IMU_INTERVAL));
+    loop {
+        ticker.next().await;
+        let mut data = [0u8; 6];
+        let read = bus
+            .lock()
+            .await
+            .write_read_async(IMU_ADDRESS, &[OUT_X_L | AUTO_INCREMENT], &mut data)
+            .await;
+        if let Err(e) = read {
+            log!(Main, warn, "IMU: Could not read the accelerometer: {:?}", Debug2Format(&e));
+            continue;
+        }
+        // The readings are left justified 12 bit numbers
+        let axis = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]) >> 4;
+        let reading = Acceleration::new(axis(0), axis(2), axis(4));
+        let now = Instant::now();
+        add_movement(last.map_or(0, |last| reading.jerk(&last)));
+        last = Some(reading);
+        // Saving on every reading would keep putting the save off, see settings_store.rs
+        if now - saved.0 >= Duration::from_secs(DANCE_SAVE_INTERVAL) && dance_meter() != saved.1 {
+            saved = (now, dance_meter());
+            update_settings(|s| s.dance = Some(saved.1));
+        }
+        if taps.update(reading, now) {
+            log!(Main, info, "IMU: Double tap, waving");
+            wave();
+            display.send(Waving.into()).await;
+        }
+        if shakes.update(reading, now) {
+            log!(Main, info, "IMU: Shaken, shuffling the default animation");
+            display.send(Shaken.into()).await;
+        }
+        if let Some(orientation) = upright.update(&reading, now) {
+            log!(Main, info, "IMU: Turned {}", orientation);
+            set_orientation(orientation);
+        }
+        if let Some(still) = stillness.update(reading, now) {
+            log!(Main, info, "IMU: Still {}", still);
+            display.send(Still(still).into()).await;
+        }
+    }
+}
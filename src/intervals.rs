@@ -0,0 +1,193 @@
+//! The timing that can be changed at runtime: how often the animations step, how often the
+//! presence register is flushed and how long a soul may go unseen before it is flushed.
+//!
+//! Each starts at its value in [configuration](crate::configuration) and can be changed over the
+//! control GATT service (see `soul_link.rs`) or the USB command line (see `console.rs`). The
+//! change is kept in the [Settings] so it survives a restart. An interval command is a
+//! [SettingId] followed by the new value as a little-endian `u16`, in milliseconds for the
+//! animation update and seconds for the others. On the command line it is `interval`, the name
+//! of the interval (`update`, `flush` or `age`) and the value, e.g. `interval update 100`.
+
+use crate::configuration::{ANIMATION_UPDATE, PRESENCE_REGISTER_FLUSH_INTERVAL, TRACKER_FLUSH_AGE};
+use crate::settings::{SettingId, Settings};
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_time::Duration;
+
+/// The animation update intervals we accept, in milliseconds
+const ANIMATION_UPDATE_RANGE: RangeInclusive<u16> = 20..=2000;
+
+/// The presence register flush intervals we accept, in seconds
+const FLUSH_INTERVAL_RANGE: RangeInclusive<u16> = 1..=60;
+
+/// The flush ages we accept, in seconds
+const FLUSH_AGE_RANGE: RangeInclusive<u16> = 2..=600;
+
+static ANIMATION_UPDATE_MS: AtomicU32 = AtomicU32::new(ANIMATION_UPDATE as u32);
+static FLUSH_INTERVAL_SECS: AtomicU32 = AtomicU32::new(PRESENCE_REGISTER_FLUSH_INTERVAL as u32);
+static FLUSH_AGE_SECS: AtomicU32 = AtomicU32::new(TRACKER_FLUSH_AGE as u32);
+
+/// The intervals in some settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Intervals {
+    /// The default animation update interval in milliseconds
+    animation_update: u32,
+    /// How often the presence register is flushed, in seconds
+    flush_interval: u32,
+    /// How long a soul may go unseen before it is flushed, in seconds
+    flush_age: u32,
+}
+
+impl Intervals {
+    /// The intervals in the settings, falling back to the configured ones for any that were
+    /// never set
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            animation_update: settings.animation_update.map_or(ANIMATION_UPDATE as u32, u32::from),
+            flush_interval: settings
+                .flush_interval
+                .map_or(PRESENCE_REGISTER_FLUSH_INTERVAL as u32, u32::from),
+            flush_age: settings.flush_age.map_or(TRACKER_FLUSH_AGE as u32, u32::from),
+        }
+    }
+
+    /// An animation's frame interval scaled by how far the animation update has been moved from
+    /// [ANIMATION_UPDATE], so animations that run faster than the default keep their relative
+    /// pace
+    pub fn scaled(&self, interval: Duration) -> Duration {
+        interval * self.animation_update / ANIMATION_UPDATE as u32
+    }
+
+    /// How often the presence register is flushed
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_secs(self.flush_interval as u64)
+    }
+
+    /// How long a soul may go unseen before it is flushed from the presence register
+    pub fn flush_age(&self) -> Duration {
+        Duration::from_secs(self.flush_age as u64)
+    }
+}
+
+/// The intervals in use
+fn current() -> Intervals {
+    Intervals {
+        animation_update: ANIMATION_UPDATE_MS.load(Ordering::Relaxed),
+        flush_interval: FLUSH_INTERVAL_SECS.load(Ordering::Relaxed),
+        flush_age: FLUSH_AGE_SECS.load(Ordering::Relaxed),
+    }
+}
+
+/// An animation's frame interval scaled to the animation update in use, see [Intervals::scaled]
+pub fn scaled(interval: Duration) -> Duration {
+    current().scaled(interval)
+}
+
+/// How often the presence register is flushed
+pub fn flush_interval() -> Duration {
+    current().flush_interval()
+}
+
+/// How long a soul may go unseen before it is flushed from the presence register
+pub fn flush_age() -> Duration {
+    current().flush_age()
+}
+
+/// Use the intervals in the settings, see [Intervals::new]
+pub fn apply_settings(settings: &Settings) {
+    let intervals = Intervals::new(settings);
+    ANIMATION_UPDATE_MS.store(intervals.animation_update, Ordering::Relaxed);
+    FLUSH_INTERVAL_SECS.store(intervals.flush_interval, Ordering::Relaxed);
+    FLUSH_AGE_SECS.store(intervals.flush_age, Ordering::Relaxed);
+}
+
+/// Decode an interval command, see the module documentation
+///
+/// # Returns
+/// The interval and its new value, or None if the command is malformed or out of range
+pub fn interval_command(data: &[u8]) -> Option<(SettingId, u16)> {
+    let [id, lo, hi] = *data else {
+        return None;
+    };
+    let setting = match id {
+        id if id == SettingId::AnimationUpdate as u8 => SettingId::AnimationUpdate,
+        id if id == SettingId::FlushInterval as u8 => SettingId::FlushInterval,
+        id if id == SettingId::FlushAge as u8 => SettingId::FlushAge,
+        _ => return None,
+    };
+    in_range(setting, u16::from_le_bytes([lo, hi]))
+}
+
+/// Parse an interval typed on the command line, see the module documentation
+///
+/// # Parameters
+/// * `name` - The name of the interval
+/// * `value` - The new value in decimal
+///
+/// # Returns
+/// The interval and its new value, or None if either makes no sense or the value is out of range
+pub fn parse_interval(name: &str, value: &str) -> Option<(SettingId, u16)> {
+    let setting = match name {
+        "update" => SettingId::AnimationUpdate,
+        "flush" => SettingId::FlushInterval,
+        "age" => SettingId::FlushAge,
+        _ => return None,
+    };
+    in_range(setting, value.parse().ok()?)
+}
+
+/// The interval and its value, if the value is one we accept
+fn in_range(setting: SettingId, value: u16) -> Option<(SettingId, u16)> {
+    let range = match setting {
+        SettingId::AnimationUpdate => ANIMATION_UPDATE_RANGE,
+        SettingId::FlushInterval => FLUSH_INTERVAL_RANGE,
+        SettingId::FlushAge => FLUSH_AGE_RANGE,
+        _ => return None,
+    };
+    range.contains(&value).then_some((setting, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_interval_commands_are_checked() {
+        assert_eq!(interval_command(&[0x02, 100, 0]), Some((SettingId::AnimationUpdate, 100)));
+        assert_eq!(interval_command(&[0x03, 5, 0]), Some((SettingId::FlushInterval, 5)));
+        assert_eq!(interval_command(&[0x04, 0x2C, 0x01]), Some((SettingId::FlushAge, 300)));
+        // Out of range
+        assert_eq!(interval_command(&[0x02, 1, 0]), None);
+        assert_eq!(interval_command(&[0x03, 0, 0]), None);
+        assert_eq!(interval_command(&[0x04, 0xFF, 0xFF]), None);
+        // Not an interval, or the wrong size
+        assert_eq!(interval_command(&[0x01, 1, 0]), None);
+        assert_eq!(interval_command(&[0x02, 100]), None);
+    }
+
+    #[test]
+    pub fn if_typed_intervals_are_checked() {
+        assert_eq!(parse_interval("update", "100"), Some((SettingId::AnimationUpdate, 100)));
+        assert_eq!(parse_interval("age", "300"), Some((SettingId::FlushAge, 300)));
+        assert_eq!(parse_interval("flush", "0"), None);
+        assert_eq!(parse_interval("flush", "five"), None);
+        assert_eq!(parse_interval("tempo", "120"), None);
+    }
+
+    #[test]
+    pub fn if_the_defaults_are_not_scaled() {
+        let interval = Duration::from_millis(ANIMATION_UPDATE / 2);
+        let intervals = Intervals::new(&Settings::new());
+        assert_eq!(intervals.scaled(interval), interval);
+        assert_eq!(intervals.flush_age(), Duration::from_secs(TRACKER_FLUSH_AGE));
+    }
+
+    #[test]
+    pub fn if_a_faster_update_speeds_everything_up() {
+        let mut settings = Settings::new();
+        settings.set_interval(SettingId::AnimationUpdate, ANIMATION_UPDATE as u16 / 2);
+        let intervals = Intervals::new(&settings);
+        assert_eq!(intervals.scaled(Duration::from_millis(100)), Duration::from_millis(50));
+        assert_eq!(intervals.flush_interval(), Duration::from_secs(PRESENCE_REGISTER_FLUSH_INTERVAL));
+    }
+}
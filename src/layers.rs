@@ -0,0 +1,74 @@
+//! Brightness for each layer of a frame.
+//!
+//! A frame is drawn in two layers: the animation in the background and the status pixel over
+//! it. Each layer is dimmed on its own so the status stays readable however far the animation
+//! has been turned down. The layers are dimmed in perceptual space, before gamma correction, so
+//! halving a layer's brightness looks half as bright. A final global cap (night mode and the
+//! brownout ceiling) is applied to the whole frame by the LED driver after gamma correction.
+
+use crate::colour::LedBuffer;
+use defmt::Format;
+use smart_leds::RGB8;
+
+/// The brightness of each layer, from 0 (off) to 255 (as drawn)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct LayerBrightness {
+    /// The animation
+    pub background: u8,
+    /// Indicators drawn over the animation
+    pub overlay: u8,
+}
+
+impl LayerBrightness {
+    /// The same brightness for every layer
+    pub const fn uniform(brightness: u8) -> Self {
+        Self {
+            background: brightness,
+            overlay: brightness,
+        }
+    }
+
+    /// Dim each layer of a frame. The pixels listed in `overlay` belong to the overlay and the
+    /// rest to the background.
+    ///
+    /// # Parameters
+    /// * `buffer` - The frame as drawn, before gamma correction
+    /// * `overlay` - The pixels in the overlay
+    pub fn apply(&self, buffer: &mut LedBuffer, overlay: &[usize]) {
+        for (i, pixel) in buffer.iter_mut().enumerate() {
+            let brightness = if overlay.contains(&i) {
+                self.overlay
+            } else {
+                self.background
+            };
+            *pixel = dim(*pixel, brightness);
+        }
+    }
+}
+
+/// Scale a pixel the same way as `smart_leds::brightness`, so full brightness leaves it alone
+fn dim(pixel: RGB8, brightness: u8) -> RGB8 {
+    let scale = |v: u8| (v as u16 * (brightness as u16 + 1) / 256) as u8;
+    RGB8::new(scale(pixel.r), scale(pixel.g), scale(pixel.b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_each_layer_is_dimmed_on_its_own() {
+        let white = RGB8::new(255, 255, 255);
+        let mut buffer = LedBuffer::default();
+        buffer.fill(white);
+        LayerBrightness::uniform(255).apply(&mut buffer, &[]);
+        assert!(buffer.iter().all(|&p| p == white));
+        let layers = LayerBrightness {
+            background: 127,
+            overlay: 255,
+        };
+        layers.apply(&mut buffer, &[0]);
+        assert_eq!(buffer[0], white);
+        assert!(buffer[1..].iter().all(|&p| p == RGB8::new(127, 127, 127)));
+    }
+}
@@ -0,0 +1,270 @@
+//! Follow-the-leader, so one soul can DJ the whole crew's stars.
+//!
+//! A soul that is leading puts a [Lead] in its beacon: the default animation it is showing, the
+//! speed it is tuned to and the [shared time](crate::clock) at which it started. Souls that hear
+//! a new lead adopt it as their default and fast-forward it to catch up with the leader, so the
+//! crew changes animation together. The lead is set by the display task and picked up by the BLE
+//! task, so it lives here rather than being passed between them.
+//!
+//! Only the souls with our group key can lead us. Leads are tagged with the key and the leader's
+//! address, like [commands](crate::command), and anything else is ignored. If more than one of
+//! the crew is leading, the [LeadFilter] makes every follower pick the same one: whoever started
+//! their animation first. Once picked, a leader is kept for as long as it is heard, so it doesn't
+//! lose the crew by changing animation.
+
+use crate::animations::{Animation, AnimationKind, frame_interval, next_buffer};
+use crate::clock::shared_now;
+use crate::command::{GroupKey, tag};
+use crate::configuration::{FOLLOW_CATCH_UP, FOLLOW_TIMEOUT};
+use crate::tuning::Tuning;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+use trouble_host::prelude::BdAddr;
+
+/// Size of an encoded [Lead], tag included
+pub const LEAD_SIZE: usize = 10;
+
+/// What the leader is showing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lead {
+    pub kind: AnimationKind,
+    /// Speed in percent of normal, see [Tuning]
+    pub speed: u8,
+    /// The shared time at which the leader started the animation
+    pub started: u32,
+}
+
+impl Lead {
+    /// A lead starting now
+    pub fn new(kind: AnimationKind, speed: u8) -> Self {
+        Self {
+            kind,
+            speed,
+            started: shared_now(),
+        }
+    }
+
+    /// The animation kind, speed and start time, little endian, then the tag
+    ///
+    /// # Arguments
+    /// * `key` - Our group key
+    /// * `sender` - Our address
+    pub fn encode(&self, key: &GroupKey, sender: &BdAddr) -> [u8; LEAD_SIZE] {
+        let [a, b, c, d] = self.started.to_le_bytes();
+        let body = [self.kind as u8, self.speed, a, b, c, d];
+        let [e, f, g, h] = lead_tag(key, &body, sender);
+        [body[0], body[1], a, b, c, d, e, f, g, h]
+    }
+
+    /// Decode a lead, None if it is the wrong size, an animation we don't know or wasn't tagged by
+    /// a soul with our group key
+    ///
+    /// # Arguments
+    /// * `data` - The lead from the beacon
+    /// * `key` - Our group key
+    /// * `sender` - The address of the soul that sent it
+    pub fn decode(data: &[u8], key: &GroupKey, sender: &BdAddr) -> Option<Self> {
+        let [kind, speed, a, b, c, d, ref received @ ..] = *data else {
+            return None;
+        };
+        if *received != lead_tag(key, &[kind, speed, a, b, c, d], sender) {
+            return None;
+        }
+        Some(Self {
+            kind: AnimationKind::from_u8(kind)?,
+            speed,
+            started: u32::from_le_bytes([a, b, c, d]),
+        })
+    }
+
+    /// Fast-forward an animation to where the leader's is now. Leads older than [FOLLOW_CATCH_UP]
+    /// frames are only caught up that far, which covers a beacon that took a while to arrive.
+    ///
+    /// # Arguments
+    /// * `anim` - A freshly created animation of the lead's kind
+    /// * `tuning` - Our tuning, which must already have the lead's speed
+    pub fn catch_up(&self, anim: &mut Animation, tuning: &Tuning) {
+        let interval = tuning.frame_interval(frame_interval(anim)).as_millis().max(1) as u32;
+        let frames = (shared_now().wrapping_sub(self.started) / interval).min(FOLLOW_CATCH_UP);
+        for _ in 0..frames {
+            next_buffer(anim);
+        }
+    }
+}
+
+/// The tag for a lead. It covers the leader's address, so a lead can't be sent on as somebody
+/// else's.
+fn lead_tag(key: &GroupKey, body: &[u8; 6], sender: &BdAddr) -> [u8; 4] {
+    let mut data = [b'L'; 13];
+    data[1..7].copy_from_slice(body);
+    data[7..].copy_from_slice(sender.raw());
+    tag(key, &data)
+}
+
+/// The leader we follow
+#[derive(Clone, Copy)]
+struct Leader {
+    /// Its tracker key
+    key: u32,
+    /// Its latest lead
+    lead: Lead,
+    /// When we last heard it
+    heard: Instant,
+    /// When we picked it
+    picked: Instant,
+}
+
+/// Decides which of the leads we hear to follow, so that the whole crew follows the same leader
+#[derive(Default)]
+pub struct LeadFilter {
+    leader: Option<Leader>,
+}
+
+impl LeadFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decide whether to adopt a lead. For the first [FOLLOW_TIMEOUT] seconds after picking a
+    /// leader we switch to anybody whose animation started earlier, with the tracker key settling
+    /// a tie, so that every follower settles on the same one. After that we stay with our leader,
+    /// taking each new animation it picks, until we haven't heard it for [FOLLOW_TIMEOUT] seconds.
+    ///
+    /// # Arguments
+    /// * `key` - The tracker key of the soul that sent the lead
+    /// * `lead` - The lead, already checked against the group key
+    /// * `now` - The time now
+    ///
+    /// # Returns
+    /// True if the lead is new to us and should be adopted
+    pub fn follow(&mut self, key: u32, lead: Lead, now: Instant) -> bool {
+        let timeout = Duration::from_secs(FOLLOW_TIMEOUT);
+        match self.leader {
+            Some(mut leader) if leader.key == key => {
+                let adopt = leader.lead != lead;
+                (leader.lead, leader.heard) = (lead, now);
+                self.leader = Some(leader);
+                adopt
+            }
+            Some(leader)
+                if now.saturating_duration_since(leader.heard) <= timeout
+                    && (now.saturating_duration_since(leader.picked) > timeout || !earlier(&lead, key, &leader)) =>
+            {
+                false
+            }
+            _ => {
+                self.leader = Some(Leader {
+                    key,
+                    lead,
+                    heard: now,
+                    picked: now,
+                });
+                true
+            }
+        }
+    }
+}
+
+/// True if a lead started before our leader's, or at the same time from a lower tracker key. The
+/// shared clock wraps, so the lead that started a little before the other is the earlier one.
+fn earlier(lead: &Lead, key: u32, leader: &Leader) -> bool {
+    match lead.started.wrapping_sub(leader.lead.started) as i32 {
+        0 => key < leader.key,
+        ahead => ahead < 0,
+    }
+}
+
+/// True while we are leading
+static LEADING: AtomicBool = AtomicBool::new(false);
+
+/// What we would lead with
+static LEAD: Mutex<CriticalSectionRawMutex, Cell<Option<Lead>>> = Mutex::new(Cell::new(None));
+
+/// Start or stop leading
+pub fn set_leading(on: bool) {
+    LEADING.store(on, Ordering::Relaxed);
+}
+
+/// True if we are leading
+pub fn leading() -> bool {
+    LEADING.load(Ordering::Relaxed)
+}
+
+/// Record what we are showing, so we can lead with it
+pub fn set_lead(lead: Lead) {
+    LEAD.lock(|l| l.set(Some(lead)));
+}
+
+/// The lead for our beacon, None unless we are leading
+pub fn lead() -> Option<Lead> {
+    if leading() { LEAD.lock(|l| l.get()) } else { None }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: GroupKey = [7; 16];
+
+    fn lead(kind: AnimationKind, started: u32) -> Lead {
+        Lead {
+            kind,
+            speed: 150,
+            started,
+        }
+    }
+
+    #[test]
+    pub fn if_it_round_trips() {
+        let sender = BdAddr::new([0xC1, 2, 3, 4, 5, 6]);
+        let lead = lead(AnimationKind::Lava, 0x1234_5678);
+        let data = lead.encode(&KEY, &sender);
+        assert_eq!(Lead::decode(&data, &KEY, &sender), Some(lead));
+        assert_eq!(Lead::decode(&[1, 100], &KEY, &sender), None);
+        // Only the group can lead, and only as itself
+        assert_eq!(Lead::decode(&data, &[8; 16], &sender), None);
+        assert_eq!(Lead::decode(&data, &KEY, &BdAddr::new([0xC1, 2, 3, 4, 5, 7])), None);
+        let mut forged = data;
+        forged[0] = AnimationKind::Ocean as u8;
+        assert_eq!(Lead::decode(&forged, &KEY, &sender), None);
+    }
+
+    #[test]
+    pub fn if_everybody_follows_the_same_leader() {
+        let start = Instant::from_secs(100);
+        let first = lead(AnimationKind::Lava, 1_000);
+        let second = lead(AnimationKind::Ocean, 2_000);
+        // Whichever we hear first, we end up following whoever started first
+        let mut filter = LeadFilter::new();
+        assert!(filter.follow(2, second, start));
+        assert!(filter.follow(1, first, start));
+        assert!(!filter.follow(2, second, start));
+        let mut other = LeadFilter::new();
+        assert!(other.follow(1, first, start));
+        assert!(!other.follow(2, second, start));
+        // Each lead is only adopted once, but a new animation from our leader is
+        assert!(!filter.follow(1, first, start + Duration::from_secs(FOLLOW_TIMEOUT / 2)));
+        let settled = start + Duration::from_secs(FOLLOW_TIMEOUT + 1);
+        let later = lead(AnimationKind::Sparkle, 3_000);
+        assert!(filter.follow(1, later, settled));
+        // Even though that makes it the later of the two leaders
+        assert!(!filter.follow(2, second, settled));
+        // Once our leader has gone quiet, somebody else can take over
+        let quiet = settled + Duration::from_secs(FOLLOW_TIMEOUT + 1);
+        assert!(filter.follow(2, second, quiet));
+    }
+
+    #[test]
+    pub fn if_leads_started_before_the_clock_wrapped_are_earlier() {
+        let start = Instant::from_secs(100);
+        let before = lead(AnimationKind::Lava, u32::MAX - 500);
+        let after = lead(AnimationKind::Ocean, 500);
+        let mut filter = LeadFilter::new();
+        assert!(filter.follow(2, after, start));
+        assert!(filter.follow(1, before, start));
+        assert!(!filter.follow(2, after, start));
+    }
+}
@@ -1,17 +1,24 @@
-use crate::configuration::LED_STRING_SIZE;
+use defmt::Debug2Format;
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::Async;
+use esp_hal::gpio::Output;
 use esp_hal::gpio::interconnect::PeripheralOutput;
 use esp_hal::rmt::PulseCode;
 use esp_hal_smartled::{SmartLedsAdapterAsync, buffer_size_async};
 use smart_leds::{RGB8, SmartLedsWriteAsync};
+use soulstar::brownout::brightness_ceiling;
+use soulstar::colour::LedBuffer;
+use soulstar::configuration::{LED_POWER_SETTLE, LED_STRING_SIZE};
+use soulstar::error::SoulStarError;
+use soulstar::gamma::{GAMMA, brightness_lut};
+use soulstar::log;
+use soulstar::power::{PowerGate, limit_current};
+use soulstar::telemetry::{Counter, increment};
 use static_cell::StaticCell;
 
 /// We must know what the LED TX buffer size is as a constant for the types involved here
 const LED_INTERNAL_BUF_LEN: usize = buffer_size_async(LED_STRING_SIZE);
 
-/// Convenience type so we speak the same language when dealing with animations etc.
-pub type LedBuffer = [RGB8; LED_STRING_SIZE];
-
 static RMT_BUFFER: StaticCell<[PulseCode; buffer_size_async(LED_STRING_SIZE)]> = StaticCell::new();
 
 /// Holds the state needed to drive the LED strip
@@ -19,6 +26,14 @@ pub struct LedDriver<'a> {
     /// Driver for the led array. We have to size it here to exactly what we will get back from
     /// the `SmartLedsAdapterAsync::new()` function when we set up the driver below
     led: SmartLedsAdapterAsync<'a, LED_INTERNAL_BUF_LEN>,
+    /// Combined gamma and brightness table for [LedDriver::lut_brightness]
+    lut: [u8; 256],
+    /// The brightness the table was built for
+    lut_brightness: u8,
+    /// Switches the strip's power rail or level shifter on, on builds that can cut its power
+    power: Option<Output<'a>>,
+    /// Decides when the strip has been dark for long enough to cut its power
+    gate: PowerGate,
 }
 
 impl<'a> LedDriver<'a> {
@@ -30,47 +45,108 @@ impl<'a> LedDriver<'a> {
     /// # Parameters
     /// * `rmt` - The RMT peripheral device to use for driving the LED strip
     /// * `pin` - The GPIO pin to which the LED strip is connected
-    pub fn new(rmt: esp_hal::rmt::Rmt<'a, Async>, pin: impl PeripheralOutput<'a>) -> Self {
+    /// * `power` - The pin that enables the strip's power, if it can be switched off. It must
+    ///   start low, with the strip off.
+    pub fn new(rmt: esp_hal::rmt::Rmt<'a, Async>, pin: impl PeripheralOutput<'a>, power: Option<Output<'a>>) -> Self {
         //
         let channel = rmt.channel0;
         let buffer = RMT_BUFFER.init([PulseCode::default(); buffer_size_async(LED_STRING_SIZE)]);
         let led = SmartLedsAdapterAsync::new(channel, pin, buffer);
-        Self { led }
+        Self {
+            led,
+            lut: GAMMA,
+            lut_brightness: 255,
+            power,
+            gate: PowerGate::new(),
+        }
     }
 }
 
 impl<'a> LedDriver<'a> {
     /// Update the contents of the buffer to the LED string, applying gamma correction and brightness.
+    /// Both are applied with a lookup table that is only rebuilt when the brightness changes. The
+    /// result is dimmed further if it would draw more current than is safe.
     ///
     /// This must be called every time you want to propagate changes you have made to the string to
     /// the actual LED devices. This is not done automatically as you may want to do multiple changes
-    /// before updating the display.
+    /// before updating the display. The brightness never goes over the ceiling a sagging battery
+    /// can support.
     ///
     /// # Parameters
     /// * `led_buffer` - Buffer containing LED values to write to the string
     /// * `brightness` - Global brightness level from 0 (off) to 255 (max brightness)
     pub async fn update_from_buffer(&mut self, led_buffer: &mut LedBuffer, brightness: u8) {
-        let source = *led_buffer;
-        let adjust_iter = smart_leds::brightness(smart_leds::gamma(source.iter().cloned()), brightness);
-        for (pix, corrected) in led_buffer.iter_mut().zip(adjust_iter) {
-            *pix = corrected;
+        // A lost frame is soon replaced by the next one, so there is nothing more to do about it
+        let _ = self.try_update_from_buffer(led_buffer, brightness).await;
+    }
+
+    /// Like [LedDriver::update_from_buffer], but reports a failure to send the frame so that the
+    /// self-test can check the RMT peripheral
+    ///
+    /// # Parameters
+    /// * `led_buffer` - Buffer containing LED values to write to the string
+    /// * `brightness` - Global brightness level from 0 (off) to 255 (max brightness)
+    pub async fn try_update_from_buffer(
+        &mut self,
+        led_buffer: &mut LedBuffer,
+        brightness: u8,
+    ) -> Result<(), SoulStarError> {
+        let brightness = brightness.min(brightness_ceiling());
+        if brightness != self.lut_brightness {
+            self.lut = brightness_lut(brightness);
+            self.lut_brightness = brightness;
+        }
+        for pix in led_buffer.iter_mut() {
+            *pix = RGB8::new(self.lut[pix.r as usize], self.lut[pix.g as usize], self.lut[pix.b as usize]);
+        }
+        if limit_current(led_buffer) {
+            increment(Counter::CurrentLimited);
+        }
+        // A strip without power has nothing to show, so there is no point sending it anything
+        if self.power.is_some() && !self.gate.update(led_buffer, Instant::now()) {
+            self.power_off();
+            return Ok(());
+        }
+        self.power_on().await;
+        if let Err(e) = self.led.write(*led_buffer).await {
+            log!(Display, error, "LED_DRIVER: Could not send a frame: {:?}", Debug2Format(&e));
+            return Err(SoulStarError::Rmt);
+        }
+        increment(Counter::FramesRendered);
+        Ok(())
+    }
+
+    /// Enable the strip's power if it is off, waiting for it to come up
+    async fn power_on(&mut self) {
+        if let Some(power) = self.power.as_mut()
+            && power.is_set_low()
+        {
+            power.set_high();
+            Timer::after(Duration::from_millis(LED_POWER_SETTLE)).await;
+        }
+    }
+
+    /// Cut the strip's power, on builds that can. Dark WS2812s still draw about 1mA each. The
+    /// power comes back on by itself with the next frame.
+    pub fn power_off(&mut self) {
+        if let Some(power) = self.power.as_mut() {
+            power.set_low();
         }
-        self.led.write(*led_buffer).await.expect("Failed to update LED driver");
     }
 
     /// Switches all the LEDS off
-    #[allow(unused)]
     pub async fn all_off(&mut self) {
         self.update_from_buffer(&mut LedBuffer::default(), 0).await;
     }
 
-    /// Switches all the LEDS to white at the specified brightness.
+    /// Switches all the LEDS to the torch colour at the specified brightness.
     ///
     /// # Parameters
+    /// * `colour` - The colour to set all the LEDs to
     /// * `brightness` - The brightness level to set all LEDs to, from 0 (off) to 255 (full brightness)
-    pub async fn torch(&mut self, brightness: u8) {
+    pub async fn torch(&mut self, colour: RGB8, brightness: u8) {
         let mut b = LedBuffer::default();
-        b.fill(RGB8 { r: 255, g: 255, b: 255 });
+        b.fill(colour);
         self.update_from_buffer(&mut b, brightness).await;
     }
 }
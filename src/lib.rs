@@ -0,0 +1,79 @@
+//! The hardware independent core of the Soul Star. Everything in here must build for the host
+//! so that it can be unit tested with `just test`. The ESP specific bits (BLE stack, RMT
+//! driver, buttons and tasks) live with the binary in `main.rs`.
+#![cfg_attr(not(test), no_std)]
+
+pub mod advert;
+pub mod animation_queue;
+pub mod animations;
+pub mod audio;
+pub mod auto_brightness;
+pub mod backpressure;
+pub mod badges;
+pub mod battery;
+pub mod beat;
+pub mod brownout;
+pub mod buttons;
+pub mod clock;
+pub mod colour;
+pub mod command;
+pub mod configuration;
+pub mod constellation;
+pub mod control;
+pub mod crew;
+pub mod crowd;
+pub mod dance;
+pub mod device_info;
+pub mod dmx;
+pub mod encounters;
+pub mod error;
+pub mod factory_reset;
+pub mod fader;
+pub mod fft;
+pub mod fields;
+pub mod flash_log;
+pub mod frame_clock;
+pub mod gamma;
+pub mod intervals;
+pub mod layers;
+pub mod leader;
+pub mod light_show;
+pub mod logging;
+pub mod math;
+pub mod motion;
+pub mod orientation;
+pub mod overrides;
+pub mod path_loss;
+pub mod payload;
+pub mod power;
+pub mod profiling;
+pub mod radio;
+pub mod self_test;
+pub mod settings;
+pub mod simulation;
+pub mod startup;
+pub mod status;
+pub mod telemetry;
+pub mod tempo;
+pub mod throbber;
+pub mod throttle;
+pub mod torch;
+pub mod tracker;
+pub mod trigger;
+pub mod tuning;
+pub mod utils;
+pub mod wave;
+
+/// defmt needs a global logger to link. On the host we just throw the log output away.
+#[cfg(test)]
+mod test_logger {
+    #[defmt::global_logger]
+    struct NullLogger;
+
+    unsafe impl defmt::Logger for NullLogger {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+}
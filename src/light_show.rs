@@ -0,0 +1,211 @@
+//! Light shows from a lighting desk, over Art-Net or sACN (E1.31).
+//!
+//! With the light show enabled the star joins the venue Wi-Fi and listens for DMX universes (see
+//! `wifi.rs` in the binary). Only the universe set in the configuration is used: each pixel
+//! takes three of its channels, red, green and blue, from [LIGHT_SHOW_ADDRESS], the same layout
+//! as the DMX output (see [dmx](crate::dmx)). While the desk keeps sending, its frames replace our
+//! own animations. Once it has been quiet for [LIGHT_SHOW_TIMEOUT] seconds we go back to them.
+//!
+//! Art-Net numbers its universes from 0 and sACN from 1, so each has its own universe setting.
+//!
+//! [LIGHT_SHOW_TIMEOUT]: crate::configuration::LIGHT_SHOW_TIMEOUT
+
+use crate::colour::LedBuffer;
+use crate::configuration::LIGHT_SHOW_ADDRESS;
+use smart_leds::RGB8;
+
+/// The UDP port Art-Net is sent to
+pub const ARTNET_PORT: u16 = 6454;
+
+/// The UDP port sACN is sent to
+pub const SACN_PORT: u16 = 5568;
+
+/// Every Art-Net packet starts with this
+const ARTNET_ID: &[u8; 8] = b"Art-Net\0";
+
+/// The Art-Net opcode for DMX data
+const OP_DMX: u16 = 0x5000;
+
+/// The oldest Art-Net protocol version we understand
+const ARTNET_VERSION: u16 = 14;
+
+/// Where the DMX data starts in an ArtDmx packet
+const ARTNET_DATA: usize = 18;
+
+/// The ACN packet identifier at the start of every sACN packet
+const ACN_ID: &[u8; 12] = b"ASC-E1.17\0\0\0";
+
+/// The root layer vector for sACN data
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+
+/// The framing layer vector for sACN data
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+
+/// The DMP layer vector for setting properties
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+
+/// Where the start code is in an sACN data packet, the DMX data follows it
+const SACN_START_CODE: usize = 125;
+
+/// Set in the sACN options when the packet is only for the desk's preview
+const SACN_PREVIEW: u8 = 0x80;
+
+/// One DMX universe as received from the desk
+#[derive(Debug, PartialEq, Eq)]
+pub struct Universe<'a> {
+    /// The universe number, as the protocol numbers them
+    pub universe: u16,
+    /// The channels, starting from channel 1
+    pub channels: &'a [u8],
+}
+
+fn u16_be(packet: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(packet.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_be(packet: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(packet.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Read an Art-Net packet
+///
+/// # Returns
+/// The universe if this is an ArtDmx packet, None for anything else
+pub fn artnet(packet: &[u8]) -> Option<Universe<'_>> {
+    if !packet.starts_with(ARTNET_ID) {
+        return None;
+    }
+    let opcode = u16::from_le_bytes(packet.get(8..10)?.try_into().ok()?);
+    if opcode != OP_DMX || u16_be(packet, 10)? < ARTNET_VERSION {
+        return None;
+    }
+    let length = u16_be(packet, 16)? as usize;
+    // The 15 bit port address is the net followed by the sub-net and universe
+    let universe = u16::from_le_bytes([packet[14], packet[15]]) & 0x7FFF;
+    let channels = packet.get(ARTNET_DATA..ARTNET_DATA + length)?;
+    Some(Universe { universe, channels })
+}
+
+/// Read an sACN packet
+///
+/// # Returns
+/// The universe if this is a data packet with DMX levels in it, None for anything else
+pub fn sacn(packet: &[u8]) -> Option<Universe<'_>> {
+    if packet.get(4..16)? != ACN_ID
+        || u32_be(packet, 18)? != VECTOR_ROOT_E131_DATA
+        || u32_be(packet, 40)? != VECTOR_E131_DATA_PACKET
+        || *packet.get(117)? != VECTOR_DMP_SET_PROPERTY
+        || packet.get(SACN_START_CODE) != Some(&0)
+        || packet[112] & SACN_PREVIEW != 0
+    {
+        return None;
+    }
+    let universe = u16_be(packet, 113)?;
+    // The property count includes the start code
+    let count = u16_be(packet, 123)? as usize;
+    let channels = packet.get(SACN_START_CODE + 1..SACN_START_CODE + count.max(1))?;
+    Some(Universe { universe, channels })
+}
+
+/// The multicast group an sACN universe is sent to
+pub fn sacn_group(universe: u16) -> [u8; 4] {
+    let [high, low] = universe.to_be_bytes();
+    [239, 255, high, low]
+}
+
+/// The frame the desk wants us to show. Pixels past the end of the universe are left dark.
+///
+/// # Parameters
+/// * `channels` - The universe's channels, starting from channel 1
+pub fn desk_frame(channels: &[u8]) -> LedBuffer {
+    let mut frame = LedBuffer::default();
+    let ours = channels.get(LIGHT_SHOW_ADDRESS as usize - 1..).unwrap_or_default();
+    for (pixel, &[r, g, b]) in frame.iter_mut().zip(ours.as_chunks::<3>().0) {
+        *pixel = RGB8::new(r, g, b);
+    }
+    frame
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::LED_STRING_SIZE;
+
+    /// An ArtDmx packet for the universe
+    fn artdmx(universe: u16, channels: &[u8]) -> heapless::Vec<u8, 600> {
+        let mut packet = heapless::Vec::new();
+        packet.extend_from_slice(ARTNET_ID).unwrap();
+        packet.extend_from_slice(&OP_DMX.to_le_bytes()).unwrap();
+        packet.extend_from_slice(&ARTNET_VERSION.to_be_bytes()).unwrap();
+        packet.extend_from_slice(&[0, 0]).unwrap();
+        packet.extend_from_slice(&universe.to_le_bytes()).unwrap();
+        packet
+            .extend_from_slice(&(channels.len() as u16).to_be_bytes())
+            .unwrap();
+        packet.extend_from_slice(channels).unwrap();
+        packet
+    }
+
+    /// An sACN data packet for the universe
+    fn e131(universe: u16, channels: &[u8], options: u8) -> heapless::Vec<u8, 700> {
+        let mut packet = heapless::Vec::new();
+        packet.resize(SACN_START_CODE + 1, 0).unwrap();
+        packet[0..2].copy_from_slice(&0x0010u16.to_be_bytes());
+        packet[4..16].copy_from_slice(ACN_ID);
+        packet[18..22].copy_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        packet[40..44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        packet[112] = options;
+        packet[113..115].copy_from_slice(&universe.to_be_bytes());
+        packet[117] = VECTOR_DMP_SET_PROPERTY;
+        packet[123..125].copy_from_slice(&(channels.len() as u16 + 1).to_be_bytes());
+        packet.extend_from_slice(channels).unwrap();
+        packet
+    }
+
+    #[test]
+    pub fn if_it_reads_artnet() {
+        let packet = artdmx(0x0102, &[1, 2, 3, 4]);
+        assert_eq!(
+            artnet(&packet),
+            Some(Universe {
+                universe: 0x0102,
+                channels: &[1, 2, 3, 4]
+            })
+        );
+        assert_eq!(sacn(&packet), None);
+        // A poll, or a packet cut short
+        let mut poll = packet.clone();
+        poll[8..10].copy_from_slice(&0x2000u16.to_le_bytes());
+        assert_eq!(artnet(&poll), None);
+        assert_eq!(artnet(&packet[..20]), None);
+    }
+
+    #[test]
+    pub fn if_it_reads_sacn() {
+        let packet = e131(7, &[10, 20, 30], 0);
+        assert_eq!(
+            sacn(&packet),
+            Some(Universe {
+                universe: 7,
+                channels: &[10, 20, 30]
+            })
+        );
+        assert_eq!(artnet(&packet), None);
+        assert_eq!(sacn(&e131(7, &[10, 20, 30], SACN_PREVIEW)), None);
+        assert_eq!(sacn_group(0x0107), [239, 255, 1, 7]);
+    }
+
+    #[test]
+    pub fn if_the_channels_become_pixels() {
+        let mut channels = [0u8; 512];
+        let first = LIGHT_SHOW_ADDRESS as usize - 1;
+        channels[first..first + 3].copy_from_slice(&[255, 128, 0]);
+        let frame = desk_frame(&channels);
+        assert_eq!(frame[0], RGB8::new(255, 128, 0));
+        assert!(frame[1..].iter().all(|&p| p == RGB8::default()));
+        // A short universe only lights the pixels it covers
+        let frame = desk_frame(&[255; 4]);
+        assert_eq!(frame[0], RGB8::new(255, 255, 255));
+        assert_eq!(frame[LED_STRING_SIZE - 1], RGB8::default());
+    }
+}
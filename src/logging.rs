@@ -0,0 +1,161 @@
+//! Runtime control of the log verbosity, one level per module, so a noisy module can be silenced or
+//! a quiet one opened up without reflashing.
+//!
+//! defmt filters at compile time, so the firmware is built with everything of ours down to trace
+//! (see `DEFMT_LOG` in `.cargo/config.toml`) and [log!](crate::log) drops whatever is below the
+//! runtime level of its module. The levels start at [DEFAULT_LOG_LEVEL] and are kept in volatile
+//! state, so a restart puts everything back. They are changed over GATT, see `soul_link.rs`, with
+//! a two byte command: the [LogModule] followed by the [LogLevel].
+//!
+//! Only the firmware logs through defmt. Host builds (the tests and the simulator) send the same
+//! messages to the `log` crate instead, see [backend], so the core modules don't need a defmt
+//! logger to run on the host and their output shows up in whatever logger the host installs.
+//! Messages must therefore stick to the format syntax the two have in common: `{}`, `{:?}` and
+//! `{:x}` with arguments that implement both `Format` and the matching `core::fmt` trait.
+
+use crate::configuration::DEFAULT_LOG_LEVEL;
+use core::sync::atomic::{AtomicU8, Ordering};
+use defmt::Format;
+
+/// Log verbosity, from silent to everything
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Format)]
+#[repr(u8)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// The level with the given number, None if there isn't one
+    pub fn from_u8(level: u8) -> Option<Self> {
+        [Self::Off, Self::Error, Self::Warn, Self::Info, Self::Debug, Self::Trace]
+            .get(level as usize)
+            .copied()
+    }
+}
+
+/// The modules that can have their own log level
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum LogModule {
+    /// Start up and the buttons
+    Main,
+    /// The display task and animations
+    Display,
+    /// BLE scanning and advertising
+    Scanner,
+    /// The soul tracker
+    Tracker,
+    /// The soul-link exchange
+    SoulLink,
+    /// The vibration motor and the trigger output
+    Outputs,
+    /// Heap and stack usage reports
+    Diagnostics,
+}
+
+/// The number of modules in [LogModule]
+const LOG_MODULES: usize = 7;
+
+impl LogModule {
+    /// The module with the given number, None if there isn't one
+    pub fn from_u8(module: u8) -> Option<Self> {
+        [Self::Main, Self::Display, Self::Scanner, Self::Tracker, Self::SoulLink, Self::Outputs, Self::Diagnostics]
+            .get(module as usize)
+            .copied()
+    }
+}
+
+/// The current level of each module, indexed by [LogModule]
+static LEVELS: [AtomicU8; LOG_MODULES] = [const { AtomicU8::new(DEFAULT_LOG_LEVEL as u8) }; LOG_MODULES];
+
+/// Change the log level of a module
+pub fn set_log_level(module: LogModule, level: LogLevel) {
+    LEVELS[module as usize].store(level as u8, Ordering::Relaxed);
+}
+
+/// The current log level of a module
+pub fn log_level(module: LogModule) -> LogLevel {
+    LogLevel::from_u8(LEVELS[module as usize].load(Ordering::Relaxed)).unwrap_or(DEFAULT_LOG_LEVEL)
+}
+
+/// True if a message at this level from the module should be logged
+pub fn enabled(module: LogModule, level: LogLevel) -> bool {
+    level <= log_level(module)
+}
+
+/// Apply a log level command received over the air
+///
+/// # Parameters
+/// * `command` - The [LogModule] number followed by the [LogLevel] number
+///
+/// # Returns
+/// The module and its new level, None if the command made no sense
+pub fn apply_log_command(command: &[u8]) -> Option<(LogModule, LogLevel)> {
+    let [module, level] = *command else {
+        return None;
+    };
+    let module = LogModule::from_u8(module)?;
+    let level = LogLevel::from_u8(level)?;
+    set_log_level(module, level);
+    Some((module, level))
+}
+
+/// Where [log!](crate::log) sends its messages: defmt on the target and `log` on the host
+#[doc(hidden)]
+pub mod backend {
+    #[cfg(target_os = "none")]
+    pub use defmt::{debug, error, info, trace, warn};
+    #[cfg(not(target_os = "none"))]
+    pub use log::{debug, error, info, trace, warn};
+}
+
+/// Log through the [backend] if the module's runtime level allows it, e.g.
+/// `log!(Tracker, debug, "TRACKER: Soul {:x} left", key)`
+#[macro_export]
+macro_rules! log {
+    (@ $module:ident, $level:ident, $log:ident, $($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogModule::$module, $crate::logging::LogLevel::$level) {
+            $crate::logging::backend::$log!($($arg)*);
+        }
+    };
+    ($module:ident, error, $($arg:tt)*) => {
+        $crate::log!(@ $module, Error, error, $($arg)*)
+    };
+    ($module:ident, warn, $($arg:tt)*) => {
+        $crate::log!(@ $module, Warn, warn, $($arg)*)
+    };
+    ($module:ident, info, $($arg:tt)*) => {
+        $crate::log!(@ $module, Info, info, $($arg)*)
+    };
+    ($module:ident, debug, $($arg:tt)*) => {
+        $crate::log!(@ $module, Debug, debug, $($arg)*)
+    };
+    ($module:ident, trace, $($arg:tt)*) => {
+        $crate::log!(@ $module, Trace, trace, $($arg)*)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_filters_per_module() {
+        assert!(apply_log_command(&[LogModule::Scanner as u8, LogLevel::Warn as u8]).is_some());
+        assert!(enabled(LogModule::Scanner, LogLevel::Error));
+        assert!(!enabled(LogModule::Scanner, LogLevel::Info));
+        assert!(apply_log_command(&[LogModule::Tracker as u8, LogLevel::Trace as u8]).is_some());
+        assert!(enabled(LogModule::Tracker, LogLevel::Debug));
+        assert_eq!(log_level(LogModule::Main), DEFAULT_LOG_LEVEL);
+        // Nonsense is ignored
+        assert!(apply_log_command(&[LogModule::Main as u8, 9]).is_none());
+        assert!(apply_log_command(&[42, 1]).is_none());
+        assert!(apply_log_command(&[1]).is_none());
+        assert_eq!(log_level(LogModule::Main), DEFAULT_LOG_LEVEL);
+    }
+}
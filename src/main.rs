@@ -7,45 +7,96 @@
 )]
 extern crate alloc;
 
-mod animations;
 mod button;
-mod colour;
-mod configuration;
+mod console;
+mod diagnostics;
 mod display_task;
+mod dmx_output;
+mod expander;
+mod haptic;
+mod i2c_bus;
+mod imu;
 mod led_driver;
+mod microphone;
+mod power_on_test;
 mod presence;
+mod sensors;
+mod sequencer;
+mod settings_store;
 mod soul_config;
-mod throbber;
-mod tracker;
-mod utils;
+mod soul_link;
+mod trigger_output;
+mod wifi;
 
-use crate::display_task::{DisplayChannel, DisplayChannelReceiver, DisplayChannelSender, display_task};
+use crate::button::{BUTTON_PRESSES, factory_reset_task, gpio_button_task, is_down};
+use crate::console::console_task;
+use crate::diagnostics::diagnostics_task;
+use crate::display_task::DisplayState::{
+    AutoBrightness, BeaconOnly, Brightness, Calibrate, HotCold, NextSoul, NightMode, Party, RssiMeter, SetDefault,
+    Shuffle, Sos, Torch, TorchLevel,
+};
+use crate::display_task::{DisplayChannel, DisplayChannelReceiver, DisplayChannelSender, display_task, update_status};
+use crate::dmx_output::dmx_task;
+use crate::expander::expander_task;
+use crate::haptic::haptic_task;
+use crate::i2c_bus::share;
+use crate::imu::imu_task;
 use crate::led_driver::LedDriver;
-use crate::presence::start_ble;
-use bt_hci::controller::ExternalController;
+use crate::microphone::{MIC_BUFFER, microphone_task};
+use crate::power_on_test::power_on_test;
+use crate::presence::{report_task, simulation_task, start_ble, update_beacon};
+use crate::sensors::sensor_task;
+use crate::sequencer::{degraded, started, with_retry};
+use crate::settings_store::{
+    load_encounters, load_overrides, load_settings, open_store, settings_task, update_settings,
+};
+use crate::soul_link::restore_encounter;
+use crate::trigger_output::trigger_task;
+use crate::wifi::{light_show_task, net_task, wifi_task};
 use core::panic::PanicInfo;
+use defmt::Debug2Format;
 use embassy_executor::Spawner;
+use embassy_net::StackResources;
 use embassy_sync::channel::Channel;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::clock::CpuClock;
-use esp_hal::timer::systimer::SystemTimer;
-use esp_radio::ble::controller::BleConnector;
-use smart_leds::RGB8;
-use static_cell::StaticCell;
-use crate::animations::Animation::Wave;
-use crate::animations::{Animation, WaveAnimation};
-use crate::button::wait_for_press;
-use crate::display_task::DisplayState::{Brightness, Torch};
-use defmt::info;
-use embassy_futures::select::Either3::{First, Second, Third};
-use embassy_futures::select::select3;
-use esp_hal::gpio::{Input, InputConfig, Pull};
+use esp_hal::dma_buffers;
+use esp_hal::efuse::Efuse;
+use esp_hal::gpio::{AnyPin, Input, InputConfig, Level, Output, OutputConfig, Pull};
+use esp_hal::i2c::master::{Config as I2cConfig, I2c};
+use esp_hal::i2s::master::{Channels, Config as I2sConfig, DataFormat, I2s};
 use esp_hal::rmt::Rmt;
 use esp_hal::rng::Rng;
 use esp_hal::time::Rate;
+use esp_hal::timer::systimer::SystemTimer;
+use esp_hal::uart::{Config as UartConfig, Uart};
+use esp_hal::usb_serial_jtag::UsbSerialJtag;
+use esp_storage::FlashStorage;
+use heapless::Vec;
 use rand_core::RngCore;
+use smart_leds::RGB8;
+use soulstar::animations::AnimationKind;
+use soulstar::badges::restore_badges;
+use soulstar::buttons::{Button, ButtonPress, ButtonPull, GpioButton, is_held};
+use soulstar::colour::Theme;
+use soulstar::command::{Effect, send_command};
+use soulstar::configuration::{
+    AUTO_BRIGHTNESS_ENABLED, BATTERY_ENABLED, COMMAND_FLASH_COLOUR, COMPANY_ID, CONSOLE_ENABLED, DMX_ENABLED, EXPANDER,
+    GPIO_BUTTONS, HAPTIC_ENABLED, IMU_ENABLED, LIGHT_SHOW_ENABLED, LONG_PRESS, MAX_GPIO_BUTTONS, MIC_ENABLED,
+    MIC_SAMPLE_RATE, SIMULATION_ENABLED, TEST_COMPANY_ID, TRIGGER_ENABLED,
+};
+use soulstar::dance::restore_dance_meter;
+use soulstar::intervals::apply_settings;
+use soulstar::leader::set_leading;
+use soulstar::log;
+use soulstar::path_loss::{CalibrationStep, set_path_loss};
+use soulstar::payload::Network;
+use soulstar::radio::{RadioMode, set_emergency, set_radio_mode};
+use soulstar::tempo::{TapTempo, set_tempo};
+use soulstar::torch::{TorchIntensity, TorchPreset};
+use soulstar::utils::clip;
+use static_cell::StaticCell;
 use trouble_host::Address;
-use crate::utils::clip;
 
 // Needed to link the RTT library to the final binary
 use defmt_rtt as _;
@@ -67,8 +118,8 @@ static RADIO_INIT: StaticCell<esp_radio::Controller> = StaticCell::new();
 /// Set a random MAC address for this beacon.
 static ADDRESS: StaticCell<Address> = StaticCell::new();
 
-/// Our default animation
-static DEFAULT_ANIMATION: StaticCell<Animation> = StaticCell::new();
+/// Sockets for the network stack when we take light shows over Wi-Fi
+static NET_RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
@@ -82,9 +133,9 @@ esp_bootloader_esp_idf::esp_app_desc!();
 #[esp_rtos::main]
 async fn main(spawner: Spawner) {
     // Set up Embassy and start the executor
-    info!("MAIN: Starting up Soul Star for {}", soul_config::ADVERTISED_NAME);
+    log!(Main, info, "MAIN: Starting up Soul Star for {}", soul_config::ADVERTISED_NAME);
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
-    let peripherals = esp_hal::init(config);
+    let mut peripherals = esp_hal::init(config);
     esp_alloc::heap_allocator!(size: 64 * 1024);
     let timer0 = SystemTimer::new(peripherals.SYSTIMER);
     let sw_interrupt = esp_hal::interrupt::software::SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
@@ -96,72 +147,441 @@ async fn main(spawner: Spawner) {
     let ble_sender = DISPLAY_SENDER.init(sender);
     let receiver = DISPLAY_RECEIVER.init(display_channel.receiver());
 
-    // Configure and start the bluetooth radio stack
-    info!("MAIN: Setting up the BLE controller");
+    // Set up the buttons wired straight to GPIO pins, as laid out in the board configuration
+    let mut gpio_buttons: Vec<(Input, GpioButton), MAX_GPIO_BUTTONS> = Vec::new();
+    for &wiring in GPIO_BUTTONS {
+        let pull = match wiring.pull {
+            ButtonPull::Up => Pull::Up,
+            ButtonPull::Down => Pull::Down,
+        };
+        // SAFETY: The board configuration is checked at compile time to give each button a pin of its
+        // own that nothing else uses
+        let pin = unsafe { AnyPin::steal(wiring.pin) };
+        let input = Input::new(pin, InputConfig::default().with_pull(pull));
+        // There is always room, as the number of buttons is checked at compile time
+        let _ = gpio_buttons.push((input, wiring));
+    }
+    let held_at_boot = |button: Button| {
+        gpio_buttons
+            .iter()
+            .any(|(input, wiring)| wiring.button == button && is_down(input, wiring.pull))
+    };
+
+    // Development units join the test network if configured to or if mode is held at power up
+    let network = if soul_config::TEST_NETWORK || held_at_boot(Button::Mode) {
+        log!(Main, info, "MAIN: Joining the test network");
+        Network::test(soul_config::TEST_COMPANY_ID.unwrap_or(TEST_COMPANY_ID))
+    } else {
+        Network::production(soul_config::COMPANY_ID.unwrap_or(COMPANY_ID))
+    };
+    // Holding the torch button at power up runs the self-test to check the wiring
+    let self_test = held_at_boot(Button::Torch);
+
+    // Kick the RMT peripheral for driving the LED string. It only fails if it is set up wrongly, so
+    // there is no point retrying. Without it there is nothing to show, but we still beacon.
+    log!(Main, info, "MAIN: Setting up LED driver controller");
+    let freq = Rate::from_mhz(80);
+    let mut led_driver_0: Option<&'static mut LedDriver> = match Rmt::new(peripherals.RMT, freq) {
+        Ok(rmt) => {
+            // Builds that can switch the strip's power off say which pin does it in the soul configuration
+            let led_power = soul_config::LED_POWER_PIN.map(|pin| {
+                // SAFETY: The pin is reserved for the strip power in the soul configuration, so nothing else uses it
+                let pin = unsafe { AnyPin::steal(pin) };
+                Output::new(pin, Level::Low, OutputConfig::default())
+            });
+            Some(LED_DRIVER.init(LedDriver::new(rmt.into_async(), peripherals.GPIO6, led_power)))
+        }
+        Err(e) => {
+            log!(Main, error, "MAIN: Could not set up the RMT: {:?}", Debug2Format(&e));
+            None
+        }
+    };
+
+    // Configure and start the bluetooth radio stack. Without it we carry on with the LEDs only.
+    log!(Main, info, "MAIN: Setting up the BLE controller");
     let mut rng = Rng::new();
-    let radio_init = RADIO_INIT.init(esp_radio::init().expect("Could not initialize wifi init"));
+    let radio_init: Option<&'static esp_radio::Controller> = with_retry("initialise the radio", esp_radio::init)
+        .await
+        .map(|radio| &*RADIO_INIT.init(radio));
     // Add delay to ensure the wireless controller is fully initialised before we set up the BLE
     Timer::after(Duration::from_millis(200)).await;
 
-    let transport = BleConnector::new(radio_init, peripherals.BT, Default::default()).unwrap();
-    let ble_controller = ExternalController::<_, 20>::new(transport);
+    if self_test && let Some(led) = led_driver_0.as_deref_mut() {
+        power_on_test(
+            led,
+            radio_init,
+            peripherals.BT.reborrow(),
+            peripherals.ADC1.reborrow(),
+            peripherals.GPIO0.reborrow(),
+        )
+        .await;
+    }
 
     // Random address is recommended for privacy. So each time the device comes to life,
     // it will have a different MAC.
     let mut addr: [u8; 6] = [0, 0, 0, 0, 0, 0];
     rng.fill_bytes(&mut addr);
-    let address = ADDRESS.init(Address::random(addr));
-    spawner
-        .spawn(start_ble(ble_controller, ble_sender, address))
-        .expect("Could not start the ble presence task");
+    let address: &'static Address = ADDRESS.init(Address::random(addr));
+    if let Some(radio_init) = radio_init {
+        started("BLE presence", spawner.spawn(start_ble(radio_init, peripherals.BT, address, network, sender)));
+        started("advertisement report", spawner.spawn(report_task(ble_sender, address, network)));
+    }
+    if SIMULATION_ENABLED {
+        started("simulation", spawner.spawn(simulation_task(sender)));
+    }
 
-    // Kick the RMT peripheral for driving the LED string
-    info!("MAIN: Setting up LED driver controller");
-    let freq = Rate::from_mhz(80);
-    let rmt = Rmt::new(peripherals.RMT, freq).unwrap().into_async();
-    let led_driver_0: &'static mut LedDriver = LED_DRIVER.init(LedDriver::new(rmt, peripherals.GPIO6));
-    // The initial animation is "Sparkle" with our own colour
-    //let animation = DEFAULT_ANIMATION.init(Sparkle(SparkleAnimation::new(RGB8::from(soul_config::COLOUR), None)));
-    let animation = DEFAULT_ANIMATION.init(Wave(WaveAnimation::new(RGB8::from(soul_config::COLOUR), None)));
+    // The initial animation is "Wave" with our own colour unless the wearer chose another one,
+    // which is remembered across restarts. It can be changed with a button press.
+    let mut store = open_store(FlashStorage::new(peripherals.FLASH));
+    let settings = load_settings(store.as_mut());
+    apply_settings(&settings);
+    if let Some(bpm) = settings.tempo {
+        set_tempo(bpm, Instant::now());
+    }
+    restore_badges(settings.badges.unwrap_or_default());
+    if let Some(model) = settings.path_loss {
+        set_path_loss(model);
+    }
+    if let Some(meter) = settings.dance {
+        restore_dance_meter(meter);
+    }
+    match store {
+        Some(mut store) => {
+            load_encounters(&mut store, restore_encounter);
+            load_overrides(&mut store);
+            started("settings", spawner.spawn(settings_task(store)));
+        }
+        None => {
+            log!(Main, error, "MAIN: Could not open the settings store, nothing will be saved");
+            degraded();
+        }
+    }
+    let mut default_animation = settings.default_animation.unwrap_or(AnimationKind::Wave);
+    // A colour chosen at runtime wins over the configured one
+    let colour = settings.colour.unwrap_or(RGB8::from(soul_config::COLOUR));
+    update_beacon(|i| i.colour = colour);
+    let [_, _, a, b, c, d] = Efuse::mac_address();
+    let theme = Theme {
+        colour,
+        stripes: soul_config::STRIPES,
+        name: soul_config::ADVERTISED_NAME,
+        id: u32::from_be_bytes([a, b, c, d]),
+    };
+    // Keep an eye on how much heap and stack we are using
+    started("diagnostics", spawner.spawn(diagnostics_task()));
+
+    // Without the LEDs there is nothing for the display, the buttons or the outputs to do, so we
+    // are just a beacon that others can see
+    let Some(led_driver_0) = led_driver_0 else {
+        log!(Main, error, "MAIN: No LED strip, carrying on as a beacon only");
+        return;
+    };
     // Start the display manager task
-    spawner
-        .spawn(display_task(receiver, led_driver_0, animation))
-        .expect("Failed to spawn display task");
-
-    // Set up buttons for the functions we need
-    let config = InputConfig::default().with_pull(Pull::Up);
-    let mut torch_toggle = Input::new(peripherals.GPIO2, config);
-    let mut inc_brightness = Input::new(peripherals.GPIO3, config);
-    let mut dec_brightness = Input::new(peripherals.GPIO15, config);
-
-    info!("MAIN: Starting main loop");
-    sender.send(Brightness(32)).await;
+    started("display", spawner.spawn(display_task(receiver, led_driver_0, default_animation, theme, rng.next_u64())));
+
+    // The vibration motor, if we have one
+    if HAPTIC_ENABLED {
+        let motor = Output::new(peripherals.GPIO5, Level::Low, OutputConfig::default());
+        started("haptic", spawner.spawn(haptic_task(motor)));
+    }
+
+    // The auxiliary trigger output, if anything is wired to it
+    if TRIGGER_ENABLED {
+        let output = Output::new(peripherals.GPIO7, Level::Low, OutputConfig::default());
+        started("trigger", spawner.spawn(trigger_task(output)));
+    }
+
+    // Light shows from a lighting desk over the venue Wi-Fi
+    if LIGHT_SHOW_ENABLED
+        && !soul_config::WIFI_SSID.is_empty()
+        && let Some(radio_init) = radio_init
+    {
+        match esp_radio::wifi::new(radio_init, peripherals.WIFI, Default::default()) {
+            Ok((controller, interfaces)) => {
+                let config = embassy_net::Config::dhcpv4(Default::default());
+                let resources = NET_RESOURCES.init(StackResources::new());
+                let (stack, runner) = embassy_net::new(interfaces.sta, config, resources, rng.next_u64());
+                started("Wi-Fi", spawner.spawn(wifi_task(controller)));
+                started("network", spawner.spawn(net_task(runner)));
+                started("light show", spawner.spawn(light_show_task(stack, sender)));
+            }
+            Err(e) => {
+                log!(Main, error, "MAIN: Could not set up the Wi-Fi: {:?}", Debug2Format(&e));
+                degraded();
+            }
+        }
+    }
+
+    // DMX512 out to venue fixtures, through an RS-485 transceiver
+    if DMX_ENABLED {
+        match Uart::new(peripherals.UART1, UartConfig::default()) {
+            Ok(uart) => {
+                started("DMX", spawner.spawn(dmx_task(uart.with_tx(peripherals.GPIO10).into_async())));
+            }
+            Err(e) => {
+                log!(Main, error, "MAIN: Could not set up the DMX UART: {:?}", Debug2Format(&e));
+                degraded();
+            }
+        }
+    }
+
+    // The microphone, for the VU meter and the beat
+    if MIC_ENABLED {
+        let (rx_buffer, rx_descriptors, _, _) = dma_buffers!(MIC_BUFFER, 0);
+        let config = I2sConfig::new_tdm_philips()
+            .with_sample_rate(Rate::from_hz(MIC_SAMPLE_RATE))
+            .with_data_format(DataFormat::Data16Channel16)
+            .with_channels(Channels::STEREO);
+        match I2s::new(peripherals.I2S0, peripherals.DMA_CH0, config) {
+            Ok(i2s) => {
+                let i2s_rx = i2s
+                    .into_async()
+                    .i2s_rx
+                    .with_bclk(peripherals.GPIO18)
+                    .with_ws(peripherals.GPIO19)
+                    .with_din(peripherals.GPIO20)
+                    .build(rx_descriptors);
+                started("microphone", spawner.spawn(microphone_task(i2s_rx, rx_buffer)));
+            }
+            Err(e) => {
+                log!(Main, error, "MAIN: Could not set up the microphone: {:?}", Debug2Format(&e));
+                degraded();
+            }
+        }
+    }
+
+    // A command line over USB, for exporting the encounter history and changing our colour
+    if CONSOLE_ENABLED {
+        let (rx, tx) = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async().split();
+        started("console", spawner.spawn(console_task(rx, tx, sender)));
+    }
+
+    // Every button, wherever it is wired, delivers its presses to the one channel
+    for (input, wiring) in gpio_buttons {
+        started("button", spawner.spawn(gpio_button_task(input, wiring)));
+    }
+    started("factory reset", spawner.spawn(factory_reset_task(sender)));
+    // The button expander and the accelerometer share the I2C bus
+    if EXPANDER.is_some() || IMU_ENABLED {
+        match I2c::new(peripherals.I2C0, I2cConfig::default()) {
+            Ok(i2c) => {
+                let i2c = i2c
+                    .with_sda(peripherals.GPIO22)
+                    .with_scl(peripherals.GPIO23)
+                    .into_async();
+                let bus = share(i2c);
+                if let Some(expander) = EXPANDER {
+                    started("expander", spawner.spawn(expander_task(bus, expander)));
+                }
+                if IMU_ENABLED {
+                    started("imu", spawner.spawn(imu_task(bus, sender)));
+                }
+            }
+            Err(e) => {
+                log!(Main, error, "MAIN: Could not set up the I2C bus: {:?}", Debug2Format(&e));
+                degraded();
+            }
+        }
+    }
+
+    log!(Main, info, "MAIN: Starting main loop");
+    sender.send(Brightness(32).into()).await;
+    if AUTO_BRIGHTNESS_ENABLED || BATTERY_ENABLED {
+        started("sensor", spawner.spawn(sensor_task(peripherals.ADC1, peripherals.GPIO1, peripherals.GPIO0, sender)));
+    }
+    // Follow the ambient light until the brightness is changed by hand
+    if AUTO_BRIGHTNESS_ENABLED {
+        sender.send(AutoBrightness(true).into()).await;
+    }
     let mut torch = false;
+    let mut torch_preset = TorchPreset::default();
+    let mut torch_intensity = TorchIntensity::default();
     let mut brightness = 32u8;
+    let mut party = false;
+    let mut hot_cold = false;
+    let mut shuffle = false;
+    let mut sos = false;
+    let mut night = false;
+    let mut radio = RadioMode::Normal;
+    let mut leader = false;
+    let mut rssi_meter = false;
+    // Set when a chord was played so the release of the held button is ignored
+    let mut chord = false;
+    let mut torch_chord = false;
+    let mut inc_chord = false;
+    let mut mode_chord = false;
+    let mut tapper = TapTempo::new();
     loop {
-        match select3(
-            wait_for_press(&mut torch_toggle),
-            wait_for_press(&mut inc_brightness),
-            wait_for_press(&mut dec_brightness),
-        )
-        .await
-        {
-            First(_) => {
-                info!("MAIN: Toggling torch mode {}", torch);
-                torch ^= true;
-                sender.send(Torch(torch)).await;
-            }
-            Second(_) => {
-                info!("MAIN: Increase brightness {}", brightness);
+        let ButtonPress { button, held } = BUTTON_PRESSES.receive().await;
+        let long_press = held >= Duration::from_millis(LONG_PRESS);
+        match button {
+            Button::Torch if torch_chord => torch_chord = false,
+            // A long press on the torch button makes us just a beacon, or back to normal
+            Button::Torch if long_press => {
+                radio = if radio == RadioMode::BeaconOnly {
+                    RadioMode::Normal
+                } else {
+                    RadioMode::BeaconOnly
+                };
+                change_radio_mode(&sender, radio).await;
+            }
+            // The torch comes on at low and gets brighter with each press, switching off after high
+            Button::Torch => {
+                if !torch {
+                    torch = true;
+                    torch_intensity = TorchIntensity::default();
+                    sender.send(TorchLevel(torch_intensity).into()).await;
+                    sender.send(Torch(true, None).into()).await;
+                } else if let Some(brighter) = torch_intensity.next() {
+                    torch_intensity = brighter;
+                    sender.send(TorchLevel(torch_intensity).into()).await;
+                } else {
+                    torch = false;
+                    sender.send(Torch(false, None).into()).await;
+                }
+                log!(Main, info, "MAIN: Torch {} at {}", torch, torch_intensity);
+            }
+            // Holding both brightness buttons for a while is the factory reset, which has a task
+            // of its own. The release of the other button is ignored.
+            Button::Increase if long_press && is_held(Button::Decrease) => chord = true,
+            Button::Decrease if long_press && is_held(Button::Increase) => inc_chord = true,
+            // Holding decrease while pressing increase toggles party mode
+            Button::Increase if is_held(Button::Decrease) => {
+                party ^= true;
+                chord = true;
+                log!(Main, info, "MAIN: Party mode {}", party);
+                sender.send(Party(party).into()).await;
+            }
+            // Holding the torch button while pressing increase calibrates the path loss 1 m from
+            // another soul, and while pressing decrease at the far distance
+            Button::Increase if is_held(Button::Torch) => {
+                torch_chord = true;
+                sender.send(Calibrate(CalibrationStep::Reference).into()).await;
+            }
+            // Holding mode while pressing increase shows the signal strength meter
+            Button::Increase if is_held(Button::Mode) => {
+                rssi_meter ^= true;
+                mode_chord = true;
+                log!(Main, info, "MAIN: Signal strength meter {}", rssi_meter);
+                sender.send(RssiMeter(rssi_meter).into()).await;
+            }
+            Button::Increase if inc_chord => inc_chord = false,
+            // A long press on increase makes us the leader, whose animation the crew follows
+            Button::Increase if long_press => {
+                leader ^= true;
+                log!(Main, info, "MAIN: Leading {}", leader);
+                set_leading(leader);
+            }
+            Button::Increase => {
+                log!(Main, info, "MAIN: Increase brightness {}", brightness);
                 brightness = clip(brightness as i16 + 16);
-                sender.send(Brightness(brightness)).await;
+                sender.send(Brightness(brightness).into()).await;
+            }
+            Button::Decrease if is_held(Button::Torch) => {
+                torch_chord = true;
+                sender.send(Calibrate(CalibrationStep::Exponent).into()).await;
+            }
+            Button::Decrease if chord => chord = false,
+            // A long press on decrease tells everybody in our group to flash
+            Button::Decrease if long_press && soul_config::GROUP_KEY.is_some() => {
+                log!(Main, info, "MAIN: Commanding the group to flash");
+                send_command(Effect::Flash(RGB8::from(COMMAND_FLASH_COLOUR)));
             }
-            Third(_) => {
-                info!("MAIN: Decrease brightness {}", brightness);
+            Button::Decrease => {
+                log!(Main, info, "MAIN: Decrease brightness {}", brightness);
                 brightness = clip(brightness as i16 - 16);
-                sender.send(Brightness(brightness)).await;
+                sender.send(Brightness(brightness).into()).await;
+            }
+            Button::Mode if mode_chord => mode_chord = false,
+            // Holding the torch button while pressing mode starts or stops the hot/cold game
+            Button::Mode if is_held(Button::Torch) => {
+                hot_cold ^= true;
+                torch_chord = true;
+                log!(Main, info, "MAIN: Hot/cold game {}", hot_cold);
+                sender.send(HotCold(hot_cold).into()).await;
+            }
+            // Holding increase while pressing mode toggles shuffle mode
+            Button::Mode if is_held(Button::Increase) => {
+                shuffle ^= true;
+                inc_chord = true;
+                log!(Main, info, "MAIN: Shuffle mode {}", shuffle);
+                sender.send(Shuffle(shuffle).into()).await;
+            }
+            // Holding decrease while pressing mode calls for help, or stops calling
+            Button::Mode if is_held(Button::Decrease) => {
+                sos ^= true;
+                chord = true;
+                call_for_help(&sender, sos).await;
+            }
+            // A long press on mode stops or starts advertising. We carry on scanning either way.
+            Button::Mode if long_press => {
+                radio = if radio == RadioMode::DoNotDisturb {
+                    RadioMode::Normal
+                } else {
+                    RadioMode::DoNotDisturb
+                };
+                change_radio_mode(&sender, radio).await;
+            }
+            // During the game, mode picks the next soul to hunt for
+            Button::Mode if hot_cold => {
+                log!(Main, info, "MAIN: Hunting the next soul");
+                sender.send(NextSoul.into()).await;
+            }
+            Button::Mode => {
+                // Cycles the torch colour while the torch is on, otherwise the default animation
+                if torch {
+                    torch_preset = torch_preset.next();
+                    log!(Main, info, "MAIN: Torch colour {}", torch_preset);
+                    sender.send(Torch(true, Some(torch_preset)).into()).await;
+                } else {
+                    default_animation = default_animation.next();
+                    log!(Main, info, "MAIN: Default animation {}", default_animation);
+                    sender.send(SetDefault(default_animation).into()).await;
+                    update_settings(|s| s.default_animation = Some(default_animation));
+                }
+            }
+            // The extra buttons on boards with an expander do without the chords
+            Button::NightMode => {
+                night ^= true;
+                log!(Main, info, "MAIN: Night mode {}", night);
+                sender.send(NightMode(night).into()).await;
+            }
+            Button::Sos => {
+                sos ^= true;
+                call_for_help(&sender, sos).await;
+            }
+            Button::Tempo => {
+                let now = Instant::now();
+                if let Some(bpm) = tapper.tap(now) {
+                    log!(Main, info, "MAIN: Tempo {} BPM", bpm);
+                    set_tempo(bpm, now);
+                    update_settings(|s| s.tempo = Some(bpm));
+                }
             }
         };
-        info!("MAIN: Button pressed");
+        log!(Main, info, "MAIN: Button {} pressed", button);
     }
 }
+
+/// Start or stop calling for help
+///
+/// # Parameters
+/// * `sender` - The display channel
+/// * `sos` - True to call for help
+async fn call_for_help(sender: &DisplayChannelSender, sos: bool) {
+    log!(Main, info, "MAIN: SOS {}", sos);
+    set_emergency(sos);
+    sender.send(Sos(sos).into()).await;
+}
+
+/// Switch the radio to a new mode and let the display know about it
+///
+/// # Parameters
+/// * `sender` - The display channel
+/// * `mode` - The new radio mode
+async fn change_radio_mode(sender: &DisplayChannelSender, mode: RadioMode) {
+    log!(Main, info, "MAIN: Radio mode {}", mode);
+    set_radio_mode(mode);
+    update_status(|s| s.do_not_disturb = mode == RadioMode::DoNotDisturb);
+    sender.send(BeaconOnly(mode == RadioMode::BeaconOnly).into()).await;
+}
@@ -0,0 +1,82 @@
+//! Fixed-point wave helpers for the animations.
+//!
+//! The RISC-V core has no FPU, so rather than each animation hand-rolling its own approximation
+//! or pulling in float maths, they share these 8-bit helpers. A phase of 0-255 covers one full
+//! cycle and every result is on a scale of 0-255.
+
+/// One cycle of a sine wave, swinging between 0 and 255 around a mid-point of 128
+const SINE: [u8; 256] = [
+    128, 131, 134, 137, 140, 143, 146, 149, 152, 155, 158, 162, 165, 167, 170, 173, 176, 179, 182, 185, 188, 190, 193,
+    196, 198, 201, 203, 206, 208, 211, 213, 215, 218, 220, 222, 224, 226, 228, 230, 232, 234, 235, 237, 238, 240, 241,
+    243, 244, 245, 246, 248, 249, 250, 250, 251, 252, 253, 253, 254, 254, 254, 255, 255, 255, 255, 255, 255, 255, 254,
+    254, 254, 253, 253, 252, 251, 250, 250, 249, 248, 246, 245, 244, 243, 241, 240, 238, 237, 235, 234, 232, 230, 228,
+    226, 224, 222, 220, 218, 215, 213, 211, 208, 206, 203, 201, 198, 196, 193, 190, 188, 185, 182, 179, 176, 173, 170,
+    167, 165, 162, 158, 155, 152, 149, 146, 143, 140, 137, 134, 131, 128, 124, 121, 118, 115, 112, 109, 106, 103, 100,
+    97, 93, 90, 88, 85, 82, 79, 76, 73, 70, 67, 65, 62, 59, 57, 54, 52, 49, 47, 44, 42, 40, 37, 35, 33, 31, 29, 27, 25,
+    23, 21, 20, 18, 17, 15, 14, 12, 11, 10, 9, 7, 6, 5, 5, 4, 3, 2, 2, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 2, 2, 3,
+    4, 5, 5, 6, 7, 9, 10, 11, 12, 14, 15, 17, 18, 20, 21, 23, 25, 27, 29, 31, 33, 35, 37, 40, 42, 44, 47, 49, 52, 54,
+    57, 59, 62, 65, 67, 70, 73, 76, 79, 82, 85, 88, 90, 93, 97, 100, 103, 106, 109, 112, 115, 118, 121, 124,
+];
+
+/// Sine of the phase. Starts at the mid-point, peaks at a quarter cycle (64) and bottoms out at
+/// three quarters (192).
+pub fn sin8(phase: u8) -> u8 {
+    SINE[phase as usize]
+}
+
+/// Cosine of the phase. Starts at the peak.
+pub fn cos8(phase: u8) -> u8 {
+    SINE[phase.wrapping_add(64) as usize]
+}
+
+/// Triangle wave. Starts at 0, ramps up to the peak at half a cycle (128) and back down again.
+pub fn triangle8(phase: u8) -> u8 {
+    if phase < 128 {
+        phase * 2
+    } else {
+        255 - (phase - 128) * 2
+    }
+}
+
+/// Scale a value by a fraction of 256, so a scale of 255 leaves it (almost) untouched
+///
+/// # Parameters
+/// * `value` - The value to scale
+/// * `scale` - The scale factor as a fraction of 256
+pub fn scale8(value: u8, scale: u8) -> u8 {
+    ((value as u16 * (scale as u16 + 1)) >> 8) as u8
+}
+
+/// Blend between two values
+///
+/// # Parameters
+/// * `from` - The value at 0
+/// * `to` - The value at 255
+/// * `amount` - How far to go from `from` to `to`
+pub fn lerp8(from: u8, to: u8, amount: u8) -> u8 {
+    if to >= from {
+        from + scale8(to - from, amount)
+    } else {
+        from - scale8(from - to, amount)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_the_waves_have_the_right_shape() {
+        assert_eq!(sin8(0), 128);
+        assert_eq!(sin8(64), 255);
+        assert_eq!(sin8(192), 0);
+        assert_eq!(cos8(0), 255);
+        assert_eq!(triangle8(0), 0);
+        assert_eq!(triangle8(128), 255);
+        assert_eq!(triangle8(255), 1);
+        assert_eq!(scale8(255, 255), 255);
+        assert_eq!(scale8(200, 127), 100);
+        assert_eq!(lerp8(10, 210, 127), 110);
+        assert_eq!(lerp8(210, 10, 255), 10);
+    }
+}
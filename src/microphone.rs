@@ -0,0 +1,85 @@
+//! An I2S MEMS microphone, for the animations that react to sound.
+//!
+//! The microphone is read by DMA into a circular buffer at [MIC_SAMPLE_RATE] and handled a block
+//! of [MIC_BLOCK] samples at a time. Each block sets the envelope for the VU meter and the
+//! spectrum for the spectrum analyser (see [audio](soulstar::audio)) and goes to the
+//! [BeatDetector]. Once the beats it finds are steady they set the tempo, as tapping the tempo
+//! button does, unless the button was tapped recently (see [tempo](soulstar::tempo)). A detected
+//! tempo isn't saved, so the tapped one comes back after a restart.
+//!
+//! The task is only started if [MIC_ENABLED](soulstar::configuration::MIC_ENABLED) is set. The
+//! microphone's L/R pin must be tied low so that it sends on the left channel.
+
+use defmt::Debug2Format;
+use embassy_time::Instant;
+use esp_hal::Async;
+use esp_hal::i2s::master::I2sRx;
+use soulstar::audio::{envelope_of, set_envelope, set_spectrum};
+use soulstar::beat::BeatDetector;
+use soulstar::configuration::{MIC_BLOCK, MIC_SAMPLE_RATE};
+use soulstar::fft::bands;
+use soulstar::log;
+use soulstar::tempo::hear_tempo;
+
+/// The size of the DMA buffer (bytes)
+pub const MIC_BUFFER: usize = 4 * 4092;
+
+/// Each frame is a 16 bit left sample followed by a 16 bit right one
+const FRAME_BYTES: usize = 4;
+
+/// Read the microphone and publish the envelope and the beat
+///
+/// # Parameters
+/// * `i2s` - The I2S receiver wired to the microphone
+/// * `buffer` - The DMA buffer, [MIC_BUFFER] bytes
+#[embassy_executor::task]
+pub async fn microphone_task(i2s: I2sRx<'static, Async>, buffer: &'static mut [u8]) {
+    let mut transfer = match i2s.read_dma_circular_async(buffer) {
+        Ok(transfer) => transfer,
+        Err(e) => {
+            log!(Main, error, "MIC: Could not start reading: {:?}", Debug2Format(&e));
+            return;
+        }
+    };
+    log!(Main, info, "MIC: Listening at {}Hz", MIC_SAMPLE_RATE);
+    let mut detector = BeatDetector::new();
+    let mut bytes = [0u8; MIC_BLOCK * FRAME_BYTES];
+    let mut block = [0i16; MIC_BLOCK];
+    let mut filled = 0;
+    // Bytes of a frame that was cut short by the last read, at the start of the buffer
+    let mut pending = 0;
+    loop {
+        let count = match transfer
+            .pop(&mut bytes[pending..(MIC_BLOCK - filled) * FRAME_BYTES])
+            .await
+        {
+            Ok(count) => count,
+            Err(e) => {
+                log!(Main, error, "MIC: Read failed: {:?}", Debug2Format(&e));
+                return;
+            }
+        };
+        let received = pending + count;
+        let (frames, rest) = bytes[..received].as_chunks::<FRAME_BYTES>();
+        for frame in frames {
+            block[filled] = i16::from_le_bytes([frame[0], frame[1]]);
+            filled += 1;
+        }
+        // Keep the start of a frame for the next read, or every sample after it would be the
+        // wrong channel
+        pending = rest.len();
+        bytes.copy_within(received - pending..received, 0);
+        if filled < MIC_BLOCK {
+            continue;
+        }
+        filled = 0;
+        set_envelope(envelope_of(&block));
+        set_spectrum(bands(&block));
+        if let Some(beat) = detector.update(&block, Instant::now())
+            && let Some(bpm) = beat.bpm
+            && !hear_tempo(bpm, beat.at)
+        {
+            log!(Main, debug, "MIC: Heard {} BPM, keeping the tapped tempo", bpm);
+        }
+    }
+}
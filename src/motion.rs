@@ -0,0 +1,292 @@
+//! Gestures from the accelerometer.
+//!
+//! The IMU task (see `imu.rs`) reads the accelerometer every [IMU_INTERVAL] milliseconds and
+//! hands each [Acceleration] to the detectors here. They only look at how much the acceleration
+//! changes from one sample to the next, so they work whichever way up the star is worn and
+//! ignore gravity.
+//!
+//! A tap on the enclosure is a short, sharp spike. Two taps close together make a double tap,
+//! which waves at the souls around us, see [wave](crate::wave). A shake keeps the acceleration
+//! swinging about for a while, which a tap is far too short to do. Each swing of a shake looks
+//! like a tap on its own, so taps are ignored while the acceleration is swinging about. A wearer who is sitting or
+//! sleeping hardly moves the star at all, so the display can be dimmed until they get up again.
+//!
+//! [IMU_INTERVAL]: crate::configuration::IMU_INTERVAL
+
+use crate::configuration::{
+    SHAKE_COOLDOWN, SHAKE_LEVEL, STILL_THRESHOLD, STILL_TIMEOUT, TAP_MAX_DURATION, TAP_MAX_GAP, TAP_MIN_GAP,
+    TAP_THRESHOLD,
+};
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+
+/// One accelerometer reading, in milli-g on each axis
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Format)]
+pub struct Acceleration {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+impl Acceleration {
+    pub const fn new(x: i16, y: i16, z: i16) -> Self {
+        Self { x, y, z }
+    }
+
+    /// How far this reading is from another, summed over the axes (milli-g)
+    pub fn jerk(&self, other: &Acceleration) -> u32 {
+        self.x.abs_diff(other.x) as u32 + self.y.abs_diff(other.y) as u32 + self.z.abs_diff(other.z) as u32
+    }
+}
+
+/// How many readings the tap detector averages the jerk over, roughly, to tell a tap from a shake.
+/// It is much shorter than [SHAKE_READINGS] so that the first few swings of a shake are caught.
+const TAP_QUIET_READINGS: u32 = 4;
+
+/// Finds double taps in the accelerometer readings
+#[derive(Default)]
+pub struct TapDetector {
+    /// The previous reading
+    last: Option<Acceleration>,
+    /// A running sum of the jerk, decaying by one [TAP_QUIET_READINGS]th each reading
+    energy: u32,
+    /// When the current spike started, None if we aren't in one
+    spike: Option<Instant>,
+    /// When the last tap was
+    tap: Option<Instant>,
+    /// The last tap could be the first of a double tap
+    first: bool,
+}
+
+impl TapDetector {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            energy: 0,
+            spike: None,
+            tap: None,
+            first: false,
+        }
+    }
+
+    /// Take a reading
+    ///
+    /// # Parameters
+    /// * `reading` - The acceleration now
+    /// * `now` - The time of the reading
+    ///
+    /// # Returns
+    /// True if this reading completes a double tap
+    pub fn update(&mut self, reading: Acceleration, now: Instant) -> bool {
+        let jerk = self.last.map_or(0, |last| reading.jerk(&last));
+        self.last = Some(reading);
+        let shaking = self.energy / TAP_QUIET_READINGS >= SHAKE_LEVEL;
+        self.energy = self.energy - self.energy / TAP_QUIET_READINGS + jerk;
+        // A spike that starts while the star is still swinging from the last one is a shake
+        if shaking && jerk >= TAP_THRESHOLD && self.spike.is_none() {
+            self.tap = None;
+            self.first = false;
+            return false;
+        }
+        if jerk >= TAP_THRESHOLD {
+            self.spike.get_or_insert(now);
+            return false;
+        }
+        let Some(start) = self.spike.take() else {
+            return false;
+        };
+        // Anything longer than a tap is the wearer moving about, which breaks up a double tap
+        if now.saturating_duration_since(start) > Duration::from_millis(TAP_MAX_DURATION) {
+            self.tap = None;
+            self.first = false;
+            return false;
+        }
+        let gap = self.tap.map(|tap| start.saturating_duration_since(tap));
+        // The enclosure rings for a moment after a tap, which is still the same tap
+        if gap.is_some_and(|gap| gap < Duration::from_millis(TAP_MIN_GAP)) {
+            return false;
+        }
+        let double = self.first && gap.is_some_and(|gap| gap <= Duration::from_millis(TAP_MAX_GAP));
+        self.tap = Some(start);
+        self.first = !double;
+        double
+    }
+}
+
+/// How many readings the shake energy is averaged over, roughly
+const SHAKE_READINGS: u32 = 16;
+
+/// Finds vigorous shakes in the accelerometer readings
+#[derive(Default)]
+pub struct ShakeDetector {
+    /// The previous reading
+    last: Option<Acceleration>,
+    /// A running sum of the jerk, decaying by one [SHAKE_READINGS]th each reading
+    energy: u32,
+    /// When the last shake was found, so one shake isn't reported over and over
+    shaken: Option<Instant>,
+}
+
+impl ShakeDetector {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            energy: 0,
+            shaken: None,
+        }
+    }
+
+    /// Take a reading
+    ///
+    /// # Parameters
+    /// * `reading` - The acceleration now
+    /// * `now` - The time of the reading
+    ///
+    /// # Returns
+    /// True if the star is being shaken, at most once every [SHAKE_COOLDOWN] milliseconds
+    pub fn update(&mut self, reading: Acceleration, now: Instant) -> bool {
+        let jerk = self.last.map_or(0, |last| reading.jerk(&last));
+        self.last = Some(reading);
+        self.energy = self.energy - self.energy / SHAKE_READINGS + jerk;
+        let cooling = self
+            .shaken
+            .is_some_and(|at| now.saturating_duration_since(at) < Duration::from_millis(SHAKE_COOLDOWN));
+        if cooling || self.energy / SHAKE_READINGS < SHAKE_LEVEL {
+            return false;
+        }
+        self.shaken = Some(now);
+        self.energy = 0;
+        true
+    }
+}
+
+/// Finds out when the wearer has kept still for a while, and when they move again
+#[derive(Default)]
+pub struct StillnessDetector {
+    /// The previous reading
+    last: Option<Acceleration>,
+    /// When the wearer last moved
+    moved: Option<Instant>,
+    /// We have said the wearer is still
+    still: bool,
+}
+
+impl StillnessDetector {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            moved: None,
+            still: false,
+        }
+    }
+
+    /// Take a reading
+    ///
+    /// # Parameters
+    /// * `reading` - The acceleration now
+    /// * `now` - The time of the reading
+    ///
+    /// # Returns
+    /// True once the wearer has kept still for [STILL_TIMEOUT] seconds and false as soon as they
+    /// move again, None while nothing changes
+    pub fn update(&mut self, reading: Acceleration, now: Instant) -> Option<bool> {
+        let jerk = self.last.map_or(0, |last| reading.jerk(&last));
+        self.last = Some(reading);
+        let moved = *self.moved.get_or_insert(now);
+        let still = if jerk >= STILL_THRESHOLD {
+            self.moved = Some(now);
+            false
+        } else {
+            now.saturating_duration_since(moved) >= Duration::from_secs(STILL_TIMEOUT)
+        };
+        if still == self.still {
+            return None;
+        }
+        self.still = still;
+        Some(still)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Feed a new detector a second of readings, one every 10 ms, jolted whenever `hit` says so
+    fn double_taps(hit: impl Fn(u64) -> bool) -> usize {
+        let mut detector = TapDetector::new();
+        let rest = Acceleration::new(0, 0, 1000);
+        let jolt = Acceleration::new(0, 3000, 1000);
+        (0..1000)
+            .step_by(10)
+            .filter(|&t| detector.update(if hit(t) { jolt } else { rest }, Instant::from_millis(t)))
+            .count()
+    }
+
+    #[test]
+    pub fn if_it_finds_double_taps() {
+        assert_eq!(double_taps(|t| t == 100 || t == 300), 1);
+        // The enclosure ringing after each tap
+        assert_eq!(double_taps(|t| [100, 120, 300, 320].contains(&t)), 1);
+        // Three taps make one double tap, not two
+        assert_eq!(double_taps(|t| t == 100 || t == 300 || t == 500), 1);
+    }
+
+    #[test]
+    pub fn if_it_ignores_everything_else() {
+        // A single tap
+        assert_eq!(double_taps(|t| t == 100), 0);
+        // Taps too far apart
+        assert_eq!(double_taps(|t| t == 100 || t == 900), 0);
+        // Shaking about, then a tap
+        assert_eq!(double_taps(|t| ((100..600).contains(&t) && t % 20 == 0) || t == 700), 0);
+    }
+
+    #[test]
+    pub fn if_a_shake_is_not_a_tap() {
+        let mut detector = TapDetector::new();
+        // Swing back and forth between +1g and -1g on x every 20 ms for a second
+        let taps = (0..1000)
+            .step_by(10)
+            .filter(|&t| {
+                let swing = Acceleration::new(if t % 40 < 20 { 1000 } else { -1000 }, 0, 1000);
+                detector.update(swing, Instant::from_millis(t))
+            })
+            .count();
+        assert_eq!(taps, 0);
+    }
+
+    #[test]
+    pub fn if_it_finds_shakes_but_not_taps() {
+        let mut detector = ShakeDetector::new();
+        let rest = Acceleration::new(0, 0, 1000);
+        let mut shakes = |hit: &dyn Fn(u64) -> bool, from: u64| {
+            (from..from + 1000)
+                .step_by(10)
+                .filter(|&t| {
+                    // Swing back and forth between +1g and -1g on x
+                    let swing = Acceleration::new(if t % 40 < 20 { 1000 } else { -1000 }, 0, 1000);
+                    detector.update(if hit(t) { swing } else { rest }, Instant::from_millis(t))
+                })
+                .count()
+        };
+        // A double tap on the enclosure
+        assert_eq!(shakes(&|t| t == 100 || t == 300, 0), 0);
+        // Half a second of shaking is one shake
+        assert_eq!(shakes(&|t| (1100..1600).contains(&t), 1000), 1);
+    }
+
+    #[test]
+    pub fn if_it_notices_the_wearer_keeping_still() {
+        let mut detector = StillnessDetector::new();
+        let rest = Acceleration::new(0, 0, 1000);
+        let start = Instant::from_secs(10);
+        assert_eq!(detector.update(rest, start), None);
+        // A little jitter doesn't count as moving
+        let jitter = Acceleration::new(20, 0, 1000);
+        assert_eq!(detector.update(jitter, start + Duration::from_secs(STILL_TIMEOUT / 2)), None);
+        assert_eq!(detector.update(rest, start + Duration::from_secs(STILL_TIMEOUT)), Some(true));
+        assert_eq!(detector.update(rest, start + Duration::from_secs(STILL_TIMEOUT + 1)), None);
+        let moved = Acceleration::new(500, 0, 1000);
+        assert_eq!(detector.update(moved, start + Duration::from_secs(STILL_TIMEOUT + 2)), Some(false));
+    }
+}
@@ -0,0 +1,188 @@
+//! Which way up a matrix panel is being worn.
+//!
+//! Animations draw a matrix as if it were upright. Gravity, as the accelerometer sees it (see
+//! [motion](crate::motion)), tells us how the panel has actually been turned and the frame is
+//! rotated to match just before it is shown, so patterns stay upright whether the panel is worn
+//! on the chest or hung upside down from a backpack.
+//!
+//! The accelerometer is assumed to be mounted with its x axis along the rows of the panel,
+//! pointing right, and its y axis along the columns, pointing up. A panel lying flat has no
+//! up, so the last orientation is kept until it is picked up again.
+
+use crate::colour::LedBuffer;
+use crate::configuration::{ORIENTATION_SETTLE, ORIENTATION_THRESHOLD};
+use crate::motion::Acceleration;
+use core::sync::atomic::{AtomicU8, Ordering};
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+
+/// How the panel has been turned from upright, as seen by somebody looking at it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum Orientation {
+    #[default]
+    Upright = 0,
+    /// A quarter turn clockwise
+    Right = 1,
+    UpsideDown = 2,
+    /// A quarter turn anticlockwise
+    Left = 3,
+}
+
+impl Orientation {
+    /// The orientation gravity points to, if it is clear enough
+    ///
+    /// # Arguments
+    /// * `reading` - The accelerometer reading, which is gravity while the panel is held still
+    pub fn from_gravity(reading: &Acceleration) -> Option<Self> {
+        // Only one axis can be over the threshold at a time, see ORIENTATION_THRESHOLD
+        let threshold = ORIENTATION_THRESHOLD as i16;
+        match (reading.x, reading.y) {
+            (_, y) if y >= threshold => Some(Self::Upright),
+            (_, y) if y <= -threshold => Some(Self::UpsideDown),
+            (x, _) if x <= -threshold => Some(Self::Right),
+            (x, _) if x >= threshold => Some(Self::Left),
+            _ => None,
+        }
+    }
+
+    const fn from_u8(value: u8) -> Self {
+        match value & 3 {
+            1 => Self::Right,
+            2 => Self::UpsideDown,
+            3 => Self::Left,
+            _ => Self::Upright,
+        }
+    }
+}
+
+/// The way up the panel is now
+static ORIENTATION: AtomicU8 = AtomicU8::new(Orientation::Upright as u8);
+
+/// The way up the panel is now
+pub fn orientation() -> Orientation {
+    Orientation::from_u8(ORIENTATION.load(Ordering::Relaxed))
+}
+
+/// Record the way up the panel is now
+pub fn set_orientation(orientation: Orientation) {
+    ORIENTATION.store(orientation as u8, Ordering::Relaxed);
+}
+
+/// Follows the orientation of the panel, only changing once the new one has held for
+/// [ORIENTATION_SETTLE] milliseconds so walking about doesn't spin the display
+#[derive(Default)]
+pub struct OrientationTracker {
+    /// The orientation we have settled on
+    current: Orientation,
+    /// A different orientation that we have seen, and since when
+    candidate: Option<(Orientation, Instant)>,
+}
+
+impl OrientationTracker {
+    pub const fn new() -> Self {
+        Self {
+            current: Orientation::Upright,
+            candidate: None,
+        }
+    }
+
+    /// Take a reading
+    ///
+    /// # Parameters
+    /// * `reading` - The acceleration now
+    /// * `now` - The time of the reading
+    ///
+    /// # Returns
+    /// The new orientation if the panel has settled into one
+    pub fn update(&mut self, reading: &Acceleration, now: Instant) -> Option<Orientation> {
+        let seen = Orientation::from_gravity(reading)?;
+        if seen == self.current {
+            self.candidate = None;
+            return None;
+        }
+        match self.candidate {
+            Some((candidate, since))
+                if candidate == seen
+                    && now.saturating_duration_since(since) >= Duration::from_millis(ORIENTATION_SETTLE) =>
+            {
+                self.current = seen;
+                self.candidate = None;
+                Some(seen)
+            }
+            Some((candidate, _)) if candidate == seen => None,
+            _ => {
+                self.candidate = Some((seen, now));
+                None
+            }
+        }
+    }
+}
+
+/// Rotate a frame drawn for an upright panel to suit the way up it is. The panel is wired row by
+/// row from the top left and any LEDs after the last row are left alone. Quarter turns only
+/// fit a square panel, so an oblong one is just turned upside down or not at all.
+///
+/// # Parameters
+/// * `frame` - The frame as drawn
+/// * `orientation` - The way up the panel is
+/// * `width` - The LEDs in each row
+/// * `height` - The number of rows
+pub fn rotate(frame: &LedBuffer, orientation: Orientation, width: usize, height: usize) -> LedBuffer {
+    let size = (width * height).min(frame.len());
+    let mut rotated = *frame;
+    let square = width == height;
+    for (i, &pixel) in frame[..size].iter().enumerate() {
+        let (row, column) = (i / width, i % width);
+        let (row, column) = match orientation {
+            Orientation::Upright => (row, column),
+            Orientation::UpsideDown => (height - 1 - row, width - 1 - column),
+            Orientation::Right if square => (width - 1 - column, row),
+            Orientation::Left if square => (column, width - 1 - row),
+            Orientation::Right | Orientation::Left => (row, column),
+        };
+        rotated[row * width + column] = pixel;
+    }
+    rotated
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use smart_leds::RGB8;
+
+    const RED: RGB8 = RGB8::new(255, 0, 0);
+
+    #[test]
+    pub fn if_it_follows_gravity() {
+        let mut tracker = OrientationTracker::new();
+        let upside_down = Acceleration::new(0, -1000, 0);
+        assert_eq!(tracker.update(&upside_down, Instant::from_millis(0)), None);
+        // A jolt the other way doesn't count
+        assert_eq!(tracker.update(&Acceleration::new(1000, 0, 0), Instant::from_millis(100)), None);
+        assert_eq!(tracker.update(&upside_down, Instant::from_millis(200)), None);
+        assert_eq!(
+            tracker.update(&upside_down, Instant::from_millis(200 + ORIENTATION_SETTLE)),
+            Some(Orientation::UpsideDown)
+        );
+        // Lying flat or tilted halfway keeps it as it was
+        assert_eq!(Orientation::from_gravity(&Acceleration::new(0, 0, 1000)), None);
+        assert_eq!(Orientation::from_gravity(&Acceleration::new(707, 707, 0)), None);
+    }
+
+    #[test]
+    pub fn if_it_rotates_the_frame() {
+        let mut frame = LedBuffer::default();
+        // The top left of a 4x4 panel
+        frame[0] = RED;
+        let corner = |orientation| rotate(&frame, orientation, 4, 4).iter().position(|&p| p == RED);
+        assert_eq!(corner(Orientation::Upright), Some(0));
+        assert_eq!(corner(Orientation::UpsideDown), Some(15));
+        // Turned clockwise, the physical bottom left is at the top left
+        assert_eq!(corner(Orientation::Right), Some(12));
+        assert_eq!(corner(Orientation::Left), Some(3));
+        // An oblong panel can't be turned sideways
+        assert_eq!(rotate(&frame, Orientation::Left, 6, 4)[0], RED);
+        assert_eq!(rotate(&frame, Orientation::UpsideDown, 6, 4)[23], RED);
+    }
+}
@@ -0,0 +1,119 @@
+//! Colours of our own for friends whose colour we can't stand.
+//!
+//! Overrides come from two places: the `[device.colours]` table in souls.toml, which is built in,
+//! and the ones given on the USB command line (see `console.rs`), which are kept in the settings
+//! store (see `settings_store.rs`) so they survive a restart. One given at runtime wins over a
+//! built-in one for the same friend. Either way, the friend is only shown in our colour on our
+//! own star. Friends are picked by their advertised name, and runtime overrides are kept under a
+//! hash of it (see [name_key]) so they take up the same few bytes whatever the name.
+
+use crate::utils::crc32;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::Vec;
+use smart_leds::RGB8;
+
+/// The most colours we can give friends at runtime
+pub const MAX_OVERRIDES: usize = 8;
+
+/// The key a runtime override is kept under
+///
+/// # Parameters
+/// * `name` - The friend's advertised name
+pub fn name_key(name: &str) -> u32 {
+    crc32(name.as_bytes())
+}
+
+/// The colours given to friends at runtime
+#[derive(Default)]
+pub struct ColourOverrides {
+    colours: Vec<(u32, RGB8), MAX_OVERRIDES>,
+}
+
+impl ColourOverrides {
+    pub const fn new() -> Self {
+        Self { colours: Vec::new() }
+    }
+
+    /// Give a friend a colour, or take it away again
+    ///
+    /// # Parameters
+    /// * `key` - The [name_key] of the friend
+    /// * `colour` - Their new colour, None to show them in their own again
+    ///
+    /// # Returns
+    /// False if there was no room for another friend
+    pub fn set(&mut self, key: u32, colour: Option<RGB8>) -> bool {
+        let index = self.colours.iter().position(|(k, _)| *k == key);
+        match (index, colour) {
+            (Some(i), Some(colour)) => self.colours[i].1 = colour,
+            (Some(i), None) => {
+                self.colours.swap_remove(i);
+            }
+            (None, Some(colour)) => return self.colours.push((key, colour)).is_ok(),
+            (None, None) => (),
+        }
+        true
+    }
+
+    /// The colour we show a friend in, None to show them in their own
+    ///
+    /// # Parameters
+    /// * `name` - The friend's advertised name
+    /// * `built_in` - The overrides from souls.toml
+    pub fn colour(&self, name: &str, built_in: &[(&str, [u8; 3])]) -> Option<RGB8> {
+        let key = name_key(name);
+        self.colours
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, colour)| *colour)
+            .or_else(|| built_in.iter().find(|(n, _)| *n == name).map(|(_, c)| RGB8::from(*c)))
+    }
+}
+
+/// Our runtime overrides
+static OVERRIDES: Mutex<CriticalSectionRawMutex, RefCell<ColourOverrides>> =
+    Mutex::new(RefCell::new(ColourOverrides::new()));
+
+/// Give a friend a colour at runtime, or take it away again, see [ColourOverrides::set]. Saving
+/// it is up to the caller.
+pub fn set_override(key: u32, colour: Option<RGB8>) -> bool {
+    OVERRIDES.lock(|o| o.borrow_mut().set(key, colour))
+}
+
+/// The colour we show a friend in, see [ColourOverrides::colour]
+pub fn colour_override(name: &str, built_in: &[(&str, [u8; 3])]) -> Option<RGB8> {
+    OVERRIDES.lock(|o| o.borrow().colour(name, built_in))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BUILT_IN: &[(&str, [u8; 3])] = &[("Dr Strange", [0xFF, 0x00, 0xFF])];
+
+    #[test]
+    pub fn if_runtime_overrides_win() {
+        let mut overrides = ColourOverrides::new();
+        let brown = RGB8::new(0x80, 0x40, 0x00);
+        assert_eq!(overrides.colour("Dr Strange", BUILT_IN), Some(RGB8::new(0xFF, 0x00, 0xFF)));
+        assert_eq!(overrides.colour("Wong", BUILT_IN), None);
+        assert!(overrides.set(name_key("Dr Strange"), Some(brown)));
+        assert_eq!(overrides.colour("Dr Strange", BUILT_IN), Some(brown));
+        // Taking it away brings back the built-in one
+        assert!(overrides.set(name_key("Dr Strange"), None));
+        assert_eq!(overrides.colour("Dr Strange", BUILT_IN), Some(RGB8::new(0xFF, 0x00, 0xFF)));
+    }
+
+    #[test]
+    pub fn if_it_runs_out_of_room() {
+        let mut overrides = ColourOverrides::new();
+        for key in 0..MAX_OVERRIDES as u32 {
+            assert!(overrides.set(key, Some(RGB8::default())));
+        }
+        assert!(!overrides.set(MAX_OVERRIDES as u32, Some(RGB8::default())));
+        // Changing one we have still works
+        assert!(overrides.set(0, Some(RGB8::new(1, 2, 3))));
+    }
+}
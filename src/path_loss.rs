@@ -0,0 +1,186 @@
+//! A calibratable path-loss model, so the distance thresholds mean the same on every star.
+//!
+//! The path loss to a soul grows with distance as `reference + 10 * exponent * log10(metres)`,
+//! but the reference and the exponent depend on the enclosure, the antenna and where the star is
+//! worn. The loss thresholds in the configuration are written for [PATH_LOSS_REFERENCE] and
+//! [PATH_LOSS_EXPONENT]. A star that has been calibrated maps the losses it measures onto that
+//! nominal model before they are compared with any threshold.
+//!
+//! To calibrate, stand 1 m from another soul and play the reference chord, then optionally step
+//! back to [CALIBRATION_FAR_DISTANCE] m and play the exponent chord. Each step averages
+//! [CALIBRATION_SAMPLES] beacons from the nearest soul, the one with the lowest average loss.
+//! The calibration is kept with the [settings](crate::settings) so it survives a restart.
+
+use crate::configuration::{CALIBRATION_SAMPLES, PATH_LOSS_EXPONENT, PATH_LOSS_REFERENCE};
+use core::sync::atomic::{AtomicI32, AtomicU8, Ordering};
+use defmt::Format;
+use heapless::Vec;
+
+/// How far apart to stand when calibrating the exponent (metres). It must be a power of two, see
+/// [FAR_DISTANCE_DB].
+pub const CALIBRATION_FAR_DISTANCE: u32 = 4;
+
+/// 10 * log10 of [CALIBRATION_FAR_DISTANCE] in hundredths of a dB. Each doubling of the distance
+/// adds 10 * log10(2) = 3.01 dB, which keeps this in integers.
+const FAR_DISTANCE_DB: i32 = 301 * CALIBRATION_FAR_DISTANCE.ilog2() as i32;
+
+const _: () = assert!(CALIBRATION_FAR_DISTANCE.is_power_of_two() && CALIBRATION_FAR_DISTANCE > 1);
+
+/// The most souls we keep apart while looking for the nearest
+const MAX_CALIBRATION_SOULS: usize = 8;
+
+/// The path-loss model of this star
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct PathLoss {
+    /// Path loss at 1 m (dB)
+    pub reference: i32,
+    /// Path-loss exponent in tenths, 20 for free space
+    pub exponent: u8,
+}
+
+impl PathLoss {
+    /// The model the configured thresholds are written for
+    pub const NOMINAL: Self = Self {
+        reference: PATH_LOSS_REFERENCE,
+        exponent: PATH_LOSS_EXPONENT,
+    };
+
+    /// Map a measured path loss onto the nominal model
+    pub fn normalise(&self, loss: i32) -> i32 {
+        Self::NOMINAL.reference + (loss - self.reference) * Self::NOMINAL.exponent as i32 / self.exponent.max(1) as i32
+    }
+}
+
+/// Our calibrated reference loss
+static REFERENCE: AtomicI32 = AtomicI32::new(PATH_LOSS_REFERENCE);
+
+/// Our calibrated exponent
+static EXPONENT: AtomicU8 = AtomicU8::new(PATH_LOSS_EXPONENT);
+
+/// Our path-loss model
+pub fn path_loss() -> PathLoss {
+    PathLoss {
+        reference: REFERENCE.load(Ordering::Relaxed),
+        exponent: EXPONENT.load(Ordering::Relaxed),
+    }
+}
+
+/// Change our path-loss model
+pub fn set_path_loss(model: PathLoss) {
+    REFERENCE.store(model.reference, Ordering::Relaxed);
+    EXPONENT.store(model.exponent, Ordering::Relaxed);
+}
+
+/// What is being calibrated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum CalibrationStep {
+    /// The loss at 1 m
+    Reference,
+    /// The exponent, from the loss at the far distance
+    Exponent,
+}
+
+/// The beacons heard from one soul while calibrating
+#[derive(Clone, Copy)]
+struct Samples {
+    soul: u32,
+    total: i32,
+    count: u32,
+}
+
+impl Samples {
+    fn average(&self) -> i32 {
+        self.total / self.count.max(1) as i32
+    }
+}
+
+/// Averages the beacons of the nearest soul into a new path-loss model
+pub struct Calibration {
+    step: CalibrationStep,
+    /// The souls heard so far. Once full, souls we haven't heard yet are ignored.
+    souls: Vec<Samples, MAX_CALIBRATION_SOULS>,
+}
+
+impl Calibration {
+    pub fn new(step: CalibrationStep) -> Self {
+        Self {
+            step,
+            souls: Vec::new(),
+        }
+    }
+
+    /// Take a beacon into account. Every soul is averaged on its own and the nearest, the one with
+    /// the lowest average loss, is measured. That is the one standing next to us if we are doing
+    /// it right, whoever happens to be heard first.
+    ///
+    /// # Arguments
+    /// * `soul` - The tracker key of the sender
+    /// * `loss` - The raw path loss to the sender (dB)
+    /// * `model` - The current model, which the step being calibrated is changed in
+    ///
+    /// # Returns
+    /// The new model once we have [CALIBRATION_SAMPLES] beacons from the nearest soul
+    pub fn sample(&mut self, soul: u32, loss: i32, model: PathLoss) -> Option<PathLoss> {
+        match self.souls.iter_mut().find(|s| s.soul == soul) {
+            Some(samples) => {
+                samples.total += loss;
+                samples.count += 1;
+            }
+            None => {
+                let _ = self.souls.push(Samples {
+                    soul,
+                    total: loss,
+                    count: 1,
+                });
+            }
+        }
+        let nearest = self.souls.iter().min_by_key(|s| s.average())?;
+        if nearest.count < CALIBRATION_SAMPLES {
+            return None;
+        }
+        let average = nearest.average();
+        Some(match self.step {
+            CalibrationStep::Reference => PathLoss {
+                reference: average,
+                ..model
+            },
+            CalibrationStep::Exponent => PathLoss {
+                exponent: ((average - model.reference) * 1000 / FAR_DISTANCE_DB).clamp(10, 60) as u8,
+                ..model
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn calibrate(step: CalibrationStep, loss: i32, model: PathLoss) -> PathLoss {
+        let mut calibration = Calibration::new(step);
+        // Somebody further away is ignored, even though we heard them first
+        for _ in 1..CALIBRATION_SAMPLES {
+            assert_eq!(calibration.sample(2, loss + 30, model), None);
+        }
+        (1..CALIBRATION_SAMPLES).for_each(|_| assert_eq!(calibration.sample(1, loss, model), None));
+        calibration.sample(1, loss, model).unwrap()
+    }
+
+    #[test]
+    pub fn if_the_far_distance_is_right() {
+        // 10 * log10(4) = 6.02 dB
+        assert_eq!(FAR_DISTANCE_DB, 602);
+    }
+
+    #[test]
+    pub fn if_calibration_maps_onto_the_nominal_model() {
+        let model = calibrate(CalibrationStep::Reference, 55, PathLoss::NOMINAL);
+        assert_eq!(model.reference, 55);
+        assert_eq!(model.normalise(55), PATH_LOSS_REFERENCE);
+        // An exponent of 3 loses 18 dB more at 4 m
+        let model = calibrate(CalibrationStep::Exponent, 73, model);
+        assert_eq!(model.exponent, 29);
+        let nominal_far = PATH_LOSS_REFERENCE + 6 * PATH_LOSS_EXPONENT as i32 / 10;
+        assert!((model.normalise(73) - nominal_far).abs() <= 1);
+    }
+}
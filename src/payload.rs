@@ -0,0 +1,569 @@
+//! The SoulStar advertisement payload codec.
+//!
+//! Our beacon carries the advertised name, the soul's preferred colour tucked into the manufacturer
+//! specific data (tagged with a company ID so we can filter out everybody else) and the transmitter
+//! power so receivers can estimate the path loss. The manufacturer data holds a list of typed
+//! fields, see [fields](crate::fields), which is how a soul calling for help says so. The company
+//! ID is normally
+//! [COMPANY_ID](crate::configuration::COMPANY_ID), but development units use a separate one so
+//! that they only see each other. Their beacons are also flagged as test traffic, which souls on
+//! the production network ignore even if somebody has given both networks the same company ID.
+//!
+//! The beacon is an extended advertisement, which firmware from before the field list can't hear
+//! and wouldn't understand. For those souls we also send a legacy copy holding only the name and
+//! the bare colour, see [encode_legacy_advertisement]. Souls that hear the real beacon skip it.
+
+use crate::advert::{
+    APPEARANCE_AD_TYPE, AdvertBuilder, AdvertOverflow, COMPLETE_LOCAL_NAME_AD_TYPE, LEGACY_ADVERTISEMENT_SIZE,
+    MANUFACTURER_DATA_AD_TYPE, TX_POWER_AD_TYPE,
+};
+use crate::command::{COMMAND_SIZE, Command};
+use crate::crew::Report;
+use crate::fields::{BeaconFields, MAX_FIELDS_SIZE, Role};
+use crate::leader::Lead;
+use crate::log;
+use crate::path_loss::path_loss;
+use crate::tracker::addr_to_key;
+use core::str::FromStr;
+use defmt::Format;
+use embassy_time::Instant;
+use heapless::{String, Vec};
+use smart_leds::RGB8;
+use trouble_host::prelude::*;
+
+/// The longest name we keep from a beacon
+pub const MAX_NAME_SIZE: usize = 24;
+
+/// The largest advertisement we send or bother to parse. Our beacon is an extended advertisement,
+/// so it isn't held to the 31 bytes of a legacy one and has room for the longest name we keep, the
+/// flags, every field there is and the transmitter power.
+pub const MAX_ADVERTISEMENT_SIZE: usize = (2 + MAX_NAME_SIZE) + 3 + (4 + MAX_FIELDS_SIZE) + 3;
+
+/// The appearance of a generic power device, as in our GAP service. Only the legacy copy of our
+/// beacon advertises it, which is how newer souls tell the copy from a beacon sent by old firmware.
+pub const APPEARANCE: u16 = 0x0780;
+
+/// The network a soul is on. Test units at the bench only see each other, so developers don't
+/// set off arrival animations on every star in the field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Network {
+    /// The company ID our beacons are tagged with
+    pub company_id: u16,
+    /// True on the test network
+    pub test: bool,
+}
+
+impl Network {
+    /// The network everybody is on at an event
+    pub const fn production(company_id: u16) -> Self {
+        Self {
+            company_id,
+            test: false,
+        }
+    }
+
+    /// The network development units use
+    pub const fn test(company_id: u16) -> Self {
+        Self { company_id, test: true }
+    }
+
+    /// True if a beacon with these fields came from a soul on this network
+    pub fn accepts(&self, fields: &BeaconFields) -> bool {
+        fields.test == self.test
+    }
+}
+
+/// The radio PHY an advertisement reached us on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Format)]
+pub enum Phy {
+    /// The original 1 Mbit/s PHY, which every legacy advertisement uses
+    #[default]
+    Le1M,
+    /// The 2 Mbit/s PHY, faster but shorter range
+    Le2M,
+    /// The coded PHY, slower but with the longest range
+    LeCoded,
+}
+
+/// How an advertisement reached us. The controller doesn't tell us which of the three primary
+/// advertising channels it heard it on, so the PHY and the kind of advertisement are all we get.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Format)]
+pub struct Reception {
+    /// The PHY the advertisement arrived on. For an extended advertisement this is its primary
+    /// PHY, where it was first heard.
+    pub phy: Phy,
+    /// It was an extended advertisement rather than a legacy one
+    pub extended: bool,
+}
+
+/// A message containing presence information from a detected nearby device
+#[allow(unused)]
+#[derive(Clone, Debug)]
+pub struct PresenceMessage {
+    /// Received Signal Strength Indicator in dBm, indicating signal strength
+    pub rssi: i8,
+    /// Transmitter power so we can calculate the loss
+    pub tx_power: i8,
+    /// MAC address as advertised by the sender
+    pub address: BdAddr,
+    /// The time at which we received the last advertisement from this soul
+    pub last_seen: Instant,
+    /// The name advertised in the beacon
+    pub name: String<MAX_NAME_SIZE>,
+    /// The preferred colour, emergency flag and whatever else the sender's firmware sends
+    pub fields: BeaconFields,
+    /// The PHY and kind of advertisement the beacon arrived on
+    pub reception: Reception,
+}
+
+impl PresenceMessage {
+    /// The compact form of this message for the display task. Any lead, command or crew report is
+    /// left out until it has been checked against the group key, and the constellation pixel until
+    /// we know it is a friend.
+    pub fn sighting(&self) -> Sighting {
+        Sighting {
+            key: addr_to_key(&self.address),
+            colour: self.fields.colour,
+            rssi: self.rssi,
+            tx_power: self.tx_power,
+            last_seen: self.last_seen,
+            favourite: false,
+            emergency: self.fields.emergency,
+            wave: self.fields.wave,
+            role: self.fields.role,
+            lead: None,
+            command: None,
+            report: None,
+            reception: self.reception,
+            pixel: None,
+        }
+    }
+}
+
+/// What the display task needs to know about a received beacon. It leaves out the name and
+/// address and is small enough to copy, so that every advertisement we receive doesn't drag
+/// a name through the display channel and into the tracker.
+#[derive(Clone, Copy, Debug)]
+pub struct Sighting {
+    /// Tracker key of the sender, see [addr_to_key]
+    pub key: u32,
+    /// The configured RGB colour preferred by the sender
+    pub colour: RGB8,
+    /// Received Signal Strength Indicator in dBm
+    pub rssi: i8,
+    /// Transmitter power so we can calculate the loss
+    pub tx_power: i8,
+    /// The time at which we received the advertisement
+    pub last_seen: Instant,
+    /// The sender is one of our favourite souls
+    pub favourite: bool,
+    /// The sender is calling for help
+    pub emergency: bool,
+    /// The sender is waving at the souls around it
+    pub wave: bool,
+    /// What the sender does at the event
+    pub role: Role,
+    /// What the sender wants us to show, if it is leading
+    pub lead: Option<Lead>,
+    /// A command from a controller in our group, once its tag has been checked
+    pub command: Option<Command>,
+    /// What a member of our crew can see of the soul we are hunting, see [crew](crate::crew)
+    pub report: Option<Report>,
+    /// The PHY and kind of advertisement the beacon arrived on
+    pub reception: Reception,
+    /// The sender's pixel in constellation mode if it is a friend, see
+    /// [constellation](crate::constellation)
+    pub pixel: Option<u16>,
+}
+
+impl Sighting {
+    /// The path loss to the sender in dB, mapped onto the nominal path-loss model so it can be
+    /// compared with the thresholds in the configuration
+    pub fn tx_loss(&self) -> i32 {
+        path_loss().normalise(self.raw_loss())
+    }
+
+    /// The path loss to the sender in dB as we measured it
+    pub fn raw_loss(&self) -> i32 {
+        self.tx_power as i32 - self.rssi as i32
+    }
+}
+
+/// Encode our beacon, or report the field that didn't fit.
+///
+/// # Parameters
+/// * `name` - The name to advertise
+/// * `fields` - Our colour and the rest of the fields for the manufacturer data
+/// * `tx_power` - The transmitter power we advertise with
+/// * `network` - The network we are on
+pub fn encode_advertisement(
+    name: &str,
+    fields: &BeaconFields,
+    tx_power: TxPower,
+    network: Network,
+) -> Result<Vec<u8, MAX_ADVERTISEMENT_SIZE>, AdvertOverflow> {
+    let fields = BeaconFields {
+        test: network.test,
+        ..*fields
+    };
+    AdvertBuilder::<MAX_ADVERTISEMENT_SIZE>::extended()
+        .name(name)
+        .flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED)
+        .manufacturer(network.company_id, &fields.encode())
+        // Transmitter power advertised as part of the beacon.
+        .tx_power(tx_power as i8)
+        .build()
+}
+
+// A beacon with nothing left to shed, a command and the longest name we keep must fit, or a
+// controller's command could be cut short by its own name
+const _: () =
+    assert!((2 + MAX_NAME_SIZE) + 3 + 4 + (2 + 3) + (2 + 1) + (2 + COMMAND_SIZE) + 3 <= MAX_ADVERTISEMENT_SIZE);
+
+/// Encode our beacon so that it always fits. Should it ever be too big, the least important
+/// fields are left out one at a time until it does, see [BeaconFields::shed], and if that isn't
+/// enough the name is cut down to what receivers keep of it anyway.
+///
+/// # Parameters
+/// * `name` - The name to advertise
+/// * `fields` - Our colour and the rest of the fields for the manufacturer data
+/// * `tx_power` - The transmitter power we advertise with
+/// * `network` - The network we are on
+pub fn encode_beacon(
+    name: &str,
+    fields: &BeaconFields,
+    tx_power: TxPower,
+    network: Network,
+) -> Vec<u8, MAX_ADVERTISEMENT_SIZE> {
+    let mut fields = *fields;
+    loop {
+        let overflow = match encode_advertisement(name, &fields, tx_power, network) {
+            Ok(data) => return data,
+            Err(overflow) => overflow,
+        };
+        if !fields.shed() {
+            log!(Scanner, warn, "SCANNER: Our name doesn't fit in our beacon: {:?}", overflow);
+            // Everything else fits alongside the longest name we keep, see MAX_ADVERTISEMENT_SIZE
+            return encode_advertisement(&truncate_name(name), &fields, tx_power, network).unwrap_or_default();
+        }
+        log!(Scanner, warn, "SCANNER: Leaving a field out of our beacon: {:?}", overflow);
+    }
+}
+
+/// Encode the legacy copy of our beacon. Firmware from before the field list only takes a
+/// manufacturer payload of exactly the colour, so that is all the copy says. The name is left out
+/// if it doesn't fit.
+///
+/// # Parameters
+/// * `name` - The name to advertise
+/// * `colour` - Our colour
+/// * `tx_power` - The transmitter power we advertise with
+/// * `network` - The network we are on
+pub fn encode_legacy_advertisement(
+    name: &str,
+    colour: RGB8,
+    tx_power: TxPower,
+    network: Network,
+) -> Vec<u8, LEGACY_ADVERTISEMENT_SIZE> {
+    let copy = || {
+        AdvertBuilder::new()
+            .flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED)
+            .manufacturer(network.company_id, &[colour.r, colour.g, colour.b])
+            .tx_power(tx_power as i8)
+            .appearance(APPEARANCE)
+    };
+    // Without the name it is 17 bytes, so that always fits
+    copy()
+        .name(name)
+        .build()
+        .or_else(|_| copy().build())
+        .unwrap_or_default()
+}
+
+/// Split advertisement data into its (type, data) structures. This never panics, whatever it is
+/// fed: it stops at the first structure that claims to be longer than the data that is left,
+/// and at a zero length, which marks the end of the significant data.
+///
+/// # Parameters
+/// * `data` - The raw advertisement data
+fn ad_structures(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = data;
+    core::iter::from_fn(move || {
+        let (&len, tail) = rest.split_first()?;
+        if len == 0 || len as usize > tail.len() {
+            return None;
+        }
+        let (structure, next) = tail.split_at(len as usize);
+        rest = next;
+        Some((structure[0], &structure[1..]))
+    })
+}
+
+/// A cheap first look at an advertisement: could it be one of our beacons? It only checks for
+/// manufacturer data with our network's company ID, without copying or decoding anything, so
+/// the scan handler can throw away the phones, earbuds and watches in a busy venue before they
+/// cost a queue slot and a full [decode_advertisement]. Anything it lets through still has to
+/// decode properly to count.
+///
+/// # Parameters
+/// * `data` - The raw advertisement data from the scan report
+/// * `network` - The network we are on
+pub fn might_be_ours(data: &[u8], network: Network) -> bool {
+    let id = network.company_id.to_le_bytes();
+    ad_structures(data).any(|(ty, d)| ty == MANUFACTURER_DATA_AD_TYPE && d.starts_with(&id))
+}
+
+/// The longest prefix of the name that fits in a presence message without splitting a character
+fn truncate_name(name: &str) -> String<MAX_NAME_SIZE> {
+    let mut end = name.len().min(MAX_NAME_SIZE);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    String::from_str(&name[..end]).unwrap_or_default()
+}
+
+/// Decode a received advertisement into a presence message. Returns None if this is not one
+/// of our beacons, which we recognise by the company ID of our network, if it comes from a
+/// soul on the other side of the test network divide or if it is only the legacy copy of a beacon.
+///
+/// Advertisements come from anybody, so this must cope with whatever arrives. Structures we
+/// can't make sense of are skipped and a truncated advertisement is parsed up to the point
+/// where it breaks off.
+///
+/// # Parameters
+/// * `data` - The raw advertisement data from the scan report
+/// * `rssi` - The signal strength the advertisement was received with
+/// * `address` - The address of the sender
+/// * `reception` - How the advertisement reached us
+/// * `network` - The network we are on
+pub fn decode_advertisement(
+    data: &[u8],
+    rssi: i8,
+    address: BdAddr,
+    reception: Reception,
+    network: Network,
+) -> Option<PresenceMessage> {
+    let mut name = None;
+    let mut fields = None;
+    let mut tx_power = 0; // Default to 0dBm if we don't get tx_power in our transmission
+    for (ty, d) in ad_structures(data) {
+        match ty {
+            // The legacy copy of a beacon we hear in full on the coded PHY
+            APPEARANCE_AD_TYPE if !reception.extended && d == APPEARANCE.to_le_bytes() => return None,
+            COMPLETE_LOCAL_NAME_AD_TYPE => name = str::from_utf8(d).ok(),
+            MANUFACTURER_DATA_AD_TYPE => {
+                // Two bytes of little endian company identifier followed by our fields
+                if let [lo, hi, ref data @ ..] = *d
+                    && u16::from_le_bytes([lo, hi]) == network.company_id
+                {
+                    fields = BeaconFields::decode(data);
+                }
+            }
+            TX_POWER_AD_TYPE => {
+                if let Some(&p) = d.first() {
+                    tx_power = p as i8;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // We filter here for our beacons only and simply drop any others we don't
+    // recognise. We use our manufacturing code to do this.
+    fields.filter(|f| network.accepts(f)).map(|fields| PresenceMessage {
+        rssi,
+        tx_power,
+        address,
+        last_seen: Instant::now(),
+        name: truncate_name(name.unwrap_or("<Unknown>")),
+        fields,
+        reception,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::{COMPANY_ID, TEST_COMPANY_ID};
+    use crate::fields::{EMERGENCY_FLAG, FIRMWARE_VERSION};
+
+    const PRODUCTION: Network = Network::production(COMPANY_ID);
+
+    const LEGACY: Reception = Reception {
+        phy: Phy::Le1M,
+        extended: false,
+    };
+
+    fn fields(emergency: bool) -> BeaconFields {
+        BeaconFields {
+            colour: RGB8::new(1, 2, 3),
+            emergency,
+            firmware: Some(FIRMWARE_VERSION),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn if_it_round_trips() {
+        let data = encode_advertisement("Dr Who", &fields(false), TxPower::Plus8dBm, PRODUCTION).unwrap();
+        let p = decode_advertisement(&data, -60, BdAddr::new([1, 2, 3, 4, 5, 6]), LEGACY, PRODUCTION).unwrap();
+        assert_eq!(p.name.as_str(), "Dr Who");
+        assert_eq!(p.fields, fields(false));
+        assert_eq!(p.tx_power, 8);
+        assert_eq!(p.rssi, -60);
+        assert_eq!(p.reception, LEGACY);
+        let data = encode_advertisement("Dr Who", &fields(true), TxPower::Plus8dBm, PRODUCTION).unwrap();
+        let p = decode_advertisement(&data, -60, BdAddr::new([1, 2, 3, 4, 5, 6]), LEGACY, PRODUCTION).unwrap();
+        assert_eq!(p.fields.colour, RGB8::new(1, 2, 3));
+        assert!(p.fields.emergency);
+        // How it reached us is passed through to the display task
+        let coded = Reception {
+            phy: Phy::LeCoded,
+            extended: true,
+        };
+        let p = decode_advertisement(&data, -60, BdAddr::new([1, 2, 3, 4, 5, 6]), coded, PRODUCTION).unwrap();
+        assert_eq!(p.sighting().reception, coded);
+    }
+
+    #[test]
+    pub fn if_it_hears_old_beacons() {
+        let data = AdvertBuilder::new()
+            .name("Dr Who")
+            .manufacturer(COMPANY_ID, &[1, 2, 3, EMERGENCY_FLAG])
+            .build()
+            .unwrap();
+        let p = decode_advertisement(&data, -60, BdAddr::new([1, 2, 3, 4, 5, 6]), LEGACY, PRODUCTION).unwrap();
+        assert_eq!(p.fields.colour, RGB8::new(1, 2, 3));
+        assert!(p.fields.emergency);
+        assert_eq!(p.fields.firmware, None);
+    }
+
+    #[test]
+    pub fn if_old_firmware_can_read_the_legacy_copy() {
+        let address = BdAddr::new([1, 2, 3, 4, 5, 6]);
+        let data = encode_legacy_advertisement("Dr Nefario", RGB8::new(1, 2, 3), TxPower::Plus8dBm, PRODUCTION);
+        // Old firmware only takes the bare colour after the company ID
+        let id = COMPANY_ID.to_le_bytes();
+        assert!(ad_structures(&data).any(|s| s == (MANUFACTURER_DATA_AD_TYPE, &[id[0], id[1], 1, 2, 3][..])));
+        assert!(ad_structures(&data).any(|s| s == (COMPLETE_LOCAL_NAME_AD_TYPE, &b"Dr Nefario"[..])));
+        // Newer souls skip it, as they hear the real beacon on the coded PHY
+        assert!(might_be_ours(&data, PRODUCTION));
+        assert!(decode_advertisement(&data, -60, address, LEGACY, PRODUCTION).is_none());
+        // A name that doesn't fit is left out rather than the colour
+        let data = encode_legacy_advertisement(
+            "Dr Nefario and all his minions",
+            RGB8::new(1, 2, 3),
+            TxPower::Plus8dBm,
+            PRODUCTION,
+        );
+        assert!(ad_structures(&data).any(|(ty, _)| ty == MANUFACTURER_DATA_AD_TYPE));
+        assert!(!ad_structures(&data).any(|(ty, _)| ty == COMPLETE_LOCAL_NAME_AD_TYPE));
+    }
+
+    #[test]
+    pub fn if_it_ignores_strangers() {
+        let data = AdvertBuilder::new().manufacturer(0x004C, &[1, 2, 3]).build().unwrap();
+        assert!(decode_advertisement(&data, -60, BdAddr::new([1, 2, 3, 4, 5, 6]), LEGACY, PRODUCTION).is_none());
+        assert!(!might_be_ours(&data, PRODUCTION));
+    }
+
+    #[test]
+    pub fn if_it_spots_our_beacons_early() {
+        let data = encode_advertisement("Dr Who", &fields(false), TxPower::Plus8dBm, PRODUCTION).unwrap();
+        assert!(might_be_ours(&data, PRODUCTION));
+        assert!(!might_be_ours(&data, Network::test(TEST_COMPANY_ID)));
+        // A name alone is not enough
+        let data = AdvertBuilder::new().name("Dr Who").build().unwrap();
+        assert!(!might_be_ours(&data, PRODUCTION));
+        assert!(!might_be_ours(&[], PRODUCTION));
+    }
+
+    #[test]
+    pub fn if_networks_keep_apart() {
+        let address = BdAddr::new([1, 2, 3, 4, 5, 6]);
+        let test = Network::test(TEST_COMPANY_ID);
+        let data = encode_advertisement("Dr Who", &fields(false), TxPower::Plus8dBm, test).unwrap();
+        assert!(decode_advertisement(&data, -60, address, LEGACY, PRODUCTION).is_none());
+        let p = decode_advertisement(&data, -60, address, LEGACY, test).unwrap();
+        assert!(p.fields.test);
+        // Test traffic stays out even when both networks share a company ID
+        let data =
+            encode_advertisement("Dr Who", &fields(false), TxPower::Plus8dBm, Network::test(COMPANY_ID)).unwrap();
+        assert!(decode_advertisement(&data, -60, address, LEGACY, PRODUCTION).is_none());
+        let data = encode_advertisement("Dr Who", &fields(false), TxPower::Plus8dBm, PRODUCTION).unwrap();
+        assert!(decode_advertisement(&data, -60, address, LEGACY, Network::test(COMPANY_ID)).is_none());
+    }
+
+    #[test]
+    pub fn if_a_beacon_that_is_too_big_sheds_fields() {
+        let address = BdAddr::new([1, 2, 3, 4, 5, 6]);
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        for _ in 0..1_000 {
+            let fields = BeaconFields::arbitrary(&mut rng);
+            // A name far longer than anybody would give a star leaves little room for the fields
+            let name: String<128> = (0..rng.usize(..=128)).map(|_| rng.alphanumeric()).collect();
+            let data = encode_beacon(&name, &fields, TxPower::Plus8dBm, PRODUCTION);
+            let p = decode_advertisement(&data, -70, address, LEGACY, PRODUCTION).unwrap();
+            assert!(name.starts_with(p.name.as_str()));
+            assert_eq!(p.fields.colour, fields.colour);
+            assert_eq!(p.fields.emergency, fields.emergency);
+            assert_eq!(p.fields.wave, fields.wave);
+            // A controller's command always goes out
+            assert_eq!(p.fields.command, fields.command);
+        }
+    }
+
+    #[test]
+    pub fn if_it_survives_garbage() {
+        let address = BdAddr::new([1, 2, 3, 4, 5, 6]);
+        // A beacon with a name that is too long, truncated part way through the tx power
+        let data =
+            encode_advertisement("Dr Who and all his many companions", &fields(false), TxPower::Plus8dBm, PRODUCTION)
+                .unwrap();
+        let p = decode_advertisement(&data[..data.len() - 1], -60, address, LEGACY, PRODUCTION).unwrap();
+        assert_eq!(p.name.as_str(), "Dr Who and all his many ");
+        assert_eq!(p.tx_power, 0);
+        // Random byte strings must never panic
+        let mut rng = fastrand::Rng::with_seed(0x5011);
+        for _ in 0..10_000 {
+            let len = rng.usize(0..=MAX_ADVERTISEMENT_SIZE);
+            let mut data = [0u8; MAX_ADVERTISEMENT_SIZE];
+            rng.fill(&mut data[..len]);
+            decode_advertisement(&data[..len], -60, address, LEGACY, PRODUCTION);
+        }
+    }
+
+    #[test]
+    pub fn if_arbitrary_beacons_round_trip() {
+        let mut rng = fastrand::Rng::with_seed(0xBEAC0);
+        for _ in 0..10_000 {
+            let name: String<MAX_NAME_SIZE> = (0..rng.usize(0..=MAX_NAME_SIZE)).map(|_| rng.alphanumeric()).collect();
+            let fields = BeaconFields::arbitrary(&mut rng);
+            let network = if rng.bool() {
+                PRODUCTION
+            } else {
+                Network::test(TEST_COMPANY_ID)
+            };
+            let mut raw = [0u8; 6];
+            rng.fill(&mut raw);
+            let address = BdAddr::new(raw);
+            // Even a beacon with everything in it and the longest name fits
+            let data = encode_advertisement(&name, &fields, TxPower::Plus8dBm, network).unwrap();
+            assert!(might_be_ours(&data, network));
+            let p = decode_advertisement(&data, -70, address, LEGACY, network).unwrap();
+            assert_eq!(p.name, name);
+            assert_eq!(
+                p.fields,
+                BeaconFields {
+                    test: network.test,
+                    ..fields
+                }
+            );
+            assert_eq!(p.tx_power, 8);
+            assert_eq!(p.sighting().key, addr_to_key(&address));
+            // Any part of a beacon is safe to parse
+            let len = rng.usize(..data.len());
+            decode_advertisement(&data[..len], -70, address, LEGACY, network);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! LED power limiting and gating.
+//!
+//! Every channel of every LED draws up to [LED_CHANNEL_CURRENT] at full drive, so a full white
+//! strip can pull more than the battery or its wiring is happy to supply. Frames are scaled down
+//! just before they are sent to the strip so the estimated current never goes over
+//! [MAX_LED_CURRENT], whatever the brightness or animation.
+//!
+//! At the other end of the scale, a WS2812 draws about 1mA even when it is dark, which adds up
+//! over a night on standby. Builds that can switch the strip's power off do so once it has been
+//! dark for [LED_POWER_IDLE] milliseconds, see [PowerGate].
+
+use crate::colour::{LedBuffer, set_brightness};
+use crate::configuration::{LED_CHANNEL_CURRENT, LED_POWER_IDLE, MAX_LED_CURRENT};
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// Estimated current drawn by the strip for the frame in milliamps. The frame must already have
+/// gamma and brightness applied, i.e. it holds what is actually sent to the LEDs.
+pub fn led_current(buffer: &LedBuffer) -> u32 {
+    let drive: u32 = buffer.iter().map(|p| p.r as u32 + p.g as u32 + p.b as u32).sum();
+    drive * LED_CHANNEL_CURRENT / 255
+}
+
+/// Scale the frame down if it would draw more than [MAX_LED_CURRENT]
+///
+/// # Parameters
+/// * `buffer` - The frame, as it will be sent to the LEDs
+///
+/// # Returns
+/// True if the frame had to be dimmed
+pub fn limit_current(buffer: &mut LedBuffer) -> bool {
+    let current = led_current(buffer);
+    if current <= MAX_LED_CURRENT {
+        return false;
+    }
+    let scale = (MAX_LED_CURRENT * 255 / current) as u8;
+    buffer.iter_mut().for_each(|p| *p = set_brightness(scale, *p));
+    true
+}
+
+/// Decides when the strip can do without power. The first dark frames are still sent so that
+/// the strip is blanked, and the power comes back with the first frame that lights anything.
+#[derive(Default)]
+pub struct PowerGate {
+    /// When the strip went dark, None while it is lit
+    dark_since: Option<Instant>,
+}
+
+impl PowerGate {
+    pub const fn new() -> Self {
+        Self { dark_since: None }
+    }
+
+    /// Look at the next frame
+    ///
+    /// # Parameters
+    /// * `buffer` - The frame, as it will be sent to the LEDs
+    /// * `now` - The time of the frame
+    ///
+    /// # Returns
+    /// True if the strip needs power for this frame
+    pub fn update(&mut self, buffer: &LedBuffer, now: Instant) -> bool {
+        if buffer.iter().any(|p| *p != RGB8::default()) {
+            self.dark_since = None;
+            return true;
+        }
+        let since = *self.dark_since.get_or_insert(now);
+        now.saturating_duration_since(since) < Duration::from_millis(LED_POWER_IDLE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_keeps_the_current_safe() {
+        let mut dim = LedBuffer::default();
+        dim.fill(RGB8::new(10, 10, 10));
+        assert!(!limit_current(&mut dim));
+        assert_eq!(dim[0], RGB8::new(10, 10, 10));
+        let mut white = LedBuffer::default();
+        white.fill(RGB8::new(255, 255, 255));
+        assert!(limit_current(&mut white));
+        assert!(led_current(&white) <= MAX_LED_CURRENT);
+        assert!(led_current(&white) > MAX_LED_CURRENT * 9 / 10);
+    }
+
+    #[test]
+    pub fn if_a_dark_strip_loses_power() {
+        let mut gate = PowerGate::new();
+        let dark = LedBuffer::default();
+        let mut lit = LedBuffer::default();
+        lit[3] = RGB8::new(0, 1, 0);
+        let at = |ms| Instant::from_millis(ms);
+        assert!(gate.update(&lit, at(0)));
+        assert!(gate.update(&dark, at(100)));
+        assert!(gate.update(&dark, at(99 + LED_POWER_IDLE)));
+        assert!(!gate.update(&dark, at(100 + LED_POWER_IDLE)));
+        assert!(gate.update(&lit, at(200 + LED_POWER_IDLE)));
+        assert!(gate.update(&dark, at(300 + LED_POWER_IDLE)));
+    }
+}
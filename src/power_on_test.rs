@@ -0,0 +1,90 @@
+//! Power-on self-test. See [self_test](soulstar::self_test) for what is checked and how the
+//! result is reported. It runs before any of the other tasks start, so it has the LEDs, the
+//! radio and the ADC to itself.
+
+use crate::led_driver::LedDriver;
+use crate::sensors::battery_millivolts;
+use defmt::Debug2Format;
+use embassy_time::{Duration, Timer};
+use esp_hal::analog::adc::{Adc, AdcCalLine, AdcConfig, AdcPin, Attenuation};
+use esp_hal::peripherals::{ADC1, BT, GPIO0};
+use esp_radio::ble::controller::BleConnector;
+use soulstar::configuration::BATTERY_ENABLED;
+use soulstar::log;
+use soulstar::self_test::{SelfTestReport, channel_test_frame};
+
+/// How long each frame of the channel test is shown (milliseconds)
+const FRAME_TIME: u64 = 60;
+
+/// How long each blink of the blink code lasts, and the gap between them (milliseconds)
+const BLINK_TIME: u64 = 300;
+
+/// The blink code is repeated this many times so it can't be missed
+const BLINK_REPEATS: u8 = 3;
+
+/// Run the self-test and show the result
+///
+/// # Parameters
+/// * `led` - The LED strip
+/// * `radio` - The initialised radio, None if it wouldn't start
+/// * `bt` - The bluetooth peripheral
+/// * `adc` - The ADC the battery is connected to
+/// * `battery` - The pin the battery divider is connected to
+pub async fn power_on_test(
+    led: &mut LedDriver<'_>,
+    radio: Option<&esp_radio::Controller<'_>>,
+    bt: BT<'_>,
+    adc: ADC1<'_>,
+    battery: GPIO0<'_>,
+) -> SelfTestReport {
+    log!(Main, info, "SELF_TEST: Running the power-on self-test");
+    let mut rmt_ok = true;
+    let mut frame = 0;
+    while let Some(mut buffer) = channel_test_frame(frame) {
+        if let Err(e) = led.try_update_from_buffer(&mut buffer, 255).await {
+            log!(Main, error, "SELF_TEST: Could not send frame {}: {}", frame, e);
+            rmt_ok = false;
+        }
+        Timer::after(Duration::from_millis(FRAME_TIME)).await;
+        frame += 1;
+    }
+    let ble_ok = match radio.map(|radio| BleConnector::new(radio, bt, Default::default())) {
+        Some(Ok(_)) => true,
+        None => {
+            log!(Main, error, "SELF_TEST: The radio didn't start");
+            false
+        }
+        Some(Err(e)) => {
+            log!(Main, error, "SELF_TEST: Could not bring up the BLE controller: {:?}", Debug2Format(&e));
+            false
+        }
+    };
+    let battery = if BATTERY_ENABLED {
+        let mut config = AdcConfig::new();
+        let mut pin: AdcPin<_, _, AdcCalLine<_>> = config.enable_pin_with_cal(battery, Attenuation::_11dB);
+        let mut adc = Adc::new(adc, config).into_async();
+        Some(battery_millivolts(&mut adc, &mut pin).await)
+    } else {
+        None
+    };
+    let report = SelfTestReport {
+        rmt_ok,
+        ble_ok,
+        battery,
+    };
+    match report.fault() {
+        None => log!(Main, info, "SELF_TEST: Passed: {}", report),
+        Some(fault) => log!(Main, error, "SELF_TEST: Failed with {}: {}", fault, report),
+    }
+    let (colour, blinks) = report.blink_code();
+    for _ in 0..BLINK_REPEATS {
+        for _ in 0..blinks {
+            led.torch(colour, 64).await;
+            Timer::after(Duration::from_millis(BLINK_TIME)).await;
+            led.all_off().await;
+            Timer::after(Duration::from_millis(BLINK_TIME)).await;
+        }
+        Timer::after(Duration::from_millis(4 * BLINK_TIME)).await;
+    }
+    report
+}
@@ -1,169 +1,696 @@
 //! The presence manager. It will set up the BLE and scan for beacons as well as generate the
 //! advertisements telling others we are in range.
+//!
+//! Our beacon is kept up to date while we advertise. Anything that changes what it says, such as
+//! our colour or mood through [update_beacon] or the battery level through [refresh_beacon],
+//! rewrites the advertisement data in place rather than stopping and restarting advertising, so
+//! there is no gap in which nobody can see us.
 
-use crate::configuration::{COMPANY_ID, TX_POWER};
-use crate::display_task::DisplayChannelSender;
 use crate::display_task::DisplayState::PresenceUpdate;
+use crate::display_task::{DisplayChannelSender, update_status};
 use crate::soul_config;
-use core::str::FromStr;
-use defmt::{Debug2Format, error, info, trace, warn};
-use embassy_futures::join::join3;
-use embassy_time::{Duration, Instant};
+use crate::soul_link::{Server, consider_exchange, exchange, next_exchange, own_profile, serve};
+use core::cell::Cell;
+use core::pin::pin;
+use defmt::Debug2Format;
+use embassy_futures::select::{Either, Either3, select, select3};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use esp_hal::efuse::Efuse;
+use esp_hal::peripherals::BT;
 use esp_radio::ble::controller::BleConnector;
-use heapless::String;
+use heapless::Vec;
 use smart_leds::RGB8;
+use soulstar::advert::LEGACY_ADVERTISEMENT_SIZE;
+use soulstar::backpressure::{DropKind, PendingSightings, record_drop};
+use soulstar::battery::battery_level;
+use soulstar::clock::{observe, shared_now};
+use soulstar::command::{Command, GroupKey, sending};
+use soulstar::configuration::{
+    ADVERTISING_REVIEW_INTERVAL, BLE_RESTART_BACKOFF_MAX, BLE_RESTART_BACKOFF_MIN, BLE_STABLE_TIME,
+    CLOCK_REFRESH_INTERVAL, CONSTELLATION_ENABLED, CREW_FINDER_ENABLED, EXCHANGE_ENABLED, EXCHANGE_RETRY,
+    MAX_SOULS_TRACKED, RAW_REPORT_QUEUE_SIZE, SCAN_MIN_UPDATE_INTERVAL, STARTUP_ATTEMPTS, TX_POWER,
+};
+use soulstar::constellation::{hashed_pixel, mask, stable_id};
+use soulstar::crew::{Report, asked, decode_hunt, encode_hunt, group_id, heard, hunt, our_report};
+use soulstar::crowd::{advertising_interval, crowd_density};
+use soulstar::encounters::PROFILE_SIZE;
+use soulstar::error::SoulStarError;
+use soulstar::fields::BeaconFields;
+use soulstar::leader::{Lead, lead};
+use soulstar::log;
+use soulstar::overrides::colour_override;
+use soulstar::payload::{
+    MAX_ADVERTISEMENT_SIZE, Network, Phy, PresenceMessage, Reception, Sighting, decode_advertisement, encode_beacon,
+    encode_legacy_advertisement, might_be_ours,
+};
+use soulstar::radio::{RadioMode, emergency, emergency_changed, radio_mode, radio_mode_changed};
+use soulstar::simulation::fake_sightings;
+use soulstar::status::Part;
+use soulstar::telemetry::{Counter, increment};
+use soulstar::throttle::UpdateThrottle;
+use soulstar::tracker::addr_to_key;
+use soulstar::wave::{waving, waving_changed};
 use trouble_host::HostResources;
-use trouble_host::prelude::AdStructure::{CompleteLocalName, Flags, ManufacturerSpecificData, Unknown};
 use trouble_host::prelude::*;
 
-/// A message containing presence information from a detected nearby device
-#[allow(unused)]
-#[derive(Clone, Debug)]
-pub struct PresenceMessage {
-    /// Received Signal Strength Indicator in dBm, indicating signal strength
-    pub rssi: i8,
-    /// Transmitter power so we can calculate the loss
-    pub tx_power: i8,
-    /// MAC address as advertised by the sender
-    pub address: BdAddr,
-    /// The time at which we received the last advertisement from this soul
-    pub last_seen: Instant,
-    /// The name advertised in the beacon
-    pub name: String<24>,
-    /// The configured RGB colour preferred by the sender
+/// The parts of our beacon the wearer can change while we advertise
+#[derive(Clone, Copy, Debug)]
+pub struct Identity {
+    /// The colour we ask other souls to show us in
     pub colour: RGB8,
+    /// The wearer's mood, meaning up to the wearer. It is set on the USB command line, see
+    /// `console.rs`, and forgotten on restart.
+    pub mood: Option<u8>,
 }
 
-pub type BleControllerType = ExternalController<BleConnector<'static>, 20>;
+/// What our beacon says about us
+static IDENTITY: Mutex<CriticalSectionRawMutex, Cell<Identity>> = Mutex::new(Cell::new(Identity {
+    colour: RGB8::new(soul_config::COLOUR[0], soul_config::COLOUR[1], soul_config::COLOUR[2]),
+    mood: None,
+}));
 
-/// Kick of a process that will advertise our beacon to the work. You must provide a BLE
-/// controller and a destination channel for the presence messages we receive. It will advertise
-/// its name, our manufacturing code with a custom colour and the transmitter power.
+/// Signalled when the beacon needs rewriting
+static BEACON_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// What our beacon says about us
+pub fn beacon_identity() -> Identity {
+    IDENTITY.lock(|i| i.get())
+}
+
+/// Change what our beacon says about us. The advertisement is rewritten in place straight away.
+pub fn update_beacon(update: impl FnOnce(&mut Identity)) {
+    IDENTITY.lock(|i| {
+        let mut identity = i.get();
+        update(&mut identity);
+        i.set(identity);
+    });
+    BEACON_CHANGED.signal(());
+}
+
+/// Rewrite the advertisement straight away, as something else that goes in it, such as the
+/// battery level, has changed
+pub fn refresh_beacon() {
+    BEACON_CHANGED.signal(());
+}
+
+/// Kick of a process that will advertise our beacon to the work. You must provide the radio.
+/// Received advertisements are queued up for the [report_task] to make sense of. It will advertise
+/// its name, our manufacturing code with a custom colour and the transmitter power. If the
+/// soul-link exchange is enabled, it also serves our profile and connects to souls that come
+/// very close to read theirs (see [crate::soul_link]).
+///
+/// The BLE stack is supervised. If it ever stops, the controller and host are torn down and
+/// set up again from scratch. Restarts back off exponentially from [BLE_RESTART_BACKOFF_MIN] to
+/// [BLE_RESTART_BACKOFF_MAX] seconds so a persistent fault doesn't keep the radio thrashing.
+/// If the controller can't be brought up [STARTUP_ATTEMPTS] times in a row, the status pixel
+/// shows that we are running without BLE until it does come up.
 ///
 /// # Parameters
-/// * `controller` - The BLE controller instance used for managing Bluetooth communications
-/// * `channel` - Static mutable reference to a display channel sender for transmitting presence messages
+/// * `radio` - The initialised radio
+/// * `bt` - The bluetooth peripheral
 /// * `address` - The address to use when advertising. It is normally a random address.
+/// * `network` - The network we advertise on
+/// * `display` - Where animation tuning from connected phones is sent
 #[embassy_executor::task]
 pub async fn start_ble(
-    controller: BleControllerType,
-    channel: &'static mut DisplayChannelSender,
+    radio: &'static esp_radio::Controller<'static>,
+    mut bt: BT<'static>,
     address: &'static Address,
+    network: Network,
+    display: DisplayChannelSender,
 ) {
-    info!("SCANNER: Starting scanner and advertisement task");
-    info!("SCANNER: Using randomised MAC address: {:?}", address);
+    log!(Scanner, info, "SCANNER: Starting scanner and advertisement task");
+    log!(Scanner, info, "SCANNER: Using randomised MAC address: {:?}", address);
+    let mut backoff = BLE_RESTART_BACKOFF_MIN;
+    let mut failures = 0u8;
+    loop {
+        let started = Instant::now();
+        match BleConnector::new(radio, bt.reborrow(), Default::default()) {
+            Ok(transport) => {
+                if failures >= STARTUP_ATTEMPTS {
+                    update_status(|s| s.recover(Part::Ble));
+                }
+                failures = 0;
+                let e = run_ble(ExternalController::<_, 20>::new(transport), address, network, display).await;
+                log!(Scanner, error, "SCANNER: The BLE stack stopped: {}", e);
+            }
+            Err(e) => {
+                increment(Counter::BleErrors);
+                log!(Scanner, error, "SCANNER: Could not create the BLE connector: {:?}", Debug2Format(&e));
+                failures = failures.saturating_add(1);
+                if failures == STARTUP_ATTEMPTS {
+                    log!(Scanner, error, "SCANNER: BLE keeps failing, carrying on with the LEDs only");
+                    update_status(|s| s.degrade(Part::Ble));
+                }
+            }
+        }
+        // A stack that ran for a good while before failing gets a fresh start on the back off
+        if started.elapsed() > Duration::from_secs(BLE_STABLE_TIME) {
+            backoff = BLE_RESTART_BACKOFF_MIN;
+        }
+        log!(Scanner, warn, "SCANNER: Restarting the BLE stack in {}s", backoff);
+        Timer::after(Duration::from_secs(backoff)).await;
+        backoff = (backoff * 2).min(BLE_RESTART_BACKOFF_MAX);
+    }
+}
+
+/// Run the BLE stack until something goes wrong
+///
+/// # Returns
+/// What went wrong
+///
+/// # Parameters
+/// * `controller` - The BLE controller instance used for managing Bluetooth communications
+/// * `address` - The address to use when advertising
+/// * `network` - The network we advertise on
+/// * `display` - Where animation tuning from connected phones is sent
+async fn run_ble<C: Controller>(
+    controller: C,
+    address: &Address,
+    network: Network,
+    display: DisplayChannelSender,
+) -> SoulStarError {
     // Set up the BLE world. This is shamelessly stolen from the TrouBLE examples
     let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
     let stack = trouble_host::new(controller, &mut resources).set_random_address(*address);
-    let mut host = stack.build();
-
-    // This is the data that will be advertised as our beacon.
-    let mut adv_data = [0; 64];
-    let len = AdStructure::encode_slice(
-        &[
-            CompleteLocalName(soul_config::ADVERTISED_NAME.as_bytes()),
-            Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
-            ManufacturerSpecificData {
-                company_identifier: COMPANY_ID,
-                payload: &soul_config::COLOUR,
-            },
-            Unknown {
-                // Transmitter power advertised as part of the beacon.
-                ty: 0x0A,
-                data: &[TX_POWER as u8],
-            },
-        ],
-        &mut adv_data[..],
-    )
-    .expect("SCANNER: Could not encode advertisement data");
-    let params = AdvertisementParameters {
-        interval_min: Duration::from_millis(200),
-        interval_max: Duration::from_millis(500),
-        primary_phy: PhyKind::LeCoded, // Longest range PHY available in BLE.
-        secondary_phy: PhyKind::LeCoded,
-        tx_power: TX_POWER,
-        ..Default::default()
+    let Host {
+        mut central,
+        mut peripheral,
+        mut runner,
+        ..
+    } = stack.build();
+
+    // The GATT server holding our soul-link profile
+    let server = match Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
+        name: soul_config::ADVERTISED_NAME,
+        appearance: &appearance::power_device::GENERIC_POWER_DEVICE,
+    })) {
+        Ok(server) => server,
+        Err(e) => {
+            log!(Scanner, error, "SCANNER: Could not create the GATT server: {:?}", Debug2Format(&e));
+            return SoulStarError::Ble;
+        }
     };
-    let advert = Advertisement::NonconnectableScannableUndirected {
-        adv_data: &adv_data[..len],
-        scan_data: &[],
+    let mut profile = [0u8; PROFILE_SIZE];
+    own_profile().encode(&mut profile);
+    if server.soul_link.profile.set(&server, &profile).is_err() {
+        log!(Scanner, error, "SCANNER: Could not set our soul-link profile");
+    }
+
+    // We restart advertising whenever the crowd changes enough to need a different interval or we
+    // raise or clear the emergency flag, and stop altogether in do-not-disturb mode unless we are
+    // calling for help. Everything else in the beacon is updated in place while we carry on
+    // advertising. Only accept connections if we take part in the soul-link exchange.
+    let advertiser = async {
+        loop {
+            let mode = radio_mode();
+            let sos = emergency();
+            if !mode.advertises() && !sos {
+                log!(Scanner, info, "SCANNER: Not advertising in {} mode", mode);
+                select(radio_mode_changed(mode), emergency_changed(sos)).await;
+                continue;
+            }
+            let adv_data = beacon(address, sos, network);
+            let legacy_data = legacy_beacon(network);
+            let (interval_min, interval_max) = advertising_interval(crowd_density());
+            log!(
+                Scanner,
+                debug,
+                "SCANNER: Advertising every {}-{}ms",
+                interval_min.as_millis(),
+                interval_max.as_millis()
+            );
+            // The coded PHY only carries extended advertisements, the legacy copy goes out on 1M
+            let params = AdvertisementParameters {
+                interval_min,
+                interval_max,
+                primary_phy: PhyKind::LeCoded, // Longest range PHY available in BLE.
+                secondary_phy: PhyKind::LeCoded,
+                tx_power: TX_POWER,
+                ..Default::default()
+            };
+            let sets = advertising_sets(&params, &adv_data, &legacy_data);
+            let mut handles = AdvertisementSet::handles(&sets);
+            let advertiser = match peripheral.advertise_ext(&sets, &mut handles).await {
+                Ok(advertiser) => advertiser,
+                Err(e) => {
+                    increment(Counter::BleErrors);
+                    log!(Scanner, error, "SCANNER: Failed to advertise: {:?}", Debug2Format(&e));
+                    return SoulStarError::Ble;
+                }
+            };
+            if EXCHANGE_ENABLED {
+                let refresh = keep_beacon_fresh(&mut peripheral, &params, &mut handles, address, mode, sos, network);
+                match select(advertiser.accept(), refresh).await {
+                    Either::First(Ok(conn)) => serve(conn, &server, display).await,
+                    Either::First(Err(e)) => {
+                        increment(Counter::BleErrors);
+                        log!(Scanner, warn, "SCANNER: Failed to accept a connection: {:?}", Debug2Format(&e));
+                    }
+                    Either::Second(_) => (),
+                }
+            } else {
+                keep_beacon_fresh(&mut peripheral, &params, &mut handles, address, mode, sos, network).await;
+            }
+        }
     };
-    let advertiser = host.peripheral.advertise(&params, advert);
 
-    // Prepare the scanner and a handler to catch its events.
-    let mut scanner = Scanner::new(host.central);
-    let handler = ScanHandler { channel };
+    let handler = ScanHandler { network };
 
+    // Passive scanning: our beacons have no scan response, so asking every phone in the room for
+    // theirs would only bring in more reports to throw away. We listen on the coded PHY for the
+    // beacons of other stars and on the 1M PHY for anything older.
     let config = ScanConfig {
-        active: true,
+        active: false,
+        phys: PhySet::M1Coded,
         interval: Duration::from_millis(1000),
         window: Duration::from_millis(500),
         ..Default::default()
     };
 
-    // I used a join over the 3 processes that must run to transmit a beacon, scan for other beacons
-    // and host the primary stack runner. This will run until all three tasks are complete which
-    // should never terminate.
+    // I used a select over the 3 processes that must run to transmit a beacon, scan for other beacons
+    // and host the primary stack runner. None of them should ever terminate, so if one does, the
+    // stack is broken and we return so it can be restarted.
     // The trick is to NOT await the scanner and advertiser tasks. They won't return from their
     // await until the host runner has started.
-    let _ = join3(host.runner.run_with_handler(&handler), advertiser, scanner.scan(&config)).await;
-    error!("BLE: Completed advertising, most likely as the result of an error");
+    // The scanner stops while we connect to a soul for a soul-link exchange and then carries on.
+    // It also stops for as long as we are just a beacon.
+    let scanner = async {
+        // The last soul we failed to link with and when, so we don't keep pestering it
+        let mut failed: Option<(Address, Instant)> = None;
+        loop {
+            let mode = radio_mode();
+            if !mode.scans() {
+                log!(Scanner, info, "SCANNER: Not scanning in {} mode", mode);
+                radio_mode_changed(mode).await;
+                continue;
+            }
+            let mut scanner = Scanner::new(central);
+            let peer = match scanner.scan_ext(&config).await {
+                Ok(_session) => match select(next_exchange(), radio_mode_changed(mode)).await {
+                    Either::First(peer) => Some(peer),
+                    Either::Second(_) => None,
+                },
+                Err(e) => {
+                    increment(Counter::BleErrors);
+                    log!(Scanner, error, "SCANNER: Failed to start scanning: {:?}", Debug2Format(&e));
+                    return SoulStarError::Ble;
+                }
+            };
+            central = scanner.into_inner();
+            let Some(peer) = peer else {
+                continue;
+            };
+            let retry_too_soon =
+                failed.is_some_and(|(f, at)| f == peer && at.elapsed() < Duration::from_secs(EXCHANGE_RETRY));
+            if !retry_too_soon && !exchange(&stack, &mut central, peer).await {
+                failed = Some((peer, Instant::now()));
+            }
+        }
+    };
+    match select3(runner.run_with_handler(&handler), advertiser, scanner).await {
+        Either3::First(_) => {
+            increment(Counter::BleErrors);
+            log!(Scanner, error, "BLE: The host runner stopped, most likely as the result of an error");
+            SoulStarError::Ble
+        }
+        Either3::Second(e) | Either3::Third(e) => e,
+    }
+}
+
+/// Wait until the crowd has changed enough that we should advertise at a different interval
+///
+/// # Parameters
+/// * `interval` - The minimum advertising interval we are currently using
+async fn crowd_changed(interval: Duration) {
+    while advertising_interval(crowd_density()).0 == interval {
+        Timer::after(Duration::from_secs(ADVERTISING_REVIEW_INTERVAL)).await;
+    }
+}
+
+/// Keep our advertisement up to date in place until we need to set up advertising again, because
+/// the crowd, the radio mode or the emergency flag has changed. The beacon is refreshed whenever
+/// [update_beacon] or [refresh_beacon] is called, we start or stop waving and every
+/// [CLOCK_REFRESH_INTERVAL] seconds for the shared clock.
+///
+/// # Parameters
+/// * `peripheral` - The peripheral role that is advertising
+/// * `params` - The parameters we are advertising with
+/// * `handles` - The handles of the advertising sets
+/// * `address` - The address we advertise with
+/// * `mode` - The radio mode we are advertising in
+/// * `sos` - The emergency flag we are advertising with
+/// * `network` - The network we advertise on
+async fn keep_beacon_fresh<C: Controller, P: PacketPool>(
+    peripheral: &mut Peripheral<'_, C, P>,
+    params: &AdvertisementParameters,
+    handles: &mut [AdvSet],
+    address: &Address,
+    mode: RadioMode,
+    sos: bool,
+    network: Network,
+) {
+    let mut restart =
+        pin!(select3(crowd_changed(params.interval_min), radio_mode_changed(mode), emergency_changed(sos)));
+    loop {
+        let refresh = select3(
+            BEACON_CHANGED.wait(),
+            waving_changed(waving()),
+            Timer::after(Duration::from_secs(CLOCK_REFRESH_INTERVAL)),
+        );
+        if let Either::First(_) = select(&mut restart, refresh).await {
+            return;
+        }
+        let adv_data = beacon(address, sos, network);
+        let legacy_data = legacy_beacon(network);
+        let sets = advertising_sets(params, &adv_data, &legacy_data);
+        if let Err(e) = peripheral.update_adv_data_ext(&sets, handles).await {
+            // Starting again from scratch picks up the new beacon anyway
+            increment(Counter::BleErrors);
+            log!(Scanner, warn, "SCANNER: Could not update our beacon: {:?}", Debug2Format(&e));
+            return;
+        }
+    }
+}
+
+/// Encode our beacon as it should be right now
+///
+/// # Parameters
+/// * `address` - The address we advertise with
+/// * `sos` - The emergency flag we are advertising with
+/// * `network` - The network we advertise on
+fn beacon(address: &Address, sos: bool, network: Network) -> Vec<u8, MAX_ADVERTISEMENT_SIZE> {
+    let identity = beacon_identity();
+    let fields = BeaconFields {
+        colour: identity.colour,
+        mood: identity.mood,
+        emergency: sos,
+        wave: waving(),
+        role: soul_config::ROLE,
+        battery: battery_level(),
+        clock: Some(shared_now()),
+        lead: soul_config::GROUP_KEY
+            .zip(lead())
+            .map(|(key, lead)| lead.encode(&key, &address.addr)),
+        command: soul_config::GROUP_KEY.and_then(|key| sending().map(|c| c.encode(&key))),
+        group: crew(),
+        stable_id: soul_config::GROUP_KEY
+            .filter(|_| CONSTELLATION_ENABLED)
+            .map(|key| mask(stable_id(&Efuse::mac_address(), &key), &address.addr, &key)),
+        hunt: crew_key()
+            .zip(hunt())
+            .map(|(key, target)| encode_hunt(target, &key, &address.addr)),
+        report: crew_key()
+            .zip(our_report(Instant::now()))
+            .map(|(key, report)| report.encode(&key, &address.addr)),
+        ..Default::default()
+    };
+    encode_beacon(soul_config::ADVERTISED_NAME, &fields, TX_POWER, network)
 }
 
-/// State for our event handler. In this case, we just need to tell it where to send the
-/// presence messages that we infer from the received device advertisements. Note that this
-/// is called from the ble host runner and not from [scanner_task].
+/// Encode the legacy copy of our beacon, for souls running firmware from before the extended one
+///
+/// # Parameters
+/// * `network` - The network we advertise on
+fn legacy_beacon(network: Network) -> Vec<u8, LEGACY_ADVERTISEMENT_SIZE> {
+    encode_legacy_advertisement(soul_config::ADVERTISED_NAME, beacon_identity().colour, TX_POWER, network)
+}
+
+/// Our advertising sets: the beacon as an extended advertisement on the coded PHY and its legacy
+/// copy on the 1M PHY. Both are connectable if we take part in the soul-link exchange.
+///
+/// # Parameters
+/// * `params` - The parameters for the extended beacon. The copy only differs in its PHYs.
+/// * `adv_data` - Our beacon
+/// * `legacy_data` - The legacy copy of our beacon
+fn advertising_sets<'a>(
+    params: &AdvertisementParameters,
+    adv_data: &'a [u8],
+    legacy_data: &'a [u8],
+) -> [AdvertisementSet<'a>; 2] {
+    let legacy_params = AdvertisementParameters {
+        primary_phy: PhyKind::Le1M,
+        secondary_phy: PhyKind::Le1M,
+        ..*params
+    };
+    let (beacon, legacy) = if EXCHANGE_ENABLED {
+        (
+            Advertisement::ExtConnectableNonscannableUndirected { adv_data },
+            Advertisement::ConnectableScannableUndirected {
+                adv_data: legacy_data,
+                scan_data: &[],
+            },
+        )
+    } else {
+        (
+            Advertisement::ExtNonconnectableNonscannableUndirected {
+                anonymous: false,
+                adv_data,
+            },
+            Advertisement::NonconnectableScannableUndirected {
+                adv_data: legacy_data,
+                scan_data: &[],
+            },
+        )
+    };
+    [
+        AdvertisementSet {
+            params: *params,
+            data: beacon,
+        },
+        AdvertisementSet {
+            params: legacy_params,
+            data: legacy,
+        },
+    ]
+}
+
+/// Our crew's group ID, None unless the crew finder is on and we have a group key
+fn crew() -> Option<u16> {
+    crew_key().map(|key| group_id(&key))
+}
+
+/// The group key our crew tags its hunts and reports with, None unless the crew finder is on
+fn crew_key() -> Option<GroupKey> {
+    soul_config::GROUP_KEY.filter(|_| CREW_FINDER_ENABLED)
+}
+
+/// An advertisement report as it came off the air, copied out of the HCI event so that it can
+/// be parsed later by the [report_task]
+struct RawReport {
+    address: Address,
+    rssi: i8,
+    reception: Reception,
+    data: Vec<u8, MAX_ADVERTISEMENT_SIZE>,
+}
+
+/// Advertisement reports waiting to be parsed
+static RAW_REPORTS: Channel<CriticalSectionRawMutex, RawReport, RAW_REPORT_QUEUE_SIZE> = Channel::new();
+
+/// Our event handler. It is called from the ble host runner, so it must be quick. It only copies
+/// the raw reports into a queue and leaves the parsing to the [report_task].
+///
+/// In a busy venue nearly every report is somebody's phone, so reports without our company ID
+/// are dropped here, before they take a queue slot. It would be cheaper still to have the
+/// controller drop them, but its filter accept list only knows addresses and ours rotate, so
+/// this byte match is as close to the controller as we can get.
 struct ScanHandler {
-    channel: &'static DisplayChannelSender,
+    network: Network,
+}
+
+impl ScanHandler {
+    /// Queue a report for the [report_task] if it might be one of our beacons
+    ///
+    /// # Parameters
+    /// * `address` - The address of the sender
+    /// * `rssi` - The signal strength the advertisement was received with
+    /// * `reception` - The PHY and kind of advertisement it arrived on
+    /// * `data` - The raw advertisement data
+    fn queue(&self, address: Address, rssi: i8, reception: Reception, data: &[u8]) {
+        increment(Counter::AdvertsSeen);
+        if !might_be_ours(data, self.network) {
+            increment(Counter::AdvertsIgnored);
+            return;
+        }
+        // Our beacons always fit, so anything bigger can't be one of ours
+        let Ok(data) = Vec::from_slice(data) else {
+            return;
+        };
+        let raw = RawReport {
+            address,
+            rssi,
+            reception,
+            data,
+        };
+        // This is not an async callback, so we cannot await here. Because we get these beacons
+        // regularly, we can just try to send it. If the queue is full, just drop it and let the
+        // peripheral send it again.
+        if RAW_REPORTS.try_send(raw).is_err() {
+            record_drop(DropKind::RawReport);
+        }
+    }
 }
 
 impl EventHandler for ScanHandler {
     fn on_adv_reports(&self, mut it: LeAdvReportsIter) {
         while let Some(Ok(report)) = it.next() {
-            let mut adv_data = AdStructure::decode(report.data);
-            let name = adv_data
-                .find_map(|a| match a.unwrap() {
-                    CompleteLocalName(d) => str::from_utf8(d).ok(),
-                    _ => None,
-                })
-                .unwrap_or("<Unknown>");
-
-            let mdf = adv_data.find_map(|a| match a.unwrap() {
-                ManufacturerSpecificData {
-                    company_identifier: d,
-                    payload,
-                } => Some((d, payload)),
-                _ => None,
-            });
-
-            let tx_power = adv_data
-                .find_map(|a| match a.unwrap() {
-                    Unknown { ty: 0x9A, data } => Some(data[0] as i8),
-                    _ => None,
-                })
-                .unwrap_or(0); // Default to 0dBm if we don't get tx_power in our transmission
-
-            // We filter here for our beacons only and simply drop any others we don't\
-            // recognise. We use our manufacturing code to do this.
-            if let Some((COMPANY_ID, colour)) = mdf
-                && colour.len() == 3
-            {
-                trace!("Advertisement: Advertisement found: {:?} {:?} {:?}", Debug2Format(&name), mdf, &report.addr);
-                let p = PresenceMessage {
-                    rssi: report.rssi,
-                    tx_power,
-                    address: report.addr,
-                    last_seen: Instant::now(),
-                    name: String::from_str(name).unwrap(),
-                    colour: RGB8::new(colour[0], colour[1], colour[2]),
-                };
-                // This is not an async callback, so we cannot await here. Because we get these beacons
-                // regularly, we can just try to send it. If the queue is full, just drop it and let the
-                // peripheral send it again.
-                if self.channel.try_send(PresenceUpdate(p)).is_err() {
-                    warn!("BLE_EVENT: Failed to send message")
+            let address = Address {
+                kind: report.addr_kind,
+                addr: report.addr,
+            };
+            // Legacy advertisements only ever use the 1M PHY
+            self.queue(address, report.rssi, Reception::default(), report.data);
+        }
+    }
+
+    fn on_ext_adv_reports(&self, mut it: LeExtAdvReportsIter) {
+        while let Some(Ok(report)) = it.next() {
+            let address = Address {
+                kind: report.addr_kind,
+                addr: report.addr,
+            };
+            // Scanning for extended advertisements brings in the legacy ones as well
+            let reception = Reception {
+                phy: match report.primary_adv_phy {
+                    PhyKind::Le1M => Phy::Le1M,
+                    PhyKind::Le2M => Phy::Le2M,
+                    _ => Phy::LeCoded,
+                },
+                extended: !report.event_kind.legacy(),
+            };
+            self.queue(address, report.rssi, reception, report.data);
+        }
+    }
+}
+
+/// Parse the advertisement reports queued up by the scan handler and tell the display task about
+/// the souls we find. Reports from each soul are throttled so we don't swamp the display channel.
+/// If the display channel is full anyway, the latest sighting of each soul is held back until
+/// there is room for it.
+///
+/// # Parameters
+/// * `channel` - Display channel sender for transmitting presence messages
+/// * `address` - Our own address, used to decide who connects for a soul-link exchange
+/// * `network` - The network we are on. Beacons from any other are ignored.
+#[embassy_executor::task]
+pub async fn report_task(channel: &'static mut DisplayChannelSender, address: &'static Address, network: Network) {
+    let mut throttle: UpdateThrottle<MAX_SOULS_TRACKED> =
+        UpdateThrottle::new(Duration::from_millis(SCAN_MIN_UPDATE_INTERVAL));
+    let mut pending: PendingSightings<MAX_SOULS_TRACKED> = PendingSightings::new();
+    loop {
+        // Feed any held back sightings to the display task while we wait for the next report
+        let report = match pending.peek() {
+            Some(sighting) => {
+                match select(RAW_REPORTS.receive(), channel.send(PresenceUpdate(sighting).into())).await {
+                    Either::First(report) => report,
+                    Either::Second(_) => {
+                        pending.sent(&sighting);
+                        continue;
+                    }
                 }
-            } // Don't care about else conditions but could log it for posterity.
+            }
+            None => RAW_REPORTS.receive().await,
+        };
+        // We filter here for our beacons only and simply drop any others we don't
+        // recognise. The decoder uses our manufacturing code to do this.
+        let Some(p) = decode_advertisement(&report.data, report.rssi, report.address.addr, report.reception, network)
+        else {
+            continue; // Don't care about else conditions but could log it for posterity.
+        };
+        increment(Counter::BeaconsSeen);
+        if p.reception.extended {
+            increment(Counter::ExtendedBeacons);
+        }
+        if p.reception.phy == Phy::LeCoded {
+            increment(Counter::CodedBeacons);
+        }
+        consider_exchange(address, report.address, &p);
+        // Help our crew find whoever they are hunting, and take their help with our own hunt. Only
+        // hunts and reports tagged by a member of the crew count.
+        let key = addr_to_key(&report.address.addr);
+        let sender = report.address.addr;
+        if let Some(target) = crew_key()
+            .zip(p.fields.hunt)
+            .and_then(|(crew, hunt)| decode_hunt(&hunt, &crew, &sender))
+        {
+            asked(target, Instant::now());
+        }
+        heard(key, p.sighting().tx_loss(), Instant::now());
+        if let Some(clock) = p.fields.clock
+            && observe(clock)
+        {
+            log!(Scanner, debug, "BLE_EVENT: Shared clock moved forward to {}", clock);
+        }
+        // Only pass on a report from each soul every so often
+        if !throttle.allow(key, Instant::now()) {
+            continue;
+        }
+        // Hold it back if the display task is busy, replacing any older sighting of the same soul.
+        // Souls we have given a colour of our own are shown in it rather than the one they chose.
+        let colour = colour_override(p.name.as_str(), soul_config::COLOUR_OVERRIDES).unwrap_or(p.fields.colour);
+        let favourite = soul_config::FAVOURITES.contains(&p.name.as_str());
+        let sighting = Sighting {
+            colour,
+            favourite,
+            pixel: constellation_pixel(&p, favourite),
+            lead: soul_config::GROUP_KEY
+                .zip(p.fields.lead)
+                .and_then(|(key, lead)| Lead::decode(&lead, &key, &sender)),
+            command: soul_config::GROUP_KEY
+                .zip(p.fields.command)
+                .and_then(|(key, c)| Command::decode(&c, &key)),
+            report: crew_key()
+                .zip(p.fields.report)
+                .and_then(|(crew, report)| Report::decode(&report, &crew, &sender)),
+            ..p.sighting()
+        };
+        if pending.contains(sighting.key) || channel.try_send(PresenceUpdate(sighting).into()).is_err() {
+            log!(Scanner, trace, "BLE_EVENT: Display busy, holding back soul {:x}", sighting.key);
+            pending.hold(sighting);
+        }
+    }
+}
+
+/// The pixel a soul has in constellation mode: the one given to it in the configuration, or
+/// one hashed from its stable ID if it is a favourite in our group. Everybody else has none.
+///
+/// # Parameters
+/// * `p` - The soul's beacon
+/// * `favourite` - The soul is one of our favourites
+fn constellation_pixel(p: &PresenceMessage, favourite: bool) -> Option<u16> {
+    if !CONSTELLATION_ENABLED {
+        return None;
+    }
+    soul_config::CONSTELLATION
+        .iter()
+        .find(|(name, _)| *name == p.name.as_str())
+        .map(|(_, pixel)| *pixel)
+        .or_else(|| {
+            let key = soul_config::GROUP_KEY.filter(|_| favourite)?;
+            Some(hashed_pixel(mask(p.fields.stable_id?, &p.address, &key)))
+        })
+}
+
+/// Feed the display task with the scripted fake souls from [simulation](soulstar::simulation), as
+/// often as the [report_task] lets real souls through. They go straight to the display, so the
+/// BLE stack never sees them.
+///
+/// # Parameters
+/// * `channel` - Display channel sender for transmitting presence messages
+#[embassy_executor::task]
+pub async fn simulation_task(channel: DisplayChannelSender) {
+    log!(Scanner, warn, "SIMULATION: Fake souls are enabled");
+    let start = Instant::now();
+    let mut ticker = Ticker::every(Duration::from_millis(SCAN_MIN_UPDATE_INTERVAL));
+    loop {
+        let now = Instant::now();
+        for sighting in fake_sightings(now - start, now) {
+            channel.send(PresenceUpdate(sighting).into()).await;
         }
+        ticker.next().await;
     }
 }
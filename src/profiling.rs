@@ -0,0 +1,149 @@
+//! Render-time profiling.
+//!
+//! Every frame has to be drawn and sent to the strip before the next one is due. The display
+//! task times both halves of every frame, drawing the animation and sending it with the RMT, and
+//! records them here against the animation that was showing. The diagnostics task reports the
+//! mean and worst case for each animation, and frames that took longer than their frame interval
+//! are counted in the [telemetry](crate::telemetry), so a heavy new effect shows up long before
+//! it starts to stutter. Times come from the system timer, in microseconds.
+
+use crate::animations::ANIMATION_COUNT;
+use crate::telemetry::{Counter, increment};
+use core::cell::RefCell;
+use defmt::Format;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Duration;
+use heapless::Vec;
+
+/// The most animations we keep timings for, enough for every one of them
+pub const MAX_PROFILED: usize = ANIMATION_COUNT;
+
+/// How long one half of a frame takes
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Format)]
+pub struct Timing {
+    /// Frames timed
+    pub frames: u32,
+    /// Total time over all the frames (microseconds)
+    total: u64,
+    /// The longest any frame took (microseconds)
+    pub max: u32,
+}
+
+impl Timing {
+    fn record(&mut self, took: Duration) {
+        let micros = took.as_micros().min(u32::MAX as u64) as u32;
+        self.frames = self.frames.saturating_add(1);
+        self.total = self.total.saturating_add(micros as u64);
+        self.max = self.max.max(micros);
+    }
+
+    /// The mean time per frame (microseconds)
+    pub fn mean(&self) -> u32 {
+        self.total.checked_div(self.frames as u64).unwrap_or_default() as u32
+    }
+}
+
+/// How long an animation takes to draw and to send
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct AnimationProfile {
+    /// The animation, see [name](crate::animations::name)
+    pub name: &'static str,
+    /// Drawing the frame
+    pub render: Timing,
+    /// Sending the frame to the strip
+    pub transmit: Timing,
+}
+
+/// The timings for each animation we have seen
+pub struct Profiler<const N: usize> {
+    profiles: Vec<AnimationProfile, N>,
+}
+
+impl<const N: usize> Default for Profiler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Profiler<N> {
+    pub const fn new() -> Self {
+        Self { profiles: Vec::new() }
+    }
+
+    /// Record the timing of a frame
+    ///
+    /// # Parameters
+    /// * `name` - The animation that drew the frame
+    /// * `render` - How long it took to draw
+    /// * `transmit` - How long it took to send to the strip
+    /// * `deadline` - The frame interval the frame had to fit in
+    ///
+    /// # Returns
+    /// True if the frame took longer than its frame interval
+    pub fn record(&mut self, name: &'static str, render: Duration, transmit: Duration, deadline: Duration) -> bool {
+        let profile = match self.profiles.iter().position(|p| p.name == name) {
+            Some(i) => Some(&mut self.profiles[i]),
+            None => {
+                let profile = AnimationProfile {
+                    name,
+                    render: Timing::default(),
+                    transmit: Timing::default(),
+                };
+                self.profiles.push(profile).ok().and_then(|_| self.profiles.last_mut())
+            }
+        };
+        if let Some(profile) = profile {
+            profile.render.record(render);
+            profile.transmit.record(transmit);
+        }
+        render + transmit > deadline
+    }
+
+    /// The timings for each animation, in the order they were first seen
+    pub fn profiles(&self) -> &[AnimationProfile] {
+        &self.profiles
+    }
+}
+
+static PROFILER: Mutex<CriticalSectionRawMutex, RefCell<Profiler<MAX_PROFILED>>> =
+    Mutex::new(RefCell::new(Profiler::new()));
+
+/// Record the timing of a frame, counting it as a [Counter::FrameOverruns] if it took longer
+/// than its frame interval. See [Profiler::record].
+pub fn record_frame(name: &'static str, render: Duration, transmit: Duration, deadline: Duration) {
+    if PROFILER.lock(|p| p.borrow_mut().record(name, render, transmit, deadline)) {
+        increment(Counter::FrameOverruns);
+    }
+}
+
+/// Look at the timings for each animation
+pub fn with_profiles<R>(f: impl FnOnce(&[AnimationProfile]) -> R) -> R {
+    PROFILER.lock(|p| f(p.borrow().profiles()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_times_each_animation() {
+        let mut profiler: Profiler<2> = Profiler::new();
+        let ms = Duration::from_millis;
+        assert!(!profiler.record("Wave", ms(1), ms(2), ms(20)));
+        assert!(!profiler.record("Wave", ms(3), ms(2), ms(20)));
+        assert!(profiler.record("Lava", ms(15), ms(6), ms(20)));
+        // No room for a third, but overruns are still spotted
+        assert!(profiler.record("Ocean", ms(25), ms(2), ms(20)));
+        let [wave, lava] = profiler.profiles() else {
+            panic!("Expected two profiles");
+        };
+        assert_eq!(wave.name, "Wave");
+        assert_eq!(wave.render.frames, 2);
+        assert_eq!(wave.render.mean(), 2000);
+        assert_eq!(wave.render.max, 3000);
+        assert_eq!(wave.transmit.mean(), 2000);
+        assert_eq!(lava.render.max, 15000);
+        assert_eq!(Timing::default().mean(), 0);
+    }
+}
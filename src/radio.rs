@@ -0,0 +1,109 @@
+//! What the radio is up to. Normally we advertise our beacon and scan for everybody else's, but
+//! the wearer can choose to stay hidden while still seeing their crew, or to just be a beacon
+//! and save the power spent on scanning.
+//!
+//! The wearer can also call for help, which sets an emergency flag in our beacon whatever the
+//! mode. It is advertised even in do-not-disturb mode.
+//!
+//! The mode and the flag are set from the buttons and picked up by the BLE task. Only the latest
+//! values matter, so an atomic for each is all we need.
+
+use crate::configuration::RADIO_MODE_POLL_INTERVAL;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use defmt::Format;
+use embassy_time::{Duration, Timer};
+
+/// What the radio does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum RadioMode {
+    /// Advertise and scan
+    #[default]
+    Normal,
+    /// Scan and show the souls around us, but don't announce ourselves
+    DoNotDisturb,
+    /// Advertise but don't scan, so we don't know who is around
+    BeaconOnly,
+}
+
+impl RadioMode {
+    /// True if we should be advertising our beacon
+    pub fn advertises(self) -> bool {
+        self != RadioMode::DoNotDisturb
+    }
+
+    /// True if we should be scanning for other souls
+    pub fn scans(self) -> bool {
+        self != RadioMode::BeaconOnly
+    }
+
+    fn from_u8(mode: u8) -> Self {
+        match mode {
+            1 => RadioMode::DoNotDisturb,
+            2 => RadioMode::BeaconOnly,
+            _ => RadioMode::Normal,
+        }
+    }
+}
+
+/// The current radio mode
+static MODE: AtomicU8 = AtomicU8::new(RadioMode::Normal as u8);
+
+/// Change what the radio does. The BLE task picks it up within [RADIO_MODE_POLL_INTERVAL]ms.
+pub fn set_radio_mode(mode: RadioMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// What the radio should be doing
+pub fn radio_mode() -> RadioMode {
+    RadioMode::from_u8(MODE.load(Ordering::Relaxed))
+}
+
+/// Wait until the radio mode is no longer the one given
+///
+/// # Parameters
+/// * `mode` - The mode the caller is currently running in
+pub async fn radio_mode_changed(mode: RadioMode) {
+    while radio_mode() == mode {
+        Timer::after(Duration::from_millis(RADIO_MODE_POLL_INTERVAL)).await;
+    }
+}
+
+/// True while we are calling for help
+static EMERGENCY: AtomicBool = AtomicBool::new(false);
+
+/// Raise or clear the emergency flag in our beacon. The BLE task picks it up within
+/// [RADIO_MODE_POLL_INTERVAL]ms.
+pub fn set_emergency(on: bool) {
+    EMERGENCY.store(on, Ordering::Relaxed);
+}
+
+/// True if our beacon should carry the emergency flag
+pub fn emergency() -> bool {
+    EMERGENCY.load(Ordering::Relaxed)
+}
+
+/// Wait until the emergency flag is no longer the one given
+///
+/// # Parameters
+/// * `on` - The emergency flag the caller is currently advertising with
+pub async fn emergency_changed(on: bool) {
+    while emergency() == on {
+        Timer::after(Duration::from_millis(RADIO_MODE_POLL_INTERVAL)).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_the_mode_round_trips() {
+        for mode in [RadioMode::Normal, RadioMode::DoNotDisturb, RadioMode::BeaconOnly] {
+            assert_eq!(RadioMode::from_u8(mode as u8), mode);
+        }
+        assert!(RadioMode::Normal.advertises() && RadioMode::Normal.scans());
+        assert!(!RadioMode::DoNotDisturb.advertises() && RadioMode::DoNotDisturb.scans());
+        assert!(RadioMode::BeaconOnly.advertises() && !RadioMode::BeaconOnly.scans());
+    }
+}
@@ -0,0 +1,134 @@
+//! Power-on self-test, for diagnosing the wiring in a finished costume.
+//!
+//! Holding the torch button while the star powers up walks a single lit pixel along the strip in
+//! red, then green, then blue, finishing each colour with the whole strip lit, so a dead pixel,
+//! a dead channel or a broken data line shows up at a glance. The RMT transmission, the BLE
+//! controller and the battery voltage are checked along the way. The result is reported in the
+//! log and as a blink code: one green blink if everything passed, otherwise red blinks counting
+//! out the number of the first [Fault].
+
+use crate::colour::LedBuffer;
+use crate::configuration::LED_STRING_SIZE;
+use core::ops::RangeInclusive;
+use defmt::Format;
+use smart_leds::RGB8;
+
+/// Brightness of each channel in the test pattern. There is no point blinding the tester.
+const TEST_LEVEL: u8 = 128;
+
+/// Battery voltages (millivolts) that could have come from a LiPo cell. Anything else means the
+/// divider is wired wrongly or not at all.
+const BATTERY_PLAUSIBLE: RangeInclusive<u16> = 3000..=4500;
+
+/// Frames in the channel test. Each channel has a frame per pixel and one with the whole strip lit.
+pub const CHANNEL_TEST_FRAMES: usize = 3 * (LED_STRING_SIZE + 1);
+
+/// Something the self-test found wrong. The number is the count of red blinks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum Fault {
+    /// The RMT peripheral failed to send a frame to the strip
+    Rmt = 1,
+    /// The BLE controller could not be brought up
+    Ble = 2,
+    /// The battery voltage makes no sense
+    Battery = 3,
+}
+
+/// What the self-test found
+#[derive(Clone, Copy, Debug, Default, Format)]
+pub struct SelfTestReport {
+    /// Every frame of the channel test was sent
+    pub rmt_ok: bool,
+    /// The BLE controller came up
+    pub ble_ok: bool,
+    /// The battery voltage in millivolts, None if no battery is wired up to measure
+    pub battery: Option<u16>,
+}
+
+impl SelfTestReport {
+    /// The first thing that is wrong, None if everything passed
+    pub fn fault(&self) -> Option<Fault> {
+        if !self.rmt_ok {
+            Some(Fault::Rmt)
+        } else if !self.ble_ok {
+            Some(Fault::Ble)
+        } else if self.battery.is_some_and(|mv| !BATTERY_PLAUSIBLE.contains(&mv)) {
+            Some(Fault::Battery)
+        } else {
+            None
+        }
+    }
+
+    /// The colour and number of blinks that report the result
+    pub fn blink_code(&self) -> (RGB8, u8) {
+        match self.fault() {
+            None => (RGB8::new(0, 255, 0), 1),
+            Some(fault) => (RGB8::new(255, 0, 0), fault as u8),
+        }
+    }
+}
+
+/// A frame of the channel test
+///
+/// # Parameters
+/// * `frame` - The frame number, up to [CHANNEL_TEST_FRAMES]
+///
+/// # Returns
+/// The frame, None once the test is over
+pub fn channel_test_frame(frame: usize) -> Option<LedBuffer> {
+    if frame >= CHANNEL_TEST_FRAMES {
+        return None;
+    }
+    let colour = match frame / (LED_STRING_SIZE + 1) {
+        0 => RGB8::new(TEST_LEVEL, 0, 0),
+        1 => RGB8::new(0, TEST_LEVEL, 0),
+        _ => RGB8::new(0, 0, TEST_LEVEL),
+    };
+    let mut buffer = LedBuffer::default();
+    match buffer.get_mut(frame % (LED_STRING_SIZE + 1)) {
+        Some(pixel) => *pixel = colour,
+        None => buffer.fill(colour),
+    }
+    Some(buffer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_every_pixel_gets_every_channel() {
+        let mut lit = [[0u8; 3]; LED_STRING_SIZE];
+        for frame in 0..CHANNEL_TEST_FRAMES {
+            let buffer = channel_test_frame(frame).unwrap();
+            let count = buffer.iter().filter(|p| **p != RGB8::default()).count();
+            assert!(count == 1 || count == LED_STRING_SIZE);
+            for (i, p) in buffer.iter().enumerate() {
+                lit[i][0] += (p.r > 0) as u8;
+                lit[i][1] += (p.g > 0) as u8;
+                lit[i][2] += (p.b > 0) as u8;
+            }
+        }
+        assert!(lit.iter().flatten().all(|&n| n == 2));
+        assert!(channel_test_frame(CHANNEL_TEST_FRAMES).is_none());
+    }
+
+    #[test]
+    pub fn if_it_blinks_the_first_fault() {
+        let mut report = SelfTestReport {
+            rmt_ok: true,
+            ble_ok: true,
+            battery: None,
+        };
+        assert_eq!(report.blink_code(), (RGB8::new(0, 255, 0), 1));
+        report.battery = Some(3800);
+        assert_eq!(report.fault(), None);
+        report.battery = Some(200);
+        assert_eq!(report.fault(), Some(Fault::Battery));
+        report.ble_ok = false;
+        assert_eq!(report.blink_code(), (RGB8::new(255, 0, 0), 2));
+        report.rmt_ok = false;
+        assert_eq!(report.fault(), Some(Fault::Rmt));
+    }
+}
@@ -0,0 +1,119 @@
+//! Ambient light and battery sensors.
+//!
+//! Both sensors hang off ADC1, so one task owns the ADC and takes turns reading them.
+//!
+//! A cheap phototransistor or LDR in a divider on an ADC pin is all it takes for auto-brightness.
+//! The sensor must be wired so that the voltage rises with the light. The readings are averaged
+//! a little to smooth out flicker from artificial lights and handed to the display task, which
+//! owns the auto-brightness policy.
+//!
+//! The battery is read through a divider, as the cell voltage is above what the ADC can measure,
+//! using the calibrated ADC so we get millivolts. The level is kept in
+//! [battery](soulstar::battery) for the beacon and the Battery Service, and a low battery lights
+//! the status pixel. The voltage is also checked several times a second so that a sagging cell
+//! can dim the strip before it browns out, see [brownout](soulstar::brownout).
+
+use crate::display_task::DisplayState::AmbientLight;
+use crate::display_task::{DisplayChannelSender, update_status};
+use crate::presence::refresh_beacon;
+use embassy_futures::join::join;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+use esp_hal::Async;
+use esp_hal::analog::adc::{Adc, AdcCalLine, AdcConfig, AdcPin, Attenuation};
+use esp_hal::peripherals::{ADC1, GPIO0, GPIO1};
+use soulstar::battery::{battery_level, battery_percent, set_battery_level};
+use soulstar::brownout::{BrownoutGuard, set_brightness_ceiling};
+use soulstar::configuration::{
+    AUTO_BRIGHTNESS_ENABLED, AUTO_BRIGHTNESS_INTERVAL, BATTERY_DIVIDER, BATTERY_ENABLED, BATTERY_INTERVAL, BATTERY_LOW,
+    BROWNOUT_INTERVAL,
+};
+use soulstar::log;
+
+/// Readings averaged for each light level or battery voltage we report
+const SAMPLES: u32 = 4;
+
+/// Read the light sensor every [AUTO_BRIGHTNESS_INTERVAL] milliseconds and report the light level
+/// to the display task, and measure the battery every [BATTERY_INTERVAL] seconds. The battery is
+/// checked for a sag every [BROWNOUT_INTERVAL] milliseconds in between. Sensors that aren't
+/// enabled in the configuration are left alone.
+///
+/// # Parameters
+/// * `adc` - The ADC the sensors are connected to
+/// * `light` - The pin the light sensor is connected to
+/// * `battery` - The pin the battery divider is connected to
+/// * `channel` - Display channel sender for the light levels
+#[embassy_executor::task]
+pub async fn sensor_task(
+    adc: ADC1<'static>,
+    light: GPIO1<'static>,
+    battery: GPIO0<'static>,
+    channel: DisplayChannelSender,
+) {
+    let mut config = AdcConfig::new();
+    let mut light: AdcPin<_, ADC1> = config.enable_pin(light, Attenuation::_11dB);
+    let mut battery: AdcPin<_, ADC1, AdcCalLine<ADC1>> = config.enable_pin_with_cal(battery, Attenuation::_11dB);
+    let adc: Mutex<NoopRawMutex, Adc<'static, ADC1, Async>> = Mutex::new(Adc::new(adc, config).into_async());
+    let light_loop = async {
+        if !AUTO_BRIGHTNESS_ENABLED {
+            return;
+        }
+        let mut ticker = Ticker::every(Duration::from_millis(AUTO_BRIGHTNESS_INTERVAL / SAMPLES as u64));
+        loop {
+            let mut total = 0u32;
+            for _ in 0..SAMPLES {
+                ticker.next().await;
+                total += adc.lock().await.read_oneshot(&mut light).await as u32;
+            }
+            channel.send(AmbientLight((total / SAMPLES) as u16).into()).await;
+        }
+    };
+    let battery_loop = async {
+        if !BATTERY_ENABLED {
+            return;
+        }
+        let mut ticker = Ticker::every(Duration::from_millis(BROWNOUT_INTERVAL));
+        let mut guard = BrownoutGuard::new();
+        let mut next_level = Instant::now();
+        loop {
+            let millivolts = battery_millivolts(&mut *adc.lock().await, &mut battery).await;
+            let now = Instant::now();
+            if let Some(ceiling) = guard.update(millivolts, now) {
+                log!(Diagnostics, warn, "SENSORS: Battery at {}mV, brightness limited to {}", millivolts, ceiling);
+                set_brightness_ceiling(ceiling);
+            }
+            if now >= next_level {
+                let percent = battery_percent(millivolts);
+                log!(Diagnostics, debug, "SENSORS: Battery at {}mV, {}%", millivolts, percent);
+                if battery_level() != Some(percent) {
+                    set_battery_level(percent);
+                    refresh_beacon();
+                }
+                update_status(|s| s.low_battery = percent <= BATTERY_LOW);
+                next_level = now + Duration::from_secs(BATTERY_INTERVAL);
+            }
+            ticker.next().await;
+        }
+    };
+    join(light_loop, battery_loop).await;
+}
+
+/// Measure the battery voltage, averaging a few readings
+///
+/// # Parameters
+/// * `adc` - The ADC the battery is connected to
+/// * `pin` - The battery pin, set up for calibrated readings
+///
+/// # Returns
+/// The cell voltage in millivolts, allowing for [BATTERY_DIVIDER]
+pub async fn battery_millivolts<'d>(
+    adc: &mut Adc<'d, ADC1<'d>, Async>,
+    pin: &mut AdcPin<GPIO0<'d>, ADC1<'d>, AdcCalLine<ADC1<'d>>>,
+) -> u16 {
+    let mut total = 0u32;
+    for _ in 0..SAMPLES {
+        total += adc.read_oneshot(pin).await as u32;
+    }
+    (total / SAMPLES) as u16 * BATTERY_DIVIDER
+}
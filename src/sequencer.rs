@@ -0,0 +1,59 @@
+//! Start-up sequencing. Steps that fail are retried as described in [startup](soulstar::startup)
+//! and left out if they never succeed, so one broken part doesn't stop the rest of the star from
+//! working. Whatever is left out shows up on the status pixel.
+
+use crate::display_task::update_status;
+use core::fmt::Debug;
+use defmt::Debug2Format;
+use embassy_executor::SpawnError;
+use embassy_time::Timer;
+use soulstar::log;
+use soulstar::startup::Retry;
+use soulstar::status::Part;
+
+/// Try a start-up step until it works or we run out of attempts
+///
+/// # Parameters
+/// * `what` - What the step does, for the log
+/// * `step` - The step, which is called again for every attempt
+///
+/// # Returns
+/// What the step came up with, or None if we gave up on it
+pub async fn with_retry<T, E: Debug>(what: &str, mut step: impl FnMut() -> Result<T, E>) -> Option<T> {
+    let mut retry = Retry::new();
+    loop {
+        match step() {
+            Ok(value) => return Some(value),
+            Err(e) => {
+                log!(Main, warn, "STARTUP: Could not {}: {:?}", what, Debug2Format(&e));
+                let Some(delay) = retry.failed() else {
+                    log!(Main, error, "STARTUP: Giving up on trying to {} after {} attempts", what, retry.failures());
+                    degraded();
+                    return None;
+                };
+                Timer::after(delay).await;
+            }
+        }
+    }
+}
+
+/// Check that a task started, carrying on without it if it didn't
+///
+/// # Parameters
+/// * `what` - The task, for the log
+/// * `result` - What the spawner said
+///
+/// # Returns
+/// True if the task is running
+pub fn started(what: &str, result: Result<(), SpawnError>) -> bool {
+    if let Err(e) = result {
+        log!(Main, error, "STARTUP: Could not start the {} task: {:?}", what, Debug2Format(&e));
+        degraded();
+    }
+    result.is_ok()
+}
+
+/// Show that part of the star isn't working
+pub fn degraded() {
+    update_status(|s| s.degrade(Part::Startup));
+}
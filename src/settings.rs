@@ -0,0 +1,238 @@
+//! The settings the wearer chooses at runtime and that should survive a restart.
+//!
+//! They are kept in flash as a single record: a magic number, the length of the body, the body
+//! and a CRC over the body. The body is a list of typed fields like the beacon
+//! [fields](crate::fields), so settings can be added without throwing away what older firmware
+//! saved. A record that is missing, damaged or from somebody else's firmware decodes as the
+//! defaults.
+
+use crate::animations::AnimationKind;
+use crate::dance::DanceMeter;
+use crate::path_loss::PathLoss;
+use crate::utils::crc32;
+use heapless::Vec;
+use smart_leds::RGB8;
+
+/// Marks the start of a settings record
+const MAGIC: [u8; 4] = *b"SOUL";
+
+/// The largest settings record we write
+pub const SETTINGS_SIZE: usize = 64;
+
+/// Magic, a length byte before the body and the CRC after it
+const OVERHEAD: usize = MAGIC.len() + 1 + 4;
+
+/// The settings we know about. New ones must take a new ID and never change the meaning of an old one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SettingId {
+    /// The [AnimationKind] shown when there is nothing else to display
+    DefaultAnimation = 0x01,
+    /// The animation update interval in milliseconds, see [intervals](crate::intervals)
+    AnimationUpdate = 0x02,
+    /// How often the presence register is flushed, in seconds
+    FlushInterval = 0x03,
+    /// How long a soul may go unseen before it is flushed, in seconds
+    FlushAge = 0x04,
+    /// The tapped tempo in beats per minute, see [tempo](crate::tempo)
+    Tempo = 0x05,
+    /// Our colour as RGB, in place of the one in the soul configuration
+    Colour = 0x06,
+    /// The badges we have earned, one bit per [Badge](crate::badges::Badge)
+    Badges = 0x07,
+    /// The calibrated [PathLoss], the reference as a signed 16-bit number and then the exponent
+    PathLoss = 0x08,
+    /// The [DanceMeter], the day and then the movement as a little-endian `u32`
+    DanceMeter = 0x09,
+}
+
+/// The saved settings. Anything that was never set is None and the firmware default applies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub default_animation: Option<AnimationKind>,
+    pub animation_update: Option<u16>,
+    pub flush_interval: Option<u16>,
+    pub flush_age: Option<u16>,
+    pub tempo: Option<u16>,
+    pub colour: Option<RGB8>,
+    pub badges: Option<u8>,
+    pub path_loss: Option<PathLoss>,
+    pub dance: Option<DanceMeter>,
+}
+
+impl Settings {
+    pub const fn new() -> Self {
+        Self {
+            default_animation: None,
+            animation_update: None,
+            flush_interval: None,
+            flush_age: None,
+            tempo: None,
+            colour: None,
+            badges: None,
+            path_loss: None,
+            dance: None,
+        }
+    }
+
+    /// Change one of the [intervals](crate::intervals). Other settings are left alone.
+    pub fn set_interval(&mut self, id: SettingId, value: u16) {
+        match id {
+            SettingId::AnimationUpdate => self.animation_update = Some(value),
+            SettingId::FlushInterval => self.flush_interval = Some(value),
+            SettingId::FlushAge => self.flush_age = Some(value),
+            SettingId::DefaultAnimation
+            | SettingId::Tempo
+            | SettingId::Colour
+            | SettingId::Badges
+            | SettingId::PathLoss
+            | SettingId::DanceMeter => (),
+        }
+    }
+
+    /// Encode the settings as a record for the flash
+    pub fn encode(&self) -> Vec<u8, SETTINGS_SIZE> {
+        let mut body: Vec<u8, { SETTINGS_SIZE - OVERHEAD }> = Vec::new();
+        let mut field = |id: SettingId, value: &[u8]| {
+            // Every setting we know fits, see if_it_round_trips
+            let _ = body.push(id as u8);
+            let _ = body.push(value.len() as u8);
+            let _ = body.extend_from_slice(value);
+        };
+        if let Some(kind) = self.default_animation {
+            field(SettingId::DefaultAnimation, &[kind as u8]);
+        }
+        if let Some(c) = self.colour {
+            field(SettingId::Colour, &[c.r, c.g, c.b]);
+        }
+        if let Some(badges) = self.badges {
+            field(SettingId::Badges, &[badges]);
+        }
+        if let Some(model) = self.path_loss {
+            let [lo, hi] = (model.reference as i16).to_le_bytes();
+            field(SettingId::PathLoss, &[lo, hi, model.exponent]);
+        }
+        if let Some(meter) = self.dance {
+            let [a, b, c, d] = meter.energy.to_le_bytes();
+            field(SettingId::DanceMeter, &[meter.day, a, b, c, d]);
+        }
+        let numbers = [
+            (SettingId::AnimationUpdate, self.animation_update),
+            (SettingId::FlushInterval, self.flush_interval),
+            (SettingId::FlushAge, self.flush_age),
+            (SettingId::Tempo, self.tempo),
+        ];
+        for (id, value) in numbers {
+            if let Some(value) = value {
+                field(id, &value.to_le_bytes());
+            }
+        }
+        let mut record = Vec::new();
+        let _ = record.extend_from_slice(&MAGIC);
+        let _ = record.push(body.len() as u8);
+        let _ = record.extend_from_slice(&body);
+        let _ = record.extend_from_slice(&crc32(&body).to_le_bytes());
+        record
+    }
+
+    /// Decode a record read from the flash. Erased flash, a damaged record or one we don't
+    /// recognise all give the defaults. Settings we don't know are skipped.
+    pub fn decode(record: &[u8]) -> Self {
+        let mut settings = Self::new();
+        let Some(mut body) = Self::body(record) else {
+            return settings;
+        };
+        while let [id, len, ref rest @ ..] = *body {
+            let Some((value, next)) = rest.split_at_checked(len as usize) else {
+                break;
+            };
+            body = next;
+            match (id, value) {
+                (id, &[kind]) if id == SettingId::DefaultAnimation as u8 => {
+                    settings.default_animation = AnimationKind::from_u8(kind)
+                }
+                (id, &[lo, hi]) if id == SettingId::AnimationUpdate as u8 => {
+                    settings.animation_update = Some(u16::from_le_bytes([lo, hi]))
+                }
+                (id, &[lo, hi]) if id == SettingId::FlushInterval as u8 => {
+                    settings.flush_interval = Some(u16::from_le_bytes([lo, hi]))
+                }
+                (id, &[lo, hi]) if id == SettingId::FlushAge as u8 => {
+                    settings.flush_age = Some(u16::from_le_bytes([lo, hi]))
+                }
+                (id, &[lo, hi]) if id == SettingId::Tempo as u8 => settings.tempo = Some(u16::from_le_bytes([lo, hi])),
+                (id, &[r, g, b]) if id == SettingId::Colour as u8 => settings.colour = Some(RGB8::new(r, g, b)),
+                (id, &[badges]) if id == SettingId::Badges as u8 => settings.badges = Some(badges),
+                (id, &[lo, hi, exponent]) if id == SettingId::PathLoss as u8 => {
+                    settings.path_loss = Some(PathLoss {
+                        reference: i16::from_le_bytes([lo, hi]) as i32,
+                        exponent,
+                    })
+                }
+                (id, &[day, a, b, c, d]) if id == SettingId::DanceMeter as u8 => {
+                    settings.dance = Some(DanceMeter {
+                        day,
+                        energy: u32::from_le_bytes([a, b, c, d]),
+                    })
+                }
+                _ => (),
+            }
+        }
+        settings
+    }
+
+    /// The body of a record, None unless the magic and CRC check out
+    fn body(record: &[u8]) -> Option<&[u8]> {
+        let rest = record.strip_prefix(&MAGIC)?;
+        let (&len, rest) = rest.split_first()?;
+        let (body, rest) = rest.split_at_checked(len as usize)?;
+        let crc = rest.first_chunk::<4>()?;
+        (u32::from_le_bytes(*crc) == crc32(body)).then_some(body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_round_trips() {
+        let settings = Settings {
+            default_animation: Some(AnimationKind::Ocean),
+            animation_update: Some(100),
+            flush_interval: None,
+            flush_age: Some(300),
+            tempo: Some(128),
+            colour: Some(RGB8::new(0, 128, 255)),
+            badges: Some(0b101),
+            path_loss: Some(PathLoss {
+                reference: -3,
+                exponent: 29,
+            }),
+            dance: Some(DanceMeter {
+                day: 2,
+                energy: 1_000_000,
+            }),
+        };
+        let record = settings.encode();
+        assert_eq!(Settings::decode(&record), settings);
+        assert_eq!(Settings::decode(&Settings::new().encode()), Settings::new());
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    pub fn if_bad_records_give_the_defaults() {
+        let mut record = Settings {
+            default_animation: Some(AnimationKind::Lava),
+            ..Settings::new()
+        }
+        .encode();
+        // Erased flash
+        assert_eq!(Settings::decode(&[0xFF; SETTINGS_SIZE]), Settings::new());
+        // A flipped bit
+        record[6] ^= 0x01;
+        assert_eq!(Settings::decode(&record), Settings::new());
+        // Truncated
+        assert_eq!(Settings::decode(&record[..5]), Settings::new());
+    }
+}
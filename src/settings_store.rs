@@ -0,0 +1,285 @@
+//! Keeps the [Settings], the encounter history and the colours we give friends in flash.
+//!
+//! They all live in a wear-levelled log (see [flash_log](soulstar::flash_log)) in the NVS partition
+//! of the standard ESP-IDF partition table, which nothing else in this firmware uses. Even so,
+//! settings changes aren't written straight away: the store waits until the settings have
+//! stopped changing for [SETTINGS_SAVE_DELAY] seconds, so that cycling through the animations
+//! costs one record rather than one per press. Encounters and colours for friends are rare, so
+//! they are written as they happen. The store also counts the restarts, which is how encounters tell one day's meeting
+//! from another's without a wall clock. Flash writes stall the executor for a few tens of
+//! milliseconds, which the display shrugs off.
+//!
+//! Older firmware kept the settings as a single record at the start of the partition. It is
+//! read before the log is opened and carried over into it, so an update doesn't lose them.
+
+use core::cell::Cell;
+use defmt::Debug2Format;
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_storage::ReadStorage;
+use esp_storage::FlashStorage;
+use smart_leds::RGB8;
+use soulstar::configuration::{SETTINGS_SAVE_DELAY, STORE_OFFSET, STORE_SECTORS};
+use soulstar::encounters::{ENCOUNTER_SIZE, Encounter, set_boot};
+use soulstar::flash_log::{FlashLog, Tag};
+use soulstar::intervals::apply_settings;
+use soulstar::log;
+use soulstar::overrides::set_override;
+use soulstar::settings::{SETTINGS_SIZE, SettingId, Settings};
+
+/// The log the settings and encounters are kept in
+pub type Store = FlashLog<FlashStorage<'static>>;
+
+/// The settings are the only record under their tag
+const SETTINGS_KEY: u32 = 0;
+
+/// The boot count is the only record under its tag
+const BOOTS_KEY: u32 = 0;
+
+/// What the store has been asked to do
+enum Request {
+    /// Write the current settings once they stop changing
+    Save,
+    /// Throw everything saved away
+    Erase,
+}
+
+/// The settings as they are now, which may not have been saved yet
+static SETTINGS: Mutex<CriticalSectionRawMutex, Cell<Settings>> = Mutex::new(Cell::new(Settings::new()));
+
+/// Requests for the store task
+static REQUEST: Signal<CriticalSectionRawMutex, Request> = Signal::new();
+
+/// What is written as it happens, rather than once the settings stop changing
+enum Record {
+    /// An encounter to remember, None to forget it
+    Encounter(Option<Encounter>),
+    /// The colour we give a friend, None to show them in their own again
+    Override(Option<RGB8>),
+}
+
+/// Records to write, under their keys
+static RECORDS: Channel<CriticalSectionRawMutex, (u32, Record), 4> = Channel::new();
+
+/// Signalled once everything saved has been erased
+static ERASED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The current settings
+pub fn settings() -> Settings {
+    SETTINGS.lock(|s| s.get())
+}
+
+/// Change the settings. They are saved once they have stopped changing for a while.
+pub fn update_settings(update: impl FnOnce(&mut Settings)) {
+    SETTINGS.lock(|s| {
+        let mut settings = s.get();
+        update(&mut settings);
+        s.set(settings);
+    });
+    REQUEST.signal(Request::Save);
+}
+
+/// Change one of the [intervals](soulstar::intervals) and start using it. It is saved with the
+/// rest of the settings so it survives a restart.
+pub fn change_interval(id: SettingId, value: u16) {
+    update_settings(|s| s.set_interval(id, value));
+    apply_settings(&settings());
+}
+
+/// Remember an encounter across restarts, or forget it
+///
+/// # Parameters
+/// * `key` - Tracker key of the soul
+/// * `encounter` - The encounter, None to forget it
+pub fn save_encounter(key: u32, encounter: Option<Encounter>) {
+    if RECORDS.try_send((key, Record::Encounter(encounter))).is_err() {
+        log!(Main, warn, "SETTINGS: Too many encounters waiting, dropped {:x}", key);
+    }
+}
+
+/// Remember the colour we give a friend across restarts, or forget it
+///
+/// # Parameters
+/// * `key` - The [name_key](soulstar::overrides::name_key) of the friend
+/// * `colour` - Their colour, None to forget it
+pub fn save_override(key: u32, colour: Option<RGB8>) {
+    if RECORDS.try_send((key, Record::Override(colour))).is_err() {
+        log!(Main, warn, "SETTINGS: Too many records waiting, dropped the colour for {:x}", key);
+    }
+}
+
+/// Erase the saved settings and encounters so that the next start comes up with the defaults.
+/// Completes once they are gone, or after a few seconds if there is no store to erase.
+pub async fn erase_settings() {
+    REQUEST.signal(Request::Erase);
+    let _ = with_timeout(Duration::from_secs(5), ERASED.wait()).await;
+}
+
+/// Open the store, carrying over the settings from older firmware if it finds them, and count
+/// this start
+///
+/// # Parameters
+/// * `flash` - The flash the store is kept in
+pub fn open_store(mut flash: FlashStorage<'static>) -> Option<Store> {
+    let mut record = [0u8; SETTINGS_SIZE];
+    let old = match flash.read(STORE_OFFSET, &mut record) {
+        Ok(()) => Settings::decode(&record),
+        Err(_) => Settings::new(),
+    };
+    let mut store = FlashLog::mount(flash, STORE_OFFSET, STORE_SECTORS).ok()?;
+    if old != Settings::new() && store.put(Tag::Settings, SETTINGS_KEY, &old.encode()).is_ok() {
+        log!(Main, info, "SETTINGS: Carried over the settings from older firmware");
+    }
+    let mut record = [0u8; 2];
+    let boot = match store.get(Tag::Boots, BOOTS_KEY, &mut record) {
+        Ok(Some(2)) => u16::from_le_bytes(record).wrapping_add(1),
+        _ => 1,
+    };
+    if store.put(Tag::Boots, BOOTS_KEY, &boot.to_le_bytes()).is_err() {
+        log!(Main, warn, "SETTINGS: Could not count this start");
+    }
+    log!(Main, info, "SETTINGS: Start number {}", boot);
+    set_boot(boot);
+    Some(store)
+}
+
+/// Read the saved settings. This is done once at start-up, before the store task takes over
+/// the store.
+///
+/// # Parameters
+/// * `store` - The store the settings are kept in, None if it couldn't be opened
+pub fn load_settings(store: Option<&mut Store>) -> Settings {
+    let mut record = [0u8; SETTINGS_SIZE];
+    let settings = match store.map(|s| s.get(Tag::Settings, SETTINGS_KEY, &mut record)) {
+        Some(Ok(Some(len))) => Settings::decode(&record[..len]),
+        _ => Settings::new(),
+    };
+    log!(Main, info, "SETTINGS: Loaded {:?}", Debug2Format(&settings));
+    SETTINGS.lock(|s| s.set(settings));
+    settings
+}
+
+/// Read the saved encounters, oldest first. This is done once at start-up, before the store
+/// task takes over the store.
+///
+/// # Parameters
+/// * `store` - The store the encounters are kept in
+/// * `restore` - Called with each encounter
+pub fn load_encounters(store: &mut Store, mut restore: impl FnMut(Encounter)) {
+    let mut count = 0;
+    let loaded = store.for_each(Tag::Encounter, |key, value| {
+        if let Some(encounter) = Encounter::decode(key, value) {
+            restore(encounter);
+            count += 1;
+        }
+    });
+    match loaded {
+        Ok(()) => log!(Main, info, "SETTINGS: Loaded {} encounters", count),
+        Err(_) => log!(Main, error, "SETTINGS: Could not load the encounters"),
+    }
+}
+
+/// Read the saved colours we give friends and start using them. This is done once at start-up,
+/// before the store task takes over the store.
+///
+/// # Parameters
+/// * `store` - The store the colours are kept in
+pub fn load_overrides(store: &mut Store) {
+    let loaded = store.for_each(Tag::ColourOverride, |key, value| {
+        if let &[r, g, b] = value
+            && !set_override(key, Some(RGB8::new(r, g, b)))
+        {
+            log!(Main, warn, "SETTINGS: No room for the colour saved for {:x}", key);
+        }
+    });
+    if loaded.is_err() {
+        log!(Main, error, "SETTINGS: Could not load the colours for friends");
+    }
+}
+
+/// Save the settings whenever they change, remember encounters and colours for friends as they
+/// happen and erase everything when asked to
+///
+/// # Parameters
+/// * `store` - The store everything is kept in
+#[embassy_executor::task]
+pub async fn settings_task(mut store: Store) {
+    let mut saved = settings();
+    let mut pending = None;
+    loop {
+        let request = match pending.take() {
+            Some(request) => request,
+            None => match select(REQUEST.wait(), RECORDS.receive()).await {
+                Either::First(request) => request,
+                Either::Second((key, Record::Encounter(encounter))) => {
+                    write_encounter(&mut store, key, encounter);
+                    continue;
+                }
+                Either::Second((key, Record::Override(colour))) => {
+                    write_override(&mut store, key, colour);
+                    continue;
+                }
+            },
+        };
+        match request {
+            Request::Save => {
+                // Wait for the settings to settle, starting again if anything else comes in
+                let settle = Timer::after(Duration::from_secs(SETTINGS_SAVE_DELAY));
+                if let Either::First(next) = select(REQUEST.wait(), settle).await {
+                    pending = Some(next);
+                    continue;
+                }
+                // Changing something and changing it back again needs no write at all
+                let current = settings();
+                if current == saved {
+                    continue;
+                }
+                match store.put(Tag::Settings, SETTINGS_KEY, &current.encode()) {
+                    Ok(()) => {
+                        log!(Main, info, "SETTINGS: Saved the settings");
+                        saved = current;
+                    }
+                    Err(_) => log!(Main, error, "SETTINGS: Could not save the settings"),
+                }
+            }
+            Request::Erase => {
+                match store.clear() {
+                    Ok(()) => log!(Main, info, "SETTINGS: Erased the store"),
+                    Err(_) => log!(Main, error, "SETTINGS: Could not erase the store"),
+                }
+                saved = Settings::new();
+                ERASED.signal(());
+            }
+        }
+    }
+}
+
+/// Write an encounter to the store, or forget it
+fn write_encounter(store: &mut Store, key: u32, encounter: Option<Encounter>) {
+    let written = match encounter {
+        Some(encounter) => {
+            let mut buffer = [0u8; ENCOUNTER_SIZE];
+            let len = encounter.encode(&mut buffer);
+            store.put(Tag::Encounter, key, &buffer[..len])
+        }
+        None => store.remove(Tag::Encounter, key),
+    };
+    if written.is_err() {
+        log!(Main, error, "SETTINGS: Could not save encounter {:x}", key);
+    }
+}
+
+/// Write the colour we give a friend to the store, or forget it
+fn write_override(store: &mut Store, key: u32, colour: Option<RGB8>) {
+    let written = match colour {
+        Some(c) => store.put(Tag::ColourOverride, key, &[c.r, c.g, c.b]),
+        None => store.remove(Tag::ColourOverride, key),
+    };
+    if written.is_err() {
+        log!(Main, error, "SETTINGS: Could not save the colour for {:x}", key);
+    }
+}
@@ -0,0 +1,163 @@
+//! Fake souls for development.
+//!
+//! Working on the presence animations or the tracker normally takes a second star (or three) and
+//! somebody to walk them around. With [SIMULATION_ENABLED](crate::configuration::SIMULATION_ENABLED)
+//! set, the souls in [FAKE_SOULS] are fed to the display task as if we had received their
+//! beacons instead. Each one arrives and leaves at a set time in a script that repeats every
+//! [SCRIPT_LENGTH] seconds, wandering towards us and away again while it is around. Real souls
+//! are still shown alongside them.
+
+use crate::configuration::TX_POWER;
+use crate::fields::Role;
+use crate::payload::{Reception, Sighting};
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// How long the script runs before it starts again (seconds)
+pub const SCRIPT_LENGTH: u64 = 120;
+
+/// Tracker keys for fake souls start here, well away from anything [addr_to_key](crate::payload::addr_to_key)
+/// is likely to give a real soul
+const FAKE_KEY_BASE: u32 = 0xFA4E_0000;
+
+/// A scripted soul
+pub struct FakeSoul {
+    pub colour: RGB8,
+    /// When the soul turns up (seconds into the script)
+    pub arrives: u64,
+    /// When the soul leaves (seconds into the script)
+    pub leaves: u64,
+    /// The RSSI (dBm) when the soul is as far away as it goes
+    pub far: i8,
+    /// The RSSI (dBm) when the soul is as close as it comes
+    pub near: i8,
+    /// How long the soul takes to come close and wander off again (seconds)
+    pub period: u64,
+    pub role: Role,
+    pub emergency: bool,
+}
+
+/// The cast. Between them they arrive, leave, come close, hover at the edge of range, overlap
+/// and call for help.
+pub const FAKE_SOULS: &[FakeSoul] = &[
+    FakeSoul {
+        colour: RGB8::new(255, 0, 0),
+        arrives: 0,
+        leaves: 100,
+        far: -85,
+        near: -45,
+        period: 30,
+        role: Role::Regular,
+        emergency: false,
+    },
+    FakeSoul {
+        colour: RGB8::new(0, 255, 0),
+        arrives: 10,
+        leaves: 60,
+        far: -70,
+        near: -55,
+        period: 20,
+        role: Role::Organiser,
+        emergency: false,
+    },
+    FakeSoul {
+        colour: RGB8::new(0, 0, 255),
+        arrives: 30,
+        leaves: 120,
+        far: -95,
+        near: -88,
+        period: 15,
+        role: Role::Regular,
+        emergency: false,
+    },
+    FakeSoul {
+        colour: RGB8::new(255, 0, 255),
+        arrives: 70,
+        leaves: 90,
+        far: -60,
+        near: -40,
+        period: 10,
+        role: Role::Medic,
+        emergency: true,
+    },
+];
+
+impl FakeSoul {
+    /// The soul's RSSI at a point in the script
+    ///
+    /// # Parameters
+    /// * `at` - How far we are into the script
+    ///
+    /// # Returns
+    /// The RSSI in dBm, or None if the soul isn't around
+    pub fn rssi(&self, at: Duration) -> Option<i8> {
+        let ms = at.as_millis();
+        if ms < self.arrives * 1000 || ms >= self.leaves * 1000 {
+            return None;
+        }
+        // Walk in from far away to the closest point half way through the period and back out again
+        let period = self.period * 1000;
+        let phase = (ms - self.arrives * 1000) % period;
+        let closeness = (period - (2 * phase).abs_diff(period)) as i64;
+        let span = self.near as i64 - self.far as i64;
+        Some((self.far as i64 + span * closeness / period as i64) as i8)
+    }
+}
+
+/// The sightings of all the fake souls that are around at a point in the script
+///
+/// # Parameters
+/// * `elapsed` - How long the simulation has been running. The script repeats.
+/// * `now` - The time to give the sightings
+pub fn fake_sightings(elapsed: Duration, now: Instant) -> impl Iterator<Item = Sighting> {
+    let at = Duration::from_millis(elapsed.as_millis() % (SCRIPT_LENGTH * 1000));
+    FAKE_SOULS.iter().enumerate().filter_map(move |(i, soul)| {
+        soul.rssi(at).map(|rssi| Sighting {
+            key: FAKE_KEY_BASE + i as u32,
+            colour: soul.colour,
+            rssi,
+            tx_power: TX_POWER as i8,
+            last_seen: now,
+            favourite: false,
+            emergency: soul.emergency,
+            wave: false,
+            role: soul.role,
+            lead: None,
+            command: None,
+            report: None,
+            reception: Reception::default(),
+            pixel: None,
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_souls_come_and_go() {
+        let count = |s| fake_sightings(Duration::from_secs(s), Instant::from_secs(s)).count();
+        assert_eq!(count(0), 1);
+        assert_eq!(count(10), 2);
+        assert_eq!(count(75), 3);
+        assert_eq!(count(110), 1);
+        // The script repeats
+        assert_eq!(count(SCRIPT_LENGTH + 10), 2);
+        for soul in FAKE_SOULS {
+            assert!(soul.leaves <= SCRIPT_LENGTH);
+        }
+    }
+
+    #[test]
+    pub fn if_souls_wander_in_and_out() {
+        let soul = &FAKE_SOULS[0];
+        let at = |ms| soul.rssi(Duration::from_millis(ms)).unwrap();
+        let half = soul.period * 500;
+        assert_eq!(at(soul.arrives * 1000), soul.far);
+        assert_eq!(at(soul.arrives * 1000 + half), soul.near);
+        assert!(at(soul.arrives * 1000 + half / 2) > soul.far);
+        assert!(at(soul.arrives * 1000 + half / 2) < soul.near);
+        assert!(soul.rssi(Duration::from_secs(soul.leaves)).is_none());
+    }
+}
@@ -3,3 +3,16 @@
 
 pub const ADVERTISED_NAME: &str = "Dr Nefario";
 pub const COLOUR: [u8; 3] = [255, 0, 0];
+pub const STRIPES: &[[u8; 3]] = &[];
+pub const CONTACT: &str = "";
+pub const COMPANY_ID: Option<u16> = None;
+pub const TEST_COMPANY_ID: Option<u16> = None;
+pub const TEST_NETWORK: bool = false;
+pub const FAVOURITES: &[&str] = &[];
+pub const COLOUR_OVERRIDES: &[(&str, [u8; 3])] = &[];
+pub const CONSTELLATION: &[(&str, u16)] = &[];
+pub const ROLE: soulstar::fields::Role = soulstar::fields::Role::Regular;
+pub const GROUP_KEY: Option<soulstar::command::GroupKey> = None;
+pub const HARDWARE_REVISION: &str = "1";
+pub const LED_POWER_PIN: Option<u8> = None;
+pub const WIFI_SSID: &str = "";
@@ -0,0 +1,439 @@
+//! Soul-link exchange. When two SoulStars see each other very close up, the one with the lower
+//! address connects to the other and reads its [Profile] from our GATT service. Profiles we
+//! receive are kept in the encounter history, which also counts how often we meet each soul
+//! again. Only one side connects so that the two don't both try to connect to each other at the
+//! same time; the other side finds out about us when it is its turn to be the lower address with
+//! someone else.
+//!
+//! The exchange is off unless [EXCHANGE_ENABLED] is set, in which case our advertisement
+//! becomes connectable. Whoever connects can also use our control service to read our badges,
+//! change our log levels, live-tune the animations and change the
+//! [intervals](soulstar::intervals). Everything but reading needs our group key, see
+//! [control](soulstar::control). We also offer the standard Battery Service, so any phone or watch
+//! can see how much charge we have left, and the standard Device Information Service with our
+//! firmware version, hardware revision and serial number.
+
+use crate::display_task::DisplayState::Tune;
+use crate::display_task::{CommandResult, DisplayChannelSender, change_colour, send_acked};
+use crate::presence::beacon_identity;
+use crate::settings_store::{change_interval, save_encounter, settings};
+use crate::soul_config;
+use core::cell::RefCell;
+use core::str::FromStr;
+use defmt::Debug2Format;
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+use esp_hal::efuse::Efuse;
+use esp_hal::rng::Rng;
+use heapless::{String, Vec};
+use rand_core::RngCore;
+use smart_leds::RGB8;
+use soulstar::badges::unlocked_badges;
+use soulstar::battery::battery_level;
+use soulstar::configuration::{EXCHANGE_ENABLED, EXCHANGE_LOSS, EXCHANGE_TIMEOUT, MAX_ENCOUNTERS};
+use soulstar::control::{CONTROL_NONCE_SIZE, CONTROL_TAG_SIZE, ControlWrite, control_value};
+use soulstar::device_info::{FIRMWARE_REVISION, SERIAL_NUMBER_SIZE, serial_number};
+use soulstar::encounters::{Encounter, EncounterHistory, MAX_PALETTE, Moment, PROFILE_SIZE, Profile};
+use soulstar::intervals::interval_command;
+use soulstar::log;
+use soulstar::logging::apply_log_command;
+use soulstar::path_loss::path_loss;
+use soulstar::payload::PresenceMessage;
+use soulstar::tracker::addr_to_key;
+use soulstar::tuning::TuningParam;
+use trouble_host::prelude::*;
+
+/// Our soul-link GATT service
+const SOUL_LINK_SERVICE: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x01, 0x00, 0x0c, 0x5a]);
+
+/// The characteristic holding our encoded profile
+const PROFILE_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x02, 0x00, 0x0c, 0x5a]);
+
+/// Our control GATT service
+const CONTROL_SERVICE: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x03, 0x00, 0x0c, 0x5a]);
+
+/// The badges we have earned, one bit per [Badge](soulstar::badges::Badge)
+const BADGES_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x05, 0x00, 0x0c, 0x5a]);
+
+/// Write a module and level here to change how much that module logs, see
+/// [apply_log_command](soulstar::logging::apply_log_command)
+const LOG_LEVEL_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x04, 0x00, 0x0c, 0x5a]);
+
+/// The nonce for this connection, which tagged writes must use, see [control](soulstar::control)
+const NONCE_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x0c, 0x00, 0x0c, 0x5a]);
+
+/// Animation speed in percent of normal
+const SPEED_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x06, 0x00, 0x0c, 0x5a]);
+
+/// How much of the strip sparkles
+const DENSITY_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x07, 0x00, 0x0c, 0x5a]);
+
+/// How slowly the tails fade
+const TAIL_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x08, 0x00, 0x0c, 0x5a]);
+
+/// The RGB colour the animations use instead of ours
+const PALETTE_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x09, 0x00, 0x0c, 0x5a]);
+
+/// Write a setting ID and a new value here to change one of the runtime intervals, see
+/// [interval_command](soulstar::intervals::interval_command)
+const INTERVALS_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x0a, 0x00, 0x0c, 0x5a]);
+
+/// Write an RGB colour here to change our own colour, see
+/// [change_colour](crate::display_task::change_colour)
+const COLOUR_CHARACTERISTIC: Uuid =
+    Uuid::new_long([0x74, 0x73, 0x6c, 0x75, 0x6f, 0x73, 0x1d, 0x9e, 0x5f, 0x4c, 0x00, 0x00, 0x0b, 0x00, 0x0c, 0x5a]);
+
+#[gatt_server]
+pub struct Server {
+    pub soul_link: SoulLinkService,
+    pub control: ControlService,
+    pub battery: BatteryService,
+    pub device_info: DeviceInformationService,
+}
+
+#[gatt_service(uuid = SOUL_LINK_SERVICE)]
+pub struct SoulLinkService {
+    #[characteristic(uuid = PROFILE_CHARACTERISTIC, read)]
+    pub profile: [u8; PROFILE_SIZE],
+}
+
+#[gatt_service(uuid = CONTROL_SERVICE)]
+pub struct ControlService {
+    #[characteristic(uuid = LOG_LEVEL_CHARACTERISTIC, write)]
+    pub log_level: [u8; 2 + CONTROL_TAG_SIZE],
+    #[characteristic(uuid = BADGES_CHARACTERISTIC, read)]
+    pub badges: u8,
+    #[characteristic(uuid = NONCE_CHARACTERISTIC, read)]
+    pub nonce: [u8; CONTROL_NONCE_SIZE],
+    #[characteristic(uuid = SPEED_CHARACTERISTIC, write)]
+    pub speed: [u8; 1 + CONTROL_TAG_SIZE],
+    #[characteristic(uuid = DENSITY_CHARACTERISTIC, write)]
+    pub density: [u8; 1 + CONTROL_TAG_SIZE],
+    #[characteristic(uuid = TAIL_CHARACTERISTIC, write)]
+    pub tail: [u8; 1 + CONTROL_TAG_SIZE],
+    #[characteristic(uuid = PALETTE_CHARACTERISTIC, write)]
+    pub palette: [u8; 3 + CONTROL_TAG_SIZE],
+    #[characteristic(uuid = INTERVALS_CHARACTERISTIC, write)]
+    pub intervals: [u8; 3 + CONTROL_TAG_SIZE],
+    #[characteristic(uuid = COLOUR_CHARACTERISTIC, write)]
+    pub colour: [u8; 3 + CONTROL_TAG_SIZE],
+}
+
+/// The standard Battery Service
+#[gatt_service(uuid = service::BATTERY)]
+pub struct BatteryService {
+    /// Charge left in percent
+    #[characteristic(uuid = characteristic::BATTERY_LEVEL, read)]
+    pub level: u8,
+}
+
+/// The standard Device Information Service
+#[gatt_service(uuid = service::DEVICE_INFORMATION)]
+pub struct DeviceInformationService {
+    #[characteristic(uuid = characteristic::FIRMWARE_REVISION_STRING, read)]
+    pub firmware: String<16>,
+    #[characteristic(uuid = characteristic::HARDWARE_REVISION_STRING, read)]
+    pub hardware: String<16>,
+    #[characteristic(uuid = characteristic::SERIAL_NUMBER_STRING, read)]
+    pub serial: String<SERIAL_NUMBER_SIZE>,
+}
+
+/// The souls we have exchanged profiles with
+static ENCOUNTERS: Mutex<CriticalSectionRawMutex, RefCell<EncounterHistory<MAX_ENCOUNTERS>>> =
+    Mutex::new(RefCell::new(EncounterHistory::new()));
+
+/// Souls waiting for us to connect to them. One is plenty as we will see them again.
+static EXCHANGE_REQUESTS: Channel<CriticalSectionRawMutex, Address, 1> = Channel::new();
+
+/// Where the display task tells us how a write from a connection went. We serve one connection
+/// at a time, so it isn't shared.
+static DISPLAY_ACK: Signal<CriticalSectionRawMutex, CommandResult> = Signal::new();
+
+/// Our own profile, built from the soul configuration. A colour changed at runtime leads the
+/// palette, ahead of any stripes.
+pub fn own_profile() -> Profile {
+    let mut palette: Vec<RGB8, MAX_PALETTE> = Vec::new();
+    if soul_config::STRIPES.is_empty() || settings().colour.is_some() {
+        let _ = palette.push(beacon_identity().colour);
+    }
+    let room = MAX_PALETTE - palette.len();
+    palette.extend(soul_config::STRIPES.iter().take(room).map(|c| RGB8::from(*c)));
+    Profile {
+        name: String::from_str(soul_config::ADVERTISED_NAME).unwrap_or_default(),
+        palette,
+        contact: String::from_str(soul_config::CONTACT).unwrap_or_default(),
+    }
+}
+
+/// Put back an encounter that was saved before the restart
+pub fn restore_encounter(encounter: Encounter) {
+    ENCOUNTERS.lock(|e| e.borrow_mut().restore(encounter));
+}
+
+/// An encounter from the history, oldest first, None once past the end
+///
+/// # Parameters
+/// * `index` - Which encounter
+pub fn encounter(index: usize) -> Option<Encounter> {
+    ENCOUNTERS.lock(|e| e.borrow().iter().nth(index).cloned())
+}
+
+/// Consider a soul we just heard for an exchange. This is called from the scan handler so it
+/// never blocks. The request is dropped if one is already pending. A soul we have already
+/// exchanged with is counted as met again instead.
+///
+/// # Parameters
+/// * `own` - Our own address
+/// * `peer` - The address the beacon came from
+/// * `presence` - The decoded beacon
+pub fn consider_exchange(own: &Address, peer: Address, presence: &PresenceMessage) {
+    let close = path_loss().normalise(presence.tx_power as i32 - presence.rssi as i32) <= EXCHANGE_LOSS;
+    if !EXCHANGE_ENABLED || !close || own.addr.raw() > peer.addr.raw() {
+        return;
+    }
+    let key = addr_to_key(&peer.addr);
+    let (known, again) = ENCOUNTERS.lock(|e| {
+        let mut history = e.borrow_mut();
+        (history.contains(key), history.seen(key, Moment::now()))
+    });
+    if let Some(again) = again {
+        log!(SoulLink, info, "SOUL_LINK: Met {} again", again.profile.name.as_str());
+        save_encounter(key, Some(again));
+    }
+    if !known {
+        let _ = EXCHANGE_REQUESTS.try_send(peer);
+    }
+}
+
+/// Wait for the next soul we should connect to
+pub async fn next_exchange() -> Address {
+    EXCHANGE_REQUESTS.receive().await
+}
+
+/// Connect to a soul, read its profile and remember it in the encounter history
+///
+/// # Parameters
+/// * `stack` - The BLE stack
+/// * `central` - The central role, which must not be scanning
+/// * `peer` - The soul to connect to
+///
+/// # Returns
+/// True if we got the profile
+pub async fn exchange<'a, C: Controller, P: PacketPool>(
+    stack: &'a Stack<'a, C, P>,
+    central: &mut Central<'a, C, P>,
+    peer: Address,
+) -> bool {
+    log!(SoulLink, info, "SOUL_LINK: Connecting to {}", Debug2Format(&peer.addr));
+    let config = ConnectConfig {
+        connect_params: Default::default(),
+        // Our beacons are extended advertisements on the coded PHY
+        scan_config: ScanConfig {
+            filter_accept_list: &[(peer.kind, &peer.addr)],
+            phys: PhySet::Coded,
+            ..Default::default()
+        },
+    };
+    let conn = match with_timeout(Duration::from_secs(EXCHANGE_TIMEOUT), central.connect_ext(&config)).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(e)) => {
+            log!(SoulLink, warn, "SOUL_LINK: Connection failed: {:?}", Debug2Format(&e));
+            return false;
+        }
+        Err(_) => {
+            log!(SoulLink, warn, "SOUL_LINK: Timed out connecting");
+            return false;
+        }
+    };
+    let Ok(client) = GattClient::<C, P, 4>::new(stack, &conn).await else {
+        log!(SoulLink, warn, "SOUL_LINK: Could not create a GATT client");
+        return false;
+    };
+    let read = async {
+        let services = client.services_by_uuid(&SOUL_LINK_SERVICE).await.ok()?;
+        let service = services.first()?;
+        let profile: Characteristic<[u8; PROFILE_SIZE]> = client
+            .characteristic_by_uuid(service, &PROFILE_CHARACTERISTIC)
+            .await
+            .ok()?;
+        let mut buffer = [0u8; PROFILE_SIZE];
+        let len = client.read_characteristic(&profile, &mut buffer).await.ok()?;
+        Profile::decode(&buffer[..len])
+    };
+    let received = match with_timeout(Duration::from_secs(EXCHANGE_TIMEOUT), select(client.task(), read)).await {
+        Ok(Either::Second(profile)) => profile,
+        _ => None,
+    };
+    match received {
+        Some(profile) => {
+            log!(SoulLink, info, "SOUL_LINK: Linked with {}", profile.name.as_str());
+            let key = addr_to_key(&peer.addr);
+            let (encounter, forgotten) = ENCOUNTERS.lock(|e| e.borrow_mut().record(key, profile, Moment::now()));
+            // Forget first, so a full store has room for the new one
+            if let Some(forgotten) = forgotten {
+                save_encounter(forgotten, None);
+            }
+            save_encounter(key, Some(encounter));
+            true
+        }
+        None => {
+            log!(SoulLink, warn, "SOUL_LINK: Could not read a profile from {}", Debug2Format(&peer.addr));
+            false
+        }
+    }
+}
+
+/// The value written to a control characteristic, None if it wasn't tagged with our group key
+fn checked<'a>(data: &'a [u8], characteristic: ControlWrite, nonce: &[u8; CONTROL_NONCE_SIZE]) -> Option<&'a [u8]> {
+    control_value(data, characteristic, nonce, soul_config::GROUP_KEY.as_ref())
+}
+
+/// The tuning parameter written to one of the tuning characteristics, None if the write was to
+/// some other characteristic, the wrong size or not tagged with our group key
+fn tuning_param(
+    server: &Server<'_>,
+    handle: u16,
+    data: &[u8],
+    nonce: &[u8; CONTROL_NONCE_SIZE],
+) -> Option<TuningParam> {
+    let control = &server.control;
+    let characteristic = match handle {
+        h if h == control.speed.handle => ControlWrite::Speed,
+        h if h == control.density.handle => ControlWrite::Density,
+        h if h == control.tail.handle => ControlWrite::Tail,
+        h if h == control.palette.handle => ControlWrite::Palette,
+        _ => return None,
+    };
+    match (characteristic, checked(data, characteristic, nonce)?) {
+        (ControlWrite::Speed, [speed]) => Some(TuningParam::Speed(*speed)),
+        (ControlWrite::Density, [density]) => Some(TuningParam::Density(*density)),
+        (ControlWrite::Tail, [tail]) => Some(TuningParam::Tail(*tail)),
+        (ControlWrite::Palette, [r, g, b]) => Some(TuningParam::Palette(RGB8::new(*r, *g, *b))),
+        _ => None,
+    }
+}
+
+/// Serve our profile to a soul that connected to us until it disconnects. Whoever connects can
+/// also read our badges, change our log levels, tune the animations and change the intervals. A
+/// colour or tuning the display doesn't take is answered with an error.
+///
+/// # Parameters
+/// * `conn` - The incoming connection
+/// * `server` - Our GATT server
+/// * `display` - Where tuning is sent
+pub async fn serve<P: PacketPool>(conn: Connection<'_, P>, server: &Server<'_>, display: DisplayChannelSender) {
+    // Badges are earned and our colour changed while nobody is connected, so bring them up to date
+    // for this connection
+    if server.control.badges.set(server, &unlocked_badges()).is_err() {
+        log!(SoulLink, warn, "SOUL_LINK: Could not set our badges");
+    }
+    let mut profile = [0u8; PROFILE_SIZE];
+    own_profile().encode(&mut profile);
+    if server.soul_link.profile.set(server, &profile).is_err() {
+        log!(SoulLink, warn, "SOUL_LINK: Could not set our profile");
+    }
+    // A fresh nonce for each connection so that writes overheard on one are no good on the next
+    let nonce = Rng::new().next_u32().to_le_bytes();
+    if server.control.nonce.set(server, &nonce).is_err() {
+        log!(SoulLink, warn, "SOUL_LINK: Could not set the nonce");
+    }
+    let info = &server.device_info;
+    let firmware = String::from_str(FIRMWARE_REVISION).unwrap_or_default();
+    let hardware = String::from_str(soul_config::HARDWARE_REVISION).unwrap_or_default();
+    if info.firmware.set(server, &firmware).is_err()
+        || info.hardware.set(server, &hardware).is_err()
+        || info.serial.set(server, &serial_number(Efuse::mac_address())).is_err()
+    {
+        log!(SoulLink, warn, "SOUL_LINK: Could not set our device information");
+    }
+    // The Battery Service has no way to say it doesn't know, so we only fill it in once measured
+    if let Some(level) = battery_level()
+        && server.battery.level.set(server, &level).is_err()
+    {
+        log!(SoulLink, warn, "SOUL_LINK: Could not set our battery level");
+    }
+    let conn = match conn.with_attribute_server(server) {
+        Ok(conn) => conn,
+        Err(e) => {
+            log!(SoulLink, warn, "SOUL_LINK: Could not serve the connection: {:?}", Debug2Format(&e));
+            return;
+        }
+    };
+    loop {
+        match conn.next().await {
+            GattConnectionEvent::Disconnected { reason } => {
+                log!(SoulLink, info, "SOUL_LINK: Disconnected: {:?}", Debug2Format(&reason));
+                break;
+            }
+            GattConnectionEvent::Gatt { event } => {
+                if let GattEvent::Write(write) = &event
+                    && write.handle() == server.control.log_level.handle
+                {
+                    match checked(write.data(), ControlWrite::LogLevel, &nonce).and_then(apply_log_command) {
+                        Some((module, level)) => {
+                            log!(SoulLink, info, "SOUL_LINK: Log level for {} set to {}", module, level)
+                        }
+                        None => log!(SoulLink, warn, "SOUL_LINK: Ignoring log level command {:?}", write.data()),
+                    }
+                }
+                if let GattEvent::Write(write) = &event
+                    && write.handle() == server.control.intervals.handle
+                {
+                    match checked(write.data(), ControlWrite::Intervals, &nonce).and_then(interval_command) {
+                        Some((id, value)) => {
+                            log!(SoulLink, info, "SOUL_LINK: Interval {:?} set to {}", Debug2Format(&id), value);
+                            change_interval(id, value);
+                        }
+                        None => log!(SoulLink, warn, "SOUL_LINK: Ignoring interval command {:?}", write.data()),
+                    }
+                }
+                // The outcome of a write the display task had to carry out
+                let mut result = CommandResult::Applied;
+                if let GattEvent::Write(write) = &event
+                    && write.handle() == server.control.colour.handle
+                {
+                    match checked(write.data(), ControlWrite::Colour, &nonce) {
+                        Some(&[r, g, b]) => {
+                            log!(SoulLink, info, "SOUL_LINK: Colour changed to {},{},{}", r, g, b);
+                            result = change_colour(&display, RGB8::new(r, g, b), &DISPLAY_ACK).await;
+                        }
+                        _ => log!(SoulLink, warn, "SOUL_LINK: Ignoring colour {:?}", write.data()),
+                    }
+                }
+                if let GattEvent::Write(write) = &event
+                    && let Some(param) = tuning_param(server, write.handle(), write.data(), &nonce)
+                {
+                    result = send_acked(&display, Tune(param), &DISPLAY_ACK).await;
+                    if result != CommandResult::Applied {
+                        log!(SoulLink, warn, "SOUL_LINK: The display didn't take the tuning: {}", result);
+                    }
+                }
+                // Tell whoever wrote that the display didn't carry it out
+                let reply = match result {
+                    CommandResult::Applied => event.accept(),
+                    _ => event.reject(AttErrorCode::WRITE_REQUEST_REJECTED),
+                };
+                match reply {
+                    Ok(reply) => reply.send().await,
+                    Err(e) => log!(SoulLink, warn, "SOUL_LINK: Error handling a GATT event: {:?}", Debug2Format(&e)),
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Start-up retries.
+//!
+//! Some of the hardware doesn't always come up first time, the radio in particular. Rather than
+//! panicking and boot looping, each step is tried up to [STARTUP_ATTEMPTS] times, waiting
+//! [STARTUP_RETRY_DELAY] milliseconds after the first failure and twice as long after each one
+//! that follows. A step that still fails is left out and the star carries on without it, with
+//! the status pixel showing that it is degraded.
+
+use crate::configuration::{STARTUP_ATTEMPTS, STARTUP_RETRY_DELAY};
+use embassy_time::Duration;
+
+/// Keeps track of the attempts at one start-up step
+pub struct Retry {
+    /// The attempts that have failed so far
+    failures: u8,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Retry {
+    pub const fn new() -> Self {
+        Self { failures: 0 }
+    }
+
+    /// Record a failed attempt
+    ///
+    /// # Returns
+    /// How long to wait before trying again, or None if it is time to give up
+    pub fn failed(&mut self) -> Option<Duration> {
+        self.failures = self.failures.saturating_add(1);
+        (self.failures < STARTUP_ATTEMPTS)
+            .then(|| Duration::from_millis(STARTUP_RETRY_DELAY << (self.failures - 1).min(16)))
+    }
+
+    /// The attempts that have failed so far
+    pub fn failures(&self) -> u8 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_backs_off_and_gives_up() {
+        let mut retry = Retry::new();
+        let delays: heapless::Vec<_, 16> = core::iter::from_fn(|| retry.failed()).collect();
+        assert_eq!(delays.len(), STARTUP_ATTEMPTS as usize - 1);
+        assert_eq!(delays[0], Duration::from_millis(STARTUP_RETRY_DELAY));
+        assert!(delays.windows(2).all(|d| d[1] == d[0] * 2));
+        assert_eq!(retry.failures(), STARTUP_ATTEMPTS);
+        assert!(retry.failed().is_none());
+    }
+}
@@ -0,0 +1,156 @@
+//! System status indicators.
+//!
+//! The status is drawn on a reserved pixel over whatever animation is running, so things like a
+//! low battery are visible without having to push anything through the animation queue. If no
+//! indicator is active, the pixel is left to the animation.
+
+use crate::colour::LedBuffer;
+use crate::configuration::STATUS_PIXEL;
+use defmt::Format;
+use smart_leds::RGB8;
+
+const LOW_BATTERY_COLOUR: RGB8 = RGB8::new(255, 0, 0);
+const CHARGING_COLOUR: RGB8 = RGB8::new(255, 96, 0);
+const BLE_CONNECTED_COLOUR: RGB8 = RGB8::new(0, 0, 255);
+const DO_NOT_DISTURB_COLOUR: RGB8 = RGB8::new(48, 0, 64);
+const DEGRADED_COLOUR: RGB8 = RGB8::new(255, 160, 0);
+
+/// The parts of the star that can fail to start, one bit each in [StatusFlags::degraded]
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum Part {
+    /// A start-up step or task we gave up on. These never come back.
+    Startup,
+    /// The BLE stack, which recovers once the controller starts again
+    Ble,
+}
+
+impl Part {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The set of status indicators that can be shown
+#[derive(Clone, Copy, Default, PartialEq, Eq, Format)]
+pub struct StatusFlags {
+    /// A central is connected to us over BLE
+    pub ble_connected: bool,
+    /// The battery needs charging
+    pub low_battery: bool,
+    /// The battery is being charged
+    pub charging: bool,
+    /// We are not advertising our beacon
+    pub do_not_disturb: bool,
+    /// The parts of the star that didn't start, one bit per [Part], which we are carrying on
+    /// without
+    pub degraded: u8,
+}
+
+impl StatusFlags {
+    pub const fn new() -> Self {
+        Self {
+            ble_connected: false,
+            low_battery: false,
+            charging: false,
+            do_not_disturb: false,
+            degraded: 0,
+        }
+    }
+
+    /// Show that part of the star isn't working
+    pub fn degrade(&mut self, part: Part) {
+        self.degraded |= part.bit();
+    }
+
+    /// Show that part of the star is working again. The other parts stay degraded.
+    pub fn recover(&mut self, part: Part) {
+        self.degraded &= !part.bit();
+    }
+
+    /// The colour of the status pixel for the given frame, or None if there is nothing to show.
+    /// Only the most important indicator is shown, with a low battery blinking to draw attention.
+    /// A degraded star double blinks, so it can't be mistaken for any of the others.
+    ///
+    /// # Arguments
+    /// * `frame` - A free running frame counter used for blinking
+    pub fn colour(&self, frame: u32) -> Option<RGB8> {
+        if self.low_battery {
+            frame.is_multiple_of(2).then_some(LOW_BATTERY_COLOUR)
+        } else if self.degraded != 0 {
+            matches!(frame % 8, 0 | 2).then_some(DEGRADED_COLOUR)
+        } else if self.charging {
+            Some(CHARGING_COLOUR)
+        } else if self.ble_connected {
+            Some(BLE_CONNECTED_COLOUR)
+        } else if self.do_not_disturb {
+            Some(DO_NOT_DISTURB_COLOUR)
+        } else {
+            None
+        }
+    }
+
+    /// Whether any indicator is active, in which case the status pixel belongs to the status
+    /// rather than the animation
+    pub fn is_active(&self) -> bool {
+        self.low_battery || self.degraded != 0 || self.charging || self.ble_connected || self.do_not_disturb
+    }
+
+    /// Draw the status over the animation in the buffer
+    pub fn apply(&self, buffer: &mut LedBuffer, frame: u32) {
+        if self.is_active() {
+            buffer[STATUS_PIXEL] = self.colour(frame).unwrap_or_default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_leaves_the_animation_alone() {
+        let mut buffer = LedBuffer::default();
+        buffer.fill(RGB8::new(1, 1, 1));
+        StatusFlags::new().apply(&mut buffer, 0);
+        assert_eq!(buffer[STATUS_PIXEL], RGB8::new(1, 1, 1));
+    }
+
+    #[test]
+    pub fn if_low_battery_wins_and_blinks() {
+        let status = StatusFlags {
+            ble_connected: true,
+            low_battery: true,
+            charging: false,
+            do_not_disturb: true,
+            degraded: Part::Startup.bit(),
+        };
+        let mut buffer = LedBuffer::default();
+        status.apply(&mut buffer, 0);
+        assert_eq!(buffer[STATUS_PIXEL], LOW_BATTERY_COLOUR);
+        status.apply(&mut buffer, 1);
+        assert_eq!(buffer[STATUS_PIXEL], RGB8::default());
+    }
+
+    #[test]
+    pub fn if_degraded_double_blinks() {
+        let mut status = StatusFlags {
+            charging: true,
+            ..StatusFlags::new()
+        };
+        status.degrade(Part::Startup);
+        let lit: [bool; 8] = core::array::from_fn(|frame| status.colour(frame as u32).is_some());
+        assert_eq!(lit, [true, false, true, false, false, false, false, false]);
+    }
+
+    #[test]
+    pub fn if_the_ble_recovering_leaves_the_rest_degraded() {
+        let mut status = StatusFlags::new();
+        status.degrade(Part::Startup);
+        status.degrade(Part::Ble);
+        status.recover(Part::Ble);
+        assert!(status.is_active());
+        status.recover(Part::Startup);
+        assert!(!status.is_active());
+    }
+}
@@ -0,0 +1,121 @@
+//! Named counters and gauges that any task can bump, so we can see how busy the Soul Star is
+//! without sprinkling the code with log breadcrumbs. Counters only ever go up (wrapping
+//! eventually) and gauges hold the latest value. The diagnostics task dumps a [Telemetry]
+//! snapshot every so often and anybody else can take one with [telemetry].
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use defmt::Format;
+
+/// Things we count
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum Counter {
+    /// Frames written to the LED strip
+    FramesRendered,
+    /// Frames dimmed to keep within the LED current limit
+    CurrentLimited,
+    /// Advertisements received from anybody
+    AdvertsSeen,
+    /// Advertisements thrown away by the scan handler because they can't be one of our beacons
+    AdvertsIgnored,
+    /// Advertisements that were one of our beacons
+    BeaconsSeen,
+    /// Beacons that arrived as extended advertisements
+    ExtendedBeacons,
+    /// Beacons that arrived on the coded PHY, which is what gives us our range
+    CodedBeacons,
+    /// Messages dropped or coalesced anywhere, see [DropKind](crate::backpressure::DropKind)
+    QueueDrops,
+    /// Errors from the BLE stack
+    BleErrors,
+    /// Frames that took longer to draw and send than their frame interval, see
+    /// [profiling](crate::profiling)
+    FrameOverruns,
+}
+
+const COUNTERS: usize = 10;
+
+/// Things we measure
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum Gauge {
+    /// The number of souls in the tracker
+    SoulsTracked,
+    /// The number of different souls seen a minute, see [SoulRate](crate::tracker::SoulRate)
+    SoulsPerMinute,
+}
+
+const GAUGES: usize = 2;
+
+static COUNTS: [AtomicU32; COUNTERS] = [const { AtomicU32::new(0) }; COUNTERS];
+
+static LEVELS: [AtomicU32; GAUGES] = [const { AtomicU32::new(0) }; GAUGES];
+
+/// Add one to a counter
+pub fn increment(counter: Counter) {
+    COUNTS[counter as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// The value of a counter
+pub fn count(counter: Counter) -> u32 {
+    COUNTS[counter as usize].load(Ordering::Relaxed)
+}
+
+/// Set a gauge to its latest value
+pub fn set_gauge(gauge: Gauge, value: u32) {
+    LEVELS[gauge as usize].store(value, Ordering::Relaxed);
+}
+
+/// The latest value of a gauge
+pub fn gauge(gauge: Gauge) -> u32 {
+    LEVELS[gauge as usize].load(Ordering::Relaxed)
+}
+
+/// All the counters and gauges at one moment
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Format)]
+pub struct Telemetry {
+    pub frames_rendered: u32,
+    pub current_limited: u32,
+    pub adverts_seen: u32,
+    pub adverts_ignored: u32,
+    pub beacons_seen: u32,
+    pub extended_beacons: u32,
+    pub coded_beacons: u32,
+    pub queue_drops: u32,
+    pub ble_errors: u32,
+    pub frame_overruns: u32,
+    pub souls_tracked: u32,
+    pub souls_per_minute: u32,
+}
+
+/// Snapshot of the counters and gauges
+pub fn telemetry() -> Telemetry {
+    Telemetry {
+        frames_rendered: count(Counter::FramesRendered),
+        current_limited: count(Counter::CurrentLimited),
+        adverts_seen: count(Counter::AdvertsSeen),
+        adverts_ignored: count(Counter::AdvertsIgnored),
+        beacons_seen: count(Counter::BeaconsSeen),
+        extended_beacons: count(Counter::ExtendedBeacons),
+        coded_beacons: count(Counter::CodedBeacons),
+        queue_drops: count(Counter::QueueDrops),
+        ble_errors: count(Counter::BleErrors),
+        frame_overruns: count(Counter::FrameOverruns),
+        souls_tracked: gauge(Gauge::SoulsTracked),
+        souls_per_minute: gauge(Gauge::SoulsPerMinute),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_counts() {
+        let before = telemetry();
+        increment(Counter::BleErrors);
+        increment(Counter::BleErrors);
+        set_gauge(Gauge::SoulsTracked, 3);
+        let after = telemetry();
+        assert!(after.ble_errors >= before.ble_errors + 2);
+        assert_eq!(gauge(Gauge::SoulsTracked), 3);
+    }
+}
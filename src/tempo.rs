@@ -0,0 +1,177 @@
+//! The beat of the music, for the animations that move in time with it.
+//!
+//! The wearer taps the tempo button in time with the music and the tempo is worked out from the
+//! gaps between the taps. The last tap marks where the beat falls, so the beat-aware animations
+//! (see [PulseAnimation](crate::animations::PulseAnimation) and
+//! [ChaseAnimation](crate::animations::ChaseAnimation)) land on it rather than just running at
+//! the right speed. The tempo is kept in the [Settings](crate::settings::Settings) until it is
+//! tapped again. Until then the animations run at [DEFAULT_BPM].
+//!
+//! A microphone can hear the tempo too (see [beat](crate::beat)), but what the wearer tapped wins
+//! for [TAPPED_TEMPO_HOLD] seconds, so the music doesn't undo a tap straight away.
+//!
+//! [DEFAULT_BPM]: crate::configuration::DEFAULT_BPM
+
+use crate::configuration::{DEFAULT_BPM, MAX_BPM, MIN_BPM, TAPPED_TEMPO_HOLD, TEMPO_TAPS};
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU16, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+/// The tempo in beats per minute
+static BPM: AtomicU16 = AtomicU16::new(DEFAULT_BPM);
+
+/// A moment the beat fell on
+static DOWNBEAT: Mutex<CriticalSectionRawMutex, Cell<Instant>> = Mutex::new(Cell::new(Instant::from_ticks(0)));
+
+/// When the tempo was last tapped, None if it never was
+static TAPPED: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+/// The tempo in beats per minute
+pub fn bpm() -> u16 {
+    BPM.load(Ordering::Relaxed)
+}
+
+/// Set the tempo the wearer tapped, clamped to the tempos we can follow
+///
+/// # Parameters
+/// * `bpm` - The new tempo in beats per minute
+/// * `downbeat` - A moment the beat fell on
+pub fn set_tempo(bpm: u16, downbeat: Instant) {
+    TAPPED.lock(|t| t.set(Some(downbeat)));
+    follow(bpm, downbeat);
+}
+
+/// Set the tempo the microphone heard, unless the wearer tapped one recently
+///
+/// # Parameters
+/// * `bpm` - The tempo heard in beats per minute
+/// * `downbeat` - A moment the beat fell on
+///
+/// # Returns
+/// True if we follow it
+pub fn hear_tempo(bpm: u16, downbeat: Instant) -> bool {
+    let heard = !tapped_holds(TAPPED.lock(|t| t.get()), downbeat);
+    if heard {
+        follow(bpm, downbeat);
+    }
+    heard
+}
+
+/// True if a tempo tapped at `tapped` still wins at `now`
+fn tapped_holds(tapped: Option<Instant>, now: Instant) -> bool {
+    tapped.is_some_and(|t| now.saturating_duration_since(t) < Duration::from_secs(TAPPED_TEMPO_HOLD))
+}
+
+/// Follow a tempo, clamped to the tempos we can follow
+fn follow(bpm: u16, downbeat: Instant) {
+    BPM.store(bpm.clamp(MIN_BPM, MAX_BPM), Ordering::Relaxed);
+    DOWNBEAT.lock(|d| d.set(downbeat));
+}
+
+/// How long one beat lasts
+pub fn beat_length() -> Duration {
+    Duration::from_micros(60_000_000 / bpm() as u64)
+}
+
+/// Where we are in the music
+///
+/// # Parameters
+/// * `now` - The time now
+///
+/// # Returns
+/// The number of beats since the downbeat and how far through the current beat we are, from 0
+/// (on the beat) to 255 (just before the next one)
+pub fn beat(now: Instant) -> (u32, u8) {
+    let length = beat_length().as_micros();
+    let since = now.saturating_duration_since(DOWNBEAT.lock(|d| d.get())).as_micros();
+    ((since / length) as u32, ((since % length) * 256 / length) as u8)
+}
+
+/// Works the tempo out from taps on the tempo button
+#[derive(Default)]
+pub struct TapTempo {
+    /// The most recent taps, oldest first
+    taps: Deque<Instant, TEMPO_TAPS>,
+}
+
+impl TapTempo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a tap
+    ///
+    /// # Parameters
+    /// * `now` - When the tempo button was tapped
+    ///
+    /// # Returns
+    /// The tempo in beats per minute once there have been enough taps in a row
+    pub fn tap(&mut self, now: Instant) -> Option<u16> {
+        // A long gap means the wearer has started tapping again
+        let slowest = Duration::from_micros(60_000_000 / MIN_BPM as u64);
+        if self
+            .taps
+            .back()
+            .is_some_and(|&last| now.saturating_duration_since(last) > slowest)
+        {
+            self.taps.clear();
+        }
+        if self.taps.is_full() {
+            self.taps.pop_front();
+        }
+        let _ = self.taps.push_back(now);
+        // Three taps make two beats, which is the least we trust
+        let first = *self.taps.front()?;
+        let beats = self.taps.len() as u64 - 1;
+        if beats < 2 {
+            return None;
+        }
+        let micros = now.saturating_duration_since(first).as_micros() / beats;
+        Some((60_000_000 / micros.max(1)).clamp(MIN_BPM as u64, MAX_BPM as u64) as u16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_taps_set_the_tempo() {
+        let mut tapper = TapTempo::new();
+        let start = Instant::from_secs(10);
+        // 120 BPM is a tap every half second
+        let at = |beat: u64| start + Duration::from_millis(500 * beat);
+        assert_eq!(tapper.tap(at(0)), None);
+        assert_eq!(tapper.tap(at(1)), None);
+        assert_eq!(tapper.tap(at(2)), Some(120));
+        assert_eq!(tapper.tap(at(3)), Some(120));
+        // A pause and then faster taps, at 150 BPM
+        let later = at(3) + Duration::from_secs(5);
+        assert_eq!(tapper.tap(later), None);
+        assert_eq!(tapper.tap(later + Duration::from_millis(400)), None);
+        assert_eq!(tapper.tap(later + Duration::from_millis(800)), Some(150));
+    }
+
+    #[test]
+    pub fn if_a_tapped_tempo_holds_off_the_microphone() {
+        let tapped = Instant::from_secs(100);
+        assert!(!tapped_holds(None, tapped));
+        assert!(tapped_holds(Some(tapped), tapped + Duration::from_secs(TAPPED_TEMPO_HOLD - 1)));
+        assert!(!tapped_holds(Some(tapped), tapped + Duration::from_secs(TAPPED_TEMPO_HOLD)));
+    }
+
+    #[test]
+    pub fn if_the_beat_follows_the_tempo() {
+        let downbeat = Instant::from_secs(100);
+        set_tempo(120, downbeat);
+        assert_eq!(bpm(), 120);
+        assert_eq!(beat(downbeat), (0, 0));
+        assert_eq!(beat(downbeat + Duration::from_millis(250)), (0, 128));
+        assert_eq!(beat(downbeat + Duration::from_millis(1500)), (3, 0));
+        set_tempo(1000, downbeat);
+        assert_eq!(bpm(), MAX_BPM);
+    }
+}
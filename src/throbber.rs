@@ -7,7 +7,7 @@ pub enum Direction {
 }
 
 /// Throbber state
-/// 
+///
 /// A throbber will slowly change its brightnes levels from `min` to 255 and back
 /// each time the `next()` method is called. You can use the brightness to modulate
 /// a LED colour before writing it to the led buffer for display
@@ -53,15 +53,17 @@ impl Throbber {
             direction: Direction::Up,
             step: step as i16,
             min: 0,
-            once:true,
+            once: true,
             done: false,
         }
     }
-    
+
     // Advances the steps by some fixed number so you can start the throbber
     // at some brightness other than min. I know this is lazy...
     pub fn advance(&mut self, steps: u8) {
-        for _ in 0..steps { self.next();}
+        for _ in 0..steps {
+            self.next();
+        }
     }
 }
 
@@ -75,14 +77,14 @@ impl Iterator for Throbber {
         }
         match self.direction {
             Direction::Up => {
-                self.brightness = self.brightness + self.step;
+                self.brightness += self.step;
                 if self.brightness >= 255 {
                     self.direction = Direction::Down;
                     self.brightness = 255;
                 }
             }
             Direction::Down => {
-                self.brightness = self.brightness - self.step;
+                self.brightness -= self.step;
                 if self.brightness < self.min as i16 {
                     // If we throb once, terminate after we hit the bottom of the cycle
                     if self.once {
@@ -97,18 +99,17 @@ impl Iterator for Throbber {
     }
 }
 
-/*
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     pub fn if_it_throbs_forever() {
-        let mut t = Throbber::new(16, 8, false);
+        let t = Throbber::new(16, 8, false);
         let mut count = 0;
         let mut max_brightness = 0;
         let mut min_brightness = 255;
-        while let Some(b) = t.next() {
+        for b in t {
             count += 1;
             max_brightness = max_brightness.max(b);
             min_brightness = min_brightness.min(b);
@@ -122,12 +123,12 @@ mod test {
 
     #[test]
     pub fn if_it_throbs_once() {
-        let mut t = Throbber::new_once(16);
+        let t = Throbber::new_once(16);
         // Iterate enough steps to hit the top at least once.
         let mut max_brightness = 0;
         let mut last_brightness = 100;
         let mut count = 0;
-        while let Some(b) = t.next() {
+        for b in t {
             count += 1;
             max_brightness = max_brightness.max(b);
             last_brightness = b;
@@ -140,4 +141,3 @@ mod test {
         assert_eq!(max_brightness, 255);
     }
 }
-*/
\ No newline at end of file
@@ -0,0 +1,71 @@
+//! Per-soul rate limiting of scan reports.
+//!
+//! Every soul advertises several times a second and the controller hands us each and every
+//! report. We could ask the controller to filter duplicates, but then we would no longer see the
+//! signal strength change as people move about. Instead, we emulate duplicate filtering by only
+//! passing on one report per soul every so often, which keeps the pressure off the display
+//! channel.
+
+use embassy_time::{Duration, Instant};
+use heapless::index_map::FnvIndexMap;
+
+/// Remembers when we last passed on a report from each soul. N must be a power of two.
+pub struct UpdateThrottle<const N: usize> {
+    interval: Duration,
+    last: FnvIndexMap<u32, Instant, N>,
+}
+
+impl<const N: usize> UpdateThrottle<N> {
+    /// Create a throttle
+    ///
+    /// # Parameters
+    /// * `interval` - The minimum time between reports from the same soul
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: FnvIndexMap::new(),
+        }
+    }
+
+    /// Check if a report from a soul should be passed on, remembering it if so. If we are
+    /// already tracking as many souls as we can, the one we heard from longest ago is forgotten.
+    ///
+    /// # Parameters
+    /// * `key` - The tracker key of the soul
+    /// * `now` - When the report arrived
+    pub fn allow(&mut self, key: u32, now: Instant) -> bool {
+        if let Some(last) = self.last.get_mut(&key) {
+            if now < *last + self.interval {
+                return false;
+            }
+            *last = now;
+            return true;
+        }
+        if self.last.len() == N {
+            let oldest = self.last.iter().min_by_key(|(_, at)| **at).map(|(k, _)| *k);
+            if let Some(oldest) = oldest {
+                self.last.remove(&oldest);
+            }
+        }
+        let _ = self.last.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_throttles_each_soul() {
+        let mut throttle: UpdateThrottle<2> = UpdateThrottle::new(Duration::from_secs(1));
+        let start = Instant::from_secs(10);
+        assert!(throttle.allow(1, start));
+        assert!(!throttle.allow(1, start + Duration::from_millis(500)));
+        assert!(throttle.allow(2, start + Duration::from_millis(500)));
+        assert!(throttle.allow(1, start + Duration::from_secs(1)));
+        // Soul 2 is the oldest so it is forgotten to make room for soul 3
+        assert!(throttle.allow(3, start + Duration::from_millis(1100)));
+        assert!(throttle.allow(2, start + Duration::from_millis(1200)));
+    }
+}
@@ -0,0 +1,116 @@
+//! Torch colour and intensity presets.
+//!
+//! The torch fills the whole strip with a single colour. White is the most useful for finding
+//! things, but warmer or red light is kinder on the eyes of everybody else around a camp. The
+//! torch has its own intensity levels rather than following the animation brightness, with the
+//! power limiter keeping the current safe at high.
+
+use crate::colour::is_whiteish;
+use defmt::Format;
+use smart_leds::RGB8;
+
+/// The brightest the night vision torch will go
+pub const NIGHT_VISION_BRIGHTNESS: u8 = 40;
+
+/// The colours the torch can be set to
+#[derive(Clone, Copy, Default, PartialEq, Eq, Format)]
+pub enum TorchPreset {
+    /// Plain full white
+    #[default]
+    White,
+    /// Warm white, like an incandescent bulb
+    Warm,
+    /// Cool, slightly blue white
+    Cool,
+    /// Red to preserve night vision
+    Red,
+    /// Dim pure red, for reading maps and rummaging in tents without ruining anybody's night
+    /// vision. It never goes above [NIGHT_VISION_BRIGHTNESS], whatever the intensity.
+    NightVision,
+    /// Amber, less harsh than white
+    Amber,
+}
+
+impl TorchPreset {
+    /// The colour to fill the strip with for this preset
+    pub fn colour(&self) -> RGB8 {
+        match self {
+            TorchPreset::White => RGB8::new(255, 255, 255),
+            TorchPreset::Warm => RGB8::new(255, 180, 100),
+            TorchPreset::Cool => RGB8::new(200, 220, 255),
+            TorchPreset::Red | TorchPreset::NightVision => RGB8::new(255, 0, 0),
+            TorchPreset::Amber => RGB8::new(255, 120, 0),
+        }
+    }
+
+    /// The brightest this preset may be driven at
+    pub fn max_brightness(&self) -> u8 {
+        match self {
+            TorchPreset::NightVision => NIGHT_VISION_BRIGHTNESS,
+            _ => 255,
+        }
+    }
+
+    /// True if this preset is too close to white for night mode
+    pub fn is_white_heavy(&self) -> bool {
+        is_whiteish(self.colour())
+    }
+
+    /// The preset after this one, wrapping around at the end. Used to cycle through the presets
+    /// with a button.
+    pub fn next(self) -> Self {
+        match self {
+            TorchPreset::White => TorchPreset::Warm,
+            TorchPreset::Warm => TorchPreset::Cool,
+            TorchPreset::Cool => TorchPreset::Red,
+            TorchPreset::Red => TorchPreset::NightVision,
+            TorchPreset::NightVision => TorchPreset::Amber,
+            TorchPreset::Amber => TorchPreset::White,
+        }
+    }
+}
+
+/// How bright the torch is
+#[derive(Clone, Copy, Default, PartialEq, Eq, Format)]
+pub enum TorchIntensity {
+    #[default]
+    Low,
+    Medium,
+    High,
+}
+
+impl TorchIntensity {
+    /// The brightness to drive the strip at. High asks for everything and leaves it to the
+    /// power limiter to keep it safe.
+    pub fn brightness(&self) -> u8 {
+        match self {
+            TorchIntensity::Low => 32,
+            TorchIntensity::Medium => 96,
+            TorchIntensity::High => 255,
+        }
+    }
+
+    /// The next brighter intensity, or None if this is already the brightest. Used to step
+    /// through the intensities with a button.
+    pub fn next(self) -> Option<Self> {
+        match self {
+            TorchIntensity::Low => Some(TorchIntensity::Medium),
+            TorchIntensity::Medium => Some(TorchIntensity::High),
+            TorchIntensity::High => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_cycles_back_to_white() {
+        let mut preset = TorchPreset::White;
+        for _ in 0..6 {
+            preset = preset.next();
+        }
+        assert!(preset == TorchPreset::White);
+    }
+}
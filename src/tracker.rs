@@ -2,101 +2,435 @@
 //! Provides basic tools to update new presences and delete expired presences.
 //! This module manages a list of active presences, their associated colors, and handles
 //! their lifecycle including addition, updates, and expiration.
+//!
+//! Each soul's latest sighting says which PHY its beacon reached us on, which the log shows
+//! alongside its path loss so the range of the PHYs can be compared in the field.
+//!
+//! It also follows the trend of each soul's path loss, so we can tell when someone is coming
+//! closer or walking away (see [Tracker::trend]), and counts how many different souls pass by
+//! each minute (see [SoulRate]).
 
-use crate::configuration::{MAX_SOULS_TRACKED, TRACKER_FLUSH_AGE};
-use crate::presence::PresenceMessage;
-use defmt::{Debug2Format, error, info};
+use crate::colour::distinct_colour;
+use crate::configuration::{MAX_SOULS_PER_MINUTE, MAX_SOULS_TRACKED, MOVEMENT_THRESHOLD};
+use crate::fields::Role;
+use crate::intervals::flush_age;
+use crate::log;
+use crate::payload::Sighting;
+use defmt::Format;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::mutex::Mutex;
 use embassy_time::{Duration, Instant};
 use heapless::Vec;
 use heapless::index_map::FnvIndexMap;
+use heapless::index_set::FnvIndexSet;
 use smart_leds::RGB8;
 use trouble_host::prelude::BdAddr;
 
-pub type PresenceMap<const S: usize> = FnvIndexMap<u32, PresenceMessage, S>;
+pub type PresenceMap<const S: usize> = FnvIndexMap<u32, Sighting, S>;
 type PresenceMutex<const S: usize> = Mutex<NoopRawMutex, PresenceMap<S>>;
 
 /// We want a u32 that sort of uniquely identifies the sender's "MAC" address. As we set this
 /// to some random value, we will have unique key for the hash that we store
-fn addr_to_key(addr: &BdAddr) -> u32 {
+pub fn addr_to_key(addr: &BdAddr) -> u32 {
     let r = addr.raw();
     r[5] as u32 | (r[4] as u32) << 8 | ((r[3] ^ r[1]) as u32) << 16 | ((r[2] ^ r[0]) as u32) << 24
 }
 
-#[derive(Clone, Debug)]
-#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct SoulSummary {
     pub colour: RGB8,
     pub tx_loss: i32,
+    pub role: Role,
+    /// The friend's own pixel in constellation mode, see [constellation](crate::constellation)
+    pub pixel: Option<u16>,
+}
+
+impl From<&Sighting> for SoulSummary {
+    fn from(s: &Sighting) -> Self {
+        SoulSummary {
+            colour: s.colour,
+            tx_loss: s.tx_loss(),
+            role: s.role,
+            pixel: s.pixel,
+        }
+    }
 }
 
 pub type VisibleSouls = Vec<SoulSummary, { MAX_SOULS_TRACKED }>;
 
+/// Which way a soul is moving
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum Movement {
+    Steady,
+    Approaching,
+    Receding,
+}
+
+/// The trend of a soul's path loss. A fast and a slow moving average of the loss, in sixteenths
+/// of a dB, drift apart when the soul is on the move.
+#[derive(Clone, Copy)]
+struct Trend {
+    fast: i32,
+    slow: i32,
+    movement: Movement,
+}
+
+impl Trend {
+    fn new(loss: i32) -> Self {
+        Self {
+            fast: loss * 16,
+            slow: loss * 16,
+            movement: Movement::Steady,
+        }
+    }
+
+    /// Take the latest path loss into account
+    ///
+    /// # Returns
+    /// The movement if the soul just started approaching or receding
+    fn update(&mut self, loss: i32) -> Option<Movement> {
+        self.fast += (loss * 16 - self.fast) / 2;
+        self.slow += (loss * 16 - self.slow) / 8;
+        let drift = (self.fast - self.slow) / 16;
+        // Back to steady only once the averages have nearly caught up with each other
+        let movement = match self.movement {
+            _ if drift <= -MOVEMENT_THRESHOLD => Movement::Approaching,
+            _ if drift >= MOVEMENT_THRESHOLD => Movement::Receding,
+            Movement::Steady => Movement::Steady,
+            _ if drift.abs() < MOVEMENT_THRESHOLD / 2 => Movement::Steady,
+            movement => movement,
+        };
+        let changed = movement != self.movement;
+        self.movement = movement;
+        (changed && movement != Movement::Steady).then_some(movement)
+    }
+}
+
+/// A rolling count of the different souls we see a minute. The souls seen in the current minute
+/// are counted exactly. Early in the minute that count hasn't caught up yet, so the count for the
+/// minute before is faded out across the minute and the larger of the two is the rate. A steady
+/// crowd reads the same all the time and an empty room fades to nothing within two minutes.
+///
+/// The generic parameter N is the most souls a minute it can count. It tops out there.
+pub struct SoulRate<const N: usize> {
+    /// When the current minute started, None until we see somebody
+    started: Option<Instant>,
+    /// The souls seen so far this minute
+    current: FnvIndexSet<u32, N>,
+    /// The number of souls seen in the minute before
+    previous: usize,
+}
+
+impl<const N: usize> Default for SoulRate<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SoulRate<N> {
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    pub fn new() -> Self {
+        Self {
+            started: None,
+            current: FnvIndexSet::new(),
+            previous: 0,
+        }
+    }
+
+    /// Count a sighting. The same soul is only counted once a minute.
+    ///
+    /// # Parameters
+    /// * `key` - The tracker key of the soul
+    /// * `now` - When we saw it
+    pub fn seen(&mut self, key: u32, now: Instant) {
+        match self.started {
+            Some(started) if now < started + Self::MINUTE => (),
+            Some(started) if now < started + Self::MINUTE * 2 => {
+                self.previous = self.current.len();
+                self.current.clear();
+                self.started = Some(started + Self::MINUTE);
+            }
+            _ => {
+                self.previous = 0;
+                self.current.clear();
+                self.started = Some(now);
+            }
+        }
+        // Once it is full we have counted as many as we can
+        let _ = self.current.insert(key);
+    }
+
+    /// The number of different souls we are seeing a minute
+    ///
+    /// # Parameters
+    /// * `now` - The time now
+    pub fn per_minute(&self, now: Instant) -> usize {
+        let Some(started) = self.started else {
+            return 0;
+        };
+        let minute = Self::MINUTE.as_millis();
+        let elapsed = now.saturating_duration_since(started).as_millis();
+        // How much of a minute that started `ago` milliseconds before this one is still to run
+        let fade = |count: usize, ago: u64| (count as u64 * minute.saturating_sub(elapsed - ago) / minute) as usize;
+        if elapsed < minute {
+            self.current.len().max(fade(self.previous, 0))
+        } else {
+            // Nobody since the minute ended, so it fades like the minute before
+            fade(self.current.len(), minute)
+        }
+    }
+
+    /// Forget everybody
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
 /// A tracker that manages a fixed-size collection of presence messages.
 /// Each presence message represents a connected device (soul) with its associated
 /// properties like name, colour, and last seen timestamp.
 ///
+/// The tracker also keeps a summary of the souls for the animations. It is updated in place as
+/// sightings arrive and a generation counter is bumped every time it changes, so that animations
+/// only need to copy it when it has actually changed. Souls whose colours are too alike to tell
+/// apart on the strip are given nudged colours in the summary (see [distinct_colour]).
+///
 /// The generic parameter S determines the maximum number of presences that can be tracked.
 pub struct Tracker<const S: usize> {
     pub souls: PresenceMutex<S>,
+    /// Summary of each soul, in the same order as the souls map, with distinct colours
+    summary: Vec<SoulSummary, S>,
+    /// Bumped every time the summary changes
+    generation: u32,
+    /// The trend of each soul's path loss
+    trends: FnvIndexMap<u32, Trend, S>,
+    /// How many different souls we see a minute
+    rate: SoulRate<MAX_SOULS_PER_MINUTE>,
+}
+
+impl<const S: usize> Default for Tracker<S> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<const S: usize> Tracker<S> {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             souls: Mutex::new(FnvIndexMap::new()),
+            summary: Vec::new(),
+            generation: 0,
+            trends: FnvIndexMap::new(),
+            rate: SoulRate::new(),
         }
     }
 
     /// Updates the tracker with the lastest presence messages
     /// It returns true if the tracker list was updated
-    pub async fn update(&mut self, presence: &PresenceMessage) -> bool {
-        let addr = presence.address;
-        let name = presence.name.clone();
+    pub async fn update(&mut self, sighting: &Sighting) -> bool {
+        self.rate.seen(sighting.key, sighting.last_seen);
         let mut guard = self.souls.lock().await;
-        match guard.insert(addr_to_key(&addr), presence.clone()) {
-            Ok(Some(_)) => false, // Already present, but we may have an updated RSSI, so at some point, we want to react to the RSSI change
+        let summary = SoulSummary::from(sighting);
+        match guard.insert(sighting.key, *sighting) {
+            // Already present, but the signal strength may have changed
+            Ok(Some(previous)) => {
+                if previous.colour != sighting.colour
+                    || previous.role != sighting.role
+                    || previous.pixel != sighting.pixel
+                {
+                    // Everybody after this soul may have been nudged around its old colour
+                    self.summary = guard.values().map(SoulSummary::from).collect();
+                    distinguish(&mut self.summary, 0);
+                    self.generation = self.generation.wrapping_add(1);
+                } else if let Some(idx) = guard.keys().position(|&k| k == sighting.key)
+                    && self.summary[idx].tx_loss != summary.tx_loss
+                {
+                    log!(
+                        Tracker,
+                        debug,
+                        "TRACKER: Soul {:x} now at {}dB loss, heard on {:?}",
+                        sighting.key,
+                        summary.tx_loss,
+                        sighting.reception
+                    );
+                    self.summary[idx].tx_loss = summary.tx_loss;
+                    self.generation = self.generation.wrapping_add(1);
+                }
+                false
+            }
             Ok(None) => {
-                info!("TRACKER: Adding {} with name {}", Debug2Format(&addr), Debug2Format(&name));
+                log!(Tracker, info, "TRACKER: Adding soul {:x}, heard on {:?}", sighting.key, sighting.reception);
+                // The map has room for it, so the summary does too
+                let _ = self.summary.push(summary);
+                distinguish(&mut self.summary, guard.len() - 1);
+                self.generation = self.generation.wrapping_add(1);
                 true
             }
             Err(_) => {
-                error!("TRACKER: Error inserting/updating the tracker");
+                log!(Tracker, error, "TRACKER: Error inserting/updating the tracker");
                 false
             }
         }
     }
 
-    /// Retrieve the information that would be used by an animation. So just colour and the
-    /// transmitter power.
-    pub async fn get_soul_summary(&self) -> VisibleSouls {
+    /// Follow the trend of a soul's path loss
+    ///
+    /// # Returns
+    /// [Movement::Approaching] or [Movement::Receding] when the soul starts coming closer or
+    /// walking away, None otherwise
+    pub fn trend(&mut self, sighting: &Sighting) -> Option<Movement> {
+        let loss = sighting.tx_loss();
+        match self.trends.get_mut(&sighting.key) {
+            Some(trend) => trend.update(loss),
+            None => {
+                // The map is as big as the tracker, so there is room unless the soul was flushed
+                let _ = self.trends.insert(sighting.key, Trend::new(loss));
+                None
+            }
+        }
+    }
+
+    /// The information that would be used by an animation. So just colour and the path loss. The
+    /// colours may have been nudged so that every soul can be told apart.
+    pub fn summary(&self) -> &[SoulSummary] {
+        &self.summary
+    }
+
+    /// The number of different souls we are seeing a minute, including any we had no room to track
+    ///
+    /// # Parameters
+    /// * `now` - The time now
+    pub fn souls_per_minute(&self, now: Instant) -> usize {
+        self.rate.per_minute(now)
+    }
+
+    /// Changes every time the summary changes
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// The summary for a single soul, or None if we can no longer see it
+    ///
+    /// # Parameters
+    /// * `key` - The tracker key of the soul, see [addr_to_key]
+    pub async fn soul(&self, key: u32) -> Option<SoulSummary> {
+        self.souls.lock().await.get(&key).map(SoulSummary::from)
+    }
+
+    /// Step through the visible souls, wrapping around at the end
+    ///
+    /// # Parameters
+    /// * `after` - The key of the current soul, or None to start at the first
+    ///
+    /// # Returns
+    /// The key and summary of the next soul, or None if we can't see anybody
+    pub async fn next_soul(&self, after: Option<u32>) -> Option<(u32, SoulSummary)> {
         let guard = self.souls.lock().await;
-        guard
-            .iter()
-            .map(|(_, p)| SoulSummary {
-                colour: p.colour,
-                tx_loss: p.tx_power as i32 - p.rssi as i32,
-            })
-            .collect()
+        let next = after
+            .and_then(|k| guard.keys().position(|&p| p == k))
+            .map_or(0, |idx| (idx + 1) % guard.len());
+        guard.iter().nth(next).map(|(k, p)| (*k, SoulSummary::from(p)))
     }
 
-    /// Flush all presence entries that are older than the time specified in the argument
+    /// Forget every soul we have seen
+    pub async fn clear(&mut self) {
+        self.souls.lock().await.clear();
+        self.summary.clear();
+        self.trends.clear();
+        self.rate.clear();
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Flush all presence entries that have not been seen for the [flush_age]
     pub async fn flush(&mut self) -> bool {
         // If our first flush happens in less time than our uptime, this crashes
-        if let Some(horizon) = Instant::now().checked_sub(Duration::from_secs(TRACKER_FLUSH_AGE)) {
+        if let Some(horizon) = Instant::now().checked_sub(flush_age()) {
             let mut guard = self.souls.lock().await;
             let len = guard.len();
             guard.retain(|_, v| {
                 if v.last_seen > horizon {
                     true
                 } else {
-                    info!("TRACKER: Removing {} with last presence at {:?}", Debug2Format(&v.name), v.last_seen);
+                    log!(Tracker, info, "TRACKER: Removing soul {:x} with last presence at {:?}", v.key, v.last_seen);
                     false
                 }
             });
-            return len > guard.len();
+            if len == guard.len() {
+                return false;
+            }
+            self.trends.retain(|k, _| guard.contains_key(k));
+            // Removing souls can reorder the map, so rebuild the summary to match
+            self.summary = guard.values().map(SoulSummary::from).collect();
+            distinguish(&mut self.summary, 0);
+            self.generation = self.generation.wrapping_add(1);
+            return true;
         };
         false
     }
 }
+
+/// Give the souls from `from` onwards colours that can be told apart from all the souls before them
+fn distinguish<const S: usize>(summary: &mut Vec<SoulSummary, S>, from: usize) {
+    let mut taken: Vec<RGB8, S> = summary[..from].iter().map(|s| s.colour).collect();
+    for soul in summary[from..].iter_mut() {
+        soul.colour = distinct_colour(soul.colour, &taken);
+        let _ = taken.push(soul.colour);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_it_spots_souls_on_the_move() {
+        let mut trend = Trend::new(80);
+        assert!((0..3).all(|_| trend.update(80).is_none()));
+        let approaching: Vec<_, 20> = (0..20).filter_map(|_| trend.update(60)).collect();
+        assert_eq!(approaching, [Movement::Approaching]);
+        assert_eq!(trend.update(90), Some(Movement::Receding));
+        assert!((0..10).all(|_| trend.update(90).is_none()));
+    }
+
+    #[test]
+    pub fn if_it_counts_souls_a_minute() {
+        let start = Instant::from_secs(100);
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut rate: SoulRate<8> = SoulRate::new();
+        assert_eq!(rate.per_minute(start), 0);
+        // Each soul counts once however often we see it
+        for secs in 0..60 {
+            rate.seen(secs as u32 % 3, at(secs));
+        }
+        assert_eq!(rate.per_minute(at(59)), 3);
+        // Early in the next minute the one before still counts
+        rate.seen(7, at(61));
+        assert_eq!(rate.per_minute(at(61)), 2);
+        rate.seen(8, at(62));
+        assert_eq!(rate.per_minute(at(90)), 2);
+        // An empty room fades away
+        assert_eq!(rate.per_minute(at(150)), 1);
+        assert_eq!(rate.per_minute(at(180)), 0);
+        rate.seen(1, at(300));
+        assert_eq!(rate.per_minute(at(300)), 1);
+        // It tops out when it is full
+        (0..20).for_each(|key| rate.seen(key, at(301)));
+        assert_eq!(rate.per_minute(at(301)), 8);
+    }
+
+    #[test]
+    pub fn if_every_address_byte_counts() {
+        let mut rng = fastrand::Rng::with_seed(0xADD2);
+        for _ in 0..10_000 {
+            let mut raw = [0u8; 6];
+            rng.fill(&mut raw);
+            let key = addr_to_key(&BdAddr::new(raw));
+            assert_eq!(addr_to_key(&BdAddr::new(raw)), key);
+            // Changing any one byte of the address gives a different key
+            let i = rng.usize(..raw.len());
+            raw[i] ^= rng.u8(1..);
+            assert_ne!(addr_to_key(&BdAddr::new(raw)), key);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! The events that can drive the auxiliary trigger output, so that EL-wire drivers, relays or
+//! sound modules can react to the souls around us.
+
+use crate::configuration::TRIGGER_EVENTS;
+use defmt::Format;
+
+/// Something happening around us that can fire the trigger output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum TriggerEvent {
+    /// A soul we weren't tracking has come into range
+    SoulJoined,
+    /// A favourite soul has been seen. Fired for every advertisement, so the output can be held
+    /// for as long as they stay near.
+    FavouriteNear,
+    /// A soul is calling for help
+    Emergency,
+}
+
+impl TriggerEvent {
+    /// True if this event is configured to fire the trigger output
+    pub fn fires(self) -> bool {
+        TRIGGER_EVENTS.contains(&self)
+    }
+}
+
+/// How the trigger output responds to an event
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum TriggerMode {
+    /// A single pulse when events start happening after a quiet spell
+    Pulse,
+    /// On for as long as events keep happening
+    Assert,
+}
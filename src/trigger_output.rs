@@ -0,0 +1,54 @@
+//! Optional auxiliary output that fires when chosen tracker events occur, see
+//! [TriggerEvent](soulstar::trigger::TriggerEvent).
+//!
+//! Like the vibration motor, events can be fired without knowing whether anything is wired up. The
+//! trigger task is only started if [TRIGGER_ENABLED](soulstar::configuration::TRIGGER_ENABLED) is
+//! set. The output behaves like a retriggerable monostable: it stays triggered until there have
+//! been no events for [TRIGGER_HOLD] seconds. In pulse mode it only pulses as it is triggered, in
+//! assert mode it is on the whole time.
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::{Level, Output};
+use soulstar::configuration::{TRIGGER_ACTIVE_HIGH, TRIGGER_HOLD, TRIGGER_MODE, TRIGGER_PULSE};
+use soulstar::log;
+use soulstar::trigger::{TriggerEvent, TriggerMode};
+
+/// The latest event. Events that arrive while triggered simply keep the output triggered.
+static EVENTS: Signal<CriticalSectionRawMutex, TriggerEvent> = Signal::new();
+
+/// Let the trigger output know that something happened. Events that are not in
+/// [TRIGGER_EVENTS](soulstar::configuration::TRIGGER_EVENTS) are ignored.
+pub fn fire(event: TriggerEvent) {
+    if event.fires() {
+        EVENTS.signal(event);
+    }
+}
+
+/// The output level for the active or idle state
+fn level(active: bool) -> Level {
+    Level::from(active == TRIGGER_ACTIVE_HIGH)
+}
+
+/// Drive the trigger output from the events
+///
+/// # Parameters
+/// * `output` - The pin the external driver, relay or sound module is wired to
+#[embassy_executor::task]
+pub async fn trigger_task(mut output: Output<'static>) {
+    output.set_level(level(false));
+    loop {
+        let event = EVENTS.wait().await;
+        log!(Outputs, info, "TRIGGER: Triggered by {}", event);
+        output.set_level(level(true));
+        if TRIGGER_MODE == TriggerMode::Pulse {
+            Timer::after(Duration::from_millis(TRIGGER_PULSE)).await;
+            output.set_level(level(false));
+        }
+        // Stay triggered until things have been quiet for a while
+        while let Either::Second(_) = select(Timer::after(Duration::from_secs(TRIGGER_HOLD)), EVENTS.wait()).await {}
+        output.set_level(level(false));
+    }
+}
@@ -0,0 +1,79 @@
+//! Live tuning of the animations, so effects can be adjusted from a phone while watching the strip.
+//!
+//! Each parameter arrives on its own (see `soul_link.rs`) and is passed to the display task in a
+//! `Tune` message. The display task keeps the [Tuning] and hands it to the animations, each of
+//! which takes the parameters that mean something to it. Tuning only lasts until the next restart.
+
+use embassy_time::Duration;
+use smart_leds::RGB8;
+
+/// The slowest the animations can be tuned to, in percent of their normal speed
+const MIN_SPEED: u8 = 10;
+
+/// One tuned parameter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TuningParam {
+    /// Animation speed in percent of normal, from 10 to 255
+    Speed(u8),
+    /// How much of the strip sparkles, 255 for all of it
+    Density(u8),
+    /// How much of each pixel is kept in every frame of a tail, 128 halves it
+    Tail(u8),
+    /// The colour to use instead of our own
+    Palette(RGB8),
+}
+
+/// The tuned animation parameters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tuning {
+    pub speed: u8,
+    pub density: u8,
+    pub tail: u8,
+    /// None to keep the animation's own colours
+    pub palette: Option<RGB8>,
+}
+
+impl Default for Tuning {
+    /// The parameters the animations were designed with
+    fn default() -> Self {
+        Self {
+            speed: 100,
+            density: 255,
+            tail: 128,
+            palette: None,
+        }
+    }
+}
+
+impl Tuning {
+    /// Change one parameter
+    pub fn set(&mut self, param: TuningParam) {
+        match param {
+            TuningParam::Speed(speed) => self.speed = speed.max(MIN_SPEED),
+            TuningParam::Density(density) => self.density = density,
+            TuningParam::Tail(tail) => self.tail = tail,
+            TuningParam::Palette(colour) => self.palette = Some(colour),
+        }
+    }
+
+    /// An animation's frame interval adjusted for the tuned speed
+    pub fn frame_interval(&self, interval: Duration) -> Duration {
+        interval * 100 / self.speed.max(MIN_SPEED) as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_speed_scales_the_frame_interval() {
+        let mut tuning = Tuning::default();
+        let interval = Duration::from_millis(200);
+        assert_eq!(tuning.frame_interval(interval), interval);
+        tuning.set(TuningParam::Speed(200));
+        assert_eq!(tuning.frame_interval(interval), Duration::from_millis(100));
+        tuning.set(TuningParam::Speed(0));
+        assert_eq!(tuning.frame_interval(interval), Duration::from_millis(2000));
+    }
+}
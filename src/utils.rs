@@ -21,7 +21,22 @@ pub fn clip_min(v: i16, min: u8) -> u8 {
     }
 }
 
-/*
+/// The standard CRC-32 (as used by Ethernet and zip)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -42,4 +57,3 @@ mod test {
         assert_eq!(clip_min(255, 10), 255);
     }
 }
-*/
\ No newline at end of file
@@ -0,0 +1,97 @@
+//! Waving at the souls around us, a hands-free hello for when the buttons are buried under a
+//! jacket.
+//!
+//! A double tap on the enclosure (see [motion](crate::motion)) sets the wave flag in our beacon
+//! for [WAVE_DURATION] seconds. A soul that hears it flashes our colour once, however many of
+//! our beacons it picks up while the wave lasts.
+
+use crate::configuration::{RADIO_MODE_POLL_INTERVAL, WAVE_DURATION};
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::index_map::FnvIndexMap;
+
+/// The most souls whose waves we remember at once. Must be a power of two.
+const MAX_WAVES: usize = 8;
+
+/// When we last waved
+static WAVED: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+/// Wave at everybody in range for the next [WAVE_DURATION] seconds
+pub fn wave() {
+    WAVED.lock(|w| w.set(Some(Instant::now())));
+}
+
+/// True while our beacon should carry the wave flag
+pub fn waving() -> bool {
+    WAVED
+        .lock(|w| w.get())
+        .is_some_and(|at| at.elapsed() < Duration::from_secs(WAVE_DURATION))
+}
+
+/// Wait until we start or stop waving
+///
+/// # Parameters
+/// * `on` - Whether the caller is currently advertising a wave
+pub async fn waving_changed(on: bool) {
+    while waving() == on {
+        Timer::after(Duration::from_millis(RADIO_MODE_POLL_INTERVAL)).await;
+    }
+}
+
+/// Decides which of the waves we hear get a flash
+#[derive(Default)]
+pub struct WaveFilter {
+    /// When we last heard each soul waving
+    heard: FnvIndexMap<u32, Instant, MAX_WAVES>,
+}
+
+impl WaveFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// True if a wave is new. A wave is beaconed over and over, so this only says yes again
+    /// once the soul has stopped waving for a while.
+    ///
+    /// # Arguments
+    /// * `key` - The tracker key of the soul that is waving
+    /// * `now` - When we heard it
+    pub fn accept(&mut self, key: u32, now: Instant) -> bool {
+        let over = |heard: Instant| now.saturating_duration_since(heard) >= Duration::from_secs(WAVE_DURATION);
+        self.heard.retain(|_, heard| !over(*heard));
+        let new = !self.heard.contains_key(&key);
+        // With too many souls waving at once, the extra ones just get a flash every time
+        let _ = self.heard.insert(key, now);
+        new
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn if_each_wave_flashes_once() {
+        let mut filter = WaveFilter::new();
+        let mut now = Instant::from_secs(100);
+        assert!(filter.accept(1, now));
+        assert!(filter.accept(2, now));
+        // The same wave again and again while it lasts
+        for _ in 0..10 {
+            now += Duration::from_millis(500);
+            assert!(!filter.accept(1, now));
+        }
+        // A new wave once they have stopped for a while
+        now += Duration::from_secs(WAVE_DURATION);
+        assert!(filter.accept(1, now));
+    }
+
+    #[test]
+    pub fn if_waving_sets_the_flag() {
+        assert!(!waving());
+        wave();
+        assert!(waving());
+    }
+}
@@ -0,0 +1,118 @@
+//! The venue Wi-Fi, for taking light shows from a lighting desk, see [light_show](soulstar::light_show).
+//!
+//! The Wi-Fi is only started if [LIGHT_SHOW_ENABLED](soulstar::configuration::LIGHT_SHOW_ENABLED)
+//! is set and the network is given in souls.toml. The password is never kept in a file, it comes
+//! from the `SOULSTAR_WIFI_PASSWORD` environment variable when building. The Wi-Fi shares the
+//! radio with BLE, so we carry on seeing souls and being seen while we listen to the desk. Art-Net
+//! is taken from broadcast or unicast packets and sACN from its universe's multicast group or
+//! unicast packets.
+
+use crate::display_task::DisplayChannelSender;
+use crate::display_task::DisplayState::LightShow;
+use crate::soul_config::WIFI_SSID;
+use defmt::Debug2Format;
+use embassy_futures::select::{Either, select};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Ipv4Address, Runner, Stack};
+use embassy_time::{Duration, Timer};
+use esp_radio::wifi::{ClientConfig, ModeConfig, WifiController, WifiDevice, WifiEvent};
+use soulstar::configuration::{ARTNET_UNIVERSE, SACN_UNIVERSE};
+use soulstar::light_show::{ARTNET_PORT, SACN_PORT, artnet, desk_frame, sacn, sacn_group};
+use soulstar::log;
+
+/// The venue Wi-Fi password, empty for an open network
+const WIFI_PASSWORD: &str = match option_env!("SOULSTAR_WIFI_PASSWORD") {
+    Some(password) => password,
+    None => "",
+};
+
+/// How long to wait before joining the Wi-Fi again after losing it (seconds)
+const RETRY_INTERVAL: u64 = 5;
+
+/// The biggest packet we expect, a full universe of sACN with room to spare
+const MAX_PACKET: usize = 700;
+
+/// Join the venue Wi-Fi and keep rejoining it whenever we lose it
+///
+/// # Parameters
+/// * `controller` - The Wi-Fi controller
+#[embassy_executor::task]
+pub async fn wifi_task(mut controller: WifiController<'static>) {
+    let config = ModeConfig::Client(
+        ClientConfig::default()
+            .with_ssid(WIFI_SSID.into())
+            .with_password(WIFI_PASSWORD.into()),
+    );
+    if let Err(e) = controller.set_config(&config) {
+        log!(Main, error, "WIFI: Could not configure the Wi-Fi: {:?}", Debug2Format(&e));
+        return;
+    }
+    loop {
+        if !matches!(controller.is_started(), Ok(true))
+            && let Err(e) = controller.start_async().await
+        {
+            log!(Main, error, "WIFI: Could not start the Wi-Fi: {:?}", Debug2Format(&e));
+        }
+        match controller.connect_async().await {
+            Ok(()) => {
+                log!(Main, info, "WIFI: Joined {}", WIFI_SSID);
+                controller.wait_for_event(WifiEvent::StaDisconnected).await;
+                log!(Main, warn, "WIFI: Lost {}", WIFI_SSID);
+            }
+            Err(e) => log!(Main, warn, "WIFI: Could not join {}: {:?}", WIFI_SSID, Debug2Format(&e)),
+        }
+        Timer::after(Duration::from_secs(RETRY_INTERVAL)).await;
+    }
+}
+
+/// Run the network stack
+#[embassy_executor::task]
+pub async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) -> ! {
+    runner.run().await
+}
+
+/// Listen for the desk and hand its frames to the display
+///
+/// # Parameters
+/// * `stack` - The network stack on the venue Wi-Fi
+/// * `display` - Display channel sender for the frames
+#[embassy_executor::task]
+pub async fn light_show_task(stack: Stack<'static>, display: DisplayChannelSender) {
+    stack.wait_config_up().await;
+    log!(Main, info, "WIFI: Listening for Art-Net universe {} and sACN universe {}", ARTNET_UNIVERSE, SACN_UNIVERSE);
+    if let Err(e) = stack.join_multicast_group(Ipv4Address::from(sacn_group(SACN_UNIVERSE))) {
+        log!(Main, warn, "WIFI: Could not join the sACN group: {:?}", Debug2Format(&e));
+    }
+    let (mut artnet_meta, mut artnet_rx) = ([PacketMetadata::EMPTY; 4], [0u8; 2 * MAX_PACKET]);
+    let (mut sacn_meta, mut sacn_rx) = ([PacketMetadata::EMPTY; 4], [0u8; 2 * MAX_PACKET]);
+    // We never send anything
+    let (mut artnet_tx_meta, mut artnet_tx) = ([PacketMetadata::EMPTY; 1], [0u8; 0]);
+    let (mut sacn_tx_meta, mut sacn_tx) = ([PacketMetadata::EMPTY; 1], [0u8; 0]);
+    let mut artnet_socket =
+        UdpSocket::new(stack, &mut artnet_meta, &mut artnet_rx, &mut artnet_tx_meta, &mut artnet_tx);
+    let mut sacn_socket = UdpSocket::new(stack, &mut sacn_meta, &mut sacn_rx, &mut sacn_tx_meta, &mut sacn_tx);
+    if artnet_socket.bind(ARTNET_PORT).is_err() || sacn_socket.bind(SACN_PORT).is_err() {
+        log!(Main, error, "WIFI: Could not listen for the desk");
+        return;
+    }
+    let mut artnet_packet = [0u8; MAX_PACKET];
+    let mut sacn_packet = [0u8; MAX_PACKET];
+    loop {
+        let received =
+            select(artnet_socket.recv_from(&mut artnet_packet), sacn_socket.recv_from(&mut sacn_packet)).await;
+        // Anything that isn't our universe is for some other fixture
+        let frame = match received {
+            Either::First(Ok((length, _))) => artnet(&artnet_packet[..length])
+                .filter(|u| u.universe == ARTNET_UNIVERSE)
+                .map(|u| desk_frame(u.channels)),
+            Either::Second(Ok((length, _))) => sacn(&sacn_packet[..length])
+                .filter(|u| u.universe == SACN_UNIVERSE)
+                .map(|u| desk_frame(u.channels)),
+            // Truncated packets are no good to us
+            _ => None,
+        };
+        if let Some(frame) = frame {
+            display.send(LightShow(frame).into()).await;
+        }
+    }
+}